@@ -0,0 +1,298 @@
+use super::expression::EvalError;
+use super::value::{hash_key, DataType, Value};
+use std::collections::HashSet;
+
+/// Running state of an aggregate function over the rows of one group
+pub trait Aggregate {
+    /// feeds the arguments evaluated for one row
+    fn step(&mut self, args: &[Value]) -> Result<(), EvalError>;
+    /// returns the result over the rows fed so far. more rows may be fed
+    /// afterwards, as for the running totals of window functions.
+    fn finalize(&mut self) -> Result<Value, EvalError>;
+}
+
+const AGGREGATES: [&str; 6] = ["avg", "count", "group_concat", "max", "min", "sum"];
+
+pub fn is_aggregate(name: &str) -> bool {
+    AGGREGATES.contains(&name)
+}
+
+/// checks the number of arguments passed to the aggregate function
+pub fn check_arguments(name: &str, num_args: usize) -> Result<(), String> {
+    let valid = match name {
+        // no arguments stands for `count(*)`
+        "count" => num_args <= 1,
+        "group_concat" => num_args == 1 || num_args == 2,
+        _ => num_args == 1,
+    };
+
+    if !valid {
+        return Err(format!("wrong number of arguments to function {}()", name));
+    }
+    Ok(())
+}
+
+/// returns the type an aggregate produces from arguments of the given type
+pub fn return_type(name: &str, arg_type: Option<DataType>) -> Option<DataType> {
+    match name {
+        "count" => Some(DataType::Integer),
+        "avg" => Some(DataType::Real),
+        "group_concat" => Some(DataType::Text),
+        "sum" => match arg_type {
+            Some(DataType::Text) => None,
+            t => t,
+        },
+        _ => arg_type,
+    }
+}
+
+/// creates fresh state for the aggregate function with the given name
+pub fn new_aggregate(name: &str, distinct: bool) -> Option<Box<dyn Aggregate>> {
+    let aggregate: Box<dyn Aggregate> = match name {
+        "count" => Box::new(Count(0)),
+        "sum" => Box::new(Sum::default()),
+        "avg" => Box::new(Avg::default()),
+        "min" => Box::new(Extreme::new(false)),
+        "max" => Box::new(Extreme::new(true)),
+        "group_concat" => Box::new(GroupConcat(None)),
+        _ => return None,
+    };
+
+    if distinct {
+        return Some(with_distinct(aggregate));
+    }
+    Some(aggregate)
+}
+
+/// wraps the aggregate so that it only sees the first occurrence of each argument
+pub fn with_distinct(aggregate: Box<dyn Aggregate>) -> Box<dyn Aggregate> {
+    Box::new(Distinct {
+        inner: aggregate,
+        seen: HashSet::new(),
+    })
+}
+
+/// count(*) counts every row, count(x) only rows where x is not NULL
+struct Count(i64);
+
+impl Aggregate for Count {
+    fn step(&mut self, args: &[Value]) -> Result<(), EvalError> {
+        if args.first().map(|v| !v.is_null()).unwrap_or(true) {
+            self.0 += 1;
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<Value, EvalError> {
+        Ok(Value::Integer(self.0))
+    }
+}
+
+/// sum stays an integer until it sees a real, and is NULL without any input
+#[derive(Default)]
+struct Sum {
+    integer: i64,
+    real: f64,
+    is_real: bool,
+    has_value: bool,
+}
+
+impl Aggregate for Sum {
+    fn step(&mut self, args: &[Value]) -> Result<(), EvalError> {
+        match args[0].to_numeric() {
+            Value::Integer(i) if !self.is_real => {
+                self.integer = self
+                    .integer
+                    .checked_add(i)
+                    .ok_or_else(|| String::from("integer overflow"))?;
+            }
+            Value::Null => return Ok(()),
+            v => {
+                if !self.is_real {
+                    self.is_real = true;
+                    self.real = self.integer as f64;
+                }
+                self.real += v.as_real().unwrap();
+            }
+        }
+        self.has_value = true;
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<Value, EvalError> {
+        Ok(match (self.has_value, self.is_real) {
+            (false, _) => Value::Null,
+            (true, false) => Value::Integer(self.integer),
+            (true, true) => Value::Real(self.real),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Avg {
+    sum: f64,
+    count: usize,
+}
+
+impl Aggregate for Avg {
+    fn step(&mut self, args: &[Value]) -> Result<(), EvalError> {
+        if let Some(v) = args[0].as_real() {
+            self.sum += v;
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<Value, EvalError> {
+        if self.count == 0 {
+            return Ok(Value::Null);
+        }
+        Ok(Value::Real(self.sum / self.count as f64))
+    }
+}
+
+/// min or max, ignoring NULLs
+struct Extreme {
+    is_max: bool,
+    best: Option<Value>,
+}
+
+impl Extreme {
+    fn new(is_max: bool) -> Self {
+        Extreme { is_max, best: None }
+    }
+}
+
+impl Aggregate for Extreme {
+    fn step(&mut self, args: &[Value]) -> Result<(), EvalError> {
+        let value = &args[0];
+        if value.is_null() {
+            return Ok(());
+        }
+
+        let replace = match &self.best {
+            None => true,
+            Some(best) => {
+                let ordering = value.compare(best);
+                if self.is_max {
+                    ordering.is_gt()
+                } else {
+                    ordering.is_lt()
+                }
+            }
+        };
+        if replace {
+            self.best = Some(value.clone());
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<Value, EvalError> {
+        Ok(self.best.clone().unwrap_or(Value::Null))
+    }
+}
+
+/// joins non-NULL values with `,` or the separator given as second argument
+struct GroupConcat(Option<String>);
+
+impl Aggregate for GroupConcat {
+    fn step(&mut self, args: &[Value]) -> Result<(), EvalError> {
+        let text = match args[0].as_text() {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+
+        match self.0.as_mut() {
+            None => self.0 = Some(text),
+            Some(joined) => {
+                let separator = match args.get(1) {
+                    Some(separator) => separator.as_text().unwrap_or_default(),
+                    None => String::from(","),
+                };
+                joined.push_str(&separator);
+                joined.push_str(&text);
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<Value, EvalError> {
+        Ok(self.0.clone().map(Value::Text).unwrap_or(Value::Null))
+    }
+}
+
+/// feeds only the first occurrence of each argument list to the wrapped aggregate
+struct Distinct {
+    inner: Box<dyn Aggregate>,
+    seen: HashSet<Vec<u8>>,
+}
+
+impl Aggregate for Distinct {
+    fn step(&mut self, args: &[Value]) -> Result<(), EvalError> {
+        if args.first().map(Value::is_null).unwrap_or(false) {
+            return Ok(());
+        }
+        if self.seen.insert(hash_key(&args[..1])) {
+            self.inner.step(args)?;
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<Value, EvalError> {
+        self.inner.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(name: &str, distinct: bool, inputs: &[Value]) -> Value {
+        let mut aggregate = new_aggregate(name, distinct).unwrap();
+        for input in inputs.iter() {
+            aggregate.step(std::slice::from_ref(input)).unwrap();
+        }
+        aggregate.finalize().unwrap()
+    }
+
+    #[test]
+    fn test_builtin_aggregates() {
+        let inputs = vec![
+            Value::Integer(3),
+            Value::Null,
+            Value::Integer(1),
+            Value::Integer(3),
+        ];
+
+        assert_eq!(run("count", false, &inputs), Value::Integer(3));
+        assert_eq!(run("count", true, &inputs), Value::Integer(2));
+        assert_eq!(run("sum", false, &inputs), Value::Integer(7));
+        assert_eq!(run("sum", true, &inputs), Value::Integer(4));
+        assert_eq!(run("avg", false, &inputs), Value::Real(7.0 / 3.0));
+        assert_eq!(run("min", false, &inputs), Value::Integer(1));
+        assert_eq!(run("max", false, &inputs), Value::Integer(3));
+        assert_eq!(
+            run("group_concat", false, &inputs),
+            Value::Text(String::from("3,1,3"))
+        );
+    }
+
+    #[test]
+    fn test_aggregates_over_no_rows() {
+        assert_eq!(run("count", false, &[]), Value::Integer(0));
+        assert_eq!(run("sum", false, &[Value::Null]), Value::Null);
+        assert_eq!(run("avg", false, &[]), Value::Null);
+        assert_eq!(run("max", false, &[]), Value::Null);
+        assert_eq!(run("group_concat", false, &[]), Value::Null);
+    }
+
+    #[test]
+    fn test_sum_switches_to_real() {
+        let inputs = vec![Value::Integer(1), Value::Real(0.5)];
+        assert_eq!(run("sum", false, &inputs), Value::Real(1.5));
+
+        let mut sum = new_aggregate("sum", false).unwrap();
+        sum.step(&[Value::Integer(i64::MAX)]).unwrap();
+        assert!(sum.step(&[Value::Integer(1)]).is_err());
+    }
+}
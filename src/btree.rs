@@ -1,4 +1,6 @@
-use super::row::Row;
+use super::key::{decode_key, encode_key, Key, MAX_KEY_SIZE};
+use super::value::{decode_record, encode_record, Value};
+use std::cmp::Ordering;
 use std::convert::TryInto;
 use std::error::Error;
 use std::mem::size_of;
@@ -6,7 +8,13 @@ use std::ops::Range;
 
 // CONSTANTS
 pub const PAGE_SIZE: usize = 4096;
-const ROW_SIZE: usize = 291;
+
+// NODE TYPES
+pub const NODE_TYPE_FREE: u8 = 0;
+pub const NODE_TYPE_LEAF: u8 = 1;
+pub const NODE_TYPE_OVERFLOW: u8 = 2;
+pub const NODE_TYPE_INDEX: u8 = 3;
+pub const NODE_TYPE_SCHEMA: u8 = 4;
 
 // NODE HEADER CONSTANTS
 const HEADER_NODE_TYPE_SIZE: usize = size_of::<u8>();
@@ -27,27 +35,98 @@ const HEADER_SIZE: usize = HEADER_NODE_TYPE_SIZE
     + HEADER_NUM_CELLS_SIZE;
 
 // NODE BODY CONSTANTS
+// Each cell is laid out as
+// `key size | key | payload size | local payload | [overflow page]`.
+// The overflow page pointer is only present when the payload does not fit locally.
 const LEAF_NODE_BODY_OFFSET: usize = HEADER_SIZE;
-const LEAF_NODE_KEY_SIZE: usize = size_of::<u32>();
-const LEAF_NODE_VALUE_SIZE: usize = ROW_SIZE;
-const CELL_SIZE: usize = LEAF_NODE_KEY_SIZE + LEAF_NODE_VALUE_SIZE;
+const LEAF_NODE_KEY_SIZE_SIZE: usize = size_of::<u32>();
+const LEAF_NODE_PAYLOAD_SIZE_SIZE: usize = size_of::<u32>();
+const LEAF_NODE_OVERFLOW_POINTER_SIZE: usize = size_of::<u32>();
+const CELL_HEADER_SIZE: usize = LEAF_NODE_KEY_SIZE_SIZE + LEAF_NODE_PAYLOAD_SIZE_SIZE;
 const CELLS_SPACE: usize = PAGE_SIZE - HEADER_SIZE;
-pub const MAX_NUM_CELLS: usize = CELLS_SPACE / CELL_SIZE;
 
-type Key = u32;
-type Value = Row;
+/// Largest payload stored inside a leaf cell. Anything beyond this spills into
+/// overflow pages, which keeps room for at least four cells with keys of any
+/// size per leaf node.
+pub const MAX_LOCAL_PAYLOAD: usize =
+    CELLS_SPACE / 4 - CELL_HEADER_SIZE - MAX_KEY_SIZE - LEAF_NODE_OVERFLOW_POINTER_SIZE;
+
+// OVERFLOW PAGE CONSTANTS
+const OVERFLOW_NEXT_POINTER_SIZE: usize = size_of::<u32>();
+const OVERFLOW_NEXT_POINTER_OFFSET: usize = HEADER_NODE_TYPE_OFFSET + HEADER_NODE_TYPE_SIZE;
+
+const OVERFLOW_DATA_LENGTH_SIZE: usize = size_of::<u32>();
+const OVERFLOW_DATA_LENGTH_OFFSET: usize =
+    OVERFLOW_NEXT_POINTER_OFFSET + OVERFLOW_NEXT_POINTER_SIZE;
+
+const OVERFLOW_HEADER_SIZE: usize =
+    HEADER_NODE_TYPE_SIZE + OVERFLOW_NEXT_POINTER_SIZE + OVERFLOW_DATA_LENGTH_SIZE;
+
+// INDEX NODE CONSTANTS
+// Each entry is laid out as `record size | record`, where the record holds the
+// indexed value followed by the key of the row.
+const INDEX_NUM_ENTRIES_SIZE: usize = size_of::<u32>();
+const INDEX_NUM_ENTRIES_OFFSET: usize = HEADER_NODE_TYPE_OFFSET + HEADER_NODE_TYPE_SIZE;
+const INDEX_HEADER_SIZE: usize = HEADER_NODE_TYPE_SIZE + INDEX_NUM_ENTRIES_SIZE;
+const INDEX_RECORD_SIZE_SIZE: usize = size_of::<u32>();
+const INDEX_ENTRIES_SPACE: usize = PAGE_SIZE - INDEX_HEADER_SIZE;
+
+/// Number of payload bytes a single overflow page can hold.
+pub const OVERFLOW_PAGE_CAPACITY: usize = PAGE_SIZE - OVERFLOW_HEADER_SIZE;
+
+/// Payload of a leaf cell. The first `MAX_LOCAL_PAYLOAD` bytes live in the
+/// cell itself and the rest is chained through overflow pages.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Payload {
+    pub size: usize,
+    pub local: Vec<u8>,
+    pub overflow: Option<u32>,
+}
+
+impl Payload {
+    pub fn new(size: usize, local: Vec<u8>, overflow: Option<u32>) -> Self {
+        Self {
+            size,
+            local,
+            overflow,
+        }
+    }
+
+    /// returns number of bytes the cell holding this payload under a key of
+    /// `key_size` bytes occupies in a leaf node
+    pub fn cell_size(&self, key_size: usize) -> usize {
+        cell_size(key_size, self.size)
+    }
+}
 
-#[derive(Eq, PartialEq, Debug)]
+/// returns number of bytes a cell with a key of `key_size` bytes and a payload
+/// of `payload_size` bytes occupies
+pub fn cell_size(key_size: usize, payload_size: usize) -> usize {
+    if payload_size > MAX_LOCAL_PAYLOAD {
+        CELL_HEADER_SIZE + key_size + MAX_LOCAL_PAYLOAD + LEAF_NODE_OVERFLOW_POINTER_SIZE
+    } else {
+        CELL_HEADER_SIZE + key_size + payload_size
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
 pub struct LeafNodeHeader {
     pub is_root: bool,
     pub parent: u32,
     pub num_cells: usize,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
 pub struct LeafNode {
     header: LeafNodeHeader,
-    body: Vec<(Key, Value)>,
+    body: Vec<(Key, Payload)>,
+}
+
+/// A page holding the tail of a payload that did not fit in its leaf cell.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct OverflowPage {
+    pub next: Option<u32>,
+    pub data: Vec<u8>,
 }
 
 fn bool_to_bytes(b: bool) -> Vec<u8> {
@@ -75,6 +154,11 @@ fn bytes_to_u32(input: &[u8]) -> u32 {
     u32::from_le_bytes(raw)
 }
 
+/// returns type of the node serialized in the given page
+pub fn node_type(raw: &[u8]) -> u8 {
+    raw[HEADER_NODE_TYPE_OFFSET]
+}
+
 impl LeafNode {
     const NODE_TYPE_RANGE: Range<usize> = HEADER_NODE_TYPE_OFFSET..HEADER_IS_ROOT_OFFSET;
     const IS_ROOT_RANGE: Range<usize> = HEADER_IS_ROOT_OFFSET..HEADER_PARENT_POINTER_OFFSET;
@@ -83,7 +167,7 @@ impl LeafNode {
     const NUM_CELLS_RANGE: Range<usize> = HEADER_NUM_CELLS_OFFSET..HEADER_SIZE;
 
     fn node_type_bytes() -> Vec<u8> {
-        vec![1]
+        vec![NODE_TYPE_LEAF]
     }
 
     pub fn num_cells(&self) -> usize {
@@ -94,7 +178,7 @@ impl LeafNode {
         self.header.is_root
     }
 
-    pub fn new(is_root: bool, parent: u32, num_cells: u32, body: Vec<(Key, Value)>) -> Self {
+    pub fn new(is_root: bool, parent: u32, num_cells: u32, body: Vec<(Key, Payload)>) -> Self {
         Self {
             header: LeafNodeHeader {
                 is_root,
@@ -105,32 +189,62 @@ impl LeafNode {
         }
     }
 
-    pub fn get_value(&self, num: usize) -> Option<Value> {
-        self.body.get(num).map(|(_, v)| v.clone())
+    pub fn get_payload(&self, num: usize) -> Option<&Payload> {
+        self.body.get(num).map(|(_, p)| p)
+    }
+
+    pub fn get_key(&self, num: usize) -> Option<&[u8]> {
+        self.body.get(num).map(|(k, _)| k.as_slice())
     }
 
-    pub fn get_key(&self, num: usize) -> Option<Key> {
-        self.body.get(num).map(|(k, _)| *k)
+    /// returns if the given key is already stored in this node
+    pub fn key_duplicated(&self, key: &[u8]) -> bool {
+        self.body
+            .binary_search_by(|(k, _)| k.as_slice().cmp(key))
+            .is_ok()
     }
 
-    pub fn key_duplicated(&self, key: Key) -> bool {
-        // if cursor.cell_num < num_cells && node.get_key(cursor.cell_num) == Some(key_to_insert) {
-        // }
-        // TODO: implement
-        false
+    /// returns number of bytes used by the cells of this node
+    pub fn used_space(&self) -> usize {
+        self.body.iter().map(|(k, p)| p.cell_size(k.len())).sum()
     }
 
-    pub fn insert_at(&mut self, pos: usize, key: Key, value: Value) -> Result<(), Box<dyn Error>> {
-        if self.num_cells() >= MAX_NUM_CELLS {
-            unimplemented!("please implement splitting a leaf node");
+    /// returns number of bytes still available for cells in this node
+    pub fn free_space(&self) -> usize {
+        CELLS_SPACE - self.used_space()
+    }
+
+    /// returns if a cell with a key of `key_size` bytes and a payload of
+    /// `payload_size` bytes fits in this node
+    pub fn has_room_for(&self, key_size: usize, payload_size: usize) -> bool {
+        cell_size(key_size, payload_size) <= self.free_space()
+    }
+
+    pub fn insert_at(
+        &mut self,
+        pos: usize,
+        key: Key,
+        payload: Payload,
+    ) -> Result<(), Box<dyn Error>> {
+        // the node is not split, so it rejects the cell that does not fit
+        if !self.has_room_for(key.len(), payload.size) {
+            return Err("Table full".into());
         }
 
-        self.body.insert(pos, (key, value));
+        self.body.insert(pos, (key, payload));
 
         self.header.num_cells += 1;
         Ok(())
     }
 
+    /// removes the cell at `pos` and returns its payload
+    pub fn remove_at(&mut self, pos: usize) -> Payload {
+        let (_, payload) = self.body.remove(pos);
+
+        self.header.num_cells -= 1;
+        payload
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         let mut buff = vec![0; PAGE_SIZE];
 
@@ -146,18 +260,27 @@ impl LeafNode {
             u32_to_bytes(self.num_cells() as u32),
         );
 
-        // Serialize values
-        for (i, (k, v)) in self.body.iter().enumerate() {
-            let key_start = LEAF_NODE_BODY_OFFSET + i * CELL_SIZE;
-            let key_end = key_start + LEAF_NODE_KEY_SIZE;
-            let key_range = key_start..key_end;
+        // Serialize cells
+        let mut offset = LEAF_NODE_BODY_OFFSET;
+        for (k, p) in self.body.iter() {
+            let key_size_end = offset + LEAF_NODE_KEY_SIZE_SIZE;
+            buff.splice(offset..key_size_end, u32_to_bytes(k.len() as u32));
+
+            let key_end = key_size_end + k.len();
+            buff.splice(key_size_end..key_end, k.iter().cloned());
 
-            let value_start = key_end;
-            let value_end = value_start + LEAF_NODE_VALUE_SIZE;
-            let value_range = value_start..value_end;
+            let size_end = key_end + LEAF_NODE_PAYLOAD_SIZE_SIZE;
+            buff.splice(key_end..size_end, u32_to_bytes(p.size as u32));
 
-            buff.splice(key_range, u32_to_bytes(*k));
-            buff.splice(value_range, v.serialize());
+            let local_end = size_end + p.local.len();
+            buff.splice(size_end..local_end, p.local.iter().cloned());
+
+            offset = local_end;
+            if let Some(overflow) = p.overflow {
+                let pointer_end = offset + LEAF_NODE_OVERFLOW_POINTER_SIZE;
+                buff.splice(offset..pointer_end, u32_to_bytes(overflow));
+                offset = pointer_end;
+            }
         }
 
         buff
@@ -172,8 +295,7 @@ impl LeafNode {
             );
         }
 
-        let node_type = &raw[0];
-        if *node_type != 1 {
+        if node_type(&raw) != NODE_TYPE_LEAF {
             return None;
         }
 
@@ -181,21 +303,34 @@ impl LeafNode {
         let parent = bytes_to_u32(&raw[LeafNode::PARENT_POINTER_RANGE]);
         let num_cells = bytes_to_u32(&raw[LeafNode::NUM_CELLS_RANGE]);
 
-        let mut body = Vec::with_capacity(MAX_NUM_CELLS);
+        // sizes are read from the page, so a corrupt one must not be trusted
+        let mut body = Vec::new();
+
+        let mut offset = LEAF_NODE_BODY_OFFSET;
+        for _ in 0..num_cells {
+            let key_size_end = offset + LEAF_NODE_KEY_SIZE_SIZE;
+            let key_size = bytes_to_u32(raw.get(offset..key_size_end)?) as usize;
 
-        for i in 0..num_cells as usize {
-            let key_start = LEAF_NODE_BODY_OFFSET + i * CELL_SIZE;
-            let key_end = key_start + LEAF_NODE_KEY_SIZE;
-            let key_range = key_start..key_end;
+            let key_end = key_size_end + key_size;
+            let key = raw.get(key_size_end..key_end)?.to_vec();
 
-            let value_start = key_end;
-            let value_end = value_start + LEAF_NODE_VALUE_SIZE;
-            let value_range = value_start..value_end;
+            let size_end = key_end + LEAF_NODE_PAYLOAD_SIZE_SIZE;
+            let size = bytes_to_u32(raw.get(key_end..size_end)?) as usize;
 
-            let key = bytes_to_u32(&raw[key_range]);
-            let value = Row::deserialize(raw[value_range].into()).unwrap();
+            let local_end = size_end + size.min(MAX_LOCAL_PAYLOAD);
+            let local = raw.get(size_end..local_end)?.to_vec();
 
-            body.push((key, value));
+            offset = local_end;
+            let overflow = if size > MAX_LOCAL_PAYLOAD {
+                let pointer_end = offset + LEAF_NODE_OVERFLOW_POINTER_SIZE;
+                let pointer = bytes_to_u32(raw.get(offset..pointer_end)?);
+                offset = pointer_end;
+                Some(pointer)
+            } else {
+                None
+            };
+
+            body.push((key, Payload::new(size, local, overflow)));
         }
 
         Some(LeafNode::new(is_root, parent, num_cells, body))
@@ -210,21 +345,230 @@ impl Default for LeafNode {
                 parent: 0,
                 num_cells: 0,
             },
-            body: Vec::with_capacity(MAX_NUM_CELLS),
+            body: Vec::new(),
+        }
+    }
+}
+
+impl OverflowPage {
+    const NEXT_POINTER_RANGE: Range<usize> =
+        OVERFLOW_NEXT_POINTER_OFFSET..OVERFLOW_DATA_LENGTH_OFFSET;
+    const DATA_LENGTH_RANGE: Range<usize> = OVERFLOW_DATA_LENGTH_OFFSET..OVERFLOW_HEADER_SIZE;
+
+    pub fn new(next: Option<u32>, data: Vec<u8>) -> Self {
+        if data.len() > OVERFLOW_PAGE_CAPACITY {
+            panic!(
+                "overflow page can hold at most {} bytes",
+                OVERFLOW_PAGE_CAPACITY
+            );
+        }
+
+        Self { next, data }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buff = vec![0; PAGE_SIZE];
+
+        buff[HEADER_NODE_TYPE_OFFSET] = NODE_TYPE_OVERFLOW;
+        // page 0 is always a root node, so it doubles as the end-of-chain marker
+        buff.splice(
+            OverflowPage::NEXT_POINTER_RANGE,
+            u32_to_bytes(self.next.unwrap_or(0)),
+        );
+        buff.splice(
+            OverflowPage::DATA_LENGTH_RANGE,
+            u32_to_bytes(self.data.len() as u32),
+        );
+        buff.splice(
+            OVERFLOW_HEADER_SIZE..OVERFLOW_HEADER_SIZE + self.data.len(),
+            self.data.iter().cloned(),
+        );
+
+        buff
+    }
+
+    pub fn deserialize(raw: Vec<u8>) -> Option<Self> {
+        if raw.len() != PAGE_SIZE || node_type(&raw) != NODE_TYPE_OVERFLOW {
+            return None;
+        }
+
+        let next = match bytes_to_u32(&raw[OverflowPage::NEXT_POINTER_RANGE]) {
+            0 => None,
+            n => Some(n),
+        };
+        let length = bytes_to_u32(&raw[OverflowPage::DATA_LENGTH_RANGE]) as usize;
+        if length > OVERFLOW_PAGE_CAPACITY {
+            return None;
+        }
+        let data = raw[OVERFLOW_HEADER_SIZE..OVERFLOW_HEADER_SIZE + length].to_vec();
+
+        Some(OverflowPage { next, data })
+    }
+}
+
+/// A node of a secondary index, mapping column values to the keys of the rows
+/// holding them. Entries are sorted by value and then by key.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct IndexNode {
+    entries: Vec<(Value, Key)>,
+}
+
+fn compare_entries(a: (&Value, &[u8]), b: (&Value, &[u8])) -> Ordering {
+    a.0.compare(b.0).then(a.1.cmp(b.1))
+}
+
+impl IndexNode {
+    const NUM_ENTRIES_RANGE: Range<usize> = INDEX_NUM_ENTRIES_OFFSET..INDEX_HEADER_SIZE;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn num_entries(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn encode_entry(value: &Value, key: &[u8]) -> Vec<u8> {
+        let mut values = vec![value.clone()];
+        values.extend(decode_key(key).expect("broken key"));
+        encode_record(&values)
+    }
+
+    /// returns number of bytes an entry for the value and key occupies in an index node
+    pub fn entry_size(value: &Value, key: &[u8]) -> usize {
+        INDEX_RECORD_SIZE_SIZE + IndexNode::encode_entry(value, key).len()
+    }
+
+    /// returns number of bytes still available for entries in this node
+    pub fn free_space(&self) -> usize {
+        let used: usize = self
+            .entries
+            .iter()
+            .map(|(value, key)| IndexNode::entry_size(value, key))
+            .sum();
+        INDEX_ENTRIES_SPACE - used
+    }
+
+    /// returns position of the entry, or where it would be inserted
+    fn find(&self, value: &Value, key: &[u8]) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|(v, k)| compare_entries((v, k), (value, key)))
+    }
+
+    /// returns if an entry for the value and key fits in this node
+    pub fn has_room_for(&self, value: &Value, key: &[u8]) -> bool {
+        IndexNode::entry_size(value, key) <= self.free_space()
+    }
+
+    /// adds an entry for the row with the given key. the node is not split,
+    /// so an entry that does not fit is an error.
+    pub fn insert(&mut self, value: Value, key: Key) -> Result<(), String> {
+        if !self.has_room_for(&value, &key) {
+            return Err(String::from("Index full"));
+        }
+
+        if let Err(pos) = self.find(&value, &key) {
+            self.entries.insert(pos, (value, key));
+        }
+        Ok(())
+    }
+
+    /// removes the entry and returns if it existed
+    pub fn remove(&mut self, value: &Value, key: &[u8]) -> bool {
+        match self.find(value, key) {
+            Ok(pos) => {
+                self.entries.remove(pos);
+                true
+            }
+            Err(_) => false,
         }
     }
+
+    /// returns keys of the rows whose value compares equal to the given value, in key order
+    pub fn lookup(&self, value: &Value) -> Vec<Key> {
+        let start = self
+            .entries
+            .partition_point(|(v, _)| v.compare(value) == Ordering::Less);
+        self.entries[start..]
+            .iter()
+            .take_while(|(v, _)| v.compare(value) == Ordering::Equal)
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buff = vec![0; PAGE_SIZE];
+
+        buff[HEADER_NODE_TYPE_OFFSET] = NODE_TYPE_INDEX;
+        buff.splice(
+            IndexNode::NUM_ENTRIES_RANGE,
+            u32_to_bytes(self.entries.len() as u32),
+        );
+
+        let mut offset = INDEX_HEADER_SIZE;
+        for (value, key) in self.entries.iter() {
+            let record = IndexNode::encode_entry(value, key);
+            let size_end = offset + INDEX_RECORD_SIZE_SIZE;
+            buff.splice(offset..size_end, u32_to_bytes(record.len() as u32));
+            buff.splice(size_end..size_end + record.len(), record.iter().cloned());
+            offset = size_end + record.len();
+        }
+
+        buff
+    }
+
+    pub fn deserialize(raw: Vec<u8>) -> Option<Self> {
+        if raw.len() != PAGE_SIZE || node_type(&raw) != NODE_TYPE_INDEX {
+            return None;
+        }
+
+        let num_entries = bytes_to_u32(&raw[IndexNode::NUM_ENTRIES_RANGE]);
+        // the count is read from the page, so a corrupt one must not be trusted
+        let mut entries = Vec::new();
+
+        let mut offset = INDEX_HEADER_SIZE;
+        for _ in 0..num_entries {
+            let size_end = offset + INDEX_RECORD_SIZE_SIZE;
+            let size = bytes_to_u32(raw.get(offset..size_end)?) as usize;
+            let record = decode_record(raw.get(size_end..size_end + size)?).ok()?;
+            match record.split_first() {
+                Some((value, key)) if !key.is_empty() => {
+                    entries.push((value.clone(), encode_key(key)))
+                }
+                _ => return None,
+            }
+            offset = size_end + size;
+        }
+
+        Some(IndexNode { entries })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::row::Row;
+
+    fn key(id: i64) -> Key {
+        encode_key(&[Value::Integer(id)])
+    }
+
+    fn cell(id: i64, username: &str, email: &str) -> (Key, Payload) {
+        let values = vec![
+            Value::Integer(id),
+            Value::Text(username.into()),
+            Value::Text(email.into()),
+        ];
+        let bytes = Row::new(values).serialize();
+        (key(id), Payload::new(bytes.len(), bytes, None))
+    }
 
     #[test]
     fn test_serialize_to_page() {
         let body = vec![
-            (1, Row::new(1, "user1".into(), "user1@example.com".into())),
-            (2, Row::new(2, "user2".into(), "user2@example.com".into())),
-            (3, Row::new(3, "user3".into(), "user3@example.com".into())),
+            cell(1, "user1", "user1@example.com"),
+            cell(2, "user2", "user2@example.com"),
+            cell(3, "user3", "user3@example.com"),
         ];
         let node = LeafNode::new(true, 10, 3, body);
         let serialized = node.serialize();
@@ -234,9 +578,9 @@ mod tests {
     #[test]
     fn test_serialize_and_deserialize() {
         let body = vec![
-            (1, Row::new(1, "user1".into(), "user1@example.com".into())),
-            (2, Row::new(2, "user2".into(), "user2@example.com".into())),
-            (3, Row::new(3, "user3".into(), "user3@example.com".into())),
+            cell(1, "user1", "user1@example.com"),
+            cell(2, "user2", "user2@example.com"),
+            cell(3, "user3", "user3@example.com"),
         ];
         let node = LeafNode::new(true, 10, 3, body);
         let serialized = node.serialize();
@@ -244,4 +588,113 @@ mod tests {
 
         assert_eq!(Some(node), deserialized);
     }
+
+    #[test]
+    fn test_deserialize_corrupt_node() {
+        let node = LeafNode::new(true, 0, 1, vec![cell(1, "user1", "user1@example.com")]);
+
+        let mut raw = node.serialize();
+        raw.splice(LeafNode::NUM_CELLS_RANGE, u32_to_bytes(u32::MAX));
+        assert_eq!(LeafNode::deserialize(raw), None);
+
+        let mut raw = node.serialize();
+        let key_size = LEAF_NODE_BODY_OFFSET..LEAF_NODE_BODY_OFFSET + LEAF_NODE_KEY_SIZE_SIZE;
+        raw.splice(key_size, u32_to_bytes(PAGE_SIZE as u32));
+        assert_eq!(LeafNode::deserialize(raw), None);
+    }
+
+    #[test]
+    fn test_insert_into_full_node() {
+        let mut node = LeafNode::default();
+        let mut id = 0;
+        loop {
+            let (key, payload) = cell(id, "user", "user@example.com");
+            if !node.has_room_for(key.len(), payload.size) {
+                break;
+            }
+            node.insert_at(id as usize, key, payload).unwrap();
+            id += 1;
+        }
+
+        let (key, payload) = cell(id, "user", "user@example.com");
+        let result = node.insert_at(id as usize, key, payload);
+        assert_eq!(
+            result.map_err(|e| e.to_string()),
+            Err(String::from("Table full"))
+        );
+        assert_eq!(node.num_cells(), id as usize);
+    }
+
+    #[test]
+    fn test_serialize_cell_with_overflow_pointer() {
+        let local = vec![7; MAX_LOCAL_PAYLOAD];
+        let body = vec![
+            (key(1), Payload::new(10_000, local, Some(5))),
+            cell(2, "user2", "user2@example.com"),
+        ];
+        let node = LeafNode::new(true, 0, 2, body);
+        let deserialized = LeafNode::deserialize(node.serialize());
+
+        assert_eq!(Some(node), deserialized);
+    }
+
+    #[test]
+    fn test_serialize_and_deserialize_overflow_page() {
+        let page = OverflowPage::new(Some(3), vec![1, 2, 3, 4]);
+        let deserialized = OverflowPage::deserialize(page.serialize());
+        assert_eq!(Some(page), deserialized);
+
+        let last = OverflowPage::new(None, vec![9; OVERFLOW_PAGE_CAPACITY]);
+        let deserialized = OverflowPage::deserialize(last.serialize());
+        assert_eq!(Some(last), deserialized);
+    }
+
+    #[test]
+    fn test_index_node_lookup_and_round_trip() -> Result<(), String> {
+        let mut node = IndexNode::new();
+        node.insert(Value::Text("b@example.com".into()), key(3))?;
+        node.insert(Value::Text("a@example.com".into()), key(7))?;
+        node.insert(Value::Text("b@example.com".into()), key(1))?;
+        node.insert(Value::Null, key(2))?;
+        let composite = encode_key(&[Value::Text("a".into()), Value::Integer(-4)]);
+        node.insert(Value::Real(1.5), composite.clone())?;
+
+        assert_eq!(
+            node.lookup(&Value::Text("b@example.com".into())),
+            vec![key(1), key(3)]
+        );
+        assert_eq!(node.lookup(&Value::Real(1.5)), vec![composite]);
+        assert_eq!(
+            node.lookup(&Value::Text("c@example.com".into())),
+            Vec::<Key>::new()
+        );
+
+        let deserialized = IndexNode::deserialize(node.serialize());
+        assert_eq!(Some(node.clone()), deserialized);
+        let mut corrupt = node.serialize();
+        corrupt.splice(IndexNode::NUM_ENTRIES_RANGE, u32_to_bytes(u32::MAX));
+        assert_eq!(IndexNode::deserialize(corrupt), None);
+
+        assert!(node.remove(&Value::Text("b@example.com".into()), &key(1)));
+        assert!(!node.remove(&Value::Text("b@example.com".into()), &key(1)));
+        assert_eq!(
+            node.lookup(&Value::Text("b@example.com".into())),
+            vec![key(3)]
+        );
+
+        // the node is not split, so it rejects the entry that does not fit
+        let mut full = IndexNode::new();
+        let value = Value::Text("x".repeat(100));
+        let mut n = 0;
+        while full.has_room_for(&value, &key(n)) {
+            full.insert(value.clone(), key(n))?;
+            n += 1;
+        }
+        assert_eq!(
+            full.insert(value.clone(), key(n)),
+            Err(String::from("Index full"))
+        );
+        assert_eq!(full.lookup(&value).len(), n as usize);
+        Ok(())
+    }
 }
@@ -1,4 +1,5 @@
 use super::meta_command::*;
+use super::result_set::{header, DisplayRow};
 use super::statement::*;
 use super::table::*;
 use std::error::Error;
@@ -9,7 +10,7 @@ pub fn start(filename: &str) -> Result<(), Box<dyn Error>> {
 
     println!("Starting Database client.");
     println!("database file: {}", filename);
-    println!("");
+    println!();
 
     let table = &mut Table::open(filename)?;
 
@@ -34,15 +35,36 @@ pub fn start(filename: &str) -> Result<(), Box<dyn Error>> {
         }
 
         match Statement::prepare(buffer) {
+            // rows are printed as the query reads them
+            Ok(statement) if statement.kind() == StatementKind::Select => {
+                match statement.query(table) {
+                    Ok(rows) => {
+                        println!("{}", header(rows.columns()));
+                        for row in rows {
+                            match row {
+                                Ok(values) => println!("{}", DisplayRow(&values)),
+                                Err(result) => print_failure(&result),
+                            }
+                        }
+                    }
+                    Err(result) => print_failure(&result),
+                }
+            }
             Ok(statement) => match statement.execute(table) {
-                ExecuteResult::InsertSuccess => println!("Insert succeed."),
-                ExecuteResult::SelectSuccess(rows) => {
-                    for row in rows.iter() {
-                        println!("{}", row);
+                ExecuteResult::InsertSuccess => {
+                    println!("Insert succeed. rowid: {}", table.last_insert_rowid())
+                }
+                ExecuteResult::SelectSuccess(result) => {
+                    println!("{}", result.header());
+                    for row in result.rows.iter() {
+                        println!("{}", DisplayRow(row));
                     }
                 }
-                ExecuteResult::TableFull => println!("Error: Table full"),
-                _ => println!("Something went wrong."),
+                ExecuteResult::UpdateSuccess(count) => println!("{} rows updated.", count),
+                ExecuteResult::DeleteSuccess(count) => println!("{} rows deleted.", count),
+                ExecuteResult::CreateTableSuccess => println!("Table created."),
+                ExecuteResult::CreateIndexSuccess => println!("Index created."),
+                failed => print_failure(&failed),
             },
             Err(e) => {
                 println!("Error preparing statement. {}", e);
@@ -52,3 +74,14 @@ pub fn start(filename: &str) -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+fn print_failure(result: &ExecuteResult) {
+    match result {
+        ExecuteResult::EmptyRow => println!("Something went wrong."),
+        failed => {
+            if let Some(reason) = failed.error() {
+                println!("Error: {}", reason);
+            }
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use super::result_set::Column;
+use super::statement::{ExecuteResult, Statement, StatementKind};
+use super::table::Table;
+use super::value::Value;
+use super::vtab::VirtualTable;
+use std::fmt;
+
+/// Error returned by the methods of a connection
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// the database file could not be opened
+    Open(String),
+    /// the sql could not be parsed, or the parameters do not match it
+    Prepare(String),
+    /// the statement failed while running
+    Execute(String),
+    /// `execute` was given a statement that returns rows
+    ExecuteReturnedRows,
+    /// `query_map` was given a statement that does not return rows
+    QueryReturnedNoRows,
+    /// the row has no column of that index or name
+    InvalidColumn(String),
+    /// the value of the column cannot be read as the requested type
+    InvalidType(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Open(reason) => write!(f, "cannot open database: {}", reason),
+            Error::Prepare(reason) => write!(f, "cannot prepare statement: {}", reason),
+            Error::Execute(reason) => write!(f, "{}", reason),
+            Error::ExecuteReturnedRows => write!(f, "execute returned rows, use query_map"),
+            Error::QueryReturnedNoRows => write!(f, "query returned no rows, use execute"),
+            Error::InvalidColumn(column) => write!(f, "no such column: {}", column),
+            Error::InvalidType(reason) => write!(f, "invalid column type: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A rust value that can be bound to a statement parameter
+pub trait ToSql {
+    fn to_sql(&self) -> Value;
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl ToSql for i64 {
+    fn to_sql(&self) -> Value {
+        Value::Integer(*self)
+    }
+}
+
+impl ToSql for i32 {
+    fn to_sql(&self) -> Value {
+        Value::Integer(*self as i64)
+    }
+}
+
+impl ToSql for f64 {
+    fn to_sql(&self) -> Value {
+        Value::Real(*self)
+    }
+}
+
+impl ToSql for bool {
+    fn to_sql(&self) -> Value {
+        Value::from(*self)
+    }
+}
+
+impl ToSql for str {
+    fn to_sql(&self) -> Value {
+        Value::Text(String::from(self))
+    }
+}
+
+impl ToSql for String {
+    fn to_sql(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn to_sql(&self) -> Value {
+        match self {
+            Some(value) => value.to_sql(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: ToSql + ?Sized> ToSql for &T {
+    fn to_sql(&self) -> Value {
+        (**self).to_sql()
+    }
+}
+
+/// A rust value a column value can be read as
+pub trait FromSql: Sized {
+    fn from_sql(value: &Value) -> Result<Self, String>;
+}
+
+fn mismatch(value: &Value, expected: &str) -> String {
+    match value.data_type() {
+        Some(data_type) => format!("expected {}, found {}", expected, data_type),
+        None => format!("expected {}, found NULL", expected),
+    }
+}
+
+impl FromSql for Value {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl FromSql for i64 {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Integer(i) => Ok(*i),
+            value => Err(mismatch(value, "integer")),
+        }
+    }
+}
+
+impl FromSql for f64 {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Real(r) => Ok(*r),
+            Value::Integer(i) => Ok(*i as f64),
+            value => Err(mismatch(value, "real")),
+        }
+    }
+}
+
+impl FromSql for bool {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Integer(i) => Ok(*i != 0),
+            value => Err(mismatch(value, "integer")),
+        }
+    }
+}
+
+impl FromSql for String {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Text(s) => Ok(s.clone()),
+            value => Err(mismatch(value, "text")),
+        }
+    }
+}
+
+impl<T: FromSql> FromSql for Option<T> {
+    fn from_sql(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_sql(value).map(Some),
+        }
+    }
+}
+
+/// Index of a column of a row, either its position from 0 or its name
+pub trait RowIndex {
+    fn index(&self, columns: &[Column]) -> Result<usize, Error>;
+}
+
+impl RowIndex for usize {
+    fn index(&self, columns: &[Column]) -> Result<usize, Error> {
+        if *self < columns.len() {
+            Ok(*self)
+        } else {
+            Err(Error::InvalidColumn(self.to_string()))
+        }
+    }
+}
+
+impl RowIndex for &str {
+    fn index(&self, columns: &[Column]) -> Result<usize, Error> {
+        columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(self))
+            .ok_or_else(|| Error::InvalidColumn(String::from(*self)))
+    }
+}
+
+/// A row of a query result handed to the closure of `query_map`
+pub struct Row<'a> {
+    columns: &'a [Column],
+    values: &'a [Value],
+}
+
+impl<'a> Row<'a> {
+    /// returns the value of the column as the requested type
+    pub fn get<I: RowIndex, T: FromSql>(&self, index: I) -> Result<T, Error> {
+        let i = index.index(self.columns)?;
+        T::from_sql(&self.values[i])
+            .map_err(|reason| Error::InvalidType(format!("{}: {}", self.columns[i].name, reason)))
+    }
+}
+
+/// Handle to a database file, running sql with bound parameters.
+/// Pages are written back to the file when the connection is dropped.
+pub struct Connection {
+    table: Table,
+}
+
+impl Connection {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let table = Table::open(path).map_err(|e| Error::Open(e.to_string()))?;
+        Ok(Connection { table })
+    }
+
+    /// runs a statement that does not return rows, and returns the number of rows it wrote.
+    /// a statement returning rows is rejected before it runs.
+    pub fn execute(&mut self, sql: &str, params: &[&dyn ToSql]) -> Result<usize, Error> {
+        let statement = prepare(sql, params)?;
+        if statement.returns_rows() {
+            return Err(Error::ExecuteReturnedRows);
+        }
+        match self.run(&statement)? {
+            ExecuteResult::CreateTableSuccess | ExecuteResult::CreateIndexSuccess => Ok(0),
+            _ => Ok(self.table.changes()),
+        }
+    }
+
+    /// runs a query and returns the closure applied to each of its rows.
+    /// the rows of a select are read as the closure asks for them, and a statement
+    /// not returning rows is rejected before it runs.
+    pub fn query_map<T, F>(
+        &mut self,
+        sql: &str,
+        params: &[&dyn ToSql],
+        mut f: F,
+    ) -> Result<Vec<T>, Error>
+    where
+        F: FnMut(&Row) -> Result<T, Error>,
+    {
+        let statement = prepare(sql, params)?;
+        if !statement.returns_rows() {
+            return Err(Error::QueryReturnedNoRows);
+        }
+        // a write has its `returning` rows once every row is written
+        if statement.kind() != StatementKind::Select {
+            return match self.run(&statement)? {
+                ExecuteResult::SelectSuccess(result) => result
+                    .rows
+                    .iter()
+                    .map(|values| {
+                        f(&Row {
+                            columns: &result.columns,
+                            values,
+                        })
+                    })
+                    .collect(),
+                _ => Ok(Vec::new()),
+            };
+        }
+
+        let rows = statement.query(&mut self.table).map_err(failed)?;
+        let columns = rows.columns().to_vec();
+        rows.map(|values| {
+            f(&Row {
+                columns: &columns,
+                values: &values.map_err(failed)?,
+            })
+        })
+        .collect()
+    }
+
+    /// registers the virtual table `name`, which selects then read like a table
+    pub fn register_virtual_table<T: VirtualTable + 'static>(
+        &mut self,
+        name: &str,
+        table: T,
+    ) -> Result<(), Error> {
+        self.table
+            .register_virtual_table(name, table)
+            .map_err(Error::Prepare)
+    }
+
+    /// returns the rowid of the most recent row inserted through the connection
+    pub fn last_insert_rowid(&self) -> i64 {
+        self.table.last_insert_rowid()
+    }
+
+    fn run(&mut self, statement: &Statement) -> Result<ExecuteResult, Error> {
+        let result = statement.execute(&mut self.table);
+        match result.error() {
+            Some(reason) => Err(Error::Execute(reason)),
+            None => Ok(result),
+        }
+    }
+}
+
+/// prepares the statement and binds the parameters to it
+fn prepare(sql: &str, params: &[&dyn ToSql]) -> Result<Statement, Error> {
+    let mut statement = Statement::prepare(sql).map_err(Error::Prepare)?;
+    if params.len() != statement.parameter_count() {
+        return Err(Error::Prepare(format!(
+            "expected {} parameters, got {}",
+            statement.parameter_count(),
+            params.len()
+        )));
+    }
+    for (i, param) in params.iter().enumerate() {
+        statement
+            .bind(i + 1, param.to_sql())
+            .map_err(Error::Prepare)?;
+    }
+    Ok(statement)
+}
+
+/// returns the error of a statement that failed while running
+fn failed(result: ExecuteResult) -> Error {
+    Error::Execute(result.error().unwrap_or_default())
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.table.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[derive(Debug, PartialEq)]
+    struct User {
+        id: i64,
+        username: String,
+        email: Option<String>,
+    }
+
+    #[test]
+    fn test_connection_execute_and_query_map() -> Result<(), Error> {
+        const TEST_FILE: &str = "db_test_connection_execute_and_query_map";
+        {
+            let mut conn = Connection::open(TEST_FILE)?;
+            assert_eq!(
+                conn.execute(
+                    "insert into users values (?, ?, ?)",
+                    &[&1, &"ann", &"ann@x.com"]
+                )?,
+                1
+            );
+            let username = String::from("bo");
+            assert_eq!(
+                conn.execute(
+                    "insert into users (username, email) values (?, ?), ('cy', 'cy@x.com')",
+                    &[&username, &"bo@x.com"],
+                )?,
+                2
+            );
+            assert_eq!(conn.last_insert_rowid(), 3);
+            assert_eq!(
+                conn.execute(
+                    "update users set email = ? where id > ?",
+                    &[&Some("team@x.com"), &1]
+                )?,
+                2
+            );
+        }
+
+        // rows written through one connection are read by the next
+        let mut conn = Connection::open(TEST_FILE)?;
+        let users = conn.query_map(
+            "select id, username, nullif(email, :team) as email from users \
+             where id <= :max order by id",
+            &[&"team@x.com", &2],
+            |row| {
+                Ok(User {
+                    id: row.get(0)?,
+                    username: row.get("username")?,
+                    email: row.get("EMAIL")?,
+                })
+            },
+        )?;
+        assert_eq!(
+            users,
+            vec![
+                User {
+                    id: 1,
+                    username: String::from("ann"),
+                    email: Some(String::from("ann@x.com")),
+                },
+                User {
+                    id: 2,
+                    username: String::from("bo"),
+                    email: None,
+                },
+            ]
+        );
+        assert_eq!(conn.execute("delete from users where id <> ?", &[&2])?, 2);
+        drop(conn);
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_connection_errors() -> Result<(), Error> {
+        const TEST_FILE: &str = "db_test_connection_errors";
+        let mut conn = Connection::open(TEST_FILE)?;
+        conn.execute("insert into users values (1, 'ann', 'ann@x.com')", &[])?;
+
+        assert!(matches!(
+            conn.execute("insert into users values (", &[]),
+            Err(Error::Prepare(_))
+        ));
+        assert!(matches!(
+            conn.execute("delete from users where id = ?", &[]),
+            Err(Error::Prepare(_))
+        ));
+        assert_eq!(
+            conn.execute("select * from users", &[]),
+            Err(Error::ExecuteReturnedRows)
+        );
+        assert_eq!(
+            conn.execute("insert into users values (?, 'bo', 'bo@x.com')", &[&1]),
+            Err(Error::Execute(String::from("Duplicate key 1")))
+        );
+        // statements are checked before they run, so these write nothing
+        assert_eq!(
+            conn.execute(
+                "insert into users values (2, 'bo', 'bo@x.com') returning id",
+                &[]
+            ),
+            Err(Error::ExecuteReturnedRows)
+        );
+        assert_eq!(
+            conn.query_map("delete from users", &[], |row| row.get::<_, i64>(0)),
+            Err(Error::QueryReturnedNoRows)
+        );
+        assert_eq!(
+            conn.query_map("select id from users", &[], |row| row.get::<_, i64>(0)),
+            Ok(vec![1])
+        );
+        assert_eq!(
+            conn.query_map(
+                "insert into users values (3, 'cy', 'cy@x.com') returning id",
+                &[],
+                |row| { row.get::<_, i64>("id") }
+            ),
+            Ok(vec![3])
+        );
+
+        let get = |conn: &mut Connection, f: fn(&Row) -> Result<i64, Error>| {
+            conn.query_map("select id, username from users", &[], f)
+        };
+        assert_eq!(
+            get(&mut conn, |row| row.get(2)),
+            Err(Error::InvalidColumn(String::from("2")))
+        );
+        assert_eq!(
+            get(&mut conn, |row| row.get("email")),
+            Err(Error::InvalidColumn(String::from("email")))
+        );
+        assert_eq!(
+            get(&mut conn, |row| row.get("username")),
+            Err(Error::InvalidType(String::from(
+                "username: expected integer, found text"
+            )))
+        );
+        drop(conn);
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use super::{row::*, table::*};
+use super::{row::*, table::*, value::Value};
 
 pub struct Cursor<'a> {
     table: &'a mut Table,
@@ -17,23 +17,86 @@ impl<'a> Cursor<'a> {
         }
     }
 
-    /// returns Vec<u8> of row the cursor is pointing at
-    pub fn get_value(&mut self) -> Option<Row> {
+    /// returns row the cursor is pointing at.
+    /// payloads spilled into overflow pages are reassembled transparently.
+    pub fn get_value(&mut self) -> Result<Row, RowError> {
         let page_num = self.page_num;
 
         // TODO: prepare
         // self.table.pager.prepare_page(page_num);
 
         let node = self.table.get_node(page_num).unwrap();
-        node.get_value(self.cell_num)
+        let payload = node
+            .get_payload(self.cell_num)
+            .ok_or_else(|| format!("no cell {} in page {}", self.cell_num, page_num))?;
+        let bytes = self.table.pager.read_payload(payload);
+        Row::deserialize(bytes)
     }
 
-    /// insert given row into the position where the cursor is pointing at
-    pub fn insert_value(&mut self, row: &Row) {
-        let _ = self
-            .table
+    /// returns key of the row the cursor is pointing at, `None` past the last row
+    pub fn key(&self) -> Option<&[u8]> {
+        self.table.get_node(self.page_num)?.get_key(self.cell_num)
+    }
+
+    /// returns the table the cursor walks
+    pub fn table(&self) -> &Table {
+        self.table
+    }
+
+    /// insert given row into the position where the cursor is pointing at.
+    /// every index of the table gets an entry for the row as well, and
+    /// nothing is written to them when the leaf node or one of them is full.
+    pub fn insert_value(&mut self, row: &Row) -> Result<(), RowError> {
+        let table = &mut *self.table;
+        let def = table.schema.table_by_root(self.page_num).unwrap();
+        let key = def.key(row);
+        let entries: Vec<(usize, Value)> = table
+            .schema
+            .indexes_of(&def.name)
+            .map(|index| {
+                let value = row.values[def.column_index(&index.column).unwrap()].clone();
+                (index.root_page_num, value)
+            })
+            .collect();
+        for (root_page_num, value) in entries.iter() {
+            let node = table.pager.get_index(*root_page_num).unwrap();
+            if !node.has_room_for(value, &key) {
+                return Err(String::from("Index full"));
+            }
+        }
+
+        table
             .pager
-            .insert_at(row, self.page_num, self.cell_num);
+            .insert_at(key.clone(), row, self.page_num, self.cell_num)
+            .map_err(|e| e.to_string())?;
+        for (root_page_num, value) in entries {
+            let node = table.pager.get_index_mut(root_page_num).unwrap();
+            node.insert(value, key.clone())?;
+        }
+        Ok(())
+    }
+
+    /// delete the row the cursor is pointing at, along with its index entries.
+    /// the cursor then points at the row that followed it.
+    pub fn delete_value(&mut self) -> Result<(), RowError> {
+        let row = self.get_value()?;
+
+        let table = &mut *self.table;
+        let node = table.pager.get_leaf_mut(self.page_num).unwrap();
+        let key = node.get_key(self.cell_num).unwrap().to_vec();
+        let payload = node.remove_at(self.cell_num);
+        if self.cell_num >= node.num_cells() {
+            self.end_of_table = true;
+        }
+        table.pager.free_payload(&payload);
+
+        let def = table.schema.table_by_root(self.page_num).unwrap();
+        for index in table.schema.indexes_of(&def.name) {
+            let value = &row.values[def.column_index(&index.column).unwrap()];
+            let node = table.pager.get_index_mut(index.root_page_num).unwrap();
+            node.remove(value, &key);
+        }
+        Ok(())
     }
 
     /// advance cursor pointer by one
@@ -47,8 +110,58 @@ impl<'a> Cursor<'a> {
         }
     }
 
+    /// move cursor pointer back by one.
+    /// moving back from the first row ends the walk.
+    pub fn retreat(&mut self) {
+        if self.cell_num == 0 {
+            self.end_of_table = true;
+        } else {
+            self.cell_num -= 1;
+        }
+    }
+
     /// returns if the cursor is pointing at the end of the table
     pub fn is_end(&self) -> bool {
         self.end_of_table
     }
+
+    /// returns the rows from the one the cursor is pointing at to the end of the table,
+    /// read one at a time as the cursor advances
+    pub fn rows(self) -> Rows<'a> {
+        Rows {
+            cursor: self,
+            reverse: false,
+        }
+    }
+
+    /// returns the rows from the one the cursor is pointing at back to the first row
+    pub fn rows_rev(self) -> Rows<'a> {
+        Rows {
+            cursor: self,
+            reverse: true,
+        }
+    }
+}
+
+/// Rows read lazily by a cursor, stopping at the end of the table
+pub struct Rows<'a> {
+    cursor: Cursor<'a>,
+    reverse: bool,
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = Result<Row, RowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.cursor.is_end() {
+            return None;
+        }
+        let row = self.cursor.get_value();
+        if self.reverse {
+            self.cursor.retreat();
+        } else {
+            self.cursor.advance();
+        }
+        Some(row)
+    }
 }
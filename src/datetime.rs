@@ -0,0 +1,531 @@
+use super::expression::EvalError;
+use super::value::{DataType, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// functions reading a time value followed by modifiers, like `date('now', '-7 days')`.
+/// a time value is ISO-8601 text, `now`, or a julian day number. `unixepoch` as first
+/// modifier reads a number as seconds since 1970 instead, which is how times stored as
+/// integers are read.
+const FUNCTIONS: [&str; 6] = [
+    "date",
+    "datetime",
+    "julianday",
+    "strftime",
+    "time",
+    "unixepoch",
+];
+
+const MS_PER_DAY: i64 = 86_400_000;
+/// 1970-01-01 00:00:00 in milliseconds since the julian day epoch
+const UNIX_EPOCH_MS: i64 = 210_866_760_000_000;
+/// 9999-12-31 23:59:59.999, the last moment that can be written with a 4 digit year
+const MAX_MS: i64 = 464_269_060_799_999;
+/// modifiers that depend on the local time zone or change the output format, which
+/// are not supported
+const UNSUPPORTED_MODIFIERS: [&str; 4] = ["localtime", "subsec", "subsecond", "utc"];
+
+pub fn is_date_function(name: &str) -> bool {
+    FUNCTIONS.contains(&name)
+}
+
+/// checks the number of arguments passed to the date function
+pub fn check_arguments(name: &str, num_args: usize) -> Result<(), String> {
+    if name == "strftime" && num_args == 0 {
+        return Err(format!("wrong number of arguments to function {}()", name));
+    }
+    Ok(())
+}
+
+/// returns the type the date function produces
+pub fn return_type(name: &str) -> Option<DataType> {
+    match name {
+        "julianday" => Some(DataType::Real),
+        "unixepoch" => Some(DataType::Integer),
+        _ => Some(DataType::Text),
+    }
+}
+
+/// calls the date function with already evaluated arguments.
+/// returns NULL when the time value or a modifier can not be read.
+pub fn call(name: &str, args: &[Value]) -> Result<Value, EvalError> {
+    let (format, args) = match name {
+        "strftime" => match args[0].as_text() {
+            Some(format) => (Some(format), &args[1..]),
+            None => return Ok(Value::Null),
+        },
+        _ => (None, args),
+    };
+    let unsupported = args
+        .iter()
+        .skip(1)
+        .filter_map(Value::as_text)
+        .map(|modifier| modifier.trim().to_lowercase())
+        .find(|modifier| UNSUPPORTED_MODIFIERS.contains(&modifier.as_str()));
+    if let Some(modifier) = unsupported {
+        return Err(format!("unsupported date modifier: {}", modifier));
+    }
+    let moment = match args.split_first() {
+        Some((value, modifiers)) => Moment::read(value, modifiers),
+        // without arguments the functions read the current time
+        None => Some(Moment::now()),
+    };
+    let moment = match moment {
+        Some(moment) => moment,
+        None => return Ok(Value::Null),
+    };
+
+    Ok(match name {
+        "date" => Value::Text(moment.format("%Y-%m-%d").unwrap()),
+        "time" => Value::Text(moment.format("%H:%M:%S").unwrap()),
+        "datetime" => Value::Text(moment.format("%Y-%m-%d %H:%M:%S").unwrap()),
+        "julianday" => Value::Real(moment.julian_day()),
+        "unixepoch" => Value::Integer(moment.unix_seconds()),
+        "strftime" => match moment.format(&format.unwrap()) {
+            Some(text) => Value::Text(text),
+            None => Value::Null,
+        },
+        _ => return Err(format!("no such function: {}", name)),
+    })
+}
+
+/// returns the days from 1970-01-01 to the date. days past the end of the month
+/// run into the next month.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    // months are counted from march, so that the leap day comes last
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// returns the year, month and day of the day counted from 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Date and time fields of a moment
+struct Civil {
+    year: i64,
+    month: i64,
+    day: i64,
+    /// milliseconds since midnight
+    ms: i64,
+}
+
+/// A moment in UTC, as milliseconds since the julian day epoch: noon of
+/// November 24, 4714 BC
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Moment(i64);
+
+impl Moment {
+    fn now() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        Moment(UNIX_EPOCH_MS + since_epoch)
+    }
+
+    /// returns the moment, or `None` when it can not be written with a 4 digit year
+    fn checked(ms: i64) -> Option<Self> {
+        if (0..=MAX_MS).contains(&ms) {
+            Some(Moment(ms))
+        } else {
+            None
+        }
+    }
+
+    fn from_julian_day(days: f64) -> Option<Self> {
+        if !days.is_finite() {
+            return None;
+        }
+        Moment::checked((days * MS_PER_DAY as f64).round() as i64)
+    }
+
+    fn from_unix_seconds(seconds: f64) -> Option<Self> {
+        if !seconds.is_finite() {
+            return None;
+        }
+        Moment::checked(UNIX_EPOCH_MS + (seconds * 1000.0).round() as i64)
+    }
+
+    fn from_civil(civil: Civil) -> Option<Self> {
+        // months past december run into the next years
+        let year = civil.year + (civil.month - 1).div_euclid(12);
+        let month = (civil.month - 1).rem_euclid(12) + 1;
+        // julian day 0 is in 4714 BC, and later years can not be written with 4 digits
+        if !(-4714..=9999).contains(&year) {
+            return None;
+        }
+        let days = days_from_civil(year, month, civil.day);
+        Moment::checked(UNIX_EPOCH_MS + days * MS_PER_DAY + civil.ms)
+    }
+
+    fn civil(&self) -> Civil {
+        let since_epoch = self.0 - UNIX_EPOCH_MS;
+        let (year, month, day) = civil_from_days(since_epoch.div_euclid(MS_PER_DAY));
+        Civil {
+            year,
+            month,
+            day,
+            ms: since_epoch.rem_euclid(MS_PER_DAY),
+        }
+    }
+
+    fn julian_day(&self) -> f64 {
+        self.0 as f64 / MS_PER_DAY as f64
+    }
+
+    fn unix_seconds(&self) -> i64 {
+        (self.0 - UNIX_EPOCH_MS).div_euclid(1000)
+    }
+
+    /// returns the day of the week, 0 for sunday
+    fn weekday(&self) -> i64 {
+        // 1970-01-01 was a thursday
+        ((self.0 - UNIX_EPOCH_MS).div_euclid(MS_PER_DAY) + 4).rem_euclid(7)
+    }
+
+    /// reads the time value and applies the modifiers to it in order
+    fn read(value: &Value, modifiers: &[Value]) -> Option<Self> {
+        let number = match value {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Real(f) => Some(*f),
+            Value::Text(text) => text.trim().parse::<f64>().ok(),
+            Value::Null => return None,
+        };
+        let mut moment = match number {
+            Some(days) => Moment::from_julian_day(days),
+            None => Moment::parse(value.as_text()?.trim()),
+        };
+
+        for (i, modifier) in modifiers.iter().enumerate() {
+            let modifier = modifier.as_text()?.trim().to_lowercase();
+            moment = if modifier == "unixepoch" {
+                // only the number given as time value can be read as seconds
+                match number {
+                    Some(seconds) if i == 0 => Moment::from_unix_seconds(seconds),
+                    _ => None,
+                }
+            } else {
+                moment?.modify(&modifier)
+            };
+        }
+        moment
+    }
+
+    /// reads `YYYY-MM-DD`, `YYYY-MM-DD HH:MM[:SS[.SSS]]` or `HH:MM[:SS[.SSS]]`, where
+    /// `T` may separate the date from the time and `Z` or `[+-]HH:MM` may follow it
+    fn parse(text: &str) -> Option<Self> {
+        if text.eq_ignore_ascii_case("now") {
+            return Some(Moment::now());
+        }
+
+        let mut reader = Reader(text.as_bytes());
+        let (year, month, day) = if text.len() > 5 && text.as_bytes()[4] == b'-' {
+            let year = reader.number(4)?;
+            reader.expect(b'-')?;
+            let month = reader.number(2)?;
+            reader.expect(b'-')?;
+            let day = reader.number(2)?;
+            if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+                return None;
+            }
+            if reader.0.is_empty() {
+                return Moment::from_civil(Civil {
+                    year,
+                    month,
+                    day,
+                    ms: 0,
+                });
+            }
+            if reader.0[0] != b' ' && reader.0[0] != b'T' && reader.0[0] != b't' {
+                return None;
+            }
+            reader.0 = &reader.0[1..];
+            (year, month, day)
+        } else {
+            // a time of day alone is on 2000-01-01
+            (2000, 1, 1)
+        };
+
+        let hour = reader.number(2)?;
+        reader.expect(b':')?;
+        let minute = reader.number(2)?;
+        let mut ms = 0;
+        if reader.expect(b':').is_some() {
+            ms = reader.number(2)? * 1000;
+            if reader.expect(b'.').is_some() {
+                let digits = reader.0.iter().take_while(|b| b.is_ascii_digit()).count();
+                let fraction = std::str::from_utf8(&reader.0[..digits]).ok()?;
+                ms += (format!("0.{}", fraction).parse::<f64>().ok()? * 1000.0).round() as i64;
+                reader.0 = &reader.0[digits..];
+            }
+        }
+        if hour > 23 || minute > 59 || ms >= 60_000 {
+            return None;
+        }
+        ms += (hour * 60 + minute) * 60_000;
+
+        // times of other zones are converted to UTC
+        match reader.0.first() {
+            None => {}
+            Some(b'Z') | Some(b'z') if reader.0.len() == 1 => {}
+            Some(&sign) if sign == b'+' || sign == b'-' => {
+                reader.0 = &reader.0[1..];
+                let offset = reader.number(2)? * 60 + {
+                    reader.expect(b':')?;
+                    reader.number(2)?
+                };
+                if !reader.0.is_empty() {
+                    return None;
+                }
+                let offset = offset * 60_000;
+                ms += if sign == b'+' { -offset } else { offset };
+            }
+            _ => return None,
+        }
+
+        Moment::from_civil(Civil {
+            year,
+            month,
+            day,
+            ms,
+        })
+    }
+
+    /// applies a modifier: `[+-]<n> day|hour|minute|second|month|year[s]`,
+    /// `start of day|month|year` or `weekday <n>`
+    fn modify(self, modifier: &str) -> Option<Self> {
+        let civil = self.civil();
+        if let Some(unit) = modifier.strip_prefix("start of ") {
+            let (month, day) = match unit {
+                "day" => (civil.month, civil.day),
+                "month" => (civil.month, 1),
+                "year" => (1, 1),
+                _ => return None,
+            };
+            return Moment::from_civil(Civil {
+                month,
+                day,
+                ms: 0,
+                ..civil
+            });
+        }
+        if let Some(weekday) = modifier.strip_prefix("weekday ") {
+            // the next day that is that day of the week, or this one
+            let weekday: i64 = weekday.trim().parse().ok().filter(|d| (0..7).contains(d))?;
+            let days = (weekday - self.weekday()).rem_euclid(7);
+            return Moment::checked(self.0 + days * MS_PER_DAY);
+        }
+
+        let (amount, unit) = modifier.split_once(char::is_whitespace)?;
+        let amount: f64 = amount.parse().ok()?;
+        let unit = unit.trim();
+        let unit = unit.strip_suffix('s').unwrap_or(unit);
+        let ms_per_unit = match unit {
+            "day" => MS_PER_DAY,
+            "hour" => 3_600_000,
+            "minute" => 60_000,
+            "second" => 1000,
+            // days past the end of a shorter month run into the next one
+            "month" => {
+                return Moment::from_civil(Civil {
+                    month: civil.month.checked_add(amount as i64)?,
+                    ..civil
+                })
+            }
+            "year" => {
+                return Moment::from_civil(Civil {
+                    year: civil.year.checked_add(amount as i64)?,
+                    ..civil
+                })
+            }
+            _ => return None,
+        };
+        Moment::checked(
+            self.0
+                .checked_add((amount * ms_per_unit as f64).round() as i64)?,
+        )
+    }
+
+    /// formats the moment with `%d`, `%f`, `%H`, `%j`, `%J`, `%m`, `%M`, `%s`, `%S`,
+    /// `%w`, `%W`, `%Y` and `%%`. returns `None` for any other conversion.
+    fn format(&self, format: &str) -> Option<String> {
+        let civil = self.civil();
+        let day_of_year =
+            days_from_civil(civil.year, civil.month, civil.day) - days_from_civil(civil.year, 1, 1);
+        let mut output = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                output.push(c);
+                continue;
+            }
+            let seconds = civil.ms / 1000 % 60;
+            output.push_str(&match chars.next()? {
+                'd' => format!("{:02}", civil.day),
+                'f' => format!("{:06.3}", (civil.ms % 60_000) as f64 / 1000.0),
+                'H' => format!("{:02}", civil.ms / 3_600_000),
+                'j' => format!("{:03}", day_of_year + 1),
+                'J' => self.julian_day().to_string(),
+                'm' => format!("{:02}", civil.month),
+                'M' => format!("{:02}", civil.ms / 60_000 % 60),
+                's' => self.unix_seconds().to_string(),
+                'S' => format!("{:02}", seconds),
+                'w' => self.weekday().to_string(),
+                // weeks start on monday, and days before the first monday are in week 0
+                'W' => {
+                    let monday_based = (self.weekday() + 6) % 7;
+                    format!("{:02}", (day_of_year + 7 - monday_based) / 7)
+                }
+                'Y' => format!("{:04}", civil.year),
+                '%' => String::from("%"),
+                _ => return None,
+            });
+        }
+        Some(output)
+    }
+}
+
+/// Reads the fields of a time value from the front of the text
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// reads a number of exactly `digits` digits
+    fn number(&mut self, digits: usize) -> Option<i64> {
+        if self.0.len() < digits || !self.0[..digits].iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let number = std::str::from_utf8(&self.0[..digits]).ok()?.parse().ok()?;
+        self.0 = &self.0[digits..];
+        Some(number)
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        if self.0.first() != Some(&byte) {
+            return None;
+        }
+        self.0 = &self.0[1..];
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.into())
+    }
+
+    fn call(name: &str, args: &[&str]) -> Value {
+        let args: Vec<Value> = args.iter().map(|arg| text(arg)).collect();
+        super::call(name, &args).unwrap()
+    }
+
+    #[test]
+    fn test_read_and_write_times() {
+        assert_eq!(call("date", &["2024-02-29 13:45:10"]), text("2024-02-29"));
+        assert_eq!(
+            call("datetime", &["2024-02-29T13:45"]),
+            text("2024-02-29 13:45:00")
+        );
+        assert_eq!(
+            call("datetime", &["2024-03-01 01:30:00+02:00"]),
+            text("2024-02-29 23:30:00")
+        );
+        assert_eq!(call("time", &["12:34:56.789Z"]), text("12:34:56"));
+        assert_eq!(
+            call("julianday", &["2000-01-01 12:00:00"]),
+            Value::Real(2_451_545.0)
+        );
+        assert_eq!(call("datetime", &["2451545"]), text("2000-01-01 12:00:00"));
+        assert_eq!(call("unixepoch", &["1970-01-02"]), Value::Integer(86_400));
+        assert_eq!(
+            super::call(
+                "datetime",
+                &[Value::Integer(1_700_000_000), text("unixepoch")]
+            ),
+            Ok(text("2023-11-14 22:13:20"))
+        );
+        assert_eq!(call("date", &["0001-01-01"]), text("0001-01-01"));
+
+        for invalid in [
+            "2024-13-01",
+            "2024-02-29 24:00",
+            "yesterday",
+            "2024-1-1",
+            "12:00 pm",
+        ] {
+            assert_eq!(call("date", &[invalid]), Value::Null, "{}", invalid);
+        }
+        assert_eq!(call("date", &["2024-01-01", "unixepoch"]), Value::Null);
+        assert!(matches!(super::call("date", &[]), Ok(Value::Text(_))));
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let datetime = |modifiers: &[&str]| {
+            let mut args = vec!["2024-01-31 10:20:30"];
+            args.extend_from_slice(modifiers);
+            call("datetime", &args)
+        };
+        assert_eq!(datetime(&["+1 day"]), text("2024-02-01 10:20:30"));
+        assert_eq!(datetime(&["-1.5 hours"]), text("2024-01-31 08:50:30"));
+        assert_eq!(
+            datetime(&["+90 minutes", "-30 seconds"]),
+            text("2024-01-31 11:50:00")
+        );
+        // february 31 runs into march
+        assert_eq!(datetime(&["+1 month"]), text("2024-03-02 10:20:30"));
+        assert_eq!(datetime(&["-13 months"]), text("2022-12-31 10:20:30"));
+        assert_eq!(
+            datetime(&["+1 year", "start of month"]),
+            text("2025-01-01 00:00:00")
+        );
+        assert_eq!(
+            datetime(&["start of year", "+1 day"]),
+            text("2024-01-02 00:00:00")
+        );
+        assert_eq!(datetime(&["start of day"]), text("2024-01-31 00:00:00"));
+        // 2024-01-31 is a wednesday
+        assert_eq!(datetime(&["weekday 0"]), text("2024-02-04 10:20:30"));
+        assert_eq!(datetime(&["weekday 3"]), text("2024-01-31 10:20:30"));
+        assert_eq!(datetime(&["+1 fortnight"]), Value::Null);
+        assert_eq!(datetime(&["+8000 years"]), Value::Null);
+        assert_eq!(datetime(&["+9223372036854775807 months"]), Value::Null);
+        assert_eq!(datetime(&["-9223372036854775807 years"]), Value::Null);
+        assert_eq!(datetime(&["+1e300 days"]), Value::Null);
+        assert_eq!(datetime(&["-1e300 seconds"]), Value::Null);
+        assert_eq!(
+            super::call("datetime", &[text("now"), text("localtime")]),
+            Err(String::from("unsupported date modifier: localtime"))
+        );
+    }
+
+    #[test]
+    fn test_strftime() {
+        let strftime = |format: &str| call("strftime", &[format, "2024-03-04 05:06:07.5"]);
+        assert_eq!(
+            strftime("%Y/%m/%d %H:%M:%S %f"),
+            text("2024/03/04 05:06:07 07.500")
+        );
+        assert_eq!(strftime("%j %w %W %%"), text("064 1 10 %"));
+        assert_eq!(strftime("%s"), text("1709528767"));
+        assert_eq!(strftime("%J"), text("2460373.7125868057"));
+        assert_eq!(strftime("%Q"), Value::Null);
+        assert!(check_arguments("strftime", 0).is_err());
+    }
+}
@@ -0,0 +1,870 @@
+use super::aggregate;
+use super::function::{self, UserFunction};
+use super::json;
+use super::statement::Subquery;
+use super::value::{DataType, Value};
+use super::window;
+use std::cmp::Ordering;
+use std::fmt;
+
+pub type EvalError = String;
+
+/// Values an expression is evaluated against
+pub trait Scope {
+    /// returns value of the column, or `None` when there is no such column
+    fn column(&self, name: &str) -> Option<Value>;
+
+    /// returns result of an aggregate function call over the current group
+    fn aggregate(&self, expr: &Expr) -> Result<Value, EvalError> {
+        match expr {
+            Expr::Function { name, .. } => Err(format!("misuse of aggregate: {}()", name)),
+            Expr::UserCall { function, .. } => {
+                Err(format!("misuse of aggregate: {}()", function.name))
+            }
+            _ => unreachable!("not a function call"),
+        }
+    }
+
+    /// returns result of a window function call for the current row
+    fn window(&self, expr: &Expr) -> Result<Value, EvalError> {
+        match expr {
+            Expr::Window { name, .. } => Err(format!("misuse of window function {}()", name)),
+            _ => unreachable!("not a window function call"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Plus,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+}
+
+/// Expression tree evaluated against a single row
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Column(String),
+    /// `?`, `?<n>` or `:<name>` with its index counted from 1, replaced by the
+    /// value bound to it when the statement runs
+    Parameter {
+        index: usize,
+        name: String,
+    },
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        negated: bool,
+    },
+    /// `name([distinct] args)`. `count(*)` has no arguments.
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        distinct: bool,
+    },
+    /// call of a function the application defined. calls of `Function` are
+    /// resolved to these when the statement runs.
+    UserCall {
+        function: UserFunction,
+        args: Vec<Expr>,
+        distinct: bool,
+    },
+    /// `name(args) over ([partition by <expr>, ...] [order by <expr> [desc], ...])`,
+    /// computed over the result rows of the select sharing its `partition by` values
+    Window {
+        name: String,
+        args: Vec<Expr>,
+        partition_by: Vec<Expr>,
+        /// `order by` terms, true when descending
+        order_by: Vec<(Expr, bool)>,
+    },
+    /// `(select ...)`, the first column of the first row of the query, or NULL.
+    /// subqueries run before the statement using them and are replaced by their
+    /// results, unless they read the columns of the rows of a select, which
+    /// runs them again for each of its rows.
+    Subquery(Box<Subquery>),
+    /// `<expr> [not] in (select ...)`
+    InSubquery {
+        expr: Box<Expr>,
+        query: Box<Subquery>,
+        negated: bool,
+    },
+    /// `exists (select ...)`
+    Exists(Box<Subquery>),
+}
+
+impl BinaryOp {
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod
+        )
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq
+            | BinaryOp::NotEq
+            | BinaryOp::Lt
+            | BinaryOp::LtEq
+            | BinaryOp::Gt
+            | BinaryOp::GtEq => 4,
+            BinaryOp::Add | BinaryOp::Sub => 5,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 6,
+            BinaryOp::Concat => 7,
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Concat => "||",
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEq => "<>",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+fn arithmetic(op: BinaryOp, left: &Value, right: &Value) -> Value {
+    let (left, right) = (left.to_numeric(), right.to_numeric());
+    if left.is_null() || right.is_null() {
+        return Value::Null;
+    }
+
+    if let (Value::Integer(a), Value::Integer(b)) = (&left, &right) {
+        let (a, b) = (*a, *b);
+        let result = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Sub => a.checked_sub(b),
+            BinaryOp::Mul => a.checked_mul(b),
+            BinaryOp::Div if b == 0 => return Value::Null,
+            BinaryOp::Div => a.checked_div(b),
+            BinaryOp::Mod if b == 0 => return Value::Null,
+            // i64::MIN % -1 overflows, though the remainder is zero
+            BinaryOp::Mod => Some(a.checked_rem(b).unwrap_or(0)),
+            _ => unreachable!("not an arithmetic operator"),
+        };
+        // fall back to floating point when the integer result overflows
+        if let Some(result) = result {
+            return Value::Integer(result);
+        }
+    }
+
+    let (a, b) = (left.as_real().unwrap(), right.as_real().unwrap());
+    match op {
+        BinaryOp::Add => Value::Real(a + b),
+        BinaryOp::Sub => Value::Real(a - b),
+        BinaryOp::Mul => Value::Real(a * b),
+        BinaryOp::Div if b == 0.0 => Value::Null,
+        BinaryOp::Div => Value::Real(a / b),
+        BinaryOp::Mod if b as i64 == 0 => Value::Null,
+        BinaryOp::Mod => Value::Real((a as i64).checked_rem(b as i64).unwrap_or(0) as f64),
+        _ => unreachable!("not an arithmetic operator"),
+    }
+}
+
+fn comparison(op: BinaryOp, left: &Value, right: &Value) -> Value {
+    if left.is_null() || right.is_null() {
+        return Value::Null;
+    }
+
+    let ordering = left.compare(right);
+    let result = match op {
+        BinaryOp::Eq => ordering == Ordering::Equal,
+        BinaryOp::NotEq => ordering != Ordering::Equal,
+        BinaryOp::Lt => ordering == Ordering::Less,
+        BinaryOp::LtEq => ordering != Ordering::Greater,
+        BinaryOp::Gt => ordering == Ordering::Greater,
+        BinaryOp::GtEq => ordering != Ordering::Less,
+        _ => unreachable!("not a comparison operator"),
+    };
+    Value::from(result)
+}
+
+/// three-valued AND where false wins over NULL on either side
+fn and(left: Option<bool>, right: Option<bool>) -> Value {
+    match (left, right) {
+        (Some(false), _) | (_, Some(false)) => Value::from(false),
+        (Some(true), Some(true)) => Value::from(true),
+        _ => Value::Null,
+    }
+}
+
+/// three-valued OR where true wins over NULL on either side
+fn or(left: Option<bool>, right: Option<bool>) -> Value {
+    match (left, right) {
+        (Some(true), _) | (_, Some(true)) => Value::from(true),
+        (Some(false), Some(false)) => Value::from(false),
+        _ => Value::Null,
+    }
+}
+
+fn negate(value: Value, negated: bool) -> Value {
+    match value.truthy() {
+        Some(b) => Value::from(b != negated),
+        None => Value::Null,
+    }
+}
+
+/// matches `text` against a LIKE pattern, where `%` matches any sequence and
+/// `_` matches a single character. ASCII letters compare case-insensitively.
+pub fn like(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // classic greedy matching with backtracking to the last `%`
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len()
+            && pattern[p] != '%'
+            && (pattern[p] == '_' || pattern[p].eq_ignore_ascii_case(&text[t]))
+        {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '%' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            p = bp + 1;
+            t = bt + 1;
+            backtrack = Some((bp, bt + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+impl Expr {
+    pub fn binary(op: BinaryOp, left: Expr, right: Expr) -> Self {
+        Expr::Binary(op, Box::new(left), Box::new(right))
+    }
+
+    /// calls `f` with this expression and then its operands.
+    /// operands of an expression for which `f` returns false are skipped.
+    pub fn walk<'a, F: FnMut(&'a Expr) -> bool>(&'a self, f: &mut F) {
+        if !f(self) {
+            return;
+        }
+
+        match self {
+            Expr::Literal(_)
+            | Expr::Column(_)
+            | Expr::Parameter { .. }
+            | Expr::Subquery(_)
+            | Expr::Exists(_) => {}
+            Expr::Unary(_, expr) | Expr::IsNull { expr, .. } | Expr::InSubquery { expr, .. } => {
+                expr.walk(f)
+            }
+            Expr::Binary(_, left, right) => {
+                left.walk(f);
+                right.walk(f);
+            }
+            Expr::InList { expr, list, .. } => {
+                expr.walk(f);
+                list.iter().for_each(|e| e.walk(f));
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                expr.walk(f);
+                low.walk(f);
+                high.walk(f);
+            }
+            Expr::Like { expr, pattern, .. } => {
+                expr.walk(f);
+                pattern.walk(f);
+            }
+            Expr::Function { args, .. } | Expr::UserCall { args, .. } => {
+                args.iter().for_each(|e| e.walk(f))
+            }
+            Expr::Window {
+                args,
+                partition_by,
+                order_by,
+                ..
+            } => {
+                args.iter().for_each(|e| e.walk(f));
+                partition_by.iter().for_each(|e| e.walk(f));
+                order_by.iter().for_each(|(e, _)| e.walk(f));
+            }
+        }
+    }
+
+    /// calls `f` with this expression and, while it returns true, with the
+    /// expressions inside it, which `f` may replace
+    pub fn walk_mut<E, F: FnMut(&mut Expr) -> Result<bool, E>>(
+        &mut self,
+        f: &mut F,
+    ) -> Result<(), E> {
+        if !f(self)? {
+            return Ok(());
+        }
+
+        match self {
+            Expr::Literal(_)
+            | Expr::Column(_)
+            | Expr::Parameter { .. }
+            | Expr::Subquery(_)
+            | Expr::Exists(_) => Ok(()),
+            Expr::Unary(_, expr) | Expr::IsNull { expr, .. } | Expr::InSubquery { expr, .. } => {
+                expr.walk_mut(f)
+            }
+            Expr::Binary(_, left, right)
+            | Expr::Like {
+                expr: left,
+                pattern: right,
+                ..
+            } => {
+                left.walk_mut(f)?;
+                right.walk_mut(f)
+            }
+            Expr::InList { expr, list, .. } => {
+                expr.walk_mut(f)?;
+                list.iter_mut().try_for_each(|e| e.walk_mut(f))
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                expr.walk_mut(f)?;
+                low.walk_mut(f)?;
+                high.walk_mut(f)
+            }
+            Expr::Function { args, .. } | Expr::UserCall { args, .. } => {
+                args.iter_mut().try_for_each(|e| e.walk_mut(f))
+            }
+            Expr::Window {
+                args,
+                partition_by,
+                order_by,
+                ..
+            } => {
+                args.iter_mut().try_for_each(|e| e.walk_mut(f))?;
+                partition_by.iter_mut().try_for_each(|e| e.walk_mut(f))?;
+                order_by.iter_mut().try_for_each(|(e, _)| e.walk_mut(f))
+            }
+        }
+    }
+
+    /// calls `f` with the name of every column referenced in this expression
+    pub fn visit_columns<F: FnMut(&str)>(&self, f: &mut F) {
+        self.walk(&mut |expr| {
+            if let Expr::Column(name) = expr {
+                f(name);
+            }
+            true
+        });
+    }
+
+    /// returns if this is a call of an aggregate function
+    pub fn is_aggregate(&self) -> bool {
+        match self {
+            Expr::Function { name, .. } => aggregate::is_aggregate(name),
+            Expr::UserCall { function, .. } => function.is_aggregate(),
+            _ => false,
+        }
+    }
+
+    /// returns if an aggregate function is called anywhere in this expression
+    pub fn contains_aggregate(&self) -> bool {
+        let mut found = false;
+        self.walk(&mut |expr| {
+            found |= expr.is_aggregate();
+            !found
+        });
+        found
+    }
+
+    /// returns the first window function call in this expression
+    pub fn find_window(&self) -> Option<&Expr> {
+        let mut found = None;
+        self.walk(&mut |expr| {
+            if let Expr::Window { .. } = expr {
+                found = found.or(Some(expr));
+            }
+            found.is_none()
+        });
+        found
+    }
+
+    pub fn eval(&self, scope: &dyn Scope) -> Result<Value, EvalError> {
+        match self {
+            Expr::Literal(v) => Ok(v.clone()),
+            Expr::Column(name) => scope
+                .column(name)
+                .ok_or_else(|| format!("no such column: {}", name)),
+            Expr::Parameter { name, .. } => Err(format!("parameter {} has not been bound", name)),
+            Expr::Unary(op, expr) => {
+                let value = expr.eval(scope)?;
+                Ok(match op {
+                    UnaryOp::Plus => value,
+                    UnaryOp::Neg => arithmetic(BinaryOp::Sub, &Value::Integer(0), &value),
+                    UnaryOp::Not => negate(value, true),
+                })
+            }
+            Expr::Binary(BinaryOp::And, left, right) => {
+                let left = left.eval(scope)?.truthy();
+                if left == Some(false) {
+                    return Ok(Value::from(false));
+                }
+                Ok(and(left, right.eval(scope)?.truthy()))
+            }
+            Expr::Binary(BinaryOp::Or, left, right) => {
+                let left = left.eval(scope)?.truthy();
+                if left == Some(true) {
+                    return Ok(Value::from(true));
+                }
+                Ok(or(left, right.eval(scope)?.truthy()))
+            }
+            Expr::Binary(op, left, right) => {
+                let (left, right) = (left.eval(scope)?, right.eval(scope)?);
+                Ok(match op {
+                    op if op.is_arithmetic() => arithmetic(*op, &left, &right),
+                    BinaryOp::Concat => match (left.as_text(), right.as_text()) {
+                        (Some(l), Some(r)) => Value::Text(l + &r),
+                        _ => Value::Null,
+                    },
+                    _ => comparison(*op, &left, &right),
+                })
+            }
+            Expr::IsNull { expr, negated } => {
+                Ok(Value::from(expr.eval(scope)?.is_null() != *negated))
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let value = expr.eval(scope)?;
+                if value.is_null() {
+                    return Ok(Value::Null);
+                }
+
+                let mut saw_null = false;
+                for item in list.iter() {
+                    let item = item.eval(scope)?;
+                    if item.is_null() {
+                        saw_null = true;
+                    } else if value.compare(&item) == Ordering::Equal {
+                        return Ok(Value::from(!negated));
+                    }
+                }
+
+                // `x in (.., NULL)` is unknown rather than false when nothing matched
+                Ok(if saw_null {
+                    Value::Null
+                } else {
+                    Value::from(*negated)
+                })
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let value = expr.eval(scope)?;
+                let lower = comparison(BinaryOp::GtEq, &value, &low.eval(scope)?);
+                let upper = comparison(BinaryOp::LtEq, &value, &high.eval(scope)?);
+                Ok(negate(and(lower.truthy(), upper.truthy()), *negated))
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                negated,
+            } => {
+                let text = expr.eval(scope)?.as_text();
+                let pattern = pattern.eval(scope)?.as_text();
+                Ok(match (text, pattern) {
+                    (Some(text), Some(pattern)) => Value::from(like(&pattern, &text) != *negated),
+                    _ => Value::Null,
+                })
+            }
+            Expr::Function { name, args, .. } => {
+                if aggregate::is_aggregate(name) {
+                    return scope.aggregate(self);
+                }
+                let values = args
+                    .iter()
+                    .map(|arg| arg.eval(scope))
+                    .collect::<Result<Vec<Value>, EvalError>>()?;
+                if json::is_json_function(name) {
+                    return json::call_with(name, &values, args);
+                }
+                function::call(name, &values)
+            }
+            Expr::UserCall { function, args, .. } => {
+                if function.is_aggregate() {
+                    return scope.aggregate(self);
+                }
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(scope))
+                    .collect::<Result<Vec<Value>, EvalError>>()?;
+                function.call(&args)
+            }
+            Expr::Window { .. } => scope.window(self),
+            Expr::Subquery(_) | Expr::InSubquery { .. } | Expr::Exists(_) => {
+                Err(String::from("subquery has not been run"))
+            }
+        }
+    }
+
+    /// returns the type this expression evaluates to when it can be known
+    /// without looking at any row. `column_type` gives the declared type of a column.
+    pub fn data_type(&self, column_type: &dyn Fn(&str) -> Option<DataType>) -> Option<DataType> {
+        match self {
+            Expr::Literal(v) => v.data_type(),
+            Expr::Column(name) => column_type(name),
+            Expr::Unary(UnaryOp::Not, _) => Some(DataType::Integer),
+            Expr::Unary(_, expr) => match expr.data_type(column_type) {
+                Some(DataType::Text) => None,
+                t => t,
+            },
+            Expr::Binary(BinaryOp::Concat, _, _) => Some(DataType::Text),
+            Expr::Subquery(_) | Expr::UserCall { .. } | Expr::Parameter { .. } => None,
+            Expr::Function { name, .. } if function::is_function(name) => {
+                function::return_type(name)
+            }
+            Expr::Function { name, args, .. } => {
+                aggregate::return_type(name, args.first().and_then(|a| a.data_type(column_type)))
+            }
+            Expr::Window { name, args, .. } => {
+                window::return_type(name, args.first().and_then(|a| a.data_type(column_type)))
+            }
+            Expr::Binary(op, left, right) if op.is_arithmetic() => {
+                match (left.data_type(column_type), right.data_type(column_type)) {
+                    (Some(DataType::Integer), Some(DataType::Integer)) => Some(DataType::Integer),
+                    (Some(DataType::Real), Some(DataType::Integer))
+                    | (Some(DataType::Integer), Some(DataType::Real))
+                    | (Some(DataType::Real), Some(DataType::Real)) => Some(DataType::Real),
+                    _ => None,
+                }
+            }
+            // everything else is a condition evaluating to 0 or 1
+            _ => Some(DataType::Integer),
+        }
+    }
+
+    /// returns if the scope satisfies this expression used as a condition
+    pub fn matches(&self, scope: &dyn Scope) -> Result<bool, EvalError> {
+        Ok(self.eval(scope)?.truthy() == Some(true))
+    }
+}
+
+impl Expr {
+    /// binding strength used to decide where parentheses are needed when printing
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(op, _, _) => op.precedence(),
+            Expr::Unary(UnaryOp::Not, _) => 3,
+            Expr::IsNull { .. }
+            | Expr::InList { .. }
+            | Expr::InSubquery { .. }
+            | Expr::Between { .. }
+            | Expr::Like { .. } => 4,
+            Expr::Unary(_, _) => 8,
+            Expr::Literal(_)
+            | Expr::Column(_)
+            | Expr::Parameter { .. }
+            | Expr::Function { .. }
+            | Expr::UserCall { .. }
+            | Expr::Window { .. }
+            | Expr::Subquery(_)
+            | Expr::Exists(_) => 9,
+        }
+    }
+
+    /// writes `expr`, wrapping it in parentheses when it binds looser than `min`
+    fn fmt_operand(&self, f: &mut fmt::Formatter, min: u8) -> fmt::Result {
+        if self.precedence() < min {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+/// prints the expression back as sql, which also names unaliased result columns
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let not = |negated: &bool| if *negated { "NOT " } else { "" };
+        match self {
+            Expr::Literal(Value::Text(s)) => write!(f, "'{}'", s.replace('\'', "''")),
+            Expr::Literal(v) => write!(f, "{}", v),
+            Expr::Column(name) | Expr::Parameter { name, .. } => write!(f, "{}", name),
+            Expr::Unary(op, expr) => {
+                let symbol = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Plus => "+",
+                    UnaryOp::Not => "NOT ",
+                };
+                write!(f, "{}", symbol)?;
+                expr.fmt_operand(f, self.precedence())
+            }
+            Expr::Binary(op, left, right) => {
+                left.fmt_operand(f, op.precedence())?;
+                write!(f, " {} ", op)?;
+                right.fmt_operand(f, op.precedence() + 1)
+            }
+            Expr::IsNull { expr, negated } => {
+                expr.fmt_operand(f, 5)?;
+                write!(f, " IS {}NULL", not(negated))
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                expr.fmt_operand(f, 5)?;
+                write!(f, " {}IN (", not(negated))?;
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                expr.fmt_operand(f, 5)?;
+                write!(f, " {}BETWEEN ", not(negated))?;
+                low.fmt_operand(f, 5)?;
+                write!(f, " AND ")?;
+                high.fmt_operand(f, 5)
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                negated,
+            } => {
+                expr.fmt_operand(f, 5)?;
+                write!(f, " {}LIKE ", not(negated))?;
+                pattern.fmt_operand(f, 5)
+            }
+            Expr::Function {
+                name,
+                args,
+                distinct,
+            } => fmt_call(f, name, args, *distinct),
+            Expr::UserCall {
+                function,
+                args,
+                distinct,
+            } => fmt_call(f, &function.name, args, *distinct),
+            Expr::Window {
+                name,
+                args,
+                partition_by,
+                order_by,
+            } => {
+                fmt_call(f, name, args, false)?;
+                write!(f, " OVER (")?;
+                for (i, expr) in partition_by.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { ", " } else { "PARTITION BY " }, expr)?;
+                }
+                if !partition_by.is_empty() && !order_by.is_empty() {
+                    write!(f, " ")?;
+                }
+                for (i, (expr, descending)) in order_by.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { ", " } else { "ORDER BY " }, expr)?;
+                    if *descending {
+                        write!(f, " DESC")?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Expr::Subquery(query) => write!(f, "({})", query.sql),
+            Expr::InSubquery {
+                expr,
+                query,
+                negated,
+            } => {
+                expr.fmt_operand(f, 5)?;
+                write!(f, " {}IN ({})", not(negated), query.sql)
+            }
+            Expr::Exists(query) => write!(f, "EXISTS ({})", query.sql),
+        }
+    }
+}
+
+/// writes `name([DISTINCT ]args)`, where `count()` is written `count(*)`
+fn fmt_call(f: &mut fmt::Formatter, name: &str, args: &[Expr], distinct: bool) -> fmt::Result {
+    write!(f, "{}(", name)?;
+    if distinct {
+        write!(f, "DISTINCT ")?;
+    }
+    if args.is_empty() && name == "count" {
+        write!(f, "*")?;
+    }
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", arg)?;
+    }
+    write!(f, ")")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a row with `id = 12`
+    struct TestRow;
+
+    impl Scope for TestRow {
+        fn column(&self, name: &str) -> Option<Value> {
+            match name {
+                "id" => Some(Value::Integer(12)),
+                _ => None,
+            }
+        }
+    }
+
+    fn row() -> TestRow {
+        TestRow
+    }
+
+    fn int(i: i64) -> Expr {
+        Expr::Literal(Value::Integer(i))
+    }
+
+    #[test]
+    fn test_display() {
+        let expr = Expr::binary(
+            BinaryOp::Mul,
+            Expr::binary(BinaryOp::Add, Expr::Column("id".into()), int(1)),
+            int(2),
+        );
+        assert_eq!(expr.to_string(), "(id + 1) * 2");
+
+        let expr = Expr::Like {
+            expr: Box::new(Expr::Column("username".into())),
+            pattern: Box::new(Expr::Literal(Value::Text("it's%".into()))),
+            negated: true,
+        };
+        assert_eq!(expr.to_string(), "username NOT LIKE 'it''s%'");
+    }
+
+    #[test]
+    fn test_like() {
+        assert!(like("a%", "alice"));
+        assert!(like("A_ICE", "alice"));
+        assert!(like("%@example.%", "alice@example.com"));
+        assert!(like("%", ""));
+        assert!(!like("b%", "alice"));
+        assert!(!like("a_", "alice"));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let expr = Expr::binary(BinaryOp::Mul, Expr::Column("id".into()), int(2));
+        assert_eq!(expr.eval(&row()), Ok(Value::Integer(24)));
+
+        let expr = Expr::binary(BinaryOp::Div, int(7), int(2));
+        assert_eq!(expr.eval(&row()), Ok(Value::Integer(3)));
+
+        let expr = Expr::binary(BinaryOp::Div, int(7), int(0));
+        assert_eq!(expr.eval(&row()), Ok(Value::Null));
+
+        let expr = Expr::binary(BinaryOp::Add, int(i64::MAX), int(1));
+        assert_eq!(expr.eval(&row()), Ok(Value::Real(i64::MAX as f64 + 1.0)));
+
+        let expr = Expr::binary(BinaryOp::Mod, int(i64::MIN), int(-1));
+        assert_eq!(expr.eval(&row()), Ok(Value::Integer(0)));
+
+        let real = Expr::Literal(Value::Real(-1e19));
+        let expr = Expr::binary(BinaryOp::Mod, real, int(-1));
+        assert_eq!(expr.eval(&row()), Ok(Value::Real(0.0)));
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        let null = Expr::Literal(Value::Null);
+        let expr = Expr::binary(BinaryOp::And, null.clone(), int(0));
+        assert_eq!(expr.eval(&row()), Ok(Value::Integer(0)));
+
+        let expr = Expr::binary(BinaryOp::Or, null.clone(), int(1));
+        assert_eq!(expr.eval(&row()), Ok(Value::Integer(1)));
+
+        let expr = Expr::binary(BinaryOp::Eq, null, int(1));
+        assert_eq!(expr.eval(&row()), Ok(Value::Null));
+        assert_eq!(expr.matches(&row()), Ok(false));
+    }
+
+    #[test]
+    fn test_in_list_with_null() {
+        let expr = Expr::InList {
+            expr: Box::new(Expr::Column("id".into())),
+            list: vec![int(1), Expr::Literal(Value::Null)],
+            negated: false,
+        };
+        assert_eq!(expr.eval(&row()), Ok(Value::Null));
+
+        let expr = Expr::InList {
+            expr: Box::new(Expr::Column("id".into())),
+            list: vec![int(1), int(12)],
+            negated: false,
+        };
+        assert_eq!(expr.eval(&row()), Ok(Value::Integer(1)));
+    }
+}
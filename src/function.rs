@@ -0,0 +1,588 @@
+use super::aggregate::Aggregate;
+use super::datetime;
+use super::expression::EvalError;
+use super::json;
+use super::value::{DataType, Value};
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::Chars;
+
+const FUNCTIONS: [&str; 15] = [
+    "abs", "coalesce", "hex", "ifnull", "instr", "length", "lower", "nullif", "printf", "replace",
+    "round", "substr", "trim", "typeof", "upper",
+];
+
+/// returns if there is a scalar function with the given name
+pub fn is_function(name: &str) -> bool {
+    FUNCTIONS.contains(&name) || datetime::is_date_function(name) || json::is_json_function(name)
+}
+
+/// checks the number of arguments passed to the scalar function
+pub fn check_arguments(name: &str, num_args: usize) -> Result<(), String> {
+    if datetime::is_date_function(name) {
+        return datetime::check_arguments(name, num_args);
+    }
+    if json::is_json_function(name) {
+        return json::check_arguments(name, num_args);
+    }
+    let valid = match name {
+        "abs" | "hex" | "length" | "lower" | "typeof" | "upper" => num_args == 1,
+        "ifnull" | "instr" | "nullif" => num_args == 2,
+        "replace" => num_args == 3,
+        "round" | "trim" => num_args == 1 || num_args == 2,
+        "substr" => num_args == 2 || num_args == 3,
+        "coalesce" => num_args >= 2,
+        "printf" => num_args >= 1,
+        _ => false,
+    };
+
+    if !valid {
+        return Err(format!("wrong number of arguments to function {}()", name));
+    }
+    Ok(())
+}
+
+/// returns the type the scalar function produces
+pub fn return_type(name: &str) -> Option<DataType> {
+    if datetime::is_date_function(name) {
+        return datetime::return_type(name);
+    }
+    if json::is_json_function(name) {
+        return json::return_type(name);
+    }
+    match name {
+        "instr" | "length" => Some(DataType::Integer),
+        "round" => Some(DataType::Real),
+        "hex" | "lower" | "printf" | "replace" | "substr" | "trim" | "typeof" | "upper" => {
+            Some(DataType::Text)
+        }
+        _ => None,
+    }
+}
+
+/// Scalar function the application defined, called with evaluated arguments
+pub type ScalarFn = dyn Fn(&[Value]) -> Result<Value, EvalError>;
+
+/// Implementation of a function the application defined
+#[derive(Clone)]
+enum Implementation {
+    Scalar(Rc<ScalarFn>),
+    /// creates fresh state for each group
+    Aggregate(Rc<dyn Fn() -> Box<dyn Aggregate>>),
+}
+
+/// A scalar or aggregate function the application defined, registered on a
+/// `Table` by `create_scalar_function` or `create_aggregate_function`
+#[derive(Clone)]
+pub struct UserFunction {
+    pub name: String,
+    /// number of arguments the function takes, or `None` for any number
+    num_args: Option<usize>,
+    implementation: Implementation,
+}
+
+impl UserFunction {
+    pub fn scalar<F>(name: &str, num_args: Option<usize>, f: F) -> Self
+    where
+        F: Fn(&[Value]) -> Result<Value, EvalError> + 'static,
+    {
+        UserFunction {
+            name: name.to_lowercase(),
+            num_args,
+            implementation: Implementation::Scalar(Rc::new(f)),
+        }
+    }
+
+    /// returns the aggregate function whose state for each group is a fresh `A`
+    pub fn aggregate<A: Aggregate + Default + 'static>(
+        name: &str,
+        num_args: Option<usize>,
+    ) -> Self {
+        UserFunction {
+            name: name.to_lowercase(),
+            num_args,
+            implementation: Implementation::Aggregate(Rc::new(|| Box::new(A::default()))),
+        }
+    }
+
+    pub fn is_aggregate(&self) -> bool {
+        matches!(self.implementation, Implementation::Aggregate(_))
+    }
+
+    /// checks the number of arguments passed to the function
+    pub fn check_arguments(&self, num_args: usize) -> Result<(), String> {
+        if self.num_args.is_some_and(|n| n != num_args) {
+            return Err(format!(
+                "wrong number of arguments to function {}()",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// calls the scalar function with already evaluated arguments
+    pub fn call(&self, args: &[Value]) -> Result<Value, EvalError> {
+        match &self.implementation {
+            Implementation::Scalar(f) => f(args),
+            Implementation::Aggregate(_) => {
+                Err(format!("misuse of aggregate function {}()", self.name))
+            }
+        }
+    }
+
+    /// creates fresh state for the aggregate function
+    pub fn new_aggregate(&self) -> Option<Box<dyn Aggregate>> {
+        match &self.implementation {
+            Implementation::Scalar(_) => None,
+            Implementation::Aggregate(new) => Some(new()),
+        }
+    }
+}
+
+impl fmt::Debug for UserFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UserFunction({})", self.name)
+    }
+}
+
+/// functions are the same when they share their implementation
+impl PartialEq for UserFunction {
+    fn eq(&self, other: &Self) -> bool {
+        let same = match (&self.implementation, &other.implementation) {
+            (Implementation::Scalar(a), Implementation::Scalar(b)) => Rc::ptr_eq(a, b),
+            (Implementation::Aggregate(a), Implementation::Aggregate(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        };
+        same && self.name == other.name && self.num_args == other.num_args
+    }
+}
+
+/// returns the value as an integer the way C `printf` reads it: NULL and text
+/// that is not a number are 0, and reals are truncated
+fn integer(value: &Value) -> i64 {
+    match value.to_numeric() {
+        Value::Integer(i) => i,
+        Value::Real(f) => f as i64,
+        _ => 0,
+    }
+}
+
+/// calls the scalar function with already evaluated arguments
+pub fn call(name: &str, args: &[Value]) -> Result<Value, EvalError> {
+    if datetime::is_date_function(name) {
+        return datetime::call(name, args);
+    }
+    if json::is_json_function(name) {
+        return json::call(name, args);
+    }
+
+    // functions that look at NULL arguments themselves
+    match name {
+        "coalesce" | "ifnull" => {
+            return Ok(args
+                .iter()
+                .find(|arg| !arg.is_null())
+                .cloned()
+                .unwrap_or(Value::Null))
+        }
+        "nullif" => {
+            return Ok(match args[0].compare(&args[1]) {
+                Ordering::Equal if !args[0].is_null() => Value::Null,
+                _ => args[0].clone(),
+            })
+        }
+        "typeof" => {
+            let name = match args[0].data_type() {
+                Some(data_type) => data_type.to_string(),
+                None => String::from("null"),
+            };
+            return Ok(Value::Text(name));
+        }
+        // the bytes of the text a value is rendered as, and nothing for NULL
+        "hex" => {
+            let text = args[0].as_text().unwrap_or_default();
+            return Ok(Value::Text(
+                text.bytes().map(|b| format!("{:02X}", b)).collect(),
+            ));
+        }
+        "printf" => {
+            return Ok(match args[0].as_text() {
+                Some(format) => Value::Text(printf(&format, &args[1..])),
+                None => Value::Null,
+            })
+        }
+        _ => {}
+    }
+
+    // the others are NULL when any argument is NULL
+    if args.iter().any(Value::is_null) {
+        return Ok(Value::Null);
+    }
+    let text = |i: usize| args[i].as_text().unwrap_or_default();
+    Ok(match name {
+        // characters of text, or of the text a number is rendered as
+        "length" => Value::Integer(text(0).chars().count() as i64),
+        "lower" => Value::Text(text(0).to_lowercase()),
+        "upper" => Value::Text(text(0).to_uppercase()),
+        "substr" => {
+            let length = args.get(2).map(integer);
+            Value::Text(substr(&text(0), integer(&args[1]), length))
+        }
+        // trim(<text> [, <characters>]) removes spaces, or the given characters, at both ends
+        "trim" => {
+            let characters = if args.len() > 1 {
+                text(1)
+            } else {
+                String::from(" ")
+            };
+            Value::Text(String::from(
+                text(0).trim_matches(|c| characters.contains(c)),
+            ))
+        }
+        "replace" => {
+            let pattern = text(1);
+            if pattern.is_empty() {
+                Value::Text(text(0))
+            } else {
+                Value::Text(text(0).replace(&pattern, &text(2)))
+            }
+        }
+        // position of the first occurrence in characters counted from 1, or 0
+        "instr" => {
+            let haystack = text(0);
+            Value::Integer(match haystack.find(&text(1)) {
+                Some(offset) => haystack[..offset].chars().count() as i64 + 1,
+                None => 0,
+            })
+        }
+        "abs" => match args[0].to_numeric() {
+            Value::Integer(i) => Value::Integer(
+                i.checked_abs()
+                    .ok_or_else(|| String::from("integer overflow"))?,
+            ),
+            Value::Real(f) => Value::Real(f.abs()),
+            value => value,
+        },
+        // halves round away from zero
+        "round" => {
+            let digits = args.get(1).map_or(0, integer).clamp(0, 30) as i32;
+            let scale = 10f64.powi(digits);
+            let value = args[0].as_real().unwrap_or(0.0);
+            Value::Real((value * scale).round() / scale)
+        }
+        _ => return Err(format!("no such function: {}", name)),
+    })
+}
+
+/// returns `length` characters of the text starting at the 1-based position `start`.
+/// a negative start counts from the end, and a negative length takes the characters
+/// before the start instead.
+fn substr(text: &str, start: i64, length: Option<i64>) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let count = chars.len() as i64;
+    let mut start = start;
+    let mut length = length.unwrap_or(count);
+    let backwards = length < 0;
+    length = length.saturating_abs();
+
+    if start < 0 {
+        start += count;
+        if start < 0 {
+            length = (length + start).max(0);
+            start = 0;
+        }
+    } else if start > 0 {
+        start -= 1;
+    } else if length > 0 {
+        // position 0 is just before the first character
+        length -= 1;
+    }
+    if backwards {
+        start -= length;
+        if start < 0 {
+            length += start;
+            start = 0;
+        }
+    }
+
+    let end = start.saturating_add(length).min(count);
+    if start >= end {
+        return String::new();
+    }
+    chars[start as usize..end as usize].iter().collect()
+}
+
+/// the largest width or precision `printf` pads to; larger ones are capped
+const MAX_PRINTF_WIDTH: usize = 100_000;
+
+/// reads the digits of a width or precision, capped at `MAX_PRINTF_WIDTH`
+fn read_number(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut number = None;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        let value = number.unwrap_or(0) * 10 + digit as usize;
+        number = Some(value.min(MAX_PRINTF_WIDTH));
+        chars.next();
+    }
+    number
+}
+
+/// formats the arguments like C `printf`, with `%[flags][width][.precision]<conversion>`
+/// for the conversions d, i, f, e, E, s, c, x, X, o and %. missing arguments are NULL.
+fn printf(format: &str, args: &[Value]) -> String {
+    let mut output = String::new();
+    let mut args = args.iter();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+
+        let mut flags = String::new();
+        while let Some(&flag) = chars.peek().filter(|c| "-+ 0#".contains(**c)) {
+            flags.push(flag);
+            chars.next();
+        }
+        let width = read_number(&mut chars).unwrap_or(0);
+        let precision = if chars.peek() == Some(&'.') {
+            chars.next();
+            Some(read_number(&mut chars).unwrap_or(0))
+        } else {
+            None
+        };
+        let conversion = match chars.next() {
+            Some('%') => {
+                output.push('%');
+                continue;
+            }
+            Some(conversion) => conversion,
+            None => break,
+        };
+        let arg = args.next().cloned().unwrap_or(Value::Null);
+
+        // the sign is kept apart from the digits, so that zeros can go between them
+        let sign = |negative: bool| {
+            if negative {
+                "-"
+            } else if flags.contains('+') {
+                "+"
+            } else if flags.contains(' ') {
+                " "
+            } else {
+                ""
+            }
+        };
+        let (sign, body, numeric) = match conversion {
+            'd' | 'i' => {
+                let n = integer(&arg);
+                let digits = format!("{:0>1$}", n.unsigned_abs(), precision.unwrap_or(0));
+                (sign(n < 0), digits, true)
+            }
+            'f' | 'e' | 'E' => {
+                let f = arg.as_real().unwrap_or(0.0);
+                let precision = precision.unwrap_or(6);
+                let digits = if conversion == 'f' {
+                    format!("{:.*}", precision, f.abs())
+                } else {
+                    exponential(f.abs(), precision, conversion == 'E')
+                };
+                (sign(f.is_sign_negative() && f != 0.0), digits, true)
+            }
+            'x' | 'X' | 'o' => {
+                let n = integer(&arg) as u64;
+                let (digits, prefix) = match conversion {
+                    'x' => (format!("{:x}", n), "0x"),
+                    'X' => (format!("{:X}", n), "0X"),
+                    _ => (format!("{:o}", n), "0"),
+                };
+                let digits = format!("{:0>1$}", digits, precision.unwrap_or(0));
+                let prefix = if flags.contains('#') && n != 0 {
+                    prefix
+                } else {
+                    ""
+                };
+                (prefix, digits, true)
+            }
+            's' => {
+                let text = arg.as_text().unwrap_or_default();
+                let text = match precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text,
+                };
+                ("", text, false)
+            }
+            'c' => {
+                let text = arg.as_text().unwrap_or_default();
+                ("", text.chars().take(1).collect(), false)
+            }
+            // anything else is copied as it is
+            other => {
+                output.push('%');
+                output.push_str(&flags);
+                output.push(other);
+                continue;
+            }
+        };
+
+        let padding = width.saturating_sub(sign.chars().count() + body.chars().count());
+        if flags.contains('-') {
+            output.push_str(sign);
+            output.push_str(&body);
+            output.extend(std::iter::repeat_n(' ', padding));
+        } else if flags.contains('0') && numeric {
+            output.push_str(sign);
+            output.extend(std::iter::repeat_n('0', padding));
+            output.push_str(&body);
+        } else {
+            output.extend(std::iter::repeat_n(' ', padding));
+            output.push_str(sign);
+            output.push_str(&body);
+        }
+    }
+    output
+}
+
+/// formats a non-negative number as `d.ddde+XX`, with at least two exponent digits
+fn exponential(f: f64, precision: usize, upper: bool) -> String {
+    let formatted = format!("{:.*e}", precision, f);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let e = if upper { 'E' } else { 'e' };
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}{}{}{:02}", mantissa, e, sign, exponent.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.into())
+    }
+
+    #[test]
+    fn test_length() {
+        let length = |value: Value| call("length", &[value]).unwrap();
+        assert_eq!(length(Value::Text("田中".into())), Value::Integer(2));
+        assert_eq!(length(Value::Integer(-12)), Value::Integer(3));
+        assert_eq!(length(Value::Null), Value::Null);
+        assert!(check_arguments("length", 2).is_err());
+    }
+
+    #[test]
+    fn test_text_functions() {
+        let call = |name: &str, args: &[Value]| call(name, args).unwrap();
+        assert_eq!(
+            call("lower", &[text("Ann@Example.COM")]),
+            text("ann@example.com")
+        );
+        assert_eq!(call("upper", &[text("straße")]), text("STRASSE"));
+        assert_eq!(call("trim", &[text("  a b ")]), text("a b"));
+        assert_eq!(call("trim", &[text("xxaxy"), text("xy")]), text("a"));
+        assert_eq!(
+            call("replace", &[text("a-b-c"), text("-"), text("+")]),
+            text("a+b+c")
+        );
+        assert_eq!(
+            call("replace", &[text("abc"), text(""), text("x")]),
+            text("abc")
+        );
+        assert_eq!(
+            call("instr", &[text("田中さん"), text("さ")]),
+            Value::Integer(3)
+        );
+        assert_eq!(call("instr", &[text("abc"), text("z")]), Value::Integer(0));
+        assert_eq!(call("upper", &[Value::Null]), Value::Null);
+        assert_eq!(call("hex", &[text("é1")]), text("C3A931"));
+        assert_eq!(call("hex", &[Value::Null]), text(""));
+
+        let substr = |start: i64, length: Option<i64>| {
+            let mut args = vec![text("hello"), Value::Integer(start)];
+            args.extend(length.map(Value::Integer));
+            call("substr", &args)
+        };
+        assert_eq!(substr(2, Some(3)), text("ell"));
+        assert_eq!(substr(2, None), text("ello"));
+        assert_eq!(substr(-3, None), text("llo"));
+        assert_eq!(substr(-3, Some(2)), text("ll"));
+        assert_eq!(substr(0, Some(2)), text("h"));
+        assert_eq!(substr(4, Some(-2)), text("el"));
+        assert_eq!(substr(-7, Some(3)), text("h"));
+        assert_eq!(substr(9, Some(2)), text(""));
+        assert_eq!(substr(2, Some(i64::MAX)), text("ello"));
+        assert_eq!(substr(2, Some(i64::MIN)), text("h"));
+    }
+
+    #[test]
+    fn test_number_and_null_functions() {
+        let call = |name: &str, args: &[Value]| call(name, args).unwrap();
+        assert_eq!(call("abs", &[Value::Integer(-3)]), Value::Integer(3));
+        assert_eq!(call("abs", &[Value::Real(-2.5)]), Value::Real(2.5));
+        assert!(super::call("abs", &[Value::Integer(i64::MIN)]).is_err());
+        assert_eq!(call("round", &[Value::Real(2.5)]), Value::Real(3.0));
+        assert_eq!(
+            call("round", &[Value::Real(-1.2345), Value::Integer(2)]),
+            Value::Real(-1.23)
+        );
+        assert_eq!(call("round", &[Value::Integer(7)]), Value::Real(7.0));
+        assert_eq!(
+            call("coalesce", &[Value::Null, Value::Null, Value::Integer(1)]),
+            Value::Integer(1)
+        );
+        assert_eq!(call("ifnull", &[Value::Null, text("x")]), text("x"));
+        assert_eq!(
+            call("nullif", &[Value::Integer(1), Value::Real(1.0)]),
+            Value::Null
+        );
+        assert_eq!(
+            call("nullif", &[Value::Integer(1), Value::Integer(2)]),
+            Value::Integer(1)
+        );
+        assert_eq!(call("typeof", &[Value::Real(1.0)]), text("real"));
+        assert_eq!(call("typeof", &[Value::Null]), text("null"));
+        assert!(check_arguments("coalesce", 1).is_err());
+        assert!(check_arguments("substr", 1).is_err());
+    }
+
+    #[test]
+    fn test_printf() {
+        let printf = |format: &str, args: &[Value]| {
+            let mut all = vec![text(format)];
+            all.extend_from_slice(args);
+            call("printf", &all).unwrap()
+        };
+        assert_eq!(
+            printf("%s has %d items", &[text("ann"), Value::Integer(3)]),
+            text("ann has 3 items")
+        );
+        assert_eq!(
+            printf(
+                "%5d|%-5d|%05d",
+                &[Value::Integer(42), Value::Integer(-4), Value::Integer(-42)]
+            ),
+            text("   42|-4   |-0042")
+        );
+        assert_eq!(
+            printf("%.2f %+.1f", &[Value::Real(1.23456), Value::Integer(2)]),
+            text("1.23 +2.0")
+        );
+        assert_eq!(printf("%e", &[Value::Real(1234.5)]), text("1.234500e+03"));
+        assert_eq!(
+            printf(
+                "%x %#X %o",
+                &[Value::Integer(255), Value::Integer(255), Value::Integer(8)]
+            ),
+            text("ff 0XFF 10")
+        );
+        assert_eq!(
+            printf("%.2s%c 100%%", &[text("abc"), text("xyz")]),
+            text("abx 100%")
+        );
+        assert_eq!(printf("%d %s.", &[]), text("0 ."));
+        let padded = printf("%999999999999999999999d", &[Value::Integer(1)]);
+        assert_eq!(padded.as_text().map(|s| s.len()), Some(MAX_PRINTF_WIDTH));
+        assert_eq!(call("printf", &[Value::Null]).unwrap(), Value::Null);
+    }
+}
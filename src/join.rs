@@ -0,0 +1,440 @@
+use super::expression::{BinaryOp, Expr};
+use super::key::{encode_key, is_key_value};
+use super::row::Row;
+use super::schema::{ColumnDef, TableDef};
+use super::source::RowSource;
+use super::statement::{EmptyScope, ExecuteResult, RowScope};
+use super::table::Table;
+use super::value::{hash_key, Value};
+use std::collections::HashMap;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum JoinKind {
+    /// only pairs of rows satisfying the `on` condition
+    Inner,
+    /// every outer row, with NULLs for the inner table when no row pairs with it
+    Left,
+}
+
+/// `[inner | left [outer] | cross] join <table> [[as] <alias>] [on <expr>]` of a select
+#[derive(Debug, PartialEq, Clone)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: String,
+    /// arguments when the table is a table-valued function, which can read
+    /// the columns of the tables joined before it
+    pub args: Option<Vec<Expr>>,
+    pub alias: Option<String>,
+    pub on: Option<Expr>,
+}
+
+impl Join {
+    /// returns the name the columns of the joined table are qualified by
+    pub fn name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.table)
+    }
+}
+
+/// How the inner rows pairing with an outer row are found
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum JoinStrategy {
+    /// scans the whole inner table again for every outer row
+    NestedLoop,
+    /// seeks the B-tree of the inner table with `table_find`, using the outer
+    /// column at `outer` as the key of its single column primary key
+    PrimaryKey { outer: usize },
+    /// looks up the outer column at `outer` in the index at `root_page_num`
+    /// on the column at `inner`, then seeks the inner rows it names
+    Index {
+        outer: usize,
+        inner: usize,
+        root_page_num: usize,
+    },
+    /// reads the inner table once into a hash table on the column at `inner`,
+    /// probed with the outer column at `outer`
+    Hash { outer: usize, inner: usize },
+}
+
+/// returns the definition of the rows of the table read under `name`, whose
+/// columns are named `<name>.<column>`. they still resolve by their own name
+/// while no other table of a join has a column of that name.
+pub fn qualified_def(table: &TableDef, name: &str) -> TableDef {
+    let columns = table
+        .columns
+        .iter()
+        .map(|column| ColumnDef {
+            name: format!("{}.{}", name, column.name),
+            ..column.clone()
+        })
+        .collect();
+    TableDef {
+        name: String::new(),
+        columns,
+        primary_key: Vec::new(),
+        checks: Vec::new(),
+        root_page_num: table.root_page_num,
+        sequence: 0,
+    }
+}
+
+/// returns the definition of the rows produced by joining the `outer` rows
+/// with the rows of `inner` read under `name`
+pub fn joined_def(outer: &TableDef, inner: &TableDef, name: &str) -> TableDef {
+    let mut def = outer.clone();
+    def.columns.extend(qualified_def(inner, name).columns);
+    def
+}
+
+impl JoinStrategy {
+    /// picks how to join the rows of `outer` with the `inner` table, from the
+    /// `<column> = <column>` terms of the `on` condition pairing a column of
+    /// each. a seek on the primary key beats an index, which beats hashing,
+    /// and without such a term every pair of rows is tried. temporary tables
+    /// have neither a B-tree nor indexes to seek, and the rows of a table-valued
+    /// function or virtual table depend on the outer row.
+    pub fn plan(table: &Table, outer: &TableDef, inner: &RowSource, join: &Join) -> Self {
+        if matches!(inner, RowSource::Function(_) | RowSource::Virtual(_)) {
+            return JoinStrategy::NestedLoop;
+        }
+        let stored = matches!(inner, RowSource::Table(_));
+        let inner = inner.def();
+        let joined = joined_def(outer, inner, join.name());
+        let mut best = JoinStrategy::NestedLoop;
+
+        let mut pending: Vec<&Expr> = join.on.iter().collect();
+        while let Some(expr) = pending.pop() {
+            let (left, right) = match expr {
+                Expr::Binary(BinaryOp::And, left, right) => {
+                    pending.push(right);
+                    pending.push(left);
+                    continue;
+                }
+                Expr::Binary(BinaryOp::Eq, left, right) => (&**left, &**right),
+                _ => continue,
+            };
+            let (left, right) = match (left, right) {
+                (Expr::Column(left), Expr::Column(right)) => {
+                    (joined.column_index(left), joined.column_index(right))
+                }
+                _ => continue,
+            };
+            let split = outer.columns.len();
+            let (outer_column, inner_column) = match (left, right) {
+                (Some(l), Some(r)) if l < split && r >= split => (l, r - split),
+                (Some(l), Some(r)) if r < split && l >= split => (r, l - split),
+                _ => continue,
+            };
+
+            let name = &inner.columns[inner_column].name;
+            let index = table.schema.index_on(&inner.name, name).filter(|_| stored);
+            let strategy = if stored && inner.primary_key == [inner_column] {
+                JoinStrategy::PrimaryKey {
+                    outer: outer_column,
+                }
+            } else if let Some(index) = index {
+                JoinStrategy::Index {
+                    outer: outer_column,
+                    inner: inner_column,
+                    root_page_num: index.root_page_num,
+                }
+            } else {
+                JoinStrategy::Hash {
+                    outer: outer_column,
+                    inner: inner_column,
+                }
+            };
+            if strategy.rank() > best.rank() {
+                best = strategy;
+            }
+        }
+        best
+    }
+
+    fn rank(&self) -> usize {
+        match self {
+            JoinStrategy::NestedLoop => 0,
+            JoinStrategy::Hash { .. } => 1,
+            JoinStrategy::Index { .. } => 2,
+            JoinStrategy::PrimaryKey { .. } => 3,
+        }
+    }
+}
+
+/// returns the row with the given key values, if there is one
+fn seek(table: &mut Table, def: &TableDef, values: &[Value]) -> Result<Option<Row>, ExecuteResult> {
+    let key = encode_key(values);
+    let mut cursor = table.table_find(def.root_page_num, &key);
+    if cursor.key() != Some(&key[..]) {
+        return Ok(None);
+    }
+    cursor
+        .get_value()
+        .map(Some)
+        .map_err(ExecuteResult::InvalidRow)
+}
+
+/// joins the `outer` rows, described by `outer_def`, with the rows of the
+/// `inner` source as `strategy` says. returns the definition of the joined rows
+/// along with the rows, outer rows first and inner rows in key order.
+pub fn join_rows(
+    table: &mut Table,
+    outer_def: &TableDef,
+    outer: Vec<Row>,
+    source: &RowSource,
+    join: &Join,
+    strategy: JoinStrategy,
+) -> Result<(TableDef, Vec<Row>), ExecuteResult> {
+    let inner = source.def();
+    let def = joined_def(outer_def, inner, join.name());
+
+    let hashed = match strategy {
+        JoinStrategy::Hash { inner: column, .. } => {
+            let mut hashed: HashMap<Vec<u8>, Vec<Row>> = HashMap::new();
+            for row in source.rows(table, &EmptyScope)? {
+                // NULL is not equal to anything, so it never pairs
+                if !row.values[column].is_null() {
+                    let key = hash_key(std::slice::from_ref(&row.values[column]));
+                    hashed.entry(key).or_default().push(row);
+                }
+            }
+            hashed
+        }
+        _ => HashMap::new(),
+    };
+
+    let mut rows = Vec::new();
+    for outer_row in outer {
+        // the outer value, converted to the type of the inner column it is compared to
+        let probe = |column: usize, inner_column: usize| -> Option<Value> {
+            let value = outer_row.values[column]
+                .clone()
+                .apply_affinity(inner.columns[inner_column].data_type());
+            Some(value).filter(|value| !value.is_null())
+        };
+
+        let candidates = match strategy {
+            JoinStrategy::NestedLoop => source.rows(
+                table,
+                &RowScope {
+                    table: outer_def,
+                    row: &outer_row,
+                },
+            )?,
+            JoinStrategy::PrimaryKey { outer: column } => {
+                match probe(column, inner.primary_key[0]).filter(is_key_value) {
+                    Some(value) => seek(table, inner, &[value])?.into_iter().collect(),
+                    None => Vec::new(),
+                }
+            }
+            JoinStrategy::Index {
+                outer: column,
+                inner: inner_column,
+                root_page_num,
+            } => {
+                let keys = match probe(column, inner_column) {
+                    Some(value) => table.pager.get_index(root_page_num).unwrap().lookup(&value),
+                    None => Vec::new(),
+                };
+                let mut candidates = Vec::with_capacity(keys.len());
+                for key in keys {
+                    let row = table
+                        .table_find(inner.root_page_num, &key)
+                        .get_value()
+                        .map_err(ExecuteResult::InvalidRow)?;
+                    candidates.push(row);
+                }
+                candidates
+            }
+            JoinStrategy::Hash {
+                outer: column,
+                inner: inner_column,
+            } => match probe(column, inner_column) {
+                Some(value) => hashed.get(&hash_key(&[value])).cloned().unwrap_or_default(),
+                None => Vec::new(),
+            },
+        };
+
+        // the whole `on` condition decides, not only the term the strategy used
+        let mut paired = false;
+        for inner_row in candidates {
+            let mut values = outer_row.values.clone();
+            values.extend(inner_row.values);
+            let row = Row::new(values);
+            let matches = match &join.on {
+                Some(on) => on
+                    .matches(&RowScope {
+                        table: &def,
+                        row: &row,
+                    })
+                    .map_err(ExecuteResult::EvalError)?,
+                None => true,
+            };
+            if matches {
+                paired = true;
+                rows.push(row);
+            }
+        }
+        if !paired && join.kind == JoinKind::Left {
+            let mut values = outer_row.values.clone();
+            values.extend(inner.columns.iter().map(|_| Value::Null));
+            rows.push(Row::new(values));
+        }
+    }
+
+    Ok((def, rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::Parser;
+    use super::super::source::TempTable;
+    use super::super::statement::Statement;
+    use super::*;
+    use std::error::Error;
+    use std::fs;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_every_strategy_joins_the_same_rows() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_every_strategy_joins_the_same_rows";
+        let mut table = Table::open(TEST_FILE)?;
+        for sql in [
+            "create table authors (id integer primary key, name text, country text)",
+            "create table books (id integer primary key, author integer, title text)",
+            "create index books_author on books (author)",
+            "insert into authors values (1, 'ann', 'fi'), (2, 'bo', 'se'), (3, 'cy', 'fi')",
+            "insert into books values (10, 2, 'x'), (11, 1, 'bo'), (12, 2, 'z'), (13, null, 'w')",
+        ] {
+            Statement::prepare(sql)?.execute(&mut table);
+        }
+        let authors = RowSource::Table(table.schema.table("authors").unwrap().clone());
+        let books = RowSource::Table(table.schema.table("books").unwrap().clone());
+        let books_author = table.schema.index("books_author").unwrap().root_page_num;
+        let join = |table: &str, on: &str| -> Result<Join, Box<dyn Error>> {
+            Ok(Join {
+                kind: JoinKind::Left,
+                table: String::from(table),
+                args: None,
+                alias: None,
+                on: Some(Parser::new(on)?.parse_expr()?),
+            })
+        };
+        let plan = |table: &Table, outer: &RowSource, inner: &RowSource, join: &Join| {
+            let outer = outer.def();
+            JoinStrategy::plan(table, &qualified_def(outer, &outer.name), inner, join)
+        };
+
+        // books joined with their authors, through every strategy that can do it
+        let by_id = join("authors", "books.author = authors.id")?;
+        assert_eq!(
+            plan(&table, &books, &authors, &by_id),
+            JoinStrategy::PrimaryKey { outer: 1 }
+        );
+        assert_eq!(
+            plan(&table, &books, &authors, &join("authors", "title = name")?),
+            JoinStrategy::Hash { outer: 2, inner: 1 }
+        );
+        assert_eq!(
+            plan(&table, &books, &authors, &join("authors", "title < name")?),
+            JoinStrategy::NestedLoop
+        );
+        // the same rows kept as a temporary table, which has no key to seek
+        let temp_authors = RowSource::Temp(Rc::new(TempTable {
+            def: authors.def().clone(),
+            rows: authors
+                .rows(&mut table, &EmptyScope)
+                .map_err(|e| format!("{:?}", e))?,
+        }));
+        assert_eq!(
+            plan(&table, &books, &temp_authors, &by_id),
+            JoinStrategy::Hash { outer: 1, inner: 0 }
+        );
+        let outer = books
+            .rows(&mut table, &EmptyScope)
+            .map_err(|e| format!("{:?}", e))?;
+        for (inner, strategy) in [
+            (&authors, JoinStrategy::NestedLoop),
+            (&authors, JoinStrategy::PrimaryKey { outer: 1 }),
+            (&authors, JoinStrategy::Hash { outer: 1, inner: 0 }),
+            (&temp_authors, JoinStrategy::NestedLoop),
+            (&temp_authors, JoinStrategy::Hash { outer: 1, inner: 0 }),
+        ] {
+            let (def, rows) = join_rows(
+                &mut table,
+                &qualified_def(books.def(), "books"),
+                outer.clone(),
+                inner,
+                &by_id,
+                strategy,
+            )
+            .map_err(|e| format!("{:?}", e))?;
+            let pairs: Vec<(&Value, &Value)> = rows
+                .iter()
+                .map(|row| (&row.values[0], &row.values[4]))
+                .collect();
+            let name = |name: &str| Value::Text(name.into());
+            assert_eq!(
+                pairs,
+                vec![
+                    (&Value::Integer(10), &name("bo")),
+                    (&Value::Integer(11), &name("ann")),
+                    (&Value::Integer(12), &name("bo")),
+                    (&Value::Integer(13), &Value::Null),
+                ],
+                "{:?}",
+                strategy
+            );
+            assert_eq!(def.column_index("authors.name"), Some(4));
+            assert_eq!(def.column_index("name"), Some(4));
+            assert_eq!(def.column_index("id"), None);
+            assert!(def.is_ambiguous("id"));
+        }
+
+        // authors joined with their books, which are found through the index
+        let by_author = Join {
+            kind: JoinKind::Inner,
+            ..join("books", "authors.id = books.author")?
+        };
+        let index = JoinStrategy::Index {
+            outer: 0,
+            inner: 1,
+            root_page_num: books_author,
+        };
+        assert_eq!(plan(&table, &authors, &books, &by_author), index);
+        let outer = authors
+            .rows(&mut table, &EmptyScope)
+            .map_err(|e| format!("{:?}", e))?;
+        for strategy in [
+            JoinStrategy::NestedLoop,
+            index,
+            JoinStrategy::Hash { outer: 0, inner: 1 },
+        ] {
+            let (_, rows) = join_rows(
+                &mut table,
+                &qualified_def(authors.def(), "authors"),
+                outer.clone(),
+                &books,
+                &by_author,
+                strategy,
+            )
+            .map_err(|e| format!("{:?}", e))?;
+            let pairs: Vec<(&Value, &Value)> = rows
+                .iter()
+                .map(|row| (&row.values[0], &row.values[3]))
+                .collect();
+            assert_eq!(
+                pairs,
+                vec![
+                    (&Value::Integer(1), &Value::Integer(11)),
+                    (&Value::Integer(2), &Value::Integer(10)),
+                    (&Value::Integer(2), &Value::Integer(12)),
+                ],
+                "{:?}",
+                strategy
+            );
+        }
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+}
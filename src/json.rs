@@ -0,0 +1,775 @@
+use super::expression::{EvalError, Expr};
+use super::value::{DataType, Value};
+use std::fmt;
+
+/// functions reading JSON stored as text. paths look like `$.tags[0]`.
+const FUNCTIONS: [&str; 6] = [
+    "json",
+    "json_array_length",
+    "json_extract",
+    "json_set",
+    "json_type",
+    "json_valid",
+];
+
+/// columns of the rows of `json_each`
+pub const EACH_COLUMNS: [&str; 6] = ["key", "value", "type", "atom", "fullkey", "path"];
+
+/// containers nested deeper than this are not read
+const MAX_DEPTH: usize = 1000;
+
+pub fn is_json_function(name: &str) -> bool {
+    FUNCTIONS.contains(&name)
+}
+
+/// checks the number of arguments passed to the JSON function
+pub fn check_arguments(name: &str, num_args: usize) -> Result<(), String> {
+    let valid = match name {
+        "json" | "json_valid" => num_args == 1,
+        "json_array_length" | "json_type" | "json_each" => num_args == 1 || num_args == 2,
+        "json_extract" => num_args >= 2,
+        // json_set(<json>, <path>, <value> [, <path>, <value> ...])
+        "json_set" => num_args >= 3 && num_args % 2 == 1,
+        _ => false,
+    };
+
+    if !valid {
+        return Err(format!("wrong number of arguments to function {}()", name));
+    }
+    Ok(())
+}
+
+/// returns the type the JSON function produces
+pub fn return_type(name: &str) -> Option<DataType> {
+    match name {
+        "json_array_length" | "json_valid" => Some(DataType::Integer),
+        "json" | "json_set" | "json_type" => Some(DataType::Text),
+        _ => None,
+    }
+}
+
+/// returns if the text is well-formed JSON
+pub fn is_valid(text: &str) -> bool {
+    Json::parse(text).is_ok()
+}
+
+/// calls the JSON function with already evaluated arguments.
+/// NULL for the JSON or a path gives NULL.
+pub fn call(name: &str, args: &[Value]) -> Result<Value, EvalError> {
+    call_with(name, args, &[])
+}
+
+/// calls the JSON function with the arguments evaluated from `exprs`, which tell
+/// json_set which of its values were made by JSON functions
+pub fn call_with(name: &str, args: &[Value], exprs: &[Expr]) -> Result<Value, EvalError> {
+    let text = match args[0].as_text() {
+        Some(text) => text,
+        None => return Ok(Value::Null),
+    };
+    if name == "json_valid" {
+        return Ok(Value::Integer(is_valid(&text) as i64));
+    }
+    let mut json = Json::parse(&text)?;
+    let mut paths = Vec::new();
+    for arg in args
+        .iter()
+        .skip(1)
+        .step_by(if name == "json_set" { 2 } else { 1 })
+    {
+        match arg.as_text() {
+            Some(path) => paths.push(Path::parse(&path)?),
+            None => return Ok(Value::Null),
+        }
+    }
+
+    Ok(match name {
+        "json" => Value::Text(json.to_string()),
+        "json_array_length" => match json.find(paths.first().map_or(&[], |path| &path.0)) {
+            Some(Json::Array(items)) => Value::Integer(items.len() as i64),
+            Some(_) => Value::Integer(0),
+            None => Value::Null,
+        },
+        "json_extract" if paths.len() == 1 => match json.find(&paths[0].0) {
+            Some(found) => found.to_value(),
+            None => Value::Null,
+        },
+        // several paths give an array of what each of them found
+        "json_extract" => {
+            let found = paths
+                .iter()
+                .map(|path| json.find(&path.0).cloned().unwrap_or(Json::Null))
+                .collect();
+            Value::Text(Json::Array(found).to_string())
+        }
+        "json_set" => {
+            for (i, path) in paths.iter().enumerate() {
+                let value = 2 * i + 2;
+                json.set(&path.0, Json::from_arg(&args[value], exprs.get(value)));
+            }
+            Value::Text(json.to_string())
+        }
+        "json_type" => match json.find(paths.first().map_or(&[], |path| &path.0)) {
+            Some(found) => Value::Text(found.type_name().into()),
+            None => Value::Null,
+        },
+        _ => return Err(format!("no such function: {}", name)),
+    })
+}
+
+/// returns the rows of `json_each(<json> [, <path>])`: one for each element of
+/// the array or each member of the object at the path, or a single row for
+/// any other value. the columns are those of `EACH_COLUMNS`.
+pub fn each(args: &[Value]) -> Result<Vec<Vec<Value>>, EvalError> {
+    let text = match args[0].as_text() {
+        Some(text) => text,
+        None => return Ok(Vec::new()),
+    };
+    let path = match args.get(1) {
+        Some(path) => match path.as_text() {
+            Some(path) => path,
+            None => return Ok(Vec::new()),
+        },
+        None => String::from("$"),
+    };
+    let json = Json::parse(&text)?;
+    let found = match json.find(&Path::parse(&path)?.0) {
+        Some(found) => found,
+        None => return Ok(Vec::new()),
+    };
+
+    let row = |key: Value, fullkey: String, value: &Json| -> Vec<Value> {
+        let atom = match value {
+            Json::Array(_) | Json::Object(_) => Value::Null,
+            _ => value.to_value(),
+        };
+        vec![
+            key,
+            value.to_value(),
+            Value::Text(value.type_name().into()),
+            atom,
+            Value::Text(fullkey),
+            Value::Text(path.clone()),
+        ]
+    };
+    Ok(match found {
+        Json::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let step = Step::Index(i);
+                row(Value::Integer(i as i64), format!("{}{}", path, step), item)
+            })
+            .collect(),
+        Json::Object(members) => members
+            .iter()
+            .map(|(key, member)| {
+                let step = Step::Key(key.clone());
+                row(
+                    Value::Text(key.clone()),
+                    format!("{}{}", path, step),
+                    member,
+                )
+            })
+            .collect(),
+        value => vec![row(Value::Null, path.clone(), value)],
+    })
+}
+
+/// A JSON value. members of an object keep their order.
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Real(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Self, EvalError> {
+        let mut reader = Reader {
+            input: text.as_bytes(),
+            pos: 0,
+        };
+        let json = reader.value(0);
+        reader.skip_whitespace();
+        match json {
+            Some(json) if reader.pos == reader.input.len() => Ok(json),
+            _ => Err(String::from("malformed JSON")),
+        }
+    }
+
+    /// converts a SQL value, storing text as a JSON string
+    fn from_value(value: &Value) -> Self {
+        match value {
+            Value::Null => Json::Null,
+            Value::Integer(i) => Json::Integer(*i),
+            Value::Real(f) => Json::Real(*f),
+            Value::Text(s) => Json::String(s.clone()),
+        }
+    }
+
+    /// converts a value passed to json_set. the text made by `json` and `json_set`
+    /// is JSON, and so is the text of arrays and objects found by `json_extract`.
+    /// any other value is converted like `from_value`.
+    fn from_arg(value: &Value, expr: Option<&Expr>) -> Self {
+        if let (Value::Text(text), Some(Expr::Function { name, .. })) = (value, expr) {
+            let parsed = match Json::parse(text) {
+                Ok(json) if name == "json" || name == "json_set" => Some(json),
+                Ok(json @ Json::Array(_)) | Ok(json @ Json::Object(_))
+                    if name == "json_extract" =>
+                {
+                    Some(json)
+                }
+                _ => None,
+            };
+            if let Some(json) = parsed {
+                return json;
+            }
+        }
+        Json::from_value(value)
+    }
+
+    /// converts to a SQL value. `true` and `false` become 1 and 0, and
+    /// arrays and objects become their JSON text.
+    fn to_value(&self) -> Value {
+        match self {
+            Json::Null => Value::Null,
+            Json::Bool(b) => Value::Integer(*b as i64),
+            Json::Integer(i) => Value::Integer(*i),
+            Json::Real(f) => Value::Real(*f),
+            Json::String(s) => Value::Text(s.clone()),
+            Json::Array(_) | Json::Object(_) => Value::Text(self.to_string()),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(true) => "true",
+            Json::Bool(false) => "false",
+            Json::Integer(_) => "integer",
+            Json::Real(_) => "real",
+            Json::String(_) => "text",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    /// returns the value at the path, if there is one
+    fn find(&self, steps: &[Step]) -> Option<&Json> {
+        let (step, rest) = match steps.split_first() {
+            Some(split) => split,
+            None => return Some(self),
+        };
+        let child = match (step, self) {
+            (Step::Key(key), Json::Object(members)) => {
+                members.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            (step, Json::Array(items)) => items.get(step.index(items.len())?),
+            _ => None,
+        };
+        child?.find(rest)
+    }
+
+    /// replaces the value at the path, or adds it when only its last step is
+    /// missing. does nothing when the path leads through a value that is not
+    /// an array or object.
+    fn set(&mut self, steps: &[Step], value: Json) {
+        let (step, rest) = match steps.split_first() {
+            Some(split) => split,
+            None => {
+                *self = value;
+                return;
+            }
+        };
+        match (step, self) {
+            (Step::Key(key), Json::Object(members)) => {
+                match members.iter_mut().find(|(k, _)| k == key) {
+                    Some((_, member)) => member.set(rest, value),
+                    None if rest.is_empty() => members.push((key.clone(), value)),
+                    None => {}
+                }
+            }
+            (step, Json::Array(items)) => match step.index(items.len()) {
+                Some(i) if i < items.len() => items[i].set(rest, value),
+                // one past the last element appends to the array
+                Some(i) if i == items.len() && rest.is_empty() => items.push(value),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    /// writes the value without any whitespace
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Integer(i) => write!(f, "{}", i),
+            // keeps a fraction or exponent, so the number reads back as real
+            Json::Real(r) if r.is_finite() => write!(f, "{:?}", r),
+            Json::Real(r) if r.is_nan() => write!(f, "null"),
+            Json::Real(r) => write!(f, "{}9e999", if *r < 0.0 { "-" } else { "" }),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, member)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", member)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Reads a JSON value from the text, returning `None` when it is malformed
+struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(|b| b" \t\n\r".contains(b))
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn consume(&mut self, literal: &str) -> bool {
+        if self.input[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            return true;
+        }
+        false
+    }
+
+    fn value(&mut self, depth: usize) -> Option<Json> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        self.skip_whitespace();
+        match *self.input.get(self.pos)? {
+            b'{' => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.consume("}") {
+                    return Some(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if !self.consume(":") {
+                        return None;
+                    }
+                    members.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    if self.consume("}") {
+                        return Some(Json::Object(members));
+                    }
+                    if !self.consume(",") {
+                        return None;
+                    }
+                }
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.consume("]") {
+                    return Some(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    if self.consume("]") {
+                        return Some(Json::Array(items));
+                    }
+                    if !self.consume(",") {
+                        return None;
+                    }
+                }
+            }
+            b'"' => self.string().map(Json::String),
+            _ if self.consume("null") => Some(Json::Null),
+            _ if self.consume("true") => Some(Json::Bool(true)),
+            _ if self.consume("false") => Some(Json::Bool(false)),
+            _ => self.number(),
+        }
+    }
+
+    /// reads `-?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?`
+    fn number(&mut self) -> Option<Json> {
+        let start = self.pos;
+        let digits = |reader: &mut Self| -> usize {
+            let from = reader.pos;
+            while reader.input.get(reader.pos).is_some_and(u8::is_ascii_digit) {
+                reader.pos += 1;
+            }
+            reader.pos - from
+        };
+        self.consume("-");
+        let int_start = self.pos;
+        let int_digits = digits(self);
+        if int_digits == 0 || (int_digits > 1 && self.input[int_start] == b'0') {
+            return None;
+        }
+        let mut real = false;
+        if self.consume(".") {
+            real = true;
+            if digits(self) == 0 {
+                return None;
+            }
+        }
+        if self.consume("e") || self.consume("E") {
+            real = true;
+            if !self.consume("+") {
+                self.consume("-");
+            }
+            if digits(self) == 0 {
+                return None;
+            }
+        }
+
+        let text = std::str::from_utf8(&self.input[start..self.pos]).ok()?;
+        match text.parse::<i64>() {
+            Ok(i) if !real => Some(Json::Integer(i)),
+            // integers too big for 64 bits are read as real
+            _ => text.parse::<f64>().ok().map(Json::Real),
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if !self.consume("\"") {
+            return None;
+        }
+        let mut bytes = Vec::new();
+        loop {
+            let b = *self.input.get(self.pos)?;
+            self.pos += 1;
+            match b {
+                b'"' => return String::from_utf8(bytes).ok(),
+                b'\\' => {
+                    let escaped = *self.input.get(self.pos)?;
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.escaped_char()?,
+                        _ => return None,
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                b if b < 0x20 => return None,
+                b => bytes.push(b),
+            }
+        }
+    }
+
+    /// reads the hex digits following `\u`, and the low half of a surrogate pair
+    fn escaped_char(&mut self) -> Option<char> {
+        let hex = |reader: &mut Self| -> Option<u32> {
+            let digits = reader.input.get(reader.pos..reader.pos + 4)?;
+            reader.pos += 4;
+            u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+        };
+        let high = hex(self)?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high);
+        }
+        if !self.consume("\\u") {
+            return None;
+        }
+        let low = hex(self)?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return None;
+        }
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+    }
+}
+
+/// Step of a path into a JSON value
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    /// `.<key>` or `."<key>"`, a member of an object
+    Key(String),
+    /// `[<n>]`, an element of an array
+    Index(usize),
+    /// `[#-<n>]`, the n-th element from the end of an array.
+    /// `[#]` is one past the last element.
+    FromEnd(usize),
+}
+
+impl Step {
+    /// returns the index the step names in an array of `len` elements, which
+    /// may be past its end
+    fn index(&self, len: usize) -> Option<usize> {
+        match self {
+            Step::Key(_) => None,
+            Step::Index(i) => Some(*i),
+            Step::FromEnd(n) => len.checked_sub(*n),
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Key(key) if key.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+                write!(f, ".{}", key)
+            }
+            Step::Key(key) => write!(f, ".\"{}\"", key),
+            Step::Index(i) => write!(f, "[{}]", i),
+            Step::FromEnd(n) => write!(f, "[#-{}]", n),
+        }
+    }
+}
+
+/// A path into a JSON value, `$` followed by steps
+struct Path(Vec<Step>);
+
+impl Path {
+    fn parse(text: &str) -> Result<Self, EvalError> {
+        let error = || format!("bad JSON path: '{}'", text);
+        let mut rest = text.strip_prefix('$').ok_or_else(error)?;
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix(".\"") {
+                let end = after.find('"').ok_or_else(error)?;
+                steps.push(Step::Key(after[..end].into()));
+                rest = &after[end + 1..];
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                if end == 0 {
+                    return Err(error());
+                }
+                steps.push(Step::Key(after[..end].into()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(error)?;
+                let index = &after[..end];
+                steps.push(match index.strip_prefix('#') {
+                    Some("") => Step::FromEnd(0),
+                    Some(n) => Step::FromEnd(
+                        n.strip_prefix('-')
+                            .and_then(|n| n.parse().ok())
+                            .ok_or_else(error)?,
+                    ),
+                    None => Step::Index(index.parse().map_err(|_| error())?),
+                });
+                rest = &after[end + 1..];
+            } else {
+                return Err(error());
+            }
+        }
+        Ok(Path(steps))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.into())
+    }
+
+    fn call(name: &str, args: &[Value]) -> Result<Value, EvalError> {
+        super::call(name, args)
+    }
+
+    #[test]
+    fn test_parse_and_write_json() {
+        let json =
+            Json::parse(r#" {"a": [1, 2.5, -3e2, true, null], "b\n": "\u00e9\ud83d\ude00"} "#);
+        assert_eq!(
+            json.unwrap().to_string(),
+            r#"{"a":[1,2.5,-300.0,true,null],"b\n":"é😀"}"#
+        );
+        for malformed in [
+            "",
+            "[1,]",
+            "{\"a\" 1}",
+            "01",
+            "1.",
+            "\"\\x\"",
+            "[1] 2",
+            "tru",
+        ] {
+            assert!(!is_valid(malformed), "{}", malformed);
+        }
+        assert!(!is_valid(&"[".repeat(MAX_DEPTH + 2)));
+        assert_eq!(
+            call("json", &[text("[1, 2")]),
+            Err(String::from("malformed JSON"))
+        );
+        assert_eq!(
+            call("json_valid", &[Value::Integer(1)]),
+            Ok(Value::Integer(1))
+        );
+    }
+
+    #[test]
+    fn test_extract_and_set() {
+        let doc = text(r#"{"tags": ["a", "b", "c"], "owner": {"name": "ann", "age": 41}}"#);
+        let extract = |path: &str| call("json_extract", &[doc.clone(), text(path)]);
+        assert_eq!(extract("$.owner.name"), Ok(text("ann")));
+        assert_eq!(extract("$.tags[#-1]"), Ok(text("c")));
+        assert_eq!(extract("$.tags"), Ok(text(r#"["a","b","c"]"#)));
+        assert_eq!(extract("$.missing"), Ok(Value::Null));
+        assert_eq!(
+            extract("owner"),
+            Err(String::from("bad JSON path: 'owner'"))
+        );
+        assert_eq!(
+            call(
+                "json_extract",
+                &[doc.clone(), text("$.owner.age"), text("$.tags[9]")]
+            ),
+            Ok(text("[41,null]"))
+        );
+        assert_eq!(
+            call("json_array_length", &[doc.clone(), text("$.tags")]),
+            Ok(Value::Integer(3))
+        );
+        assert_eq!(
+            call("json_array_length", std::slice::from_ref(&doc)),
+            Ok(Value::Integer(0))
+        );
+        assert_eq!(
+            call("json_type", &[doc.clone(), text("$.owner")]),
+            Ok(text("object"))
+        );
+
+        assert_eq!(
+            call(
+                "json_set",
+                &[
+                    doc.clone(),
+                    text("$.owner.age"),
+                    Value::Integer(42),
+                    text("$.tags[#]"),
+                    text("d"),
+                    text("$.\"new key\""),
+                    Value::Null,
+                    text("$.nothing.here"),
+                    Value::Integer(1),
+                ]
+            ),
+            Ok(text(
+                r#"{"tags":["a","b","c","d"],"owner":{"name":"ann","age":42},"new key":null}"#
+            ))
+        );
+        assert_eq!(
+            call("json_set", &[Value::Null, text("$.a"), Value::Integer(1)]),
+            Ok(Value::Null)
+        );
+    }
+
+    #[test]
+    fn test_set_values_made_by_json_functions() {
+        let made_by = |name: &str| Expr::Function {
+            name: name.into(),
+            args: Vec::new(),
+            distinct: false,
+        };
+        let set = |value: &str, expr: Expr| {
+            let args = [text(r#"{"a":1}"#), text("$.a"), text(value)];
+            let exprs = [made_by("json"), made_by("json"), expr];
+            call_with("json_set", &args, &exprs)
+        };
+        assert_eq!(set("[1]", made_by("json")), Ok(text(r#"{"a":[1]}"#)));
+        assert_eq!(
+            set(r#"{"b":2}"#, made_by("json_set")),
+            Ok(text(r#"{"a":{"b":2}}"#))
+        );
+        assert_eq!(
+            set("[1]", made_by("json_extract")),
+            Ok(text(r#"{"a":[1]}"#))
+        );
+        // json_extract gives strings as plain text, and other functions give text
+        assert_eq!(set("1", made_by("json_extract")), Ok(text(r#"{"a":"1"}"#)));
+        assert_eq!(set("[1]", made_by("lower")), Ok(text(r#"{"a":"[1]"}"#)));
+        assert_eq!(
+            set("[1]", Expr::Literal(text("[1]"))),
+            Ok(text(r#"{"a":"[1]"}"#))
+        );
+    }
+
+    #[test]
+    fn test_each() {
+        let rows = each(&[text(r#"{"a": 1, "b": [2, 3]}"#)]).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    text("a"),
+                    Value::Integer(1),
+                    text("integer"),
+                    Value::Integer(1),
+                    text("$.a"),
+                    text("$")
+                ],
+                vec![
+                    text("b"),
+                    text("[2,3]"),
+                    text("array"),
+                    Value::Null,
+                    text("$.b"),
+                    text("$")
+                ],
+            ]
+        );
+        let rows = each(&[text(r#"{"a": 1, "b": [2, 3]}"#), text("$.b")]).unwrap();
+        assert_eq!(rows[1][0], Value::Integer(1));
+        assert_eq!(rows[1][4], text("$.b[1]"));
+        assert_eq!(each(&[text("7")]).unwrap()[0][0], Value::Null);
+        assert!(each(&[text("[]")]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+use super::value::Value;
+use std::convert::TryInto;
+
+// KEY ENCODING TAGS
+// integers sort before text, as they do in `Value::compare`
+const TAG_INTEGER: u8 = 1;
+const TAG_TEXT: u8 = 2;
+
+/// Byte strings longer than this can not be used as a key
+pub const MAX_KEY_SIZE: usize = 256;
+
+/// Key of a B-tree cell. Keys are encoded so that comparing them byte by byte
+/// orders them like the values they were made from.
+pub type Key = Vec<u8>;
+
+/// returns if the value can be part of a key
+pub fn is_key_value(value: &Value) -> bool {
+    matches!(value, Value::Integer(_) | Value::Text(_))
+}
+
+/// encodes the values of a primary key, in column order.
+/// integers are stored big-endian with the sign bit flipped, and text is
+/// terminated by `00 00` with every `00` byte inside it escaped as `00 ff`,
+/// so a shorter text sorts before any text it is a prefix of.
+pub fn encode_key(values: &[Value]) -> Key {
+    let mut key = Vec::new();
+    for value in values.iter() {
+        match value {
+            Value::Integer(i) => {
+                key.push(TAG_INTEGER);
+                key.extend_from_slice(&((*i as u64) ^ (1 << 63)).to_be_bytes());
+            }
+            Value::Text(s) => {
+                key.push(TAG_TEXT);
+                for byte in s.bytes() {
+                    key.push(byte);
+                    if byte == 0 {
+                        key.push(0xff);
+                    }
+                }
+                key.extend_from_slice(&[0, 0]);
+            }
+            value => panic!("invalid primary key {}", value),
+        }
+    }
+    key
+}
+
+/// returns the values the key was encoded from
+pub fn decode_key(key: &[u8]) -> Result<Vec<Value>, String> {
+    let mut values = Vec::new();
+    let mut offset = 0;
+    while offset < key.len() {
+        offset += 1;
+        match key[offset - 1] {
+            TAG_INTEGER => {
+                let raw = key
+                    .get(offset..offset + 8)
+                    .ok_or_else(|| String::from("key is truncated"))?;
+                offset += 8;
+                let i = u64::from_be_bytes(raw.try_into().unwrap()) ^ (1 << 63);
+                values.push(Value::Integer(i as i64));
+            }
+            TAG_TEXT => {
+                let mut bytes = Vec::new();
+                loop {
+                    match (key.get(offset), key.get(offset + 1)) {
+                        (Some(0), Some(0)) => break,
+                        (Some(0), Some(0xff)) => {
+                            bytes.push(0);
+                            offset += 2;
+                        }
+                        (Some(0), _) | (None, _) => return Err(String::from("key is truncated")),
+                        (Some(byte), _) => {
+                            bytes.push(*byte);
+                            offset += 1;
+                        }
+                    }
+                }
+                offset += 2;
+                let text =
+                    String::from_utf8(bytes).map_err(|e| format!("invalid UTF-8 in key: {}", e))?;
+                values.push(Value::Text(text));
+            }
+            tag => return Err(format!("unknown key tag {}", tag)),
+        }
+    }
+    Ok(values)
+}
+
+/// returns the key as the comma separated values it was encoded from
+pub fn display_key(key: &[u8]) -> String {
+    match decode_key(key) {
+        Ok(values) => values
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<String>>()
+            .join(", "),
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_sort_like_their_values() {
+        let keys = vec![
+            vec![Value::Integer(i64::MIN)],
+            vec![Value::Integer(-1)],
+            vec![Value::Integer(0)],
+            vec![Value::Integer(1), Value::Text("b".into())],
+            vec![Value::Integer(1), Value::Text("b\0".into())],
+            vec![Value::Integer(1), Value::Text("ba".into())],
+            vec![Value::Integer(256)],
+            vec![Value::Integer(i64::MAX)],
+            vec![Value::Text("".into())],
+            vec![Value::Text("a".into()), Value::Integer(2)],
+            vec![Value::Text("a\0b".into()), Value::Integer(1)],
+            vec![Value::Text("田中".into())],
+        ];
+        let encoded: Vec<Key> = keys.iter().map(|values| encode_key(values)).collect();
+
+        for (pair, values) in encoded.windows(2).zip(keys.iter()) {
+            assert!(pair[0] < pair[1], "{:?}", values);
+        }
+        for (key, values) in encoded.iter().zip(keys.iter()) {
+            assert_eq!(&decode_key(key).unwrap(), values);
+        }
+        assert_eq!(display_key(&encoded[3]), "1, b");
+        assert!(decode_key(&encoded[3][..12]).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Symbol(&'static str),
+    /// `?`, `?<n>` or `:<name>`, spelled as written
+    Parameter(String),
+}
+
+pub type LexError = String;
+
+const SYMBOLS: [&str; 19] = [
+    "<=", ">=", "<>", "!=", "==", "||", "=", "<", ">", "+", "-", "*", "/", "%", "(", ")", ",", ".",
+    ";",
+];
+
+impl Token {
+    /// returns if the token is the given keyword, ignoring case
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        match self {
+            Token::Ident(s) => s.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+}
+
+fn lex_number(chars: &mut Peekable<Chars>) -> Result<Token, LexError> {
+    let mut raw = String::new();
+    let mut is_real = false;
+
+    while let Some(&c) = chars.peek() {
+        if c.is_ascii_digit() {
+            raw.push(c);
+        } else if c == '.' && !is_real {
+            is_real = true;
+            raw.push(c);
+        } else if (c == 'e' || c == 'E') && !raw.is_empty() {
+            is_real = true;
+            raw.push(c);
+            chars.next();
+            if let Some(&sign) = chars.peek() {
+                if sign == '+' || sign == '-' {
+                    raw.push(sign);
+                    chars.next();
+                }
+            }
+            continue;
+        } else {
+            break;
+        }
+        chars.next();
+    }
+
+    if is_real {
+        raw.parse::<f64>()
+            .map(Token::Real)
+            .map_err(|_| format!("malformed number `{}`", raw))
+    } else {
+        match raw.parse::<i64>() {
+            Ok(i) => Ok(Token::Integer(i)),
+            // integers too large for 64 bits are kept as reals like sqlite does
+            Err(_) => raw
+                .parse::<f64>()
+                .map(Token::Real)
+                .map_err(|_| format!("malformed number `{}`", raw)),
+        }
+    }
+}
+
+/// reads text surrounded by `quote`. a doubled quote stands for the quote itself.
+fn lex_quoted(chars: &mut Peekable<Chars>, quote: char) -> Result<String, LexError> {
+    chars.next();
+    let mut text = String::new();
+
+    loop {
+        match chars.next() {
+            Some(c) if c == quote => {
+                if chars.peek() == Some(&quote) {
+                    chars.next();
+                    text.push(quote);
+                } else {
+                    return Ok(text);
+                }
+            }
+            Some(c) => text.push(c),
+            None => return Err(format!("unterminated string `{}{}`", quote, text)),
+        }
+    }
+}
+
+/// splits sql text into tokens
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    'outer: while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            tokens.push(lex_number(&mut chars)?);
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                ident.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Ident(ident));
+            continue;
+        }
+
+        match c {
+            '\'' => {
+                tokens.push(Token::Text(lex_quoted(&mut chars, '\'')?));
+                continue;
+            }
+            '"' | '`' => {
+                tokens.push(Token::Ident(lex_quoted(&mut chars, c)?));
+                continue;
+            }
+            '?' | ':' => {
+                let mut parameter = String::from(c);
+                chars.next();
+                while let Some(&c) = chars.peek() {
+                    let valid = if parameter.starts_with('?') {
+                        c.is_ascii_digit()
+                    } else {
+                        c.is_alphanumeric() || c == '_'
+                    };
+                    if !valid {
+                        break;
+                    }
+                    parameter.push(c);
+                    chars.next();
+                }
+                if parameter == ":" {
+                    return Err(String::from("unexpected character `:`"));
+                }
+                tokens.push(Token::Parameter(parameter));
+                continue;
+            }
+            _ => {}
+        }
+
+        let rest: String = chars.clone().take(2).collect();
+        for symbol in SYMBOLS.iter() {
+            if rest.starts_with(symbol) {
+                for _ in 0..symbol.len() {
+                    chars.next();
+                }
+                tokens.push(Token::Symbol(symbol));
+                continue 'outer;
+            }
+        }
+
+        return Err(format!("unexpected character `{}`", c));
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_where_clause() {
+        let tokens = tokenize("select where id >= 10 and name <> 'it''s'").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("select".into()),
+                Token::Ident("where".into()),
+                Token::Ident("id".into()),
+                Token::Symbol(">="),
+                Token::Integer(10),
+                Token::Ident("and".into()),
+                Token::Ident("name".into()),
+                Token::Symbol("<>"),
+                Token::Text("it's".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_numbers() {
+        let tokens = tokenize("1 2.5 3e2 .").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Integer(1),
+                Token::Real(2.5),
+                Token::Real(300.0),
+                Token::Symbol("."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_parameters() {
+        let tokens = tokenize("? ?12 :user_id").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Parameter("?".into()),
+                Token::Parameter("?12".into()),
+                Token::Parameter(":user_id".into()),
+            ]
+        );
+        assert!(tokenize("select : x").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_unterminated_string() {
+        assert!(tokenize("select where name = 'abc").is_err());
+    }
+}
@@ -1,8 +1,25 @@
+pub mod aggregate;
 pub mod btree;
 pub mod cli;
+pub mod connection;
 pub mod cursor;
+pub mod datetime;
+pub mod expression;
+pub mod function;
+pub mod join;
+pub mod json;
+pub mod key;
+pub mod lexer;
 pub mod meta_command;
 pub mod pager;
+pub mod parser;
+pub mod result_set;
 pub mod row;
+pub mod schema;
+pub mod sorter;
+pub mod source;
 pub mod statement;
 pub mod table;
+pub mod value;
+pub mod vtab;
+pub mod window;
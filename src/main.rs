@@ -1,12 +1,4 @@
-mod btree;
-mod cli;
-mod cursor;
-mod meta_command;
-mod pager;
-mod row;
-mod statement;
-mod table;
-
+use db_tutorial::cli;
 use std::env::args;
 
 fn main() {
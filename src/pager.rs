@@ -1,12 +1,59 @@
-use super::btree::{LeafNode, PAGE_SIZE};
+use super::btree::{
+    node_type, IndexNode, LeafNode, OverflowPage, Payload, MAX_LOCAL_PAYLOAD, NODE_TYPE_FREE,
+    NODE_TYPE_INDEX, NODE_TYPE_LEAF, NODE_TYPE_OVERFLOW, NODE_TYPE_SCHEMA, OVERFLOW_PAGE_CAPACITY,
+    PAGE_SIZE,
+};
+use super::key::Key;
 use super::row::Row;
+use super::schema::SchemaPage;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::OpenOptions;
 use std::io::{Read, Seek, SeekFrom, Write};
 
+/// In-memory representation of a single page of the database file
+#[derive(PartialEq, Debug, Clone)]
+pub enum Page {
+    Free,
+    Leaf(LeafNode),
+    Overflow(OverflowPage),
+    Index(IndexNode),
+    Schema(SchemaPage),
+}
+
+impl Page {
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            Page::Free => vec![0; PAGE_SIZE],
+            Page::Leaf(node) => node.serialize(),
+            Page::Overflow(page) => page.serialize(),
+            Page::Index(node) => node.serialize(),
+            Page::Schema(page) => page.serialize(),
+        }
+    }
+
+    pub fn deserialize(raw: Vec<u8>) -> Option<Self> {
+        match node_type(&raw) {
+            NODE_TYPE_FREE => Some(Page::Free),
+            NODE_TYPE_LEAF => LeafNode::deserialize(raw).map(Page::Leaf),
+            NODE_TYPE_OVERFLOW => OverflowPage::deserialize(raw).map(Page::Overflow),
+            NODE_TYPE_INDEX => IndexNode::deserialize(raw).map(Page::Index),
+            NODE_TYPE_SCHEMA => SchemaPage::deserialize(raw).map(Page::Schema),
+            _ => None,
+        }
+    }
+}
+
+/// Content of the pages from before the changes of a statement, for undoing them
+struct Journal {
+    num_pages: usize,
+    pages: HashMap<usize, Page>,
+}
+
 pub struct Pager {
     pub file: std::fs::File,
-    pub pages: Vec<LeafNode>,
+    pub pages: Vec<Page>,
+    journal: Option<Journal>,
 }
 
 impl Pager {
@@ -15,10 +62,15 @@ impl Pager {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(filename)?;
 
         let pages = Vec::new();
-        let mut pager = Pager { file, pages };
+        let mut pager = Pager {
+            file,
+            pages,
+            journal: None,
+        };
 
         let file_length = pager.get_file_length();
         let num_pages = file_length as usize / PAGE_SIZE;
@@ -43,23 +95,22 @@ impl Pager {
         self.file
             .seek(SeekFrom::Start((page_num * PAGE_SIZE) as u64))
             .unwrap();
-        let content = &self.pages[page_num].serialize();
-        let _ = self.file.write_all(content);
+        let content = self.pages[page_num].serialize();
+        let _ = self.file.write_all(&content);
     }
 
     pub fn get_file_length(&mut self) -> u64 {
-        let file_length = self.file.seek(SeekFrom::End(0)).unwrap();
-        file_length
+        self.file.seek(SeekFrom::End(0)).unwrap()
     }
 
     pub fn prepare_page(&mut self, page_num: usize) {
         if self.pages.get(page_num).is_some() {
-            return ();
+            return;
         }
 
         let file_length = self.get_file_length() as usize;
         let num_pages_on_file = file_length / PAGE_SIZE;
-        if file_length % PAGE_SIZE != 0 {
+        if !file_length.is_multiple_of(PAGE_SIZE) {
             // this should not happen
             panic!("broken file");
         }
@@ -74,15 +125,155 @@ impl Pager {
         let _ = self.file.seek(SeekFrom::Start(file_offset));
         let _ = self.file.read(&mut buff);
 
-        if let Some(node) = LeafNode::deserialize(buff) {
-            self.pages.push(node);
+        if let Some(page) = Page::deserialize(buff) {
+            self.pages.push(page);
         } else {
             panic!("broken file")
         }
     }
 
+    pub fn get_leaf(&self, page_num: usize) -> Option<&LeafNode> {
+        match self.pages.get(page_num) {
+            Some(Page::Leaf(node)) => Some(node),
+            _ => None,
+        }
+    }
+
+    /// starts recording the pages about to change so `rollback` can restore them
+    pub fn begin(&mut self) {
+        self.journal = Some(Journal {
+            num_pages: self.pages.len(),
+            pages: HashMap::new(),
+        });
+    }
+
+    /// keeps the changes made since `begin`
+    pub fn commit(&mut self) {
+        self.journal = None;
+    }
+
+    /// undoes the changes made since `begin`
+    pub fn rollback(&mut self) {
+        if let Some(journal) = self.journal.take() {
+            self.pages.truncate(journal.num_pages);
+            for (page_num, page) in journal.pages {
+                self.pages[page_num] = page;
+            }
+        }
+    }
+
+    /// records the page before its first change since `begin`.
+    /// pages allocated after `begin` are dropped by `rollback` instead.
+    fn journal_page(&mut self, page_num: usize) {
+        if let Some(journal) = &mut self.journal {
+            if page_num < journal.num_pages && !journal.pages.contains_key(&page_num) {
+                journal.pages.insert(page_num, self.pages[page_num].clone());
+            }
+        }
+    }
+
+    /// replaces the page at `page_num`
+    pub fn set_page(&mut self, page_num: usize, page: Page) {
+        self.journal_page(page_num);
+        self.pages[page_num] = page;
+    }
+
+    pub fn get_leaf_mut(&mut self, page_num: usize) -> Option<&mut LeafNode> {
+        self.journal_page(page_num);
+        match self.pages.get_mut(page_num) {
+            Some(Page::Leaf(node)) => Some(node),
+            _ => None,
+        }
+    }
+
+    pub fn get_index(&self, page_num: usize) -> Option<&IndexNode> {
+        match self.pages.get(page_num) {
+            Some(Page::Index(node)) => Some(node),
+            _ => None,
+        }
+    }
+
+    pub fn get_index_mut(&mut self, page_num: usize) -> Option<&mut IndexNode> {
+        self.journal_page(page_num);
+        match self.pages.get_mut(page_num) {
+            Some(Page::Index(node)) => Some(node),
+            _ => None,
+        }
+    }
+
+    /// stores the given page in the first free slot and returns its page number
+    pub fn allocate_page(&mut self, page: Page) -> usize {
+        if let Some(page_num) = self.pages.iter().position(|p| *p == Page::Free) {
+            self.set_page(page_num, page);
+            return page_num;
+        }
+
+        self.pages.push(page);
+        self.pages.len() - 1
+    }
+
+    /// splits `bytes` into the part kept in the leaf cell and a chain of overflow pages
+    pub fn write_payload(&mut self, bytes: Vec<u8>) -> Payload {
+        let size = bytes.len();
+        if size <= MAX_LOCAL_PAYLOAD {
+            return Payload::new(size, bytes, None);
+        }
+
+        let local = bytes[..MAX_LOCAL_PAYLOAD].to_vec();
+
+        // allocate the chain back to front so each page knows its successor
+        let mut next = None;
+        for chunk in bytes[MAX_LOCAL_PAYLOAD..]
+            .chunks(OVERFLOW_PAGE_CAPACITY)
+            .rev()
+        {
+            let page = OverflowPage::new(next, chunk.to_vec());
+            next = Some(self.allocate_page(Page::Overflow(page)) as u32);
+        }
+
+        Payload::new(size, local, next)
+    }
+
+    /// returns full content of the payload, following its overflow chain.
+    /// a corrupt chain is cut off after `payload.size` bytes or after visiting as
+    /// many pages as there are, so that a cycle can not make it run forever.
+    pub fn read_payload(&self, payload: &Payload) -> Vec<u8> {
+        let mut bytes = payload.local.clone();
+
+        let mut next = payload.overflow;
+        let mut pages_left = self.pages.len();
+        while let Some(page_num) = next {
+            if bytes.len() >= payload.size || pages_left == 0 {
+                break;
+            }
+            pages_left -= 1;
+            match self.pages.get(page_num as usize) {
+                Some(Page::Overflow(page)) => {
+                    bytes.extend_from_slice(&page.data);
+                    next = page.next;
+                }
+                _ => panic!("broken overflow chain"),
+            }
+        }
+
+        bytes
+    }
+
+    /// releases the overflow pages of the payload so they can be reused
+    pub fn free_payload(&mut self, payload: &Payload) {
+        let mut next = payload.overflow;
+        while let Some(page_num) = next {
+            next = match &self.pages[page_num as usize] {
+                Page::Overflow(page) => page.next,
+                _ => panic!("broken overflow chain"),
+            };
+            self.set_page(page_num as usize, Page::Free);
+        }
+    }
+
     pub fn insert_at(
         &mut self,
+        key: Key,
         row: &Row,
         page_num: usize,
         pos: usize,
@@ -90,8 +281,68 @@ impl Pager {
         if self.pages.len() <= page_num {
             panic!("index out of bounds");
         }
-        let node = &mut self.pages[page_num];
-        let _ = node.insert_at(pos, row.id, row.clone());
+        let payload = self.write_payload(row.serialize());
+        let node = self
+            .get_leaf_mut(page_num)
+            .expect("page is not a leaf node");
+        node.insert_at(pos, key, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_payload_spills_into_overflow_pages() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_pager_overflow";
+        {
+            let mut pager = Pager::open(TEST_FILE)?;
+            pager
+                .pages
+                .push(Page::Leaf(LeafNode::new(true, 0, 0, Vec::new())));
+
+            let bytes: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+            let payload = pager.write_payload(bytes.clone());
+
+            assert_eq!(payload.local.len(), MAX_LOCAL_PAYLOAD);
+            assert!(payload.overflow.is_some());
+            assert_eq!(pager.read_payload(&payload), bytes);
+
+            // freed pages come back, and pages allocated since `begin` go away
+            let pages = pager.pages.clone();
+            pager.begin();
+            pager.free_payload(&payload);
+            pager.allocate_page(Page::Free);
+            pager.allocate_page(Page::Free);
+            pager.rollback();
+            assert_eq!(pager.pages, pages);
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cyclic_overflow_chain_ends() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_pager_cyclic_overflow";
+        {
+            let mut pager = Pager::open(TEST_FILE)?;
+            pager
+                .pages
+                .push(Page::Leaf(LeafNode::new(true, 0, 0, Vec::new())));
+            // a corrupt overflow page pointing back to itself
+            pager
+                .pages
+                .push(Page::Overflow(OverflowPage::new(Some(1), vec![7; 4])));
+
+            let payload = Payload::new(usize::MAX, vec![1, 2], Some(1));
+            assert_eq!(
+                pager.read_payload(&payload),
+                vec![1, 2, 7, 7, 7, 7, 7, 7, 7, 7]
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
         Ok(())
     }
 }
@@ -0,0 +1,682 @@
+use super::expression::{BinaryOp, Expr, UnaryOp};
+use super::lexer::{tokenize, Token};
+use super::statement::{Statement, Subquery};
+use super::value::Value;
+
+pub type ParseError = String;
+
+/// Words that can not be used as bare column names
+const RESERVED: [&str; 48] = [
+    "all",
+    "and",
+    "as",
+    "asc",
+    "between",
+    "by",
+    "check",
+    "create",
+    "cross",
+    "default",
+    "delete",
+    "desc",
+    "distinct",
+    "exists",
+    "from",
+    "group",
+    "having",
+    "in",
+    "index",
+    "inner",
+    "insert",
+    "into",
+    "is",
+    "join",
+    "left",
+    "like",
+    "limit",
+    "not",
+    "null",
+    "offset",
+    "on",
+    "or",
+    "order",
+    "outer",
+    "over",
+    "partition",
+    "primary",
+    "recursive",
+    "returning",
+    "select",
+    "set",
+    "table",
+    "union",
+    "unique",
+    "update",
+    "values",
+    "where",
+    "with",
+];
+
+/// largest index a parameter can have
+const MAX_PARAMETERS: usize = 32766;
+
+/// Recursive descent parser over the tokens of a single statement
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// names of the parameters read so far by their index, counted from 1.
+    /// `?` and `?<n>` have no name.
+    parameters: Vec<Option<String>>,
+}
+
+impl Parser {
+    pub fn new(input: &str) -> Result<Self, ParseError> {
+        let mut tokens = tokenize(input)?;
+        // a trailing semicolon is allowed but carries no meaning
+        if tokens.last() == Some(&Token::Symbol(";")) {
+            tokens.pop();
+        }
+
+        Ok(Parser {
+            tokens,
+            pos: 0,
+            parameters: Vec::new(),
+        })
+    }
+
+    /// returns the names of the parameters read so far by their index
+    pub fn parameters(&self) -> &[Option<String>] {
+        &self.parameters
+    }
+
+    /// returns the index of the parameter, counted from 1. `?` takes the index
+    /// following the largest so far, `?<n>` takes n and every `:<name>` of the
+    /// same name shares one.
+    fn parameter_index(&mut self, parameter: &str) -> Result<usize, ParseError> {
+        if parameter == "?" {
+            self.parameters.push(None);
+            return Ok(self.parameters.len());
+        }
+        if let Some(n) = parameter.strip_prefix('?') {
+            let index = n
+                .parse()
+                .ok()
+                .filter(|i| (1..=MAX_PARAMETERS).contains(i))
+                .ok_or_else(|| {
+                    format!("variable number must be between ?1 and ?{}", MAX_PARAMETERS)
+                })?;
+            if self.parameters.len() < index {
+                self.parameters.resize(index, None);
+            }
+            return Ok(index);
+        }
+        let name = Some(parameter.to_string());
+        if let Some(i) = self.parameters.iter().position(|p| *p == name) {
+            return Ok(i + 1);
+        }
+        self.parameters.push(name);
+        Ok(self.parameters.len())
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    /// fails unless every token has been consumed
+    pub fn expect_end(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(format!("unexpected token {}", describe(token))),
+        }
+    }
+
+    pub fn peek_keyword(&self, keyword: &str) -> bool {
+        self.peek().map(|t| t.is_keyword(keyword)).unwrap_or(false)
+    }
+
+    /// consumes the next token if it is the given keyword
+    pub fn consume_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword(keyword) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    pub fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.consume_keyword(keyword) {
+            return Ok(());
+        }
+        Err(format!(
+            "expected `{}` but found {}",
+            keyword,
+            self.describe_next()
+        ))
+    }
+
+    pub fn peek_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    /// consumes the next token if it is the given symbol
+    pub fn consume_symbol(&mut self, symbol: &str) -> bool {
+        if self.peek_symbol(symbol) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    pub fn expect_symbol(&mut self, symbol: &str) -> Result<(), ParseError> {
+        if self.consume_symbol(symbol) {
+            return Ok(());
+        }
+        Err(format!(
+            "expected `{}` but found {}",
+            symbol,
+            self.describe_next()
+        ))
+    }
+
+    /// reads a name that is not a reserved word
+    pub fn expect_ident(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Token::Ident(name)) if !is_reserved(name) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(format!(
+                "expected a name but found {}",
+                self.describe_next()
+            )),
+        }
+    }
+
+    /// reads an integer literal, which may be negative
+    pub fn expect_integer(&mut self) -> Result<i64, ParseError> {
+        let negative = self.consume_symbol("-");
+        match self.peek() {
+            Some(Token::Integer(i)) => {
+                let i = *i;
+                self.pos += 1;
+                Ok(if negative { -i } else { i })
+            }
+            _ => Err(format!(
+                "expected an integer but found {}",
+                self.describe_next()
+            )),
+        }
+    }
+
+    fn describe_next(&self) -> String {
+        self.peek()
+            .map(describe)
+            .unwrap_or_else(|| String::from("end of input"))
+    }
+
+    /// parses `expr [, expr]*`
+    pub fn parse_expr_list(&mut self) -> Result<Vec<Expr>, ParseError> {
+        let mut list = vec![self.parse_expr()?];
+        while self.consume_symbol(",") {
+            list.push(self.parse_expr()?);
+        }
+        Ok(list)
+    }
+
+    pub fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.consume_keyword("or") {
+            left = Expr::binary(BinaryOp::Or, left, self.parse_and()?);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_not()?;
+        while self.consume_keyword("and") {
+            left = Expr::binary(BinaryOp::And, left, self.parse_not()?);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if self.consume_keyword("not") {
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_additive()?;
+
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("=")) | Some(Token::Symbol("==")) => Some(BinaryOp::Eq),
+                Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => Some(BinaryOp::NotEq),
+                Some(Token::Symbol("<")) => Some(BinaryOp::Lt),
+                Some(Token::Symbol("<=")) => Some(BinaryOp::LtEq),
+                Some(Token::Symbol(">")) => Some(BinaryOp::Gt),
+                Some(Token::Symbol(">=")) => Some(BinaryOp::GtEq),
+                _ => None,
+            };
+            if let Some(op) = op {
+                self.pos += 1;
+                left = Expr::binary(op, left, self.parse_additive()?);
+                continue;
+            }
+
+            if self.consume_keyword("is") {
+                let negated = self.consume_keyword("not");
+                self.expect_keyword("null")?;
+                left = Expr::IsNull {
+                    expr: Box::new(left),
+                    negated,
+                };
+                continue;
+            }
+
+            // `not` only continues a comparison when followed by in, between or like
+            let negated = self.peek_keyword("not")
+                && self
+                    .tokens
+                    .get(self.pos + 1)
+                    .map(|t| t.is_keyword("in") || t.is_keyword("between") || t.is_keyword("like"))
+                    .unwrap_or(false);
+            if negated {
+                self.pos += 1;
+            }
+
+            if self.consume_keyword("in") {
+                self.expect_symbol("(")?;
+                if self.peek_keyword("select") || self.peek_keyword("with") {
+                    let query = self.parse_subquery()?;
+                    self.expect_symbol(")")?;
+                    left = Expr::InSubquery {
+                        expr: Box::new(left),
+                        query,
+                        negated,
+                    };
+                    continue;
+                }
+                let list = if self.peek_symbol(")") {
+                    Vec::new()
+                } else {
+                    self.parse_expr_list()?
+                };
+                self.expect_symbol(")")?;
+                left = Expr::InList {
+                    expr: Box::new(left),
+                    list,
+                    negated,
+                };
+            } else if self.consume_keyword("between") {
+                let low = self.parse_additive()?;
+                self.expect_keyword("and")?;
+                let high = self.parse_additive()?;
+                left = Expr::Between {
+                    expr: Box::new(left),
+                    low: Box::new(low),
+                    high: Box::new(high),
+                    negated,
+                };
+            } else if self.consume_keyword("like") {
+                left = Expr::Like {
+                    expr: Box::new(left),
+                    pattern: Box::new(self.parse_additive()?),
+                    negated,
+                };
+            } else {
+                return Ok(left);
+            }
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("+")) => BinaryOp::Add,
+                Some(Token::Symbol("-")) => BinaryOp::Sub,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::binary(op, left, self.parse_multiplicative()?);
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_concat()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("*")) => BinaryOp::Mul,
+                Some(Token::Symbol("/")) => BinaryOp::Div,
+                Some(Token::Symbol("%")) => BinaryOp::Mod,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::binary(op, left, self.parse_concat()?);
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+        while self.consume_symbol("||") {
+            left = Expr::binary(BinaryOp::Concat, left, self.parse_unary()?);
+        }
+        Ok(left)
+    }
+
+    pub fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        if self.consume_symbol("-") {
+            return Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.parse_unary()?)));
+        }
+        if self.consume_symbol("+") {
+            return Ok(Expr::Unary(UnaryOp::Plus, Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        match self.next() {
+            Some(Token::Integer(i)) => Ok(Expr::Literal(Value::Integer(i))),
+            Some(Token::Real(f)) => Ok(Expr::Literal(Value::Real(f))),
+            Some(Token::Text(s)) => Ok(Expr::Literal(Value::Text(s))),
+            Some(Token::Parameter(name)) => Ok(Expr::Parameter {
+                index: self.parameter_index(&name)?,
+                name,
+            }),
+            Some(Token::Symbol("(")) => {
+                let expr = if self.peek_keyword("select") || self.peek_keyword("with") {
+                    Expr::Subquery(self.parse_subquery()?)
+                } else {
+                    self.parse_expr()?
+                };
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) if name.eq_ignore_ascii_case("exists") => {
+                self.expect_symbol("(")?;
+                let query = self.parse_subquery()?;
+                self.expect_symbol(")")?;
+                Ok(Expr::Exists(query))
+            }
+            Some(Token::Ident(name)) if name.eq_ignore_ascii_case("null") => {
+                Ok(Expr::Literal(Value::Null))
+            }
+            Some(Token::Ident(name)) if !is_reserved(&name) => {
+                if self.consume_symbol("(") {
+                    return self.parse_function(name.to_lowercase());
+                }
+                // a column qualified by its table, `<table>.<column>`
+                if self.consume_symbol(".") {
+                    let column = self.expect_ident()?;
+                    return Ok(Expr::Column(format!("{}.{}", name, column).to_lowercase()));
+                }
+                Ok(Expr::Column(name.to_lowercase()))
+            }
+            Some(token) => Err(format!("unexpected token {}", describe(&token))),
+            None => Err(String::from("unexpected end of input")),
+        }
+    }
+
+    /// parses the query of a subquery, up to its closing parenthesis
+    fn parse_subquery(&mut self) -> Result<Box<Subquery>, ParseError> {
+        let start = self.pos;
+        let statement = Statement::parse_query(self)?;
+        Ok(Box::new(Subquery {
+            statement,
+            sql: render(&self.tokens[start..self.pos]),
+        }))
+    }
+
+    /// parses the arguments of `name(...)` following the opening parenthesis.
+    /// `name(*)` is parsed as a call without arguments.
+    fn parse_function(&mut self, name: String) -> Result<Expr, ParseError> {
+        let distinct = self.consume_keyword("distinct");
+        let no_args = (!distinct && self.consume_symbol("*")) || self.peek_symbol(")");
+        let args = if no_args {
+            Vec::new()
+        } else {
+            self.parse_expr_list()?
+        };
+        self.expect_symbol(")")?;
+
+        if self.consume_keyword("over") {
+            if distinct {
+                return Err(String::from(
+                    "DISTINCT is not supported for window functions",
+                ));
+            }
+            return self.parse_window(name, args);
+        }
+        Ok(Expr::Function {
+            name,
+            args,
+            distinct,
+        })
+    }
+
+    /// parses `([partition by <expr>, ...] [order by <expr> [asc|desc], ...])`
+    /// following `over`
+    fn parse_window(&mut self, name: String, args: Vec<Expr>) -> Result<Expr, ParseError> {
+        self.expect_symbol("(")?;
+        let mut partition_by = Vec::new();
+        if self.consume_keyword("partition") {
+            self.expect_keyword("by")?;
+            partition_by = self.parse_expr_list()?;
+        }
+        let mut order_by = Vec::new();
+        if self.consume_keyword("order") {
+            self.expect_keyword("by")?;
+            loop {
+                let expr = self.parse_expr()?;
+                let descending = self.consume_keyword("desc");
+                if !descending {
+                    self.consume_keyword("asc");
+                }
+                order_by.push((expr, descending));
+                if !self.consume_symbol(",") {
+                    break;
+                }
+            }
+        }
+        self.expect_symbol(")")?;
+
+        Ok(Expr::Window {
+            name,
+            args,
+            partition_by,
+            order_by,
+        })
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    RESERVED.iter().any(|r| r.eq_ignore_ascii_case(name))
+}
+
+/// prints the tokens back as sql, spaced the way it is usually written
+fn render(tokens: &[Token]) -> String {
+    let mut sql = String::new();
+    let mut previous: Option<&Token> = None;
+    for token in tokens.iter() {
+        let glued = match (previous, token) {
+            (None, _) => true,
+            (_, Token::Symbol(")")) | (_, Token::Symbol(",")) | (_, Token::Symbol(".")) => true,
+            (Some(Token::Symbol("(")), _) | (Some(Token::Symbol(".")), _) => true,
+            // function calls
+            (Some(Token::Ident(name)), Token::Symbol("(")) => !is_reserved(name),
+            _ => false,
+        };
+        if !glued {
+            sql.push(' ');
+        }
+        match token {
+            Token::Ident(name) => sql.push_str(name),
+            Token::Integer(i) => sql.push_str(&i.to_string()),
+            Token::Real(f) => sql.push_str(&format!("{:?}", f)),
+            Token::Text(s) => sql.push_str(&format!("'{}'", s.replace('\'', "''"))),
+            Token::Symbol(symbol) => sql.push_str(symbol),
+            Token::Parameter(name) => sql.push_str(name),
+        }
+        previous = Some(token);
+    }
+    sql
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(s) => format!("`{}`", s),
+        Token::Integer(i) => format!("`{}`", i),
+        Token::Real(f) => format!("`{}`", f),
+        Token::Text(s) => format!("'{}'", s),
+        Token::Symbol(s) => format!("`{}`", s),
+        Token::Parameter(s) => format!("`{}`", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Expr {
+        let mut parser = Parser::new(input).unwrap();
+        let expr = parser.parse_expr().unwrap();
+        parser.expect_end().unwrap();
+        expr
+    }
+
+    fn column(name: &str) -> Expr {
+        Expr::Column(name.into())
+    }
+
+    fn int(i: i64) -> Expr {
+        Expr::Literal(Value::Integer(i))
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(
+            parse("1 + 2 * 3"),
+            Expr::binary(
+                BinaryOp::Add,
+                int(1),
+                Expr::binary(BinaryOp::Mul, int(2), int(3))
+            )
+        );
+        assert_eq!(
+            parse("a = 1 or b = 2 and not c"),
+            Expr::binary(
+                BinaryOp::Or,
+                Expr::binary(BinaryOp::Eq, column("a"), int(1)),
+                Expr::binary(
+                    BinaryOp::And,
+                    Expr::binary(BinaryOp::Eq, column("b"), int(2)),
+                    Expr::Unary(UnaryOp::Not, Box::new(column("c")))
+                )
+            )
+        );
+        assert_eq!(
+            parse("Excluded.Name || t.x"),
+            Expr::binary(BinaryOp::Concat, column("excluded.name"), column("t.x"))
+        );
+    }
+
+    #[test]
+    fn test_between_binds_its_own_and() {
+        assert_eq!(
+            parse("id not between 1 and 10 and x is not null"),
+            Expr::binary(
+                BinaryOp::And,
+                Expr::Between {
+                    expr: Box::new(column("id")),
+                    low: Box::new(int(1)),
+                    high: Box::new(int(10)),
+                    negated: true,
+                },
+                Expr::IsNull {
+                    expr: Box::new(column("x")),
+                    negated: true,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_in_and_like() {
+        assert_eq!(
+            parse("id in (1, 2) and name not like 'a%'"),
+            Expr::binary(
+                BinaryOp::And,
+                Expr::InList {
+                    expr: Box::new(column("id")),
+                    list: vec![int(1), int(2)],
+                    negated: false,
+                },
+                Expr::Like {
+                    expr: Box::new(column("name")),
+                    pattern: Box::new(Expr::Literal(Value::Text("a%".into()))),
+                    negated: true,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_function_call() {
+        assert_eq!(
+            parse("count(*) + SUM(distinct id)"),
+            Expr::binary(
+                BinaryOp::Add,
+                Expr::Function {
+                    name: "count".into(),
+                    args: Vec::new(),
+                    distinct: false,
+                },
+                Expr::Function {
+                    name: "sum".into(),
+                    args: vec![column("id")],
+                    distinct: true,
+                }
+            )
+        );
+        assert_eq!(
+            parse("count(*) + SUM(distinct id)").to_string(),
+            "count(*) + sum(DISTINCT id)"
+        );
+    }
+
+    #[test]
+    fn test_subquery_keeps_its_sql() {
+        let expr = parse("id NOT IN (SELECT boss FROM staff WHERE boss>1) or exists(select 1)");
+        assert_eq!(
+            expr.to_string(),
+            "id NOT IN (SELECT boss FROM staff WHERE boss > 1) OR EXISTS (select 1)"
+        );
+        assert_eq!(
+            parse("(select max(id) from t) + 1").to_string(),
+            "(select max(id) from t) + 1"
+        );
+    }
+
+    #[test]
+    fn test_reserved_word_is_not_a_column() {
+        let mut parser = Parser::new("where").unwrap();
+        assert!(parser.parse_expr().is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use super::value::{DataType, Value};
+use std::fmt;
+
+/// Name and type of a single column of a query result.
+/// `data_type` is `None` when the type depends on the row, e.g. `NULL` literals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub data_type: Option<DataType>,
+}
+
+impl Column {
+    pub fn new(name: &str, data_type: Option<DataType>) -> Self {
+        Column {
+            name: String::from(name),
+            data_type,
+        }
+    }
+}
+
+/// Rows returned by a query together with the description of their columns
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultSet {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl ResultSet {
+    pub fn new(columns: Vec<Column>, rows: Vec<Vec<Value>>) -> Self {
+        ResultSet { columns, rows }
+    }
+
+    /// returns a printable header line listing the column names
+    pub fn header(&self) -> String {
+        header(&self.columns)
+    }
+}
+
+/// returns a printable header line listing the names of the columns
+pub fn header(columns: &[Column]) -> String {
+    let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+    format!("({})", names.join(", "))
+}
+
+/// Formats a result row the same way `Row` is printed: `(a, b, c)`
+pub struct DisplayRow<'a>(pub &'a [Value]);
+
+impl<'a> fmt::Display for DisplayRow<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(")?;
+        for (i, value) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", value)?;
+        }
+        write!(f, ")")
+    }
+}
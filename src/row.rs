@@ -1,102 +1,38 @@
-use std::convert::TryInto;
+use super::result_set::DisplayRow;
+use super::value::{decode_record, encode_record, Value};
 use std::fmt;
-use std::iter::{self, FromIterator};
 
-pub const ROW_SIZE: usize = 291;
+pub type RowError = String;
 
-pub const COLUMN_ID_SIZE: usize = 4;
-pub const COLUMN_USERNAME_SIZE: usize = 32;
-pub const COLUMN_EMAIL_SIZE: usize = 255;
-const NULL_CHAR: char = '\0';
-
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// Values of a single row of a table, in column order
+#[derive(Debug, PartialEq, Clone)]
 pub struct Row {
-    pub id: u32,
-    pub username: String,
-    pub email: String,
+    pub values: Vec<Value>,
 }
 
 impl Row {
-    pub fn new(id: u32, username: String, email: String) -> Self {
-        Row {
-            id,
-            username,
-            email,
-        }
+    pub fn new(values: Vec<Value>) -> Self {
+        Row { values }
     }
 
-    /// serialize row for id, username, email
-    /// each field has length of 4, 32, 255 bytes.
-    /// length of serialized vector will be 291
+    /// serialize row as a record of its values.
+    /// rows have no fixed size, so large values may spill into overflow pages.
     pub fn serialize(&self) -> Vec<u8> {
-        let mut result = Vec::<u8>::with_capacity(ROW_SIZE);
-
-        // id_bytes length is 4
-        let id_bytes = self.id.to_le_bytes();
-        for b in id_bytes.iter() {
-            result.push(*b);
-        }
-
-        for b in self
-            .username
-            .chars()
-            .chain(iter::repeat(NULL_CHAR))
-            .take(COLUMN_USERNAME_SIZE)
-        {
-            result.push(b as u8)
-        }
-
-        for b in self
-            .email
-            .chars()
-            .chain(iter::repeat(NULL_CHAR))
-            .take(COLUMN_EMAIL_SIZE)
-        {
-            result.push(b as u8)
-        }
-
-        result
+        encode_record(&self.values)
     }
 
-    /// deserialize fixed length vector of u8
-    /// input vector must be length of `ROW_SIZE`
-    pub fn deserialize(input: Vec<u8>) -> Option<Self> {
-        if input.len() != ROW_SIZE {
-            return None;
-        }
-
-        let raw_id: &[u8; 4] = &input[..COLUMN_ID_SIZE]
-            .try_into()
-            .expect("slice with incorrect length");
-        let id = u32::from_le_bytes(*raw_id);
-
-        let raw_username = &input[COLUMN_ID_SIZE..COLUMN_USERNAME_SIZE];
-        let username: String = String::from_iter(raw_username.iter().map(|c| *c as char))
-            .trim_end_matches(NULL_CHAR)
-            .into();
-
-        let raw_email = &input[COLUMN_ID_SIZE + COLUMN_USERNAME_SIZE..];
-        let email: String = String::from_iter(raw_email.iter().map(|c| *c as char))
-            .trim_end_matches(NULL_CHAR)
-            .into();
-
-        Some(Row {
-            id,
-            username,
-            email,
-        })
+    /// deserialize vector of u8 produced by `Row::serialize`.
+    /// returns an error instead of guessing when the data is truncated or not valid UTF-8.
+    pub fn deserialize(input: Vec<u8>) -> Result<Self, RowError> {
+        decode_record(&input)
+            .map(Row::new)
+            .map_err(|e| format!("broken row: {}", e))
     }
 }
 
 impl fmt::Display for Row {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "({}, {}, {})",
-            self.id,
-            self.username.trim_end_matches(NULL_CHAR),
-            self.email.trim_end_matches(NULL_CHAR)
-        )
+        write!(f, "{}", DisplayRow(&self.values))
     }
 }
 
@@ -104,37 +40,77 @@ impl fmt::Display for Row {
 mod tests {
     use super::*;
 
+    fn user(id: i64, username: &str, email: &str) -> Row {
+        Row::new(vec![
+            Value::Integer(id),
+            Value::Text(String::from(username)),
+            Value::Text(String::from(email)),
+        ])
+    }
+
     #[test]
     fn test_serialize_length() {
-        let row = Row {
-            id: 12,
-            username: String::from("John Doe"),
-            email: String::from("john@example.com"),
-        };
+        // count, then tag and 8 bytes for the id and tag, length and bytes for each text
+        let serialized = user(12, "John Doe", "john@example.com").serialize();
+        assert_eq!(serialized.len(), 4 + 9 + 5 + 8 + 5 + 16);
 
+        let serialized = user(112, "Takamichi Tsutsumi", "tkmct@gmail.com").serialize();
+        assert_eq!(serialized.len(), 4 + 9 + 5 + 18 + 5 + 15);
+    }
+
+    #[test]
+    fn test_serialize_row() {
+        let row = user(12, "John Doe", "john@example.com");
         let serialized = row.serialize();
-        assert_eq!(serialized.len(), 291);
+        let deserialized = Row::deserialize(serialized);
 
-        let row = Row {
-            id: 112,
-            username: String::from("Takamichi Tsutsumi"),
-            email: String::from("tkmct@gmail.com"),
-        };
+        assert_eq!(Ok(row), deserialized);
+    }
 
+    #[test]
+    fn test_serialize_large_row() {
+        let email: String = ['a'; 20_000].iter().collect();
+        let row = user(1, "John Doe", &email);
         let serialized = row.serialize();
-        assert_eq!(serialized.len(), 291);
+        let deserialized = Row::deserialize(serialized);
+
+        assert_eq!(Ok(row), deserialized);
     }
 
     #[test]
-    fn test_serialize_row() {
-        let row = Row {
-            id: 12,
-            username: String::from("John Doe"),
-            email: String::from("john@example.com"),
-        };
+    fn test_deserialize_truncated_input() {
+        let mut serialized = user(1, "John Doe", "john@example.com").serialize();
+        serialized.pop();
+
+        assert!(Row::deserialize(serialized).is_err());
+        // a corrupt value count
+        assert!(Row::deserialize(vec![0xff, 0xff, 0xff, 0x7f]).is_err());
+    }
+
+    #[test]
+    fn test_serialize_non_ascii_row() {
+        let row = user(1, "José", "田中@example.jp");
         let serialized = row.serialize();
+        assert_eq!(serialized.len(), 4 + 9 + 5 + 5 + 5 + 17);
+
         let deserialized = Row::deserialize(serialized);
+        assert_eq!(Ok(row), deserialized);
+    }
+
+    #[test]
+    fn test_deserialize_invalid_utf8() {
+        let mut serialized = user(1, "ab", "").serialize();
+        // overwrite username with a lone continuation byte
+        serialized[4 + 9 + 5] = 0x80;
 
-        assert_eq!(Some(row), deserialized);
+        assert!(Row::deserialize(serialized).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            user(1, "alice", "alice@example.com").to_string(),
+            "(1, alice, alice@example.com)"
+        );
     }
 }
@@ -0,0 +1,630 @@
+use super::btree::{node_type, NODE_TYPE_SCHEMA, PAGE_SIZE};
+use super::expression::Expr;
+use super::key::{encode_key, Key};
+use super::parser::{ParseError, Parser};
+use super::row::Row;
+use super::value::{decode_record, encode_record, DataType, Value};
+use std::convert::TryInto;
+
+// SCHEMA PAGE LAYOUT
+// `node type | record size | record`, where the record lists the
+// `sql, root page, sequence` of every table and index except the built-in
+// table. indexes have a NULL sequence.
+const SCHEMA_RECORD_SIZE_OFFSET: usize = 1;
+const SCHEMA_HEADER_SIZE: usize = SCHEMA_RECORD_SIZE_OFFSET + 4;
+
+/// definition of the table every database starts with, stored in page 0
+const USERS_SQL: &str = "create table users (id integer primary key, \
+     username text not null check (length(username) <= 32), email text not null)";
+
+/// Words ending the type name of a column definition
+const CONSTRAINT_KEYWORDS: [&str; 6] = ["primary", "not", "null", "unique", "check", "default"];
+
+/// Definition of a single column of a table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub name: String,
+    /// type name as declared, e.g. `varchar(255)`. may be empty.
+    pub type_name: String,
+    pub primary_key: bool,
+    /// ids allocated for the primary key are never reused
+    pub autoincrement: bool,
+    pub not_null: bool,
+    pub unique: bool,
+    /// value of the column when an insert leaves it out
+    pub default: Option<Expr>,
+    /// left out of `*` and read by name only, like the arguments of a virtual table
+    pub hidden: bool,
+}
+
+impl ColumnDef {
+    /// returns a column without constraints
+    pub fn new(name: &str, type_name: &str) -> Self {
+        ColumnDef {
+            name: String::from(name),
+            type_name: String::from(type_name),
+            primary_key: false,
+            autoincrement: false,
+            not_null: false,
+            unique: false,
+            default: None,
+            hidden: false,
+        }
+    }
+
+    /// returns the type values stored in this column are converted to
+    pub fn data_type(&self) -> Option<DataType> {
+        DataType::from_declared(&self.type_name)
+    }
+
+    /// returns if the column is declared `json`, which only stores well-formed JSON
+    pub fn is_json(&self) -> bool {
+        self.type_name.eq_ignore_ascii_case("json")
+    }
+
+    /// parses `<name> [<type>] [<constraint>]*`, where a constraint is one of
+    /// `primary key [autoincrement]`, `not null`, `null`, `unique`, `check (<expr>)` or `default <value>`.
+    /// `check` constraints are added to `checks` as they apply to the whole row.
+    fn parse(parser: &mut Parser, checks: &mut Vec<Expr>) -> Result<Self, ParseError> {
+        let name = parser.expect_ident()?.to_lowercase();
+
+        let mut words = Vec::new();
+        while !CONSTRAINT_KEYWORDS.iter().any(|k| parser.peek_keyword(k)) {
+            match parser.expect_ident() {
+                Ok(word) => words.push(word.to_lowercase()),
+                Err(_) => break,
+            }
+        }
+        let mut type_name = words.join(" ");
+        if !words.is_empty() && parser.consume_symbol("(") {
+            let mut sizes = vec![parser.expect_integer()?.to_string()];
+            if parser.consume_symbol(",") {
+                sizes.push(parser.expect_integer()?.to_string());
+            }
+            parser.expect_symbol(")")?;
+            type_name = format!("{}({})", type_name, sizes.join(","));
+        }
+
+        let mut column = ColumnDef::new(&name, &type_name);
+        loop {
+            if parser.consume_keyword("primary") {
+                parser.expect_keyword("key")?;
+                column.primary_key = true;
+                column.autoincrement = parser.consume_keyword("autoincrement");
+            } else if parser.consume_keyword("not") {
+                parser.expect_keyword("null")?;
+                column.not_null = true;
+            } else if parser.consume_keyword("unique") {
+                column.unique = true;
+            } else if parser.consume_keyword("check") {
+                checks.push(parse_check(parser)?);
+            } else if parser.consume_keyword("default") {
+                // a signed literal or an expression in parentheses
+                let default = parser.parse_unary()?;
+                let mut constant = true;
+                default.visit_columns(&mut |_| constant = false);
+                if !constant {
+                    return Err(format!(
+                        "default value of column [{}] is not constant",
+                        column.name
+                    ));
+                }
+                column.default = Some(default);
+            } else if !parser.consume_keyword("null") {
+                return Ok(column);
+            }
+        }
+    }
+
+    fn sql(&self) -> String {
+        let mut sql = self.name.clone();
+        if !self.type_name.is_empty() {
+            sql.push(' ');
+            sql.push_str(&self.type_name);
+        }
+        if self.primary_key {
+            sql.push_str(" primary key");
+        }
+        if self.autoincrement {
+            sql.push_str(" autoincrement");
+        }
+        if self.not_null {
+            sql.push_str(" not null");
+        }
+        if self.unique {
+            sql.push_str(" unique");
+        }
+        if let Some(default) = &self.default {
+            sql.push_str(&format!(" default ({})", default));
+        }
+        sql
+    }
+}
+
+/// parses `(<expr>)` following `check`
+fn parse_check(parser: &mut Parser) -> Result<Expr, ParseError> {
+    parser.expect_symbol("(")?;
+    let check = parser.parse_expr()?;
+    parser.expect_symbol(")")?;
+    if check.contains_aggregate() {
+        return Err(String::from(
+            "aggregate functions are not allowed in the CHECK clause",
+        ));
+    }
+    Ok(check)
+}
+
+/// Definition of a table, whose rows are stored in the B-tree at `root_page_num`
+/// keyed by the encoded values of the primary key
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableDef {
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    /// positions of the primary key columns, in key order
+    pub primary_key: Vec<usize>,
+    /// conditions every row has to satisfy, from column and table `check` constraints
+    pub checks: Vec<Expr>,
+    pub root_page_num: usize,
+    /// largest key ever inserted into an `autoincrement` table
+    pub sequence: i64,
+}
+
+impl TableDef {
+    /// parses the part of `create table` following the keywords:
+    /// `<name> (<column> [, ...] [, primary key (<column> [, ...])] [, check (<expr>)]*)`
+    pub fn parse(parser: &mut Parser) -> Result<Self, ParseError> {
+        let name = parser.expect_ident()?.to_lowercase();
+        parser.expect_symbol("(")?;
+        let mut columns: Vec<ColumnDef> = Vec::new();
+        let mut checks = Vec::new();
+        let mut key_columns: Option<Vec<String>> = None;
+        loop {
+            if parser.consume_keyword("check") {
+                checks.push(parse_check(parser)?);
+            } else if parser.consume_keyword("primary") {
+                parser.expect_keyword("key")?;
+                parser.expect_symbol("(")?;
+                let mut names = vec![parser.expect_ident()?.to_lowercase()];
+                while parser.consume_symbol(",") {
+                    names.push(parser.expect_ident()?.to_lowercase());
+                }
+                parser.expect_symbol(")")?;
+                if key_columns.replace(names).is_some() {
+                    return Err(format!("table {} has more than one primary key", name));
+                }
+            } else {
+                let column = ColumnDef::parse(parser, &mut checks)?;
+                if columns.iter().any(|c| c.name == column.name) {
+                    return Err(format!("duplicate column name: {}", column.name));
+                }
+                columns.push(column);
+            }
+            if !parser.consume_symbol(",") {
+                break;
+            }
+        }
+        parser.expect_symbol(")")?;
+
+        let mut missing = None;
+        for check in checks.iter() {
+            check.visit_columns(&mut |column| {
+                if missing.is_none() && !columns.iter().any(|c| c.name == column) {
+                    missing = Some(format!("no such column: {}", column));
+                }
+            });
+        }
+        if let Some(missing) = missing {
+            return Err(missing);
+        }
+
+        // rows are keyed by the primary key, so there has to be exactly one
+        let mut primary_key: Vec<usize> = (0..columns.len())
+            .filter(|&i| columns[i].primary_key)
+            .collect();
+        match (primary_key.len(), key_columns) {
+            (0, None) => return Err(format!("table {} needs a primary key", name)),
+            (0, Some(names)) => {
+                for column in names.iter() {
+                    match columns.iter().position(|c| c.name == *column) {
+                        Some(i) if !primary_key.contains(&i) => primary_key.push(i),
+                        Some(_) => {}
+                        None => return Err(format!("no such column: {}", column)),
+                    }
+                }
+            }
+            (1, None) => {}
+            _ => return Err(format!("table {} has more than one primary key", name)),
+        }
+
+        let table = TableDef {
+            name,
+            columns,
+            primary_key,
+            checks,
+            root_page_num: 0,
+            sequence: 0,
+        };
+        if table.columns.iter().any(|c| c.autoincrement) && table.rowid().is_none() {
+            return Err(String::from(
+                "AUTOINCREMENT is only allowed on an INTEGER PRIMARY KEY",
+            ));
+        }
+        Ok(table)
+    }
+
+    pub fn sql(&self) -> String {
+        let mut definitions: Vec<String> = self.columns.iter().map(ColumnDef::sql).collect();
+        if !self.columns.iter().any(|c| c.primary_key) {
+            let names: Vec<&str> = self
+                .primary_key
+                .iter()
+                .map(|&i| self.columns[i].name.as_str())
+                .collect();
+            definitions.push(format!("primary key ({})", names.join(", ")));
+        }
+        for check in self.checks.iter() {
+            definitions.push(format!("check ({})", check));
+        }
+        format!("create table {} ({})", self.name, definitions.join(", "))
+    }
+
+    /// returns the columns `*` expands to, which leaves out hidden columns
+    pub fn visible_columns(&self) -> impl Iterator<Item = &ColumnDef> {
+        self.columns.iter().filter(|c| !c.hidden)
+    }
+
+    /// returns the position of the column, which may be qualified as `<table>.<column>`.
+    /// the columns of joined tables are named qualified already, and resolve by
+    /// their own name as long as only one table has a column of that name.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        if let Some(position) = self.columns.iter().position(|c| c.name == name) {
+            return Some(position);
+        }
+        match name.split_once('.') {
+            Some((table, column)) if table == self.name => self.column_index(column),
+            Some(_) => None,
+            None => {
+                let mut matching = self.qualified_matches(name);
+                match (matching.next(), matching.next()) {
+                    (Some(position), None) => Some(position),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// returns if the name is shared by columns of several joined tables
+    pub fn is_ambiguous(&self, name: &str) -> bool {
+        !name.contains('.') && self.qualified_matches(name).nth(1).is_some()
+    }
+
+    /// returns positions of the qualified columns named `<table>.<name>`
+    fn qualified_matches<'a>(&'a self, name: &'a str) -> impl Iterator<Item = usize> + 'a {
+        self.columns.iter().enumerate().filter_map(move |(i, c)| {
+            let (_, column) = c.name.split_once('.')?;
+            Some(i).filter(|_| column == name)
+        })
+    }
+
+    pub fn column(&self, name: &str) -> Option<&ColumnDef> {
+        self.column_index(name)
+            .map(|position| &self.columns[position])
+    }
+
+    /// returns if the column is part of the primary key
+    pub fn is_primary_key(&self, position: usize) -> bool {
+        self.primary_key.contains(&position)
+    }
+
+    /// returns position of the integer primary key column, whose values are
+    /// allocated when a row is inserted without one
+    pub fn rowid(&self) -> Option<usize> {
+        match self.primary_key.as_slice() {
+            [i] if self.columns[*i].data_type() == Some(DataType::Integer) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// returns if ids of deleted rows must not be allocated again
+    pub fn autoincrement(&self) -> bool {
+        self.rowid().is_some_and(|i| self.columns[i].autoincrement)
+    }
+
+    /// returns the values of the primary key of a row, in key order
+    pub fn key_values(&self, row: &Row) -> Vec<Value> {
+        self.primary_key
+            .iter()
+            .map(|&i| row.values[i].clone())
+            .collect()
+    }
+
+    /// returns the B-tree key of a row whose primary key has been validated
+    pub fn key(&self, row: &Row) -> Key {
+        encode_key(&self.key_values(row))
+    }
+}
+
+/// Definition of a secondary index over one column of a table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    pub name: String,
+    pub table: String,
+    pub column: String,
+    pub unique: bool,
+    /// page holding the index node
+    pub root_page_num: usize,
+}
+
+impl Index {
+    /// parses the part of `create [unique] index` following the keywords:
+    /// `<name> on <table> (<column>)`
+    pub fn parse(parser: &mut Parser, unique: bool) -> Result<Self, ParseError> {
+        let name = parser.expect_ident()?;
+        parser.expect_keyword("on")?;
+        let table = parser.expect_ident()?.to_lowercase();
+
+        parser.expect_symbol("(")?;
+        let column = parser.expect_ident()?.to_lowercase();
+        parser.expect_symbol(")")?;
+
+        Ok(Index {
+            name,
+            table,
+            column,
+            unique,
+            root_page_num: 0,
+        })
+    }
+
+    pub fn sql(&self) -> String {
+        format!(
+            "create {}index {} on {} ({})",
+            if self.unique { "unique " } else { "" },
+            self.name,
+            self.table,
+            self.column
+        )
+    }
+}
+
+/// Tables and indexes of the database
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub tables: Vec<TableDef>,
+    pub indexes: Vec<Index>,
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Schema {
+    /// returns the schema of a new database, holding only the built-in table
+    pub fn new() -> Self {
+        let mut parser = Parser::new(USERS_SQL).unwrap();
+        parser.expect_keyword("create").unwrap();
+        parser.expect_keyword("table").unwrap();
+        let users = TableDef::parse(&mut parser).unwrap();
+
+        Schema {
+            tables: vec![users],
+            indexes: Vec::new(),
+        }
+    }
+
+    pub fn table(&self, name: &str) -> Option<&TableDef> {
+        self.tables
+            .iter()
+            .find(|table| table.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn table_by_root(&self, root_page_num: usize) -> Option<&TableDef> {
+        self.tables
+            .iter()
+            .find(|table| table.root_page_num == root_page_num)
+    }
+
+    pub fn index(&self, name: &str) -> Option<&Index> {
+        self.indexes
+            .iter()
+            .find(|index| index.name.eq_ignore_ascii_case(name))
+    }
+
+    /// returns every index of the given table
+    pub fn indexes_of<'a>(&'a self, table: &'a str) -> impl Iterator<Item = &'a Index> {
+        self.indexes
+            .iter()
+            .filter(move |index| index.table == table)
+    }
+
+    /// returns an index on the given column, if there is one
+    pub fn index_on(&self, table: &str, column: &str) -> Option<&Index> {
+        self.indexes
+            .iter()
+            .find(|index| index.table == table && index.column == column)
+    }
+}
+
+/// Page describing the database schema. It is allocated by the first `create`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SchemaPage {
+    pub schema: Schema,
+}
+
+impl SchemaPage {
+    pub fn new(schema: Schema) -> Self {
+        SchemaPage { schema }
+    }
+
+    fn record(&self) -> Vec<u8> {
+        let mut values = Vec::new();
+        // the built-in table is part of every schema and is not stored
+        let tables = self.schema.tables.iter().filter(|t| t.root_page_num != 0);
+        for (sql, root, sequence) in tables
+            .map(|t| (t.sql(), t.root_page_num, Value::Integer(t.sequence)))
+            .chain(
+                self.schema
+                    .indexes
+                    .iter()
+                    .map(|i| (i.sql(), i.root_page_num, Value::Null)),
+            )
+        {
+            values.push(Value::Text(sql));
+            values.push(Value::Integer(root as i64));
+            values.push(sequence);
+        }
+        encode_record(&values)
+    }
+
+    /// returns if the schema fits into a single page
+    pub fn fits(&self) -> bool {
+        SCHEMA_HEADER_SIZE + self.record().len() <= PAGE_SIZE
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let record = self.record();
+        if !self.fits() {
+            panic!("schema does not fit into a page");
+        }
+
+        let mut buff = vec![0; PAGE_SIZE];
+        buff[0] = NODE_TYPE_SCHEMA;
+        buff.splice(
+            SCHEMA_RECORD_SIZE_OFFSET..SCHEMA_HEADER_SIZE,
+            (record.len() as u32).to_le_bytes().iter().cloned(),
+        );
+        buff.splice(
+            SCHEMA_HEADER_SIZE..SCHEMA_HEADER_SIZE + record.len(),
+            record,
+        );
+        buff
+    }
+
+    pub fn deserialize(raw: Vec<u8>) -> Option<Self> {
+        if raw.len() != PAGE_SIZE || node_type(&raw) != NODE_TYPE_SCHEMA {
+            return None;
+        }
+
+        let size = u32::from_le_bytes(
+            raw[SCHEMA_RECORD_SIZE_OFFSET..SCHEMA_HEADER_SIZE]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let values = decode_record(raw.get(SCHEMA_HEADER_SIZE..SCHEMA_HEADER_SIZE + size)?).ok()?;
+
+        let mut schema = Schema::new();
+        for chunk in values.chunks(3) {
+            let (sql, root, sequence) = match chunk {
+                [Value::Text(sql), Value::Integer(root), sequence] => {
+                    (sql, *root as usize, sequence)
+                }
+                _ => return None,
+            };
+
+            let mut parser = Parser::new(sql).ok()?;
+            parser.expect_keyword("create").ok()?;
+            if parser.consume_keyword("table") {
+                let table = TableDef::parse(&mut parser).ok()?;
+                schema.tables.push(TableDef {
+                    root_page_num: root,
+                    sequence: match sequence {
+                        Value::Integer(sequence) => *sequence,
+                        _ => return None,
+                    },
+                    ..table
+                });
+            } else {
+                let unique = parser.consume_keyword("unique");
+                parser.expect_keyword("index").ok()?;
+                let index = Index::parse(&mut parser, unique).ok()?;
+                schema.indexes.push(Index {
+                    root_page_num: root,
+                    ..index
+                });
+            }
+        }
+
+        Some(SchemaPage { schema })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_table(sql: &str) -> Result<TableDef, ParseError> {
+        let mut parser = Parser::new(sql)?;
+        parser.expect_keyword("create")?;
+        parser.expect_keyword("table")?;
+        TableDef::parse(&mut parser)
+    }
+
+    #[test]
+    fn test_parse_create_table() {
+        let table = parse_table(
+            "create table Posts (id INTEGER primary key, title varchar(255) not null unique, body)",
+        )
+        .unwrap();
+        assert_eq!(table.name, "posts");
+        assert_eq!(table.column("title").unwrap().type_name, "varchar(255)");
+        assert_eq!(table.column("body").unwrap().data_type(), None);
+        assert_eq!(
+            table.sql(),
+            "create table posts (id integer primary key, title varchar(255) not null unique, body)"
+        );
+
+        assert_eq!(
+            parse_table("create table t (a text)"),
+            Err(String::from("table t needs a primary key"))
+        );
+        assert_eq!(
+            parse_table("create table t (a int primary key, b int primary key)"),
+            Err(String::from("table t has more than one primary key"))
+        );
+        assert_eq!(
+            parse_table("create table t (a text primary key autoincrement)"),
+            Err(String::from(
+                "AUTOINCREMENT is only allowed on an INTEGER PRIMARY KEY"
+            ))
+        );
+
+        let table =
+            parse_table("create table t (a text, b int, c, primary key (b, a), check (c > 0))")
+                .unwrap();
+        assert_eq!(table.primary_key, vec![1, 0]);
+        assert_eq!(table.rowid(), None);
+        assert_eq!(
+            table.sql(),
+            "create table t (a text, b int, c, primary key (b, a), check (c > 0))"
+        );
+        assert_eq!(
+            parse_table("create table t (id int primary key, a, a)"),
+            Err(String::from("duplicate column name: a"))
+        );
+    }
+
+    #[test]
+    fn test_serialize_and_deserialize_schema_page() {
+        let mut schema = Schema::new();
+        schema.tables.push(TableDef {
+            root_page_num: 3,
+            sequence: 12,
+            ..parse_table(
+                "create table posts (id integer primary key autoincrement, title text unique)",
+            )
+            .unwrap()
+        });
+        schema.indexes.push(Index {
+            name: String::from("idx_email"),
+            table: String::from("users"),
+            column: String::from("email"),
+            unique: true,
+            root_page_num: 4,
+        });
+
+        let page = SchemaPage::new(schema);
+        assert_eq!(SchemaPage::deserialize(page.serialize()), Some(page));
+    }
+}
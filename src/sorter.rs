@@ -0,0 +1,269 @@
+use super::btree::PAGE_SIZE;
+use super::value::{decode_record, encode_record, Value};
+use std::cmp::Ordering;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::vec::IntoIter;
+
+/// Bytes of rows a sorter keeps in memory before spilling a sorted run to disk
+pub const SORT_MEMORY_LIMIT: usize = 256 * PAGE_SIZE;
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A row waiting to be sorted: the values it is sorted by and the values it returns
+type SortEntry = (Vec<Value>, Vec<Value>);
+
+fn compare_keys(descending: &[bool], a: &[Value], b: &[Value]) -> Ordering {
+    for ((a, b), desc) in a.iter().zip(b.iter()).zip(descending.iter()) {
+        let ordering = a.compare(b);
+        if ordering != Ordering::Equal {
+            return if *desc { ordering.reverse() } else { ordering };
+        }
+    }
+    Ordering::Equal
+}
+
+/// A sorted run written to a temporary file, removed again when dropped
+struct SpillRun {
+    path: PathBuf,
+    reader: BufReader<File>,
+}
+
+impl SpillRun {
+    fn write(entries: Vec<SortEntry>) -> io::Result<Self> {
+        let path = env::temp_dir().join(format!(
+            "db_tutorial_sort_{}_{}",
+            process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, AtomicOrdering::SeqCst)
+        ));
+
+        let mut writer = BufWriter::with_capacity(PAGE_SIZE, File::create(&path)?);
+        for (keys, values) in entries.iter() {
+            for record in [encode_record(keys), encode_record(values)].iter() {
+                writer.write_all(&(record.len() as u32).to_le_bytes())?;
+                writer.write_all(record)?;
+            }
+        }
+        writer.flush()?;
+
+        let reader = BufReader::with_capacity(PAGE_SIZE, File::open(&path)?);
+        Ok(SpillRun { path, reader })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Vec<Value>>> {
+        let mut raw_length = [0; 4];
+        match self.reader.read_exact(&mut raw_length) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let mut record = vec![0; u32::from_le_bytes(raw_length) as usize];
+        self.reader.read_exact(&mut record)?;
+        decode_record(&record)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn next_entry(&mut self) -> io::Result<Option<SortEntry>> {
+        let keys = match self.read_record()? {
+            Some(keys) => keys,
+            None => return Ok(None),
+        };
+        let values = self
+            .read_record()?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "sort run is truncated"))?;
+        Ok(Some((keys, values)))
+    }
+}
+
+impl Drop for SpillRun {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// External merge sorter for query results.
+/// Rows are sorted in memory until they exceed the memory limit, at which point
+/// the buffer is written to disk as a sorted run. Runs are merged on the way out.
+pub struct Sorter {
+    descending: Vec<bool>,
+    memory_limit: usize,
+    buffer: Vec<SortEntry>,
+    buffer_size: usize,
+    runs: Vec<SpillRun>,
+}
+
+impl Sorter {
+    /// `descending` holds one flag per sort key
+    pub fn new(descending: Vec<bool>) -> Self {
+        Sorter::with_memory_limit(descending, SORT_MEMORY_LIMIT)
+    }
+
+    pub fn with_memory_limit(descending: Vec<bool>, memory_limit: usize) -> Self {
+        Sorter {
+            descending,
+            memory_limit,
+            buffer: Vec::new(),
+            buffer_size: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    /// returns number of runs spilled to disk so far
+    pub fn num_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    fn sort_buffer(&mut self) -> Vec<SortEntry> {
+        let descending = &self.descending;
+        let mut buffer = std::mem::take(&mut self.buffer);
+        // stable, so rows with equal keys keep their scan order
+        buffer.sort_by(|a, b| compare_keys(descending, &a.0, &b.0));
+        self.buffer_size = 0;
+        buffer
+    }
+
+    pub fn push(&mut self, keys: Vec<Value>, values: Vec<Value>) -> io::Result<()> {
+        self.buffer_size += encode_record(&keys).len() + encode_record(&values).len();
+        self.buffer.push((keys, values));
+
+        if self.buffer_size > self.memory_limit {
+            let sorted = self.sort_buffer();
+            self.runs.push(SpillRun::write(sorted)?);
+        }
+        Ok(())
+    }
+
+    /// returns the sorted rows
+    pub fn finish(mut self) -> io::Result<SortedRows> {
+        let sorted = self.sort_buffer();
+        if self.runs.is_empty() {
+            return Ok(SortedRows(Output::Memory(sorted.into_iter())));
+        }
+
+        // whatever is left in memory takes part in the merge as the last run
+        let mut runs = std::mem::take(&mut self.runs);
+        if !sorted.is_empty() {
+            runs.push(SpillRun::write(sorted)?);
+        }
+
+        let mut heads = Vec::with_capacity(runs.len());
+        for run in runs.iter_mut() {
+            heads.push(run.next_entry()?);
+        }
+
+        Ok(SortedRows(Output::Merge {
+            descending: std::mem::take(&mut self.descending),
+            runs,
+            heads,
+        }))
+    }
+}
+
+/// Iterator over the output of a `Sorter`
+pub struct SortedRows(Output);
+
+enum Output {
+    Memory(IntoIter<SortEntry>),
+    Merge {
+        descending: Vec<bool>,
+        runs: Vec<SpillRun>,
+        heads: Vec<Option<SortEntry>>,
+    },
+}
+
+impl Iterator for SortedRows {
+    type Item = io::Result<Vec<Value>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            Output::Memory(entries) => entries.next().map(|(_, values)| Ok(values)),
+            Output::Merge {
+                descending,
+                runs,
+                heads,
+            } => {
+                // pick the smallest head. earlier runs win ties, which keeps the merge stable
+                let mut smallest: Option<usize> = None;
+                for (i, head) in heads.iter().enumerate() {
+                    if let Some((keys, _)) = head {
+                        let is_smaller = match smallest {
+                            None => true,
+                            Some(j) => {
+                                let current = &heads[j].as_ref().unwrap().0;
+                                compare_keys(descending, keys, current) == Ordering::Less
+                            }
+                        };
+                        if is_smaller {
+                            smallest = Some(i);
+                        }
+                    }
+                }
+
+                let i = smallest?;
+                let next = match runs[i].next_entry() {
+                    Ok(next) => next,
+                    Err(e) => return Some(Err(e)),
+                };
+                let (_, values) = std::mem::replace(&mut heads[i], next).unwrap();
+                Some(Ok(values))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// pushes every key along with its position in `keys`
+    fn push_keys(sorter: &mut Sorter, keys: &[i64]) {
+        for (i, key) in keys.iter().enumerate() {
+            sorter
+                .push(vec![Value::Integer(*key)], vec![Value::Integer(i as i64)])
+                .unwrap();
+        }
+    }
+
+    /// returns the positions of the pushed keys in sorted order
+    fn sorted_positions(sorter: Sorter) -> Vec<usize> {
+        sorter
+            .finish()
+            .unwrap()
+            .map(|values| match values.unwrap()[0] {
+                Value::Integer(i) => i as usize,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_in_memory() {
+        let mut sorter = Sorter::new(vec![true]);
+        push_keys(&mut sorter, &[3, 1, 2, 3]);
+
+        assert_eq!(sorter.num_runs(), 0);
+        assert_eq!(sorted_positions(sorter), vec![0, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_sort_spills_to_disk() {
+        let keys: Vec<i64> = (0..1000).map(|i| (i * 7919) % 1000).collect();
+
+        // roughly 20 entries per run
+        let mut sorter = Sorter::with_memory_limit(vec![false], 500);
+        push_keys(&mut sorter, &keys);
+        assert!(sorter.num_runs() > 1);
+
+        let sorted: Vec<i64> = sorted_positions(sorter)
+            .into_iter()
+            .map(|i| keys[i])
+            .collect();
+        assert_eq!(sorted, (0..1000).collect::<Vec<i64>>());
+    }
+}
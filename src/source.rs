@@ -0,0 +1,335 @@
+use super::expression::{EvalError, Expr, Scope};
+use super::json;
+use super::result_set::Column;
+use super::row::Row;
+use super::schema::{ColumnDef, TableDef};
+use super::statement::ExecuteResult;
+use super::table::Table;
+use super::value::Value;
+use super::vtab::{ColumnConstraint, ConstraintOp, VirtualCursor, VirtualTable};
+use std::fmt;
+use std::rc::Rc;
+
+/// name of the hidden column holding the rowid of a row of a virtual table
+const ROWID: &str = "rowid";
+
+/// returns the definition of the rows of a table the database does not store
+fn unstored_def(name: &str, columns: Vec<ColumnDef>) -> TableDef {
+    TableDef {
+        name: String::from(name),
+        columns,
+        primary_key: Vec::new(),
+        checks: Vec::new(),
+        // there is no B-tree
+        root_page_num: usize::MAX,
+        sequence: 0,
+    }
+}
+
+/// Rows computed while a statement runs, like those of a `with` clause,
+/// read by name like a table
+#[derive(Debug, PartialEq, Clone)]
+pub struct TempTable {
+    pub def: TableDef,
+    pub rows: Vec<Row>,
+}
+
+impl TempTable {
+    /// returns the temporary table `name` holding the rows of a query result
+    /// with the given columns. `names` renames the columns when not empty.
+    pub fn new(
+        name: &str,
+        names: &[String],
+        columns: &[Column],
+        rows: Vec<Vec<Value>>,
+    ) -> Result<Self, String> {
+        if !names.is_empty() && names.len() != columns.len() {
+            return Err(format!(
+                "table {} has {} values for {} columns",
+                name,
+                columns.len(),
+                names.len()
+            ));
+        }
+
+        let columns = columns
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let type_name = c.data_type.map(|t| t.to_string()).unwrap_or_default();
+                ColumnDef::new(&names.get(i).unwrap_or(&c.name).to_lowercase(), &type_name)
+            })
+            .collect();
+        Ok(TempTable {
+            def: unstored_def(name, columns),
+            rows: rows.into_iter().map(Row::new).collect(),
+        })
+    }
+}
+
+/// A table-valued function named in `from`, like `json_each(<json> [, <path>])`,
+/// whose rows are computed from its arguments
+#[derive(Debug, Clone)]
+pub struct TableFunction {
+    pub def: TableDef,
+    pub args: Vec<Expr>,
+}
+
+impl TableFunction {
+    /// returns if `name` is a built-in table-valued function
+    pub fn exists(name: &str) -> bool {
+        name == "json_each"
+    }
+
+    /// checks a built-in table-valued function `name` takes that many arguments.
+    /// other names can be virtual tables, looked up when the statement runs.
+    pub fn check(name: &str, num_args: usize) -> Result<(), String> {
+        if Self::exists(name) {
+            json::check_arguments(name, num_args)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn new(name: &str, args: &[Expr]) -> Self {
+        let columns = json::EACH_COLUMNS
+            .iter()
+            .map(|name| ColumnDef::new(name, ""))
+            .collect();
+        TableFunction {
+            def: unstored_def(name, columns),
+            args: args.to_vec(),
+        }
+    }
+
+    /// returns the rows for the arguments evaluated in `scope`
+    pub fn rows(&self, scope: &dyn Scope) -> Result<Vec<Row>, ExecuteResult> {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.eval(scope))
+            .collect::<Result<Vec<Value>, _>>()
+            .map_err(ExecuteResult::EvalError)?;
+        let rows = json::each(&args).map_err(ExecuteResult::EvalError)?;
+        Ok(rows.into_iter().map(Row::new).collect())
+    }
+}
+
+/// A virtual table named in `from`, read by `name(<expr> [, ...])` or by its
+/// name alone, whose rows are computed again for each row it is joined with
+#[derive(Clone)]
+pub struct VirtualSource {
+    pub def: TableDef,
+    pub table: Rc<dyn VirtualTable>,
+    pub args: Vec<Expr>,
+    /// terms of the `where` clause on its columns, which it can use to skip rows
+    pub constraints: Vec<ColumnConstraint>,
+    /// number of columns the table declares, followed by a hidden `rowid` when it
+    /// does not declare one
+    num_columns: usize,
+}
+
+impl VirtualSource {
+    pub fn new(name: &str, table: Rc<dyn VirtualTable>, args: &[Expr]) -> Self {
+        let mut columns = table.columns();
+        let num_columns = columns.len();
+        if !columns.iter().any(|c| c.name == ROWID) {
+            columns.push(ColumnDef {
+                hidden: true,
+                ..ColumnDef::new(ROWID, "integer")
+            });
+        }
+        VirtualSource {
+            def: unstored_def(name, columns),
+            table,
+            args: args.to_vec(),
+            constraints: Vec::new(),
+            num_columns,
+        }
+    }
+
+    /// returns the rows for the arguments evaluated in `scope`
+    pub fn rows(&self, scope: &dyn Scope) -> Result<Vec<Row>, ExecuteResult> {
+        self.open(&*self.table, scope)?
+            .collect::<Result<_, _>>()
+            .map_err(ExecuteResult::EvalError)
+    }
+
+    /// returns the rows of the table as registered with `table`, for the
+    /// arguments evaluated in `scope`, read from its cursor as they are asked for
+    pub fn stream<'a>(
+        &self,
+        table: &'a Table,
+        scope: &dyn Scope,
+    ) -> Result<VirtualRows<'a>, ExecuteResult> {
+        match table.virtual_table(&self.def.name) {
+            Some(virtual_table) => self.open(&**virtual_table, scope),
+            None => Err(ExecuteResult::SchemaError(format!(
+                "no such table: {}",
+                self.def.name
+            ))),
+        }
+    }
+
+    fn open<'a>(
+        &self,
+        table: &'a dyn VirtualTable,
+        scope: &dyn Scope,
+    ) -> Result<VirtualRows<'a>, ExecuteResult> {
+        let mut args = self
+            .args
+            .iter()
+            .map(|arg| arg.eval(scope))
+            .collect::<Result<Vec<Value>, _>>()
+            .map_err(ExecuteResult::EvalError)?;
+        // the hidden columns past the arguments given take the values they are
+        // equal to in the `where` clause, so `where start = 1` reads like `(1)`
+        let hidden = self.def.columns[..self.num_columns]
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| column.hidden)
+            .skip(args.len());
+        for (i, _) in hidden {
+            let equal = self
+                .constraints
+                .iter()
+                .find(|c| c.column == i && c.op == ConstraintOp::Eq);
+            match equal {
+                Some(constraint) => args.push(constraint.value.clone()),
+                None => break,
+            }
+        }
+        let mut cursor = table.open().map_err(ExecuteResult::EvalError)?;
+        cursor
+            .filter(&args, &self.constraints)
+            .map_err(ExecuteResult::EvalError)?;
+        Ok(VirtualRows {
+            cursor,
+            num_columns: self.num_columns,
+            rowid: self.def.columns.len() > self.num_columns,
+            done: false,
+        })
+    }
+}
+
+/// Rows of a virtual table, read from its cursor as they are asked for
+pub struct VirtualRows<'a> {
+    cursor: Box<dyn VirtualCursor + 'a>,
+    num_columns: usize,
+    /// if the hidden `rowid` column follows the declared ones
+    rowid: bool,
+    /// a row failed, so no more are read
+    done: bool,
+}
+
+impl<'a> VirtualRows<'a> {
+    fn read(&mut self) -> Result<Row, EvalError> {
+        let cursor = &mut self.cursor;
+        let mut values = (0..self.num_columns)
+            .map(|i| cursor.column(i))
+            .collect::<Result<Vec<Value>, _>>()?;
+        if self.rowid {
+            values.push(Value::Integer(cursor.rowid()?));
+        }
+        cursor.next()?;
+        Ok(Row::new(values))
+    }
+}
+
+impl<'a> Iterator for VirtualRows<'a> {
+    type Item = Result<Row, EvalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.cursor.eof() {
+            return None;
+        }
+        let row = self.read();
+        self.done = row.is_err();
+        Some(row)
+    }
+}
+
+impl fmt::Debug for VirtualSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VirtualSource")
+            .field("def", &self.def)
+            .field("args", &self.args)
+            .field("constraints", &self.constraints)
+            .finish()
+    }
+}
+
+/// Where the rows of a table named by a select come from
+#[derive(Debug, Clone)]
+pub enum RowSource {
+    /// a table of the database, scanned with a `Cursor`
+    Table(TableDef),
+    /// a temporary table, which hides a table of the same name
+    Temp(Rc<TempTable>),
+    /// a table-valued function, computed again for each row it is joined with
+    Function(TableFunction),
+    /// a virtual table the application registered
+    Virtual(VirtualSource),
+}
+
+impl RowSource {
+    /// returns the source of the rows of the table `name`, or of the
+    /// table-valued function `name` when it is called with `args`.
+    /// a virtual table read by its name alone is called without arguments.
+    pub fn find(
+        table: &Table,
+        temps: &[Rc<TempTable>],
+        name: &str,
+        args: Option<&[Expr]>,
+    ) -> Option<Self> {
+        if let Some(args) = args {
+            if TableFunction::exists(name) {
+                return Some(RowSource::Function(TableFunction::new(name, args)));
+            }
+            let virtual_table = Rc::clone(table.virtual_table(name)?);
+            return Some(RowSource::Virtual(VirtualSource::new(
+                name,
+                virtual_table,
+                args,
+            )));
+        }
+        if let Some(temp) = temps.iter().rev().find(|temp| temp.def.name == name) {
+            return Some(RowSource::Temp(Rc::clone(temp)));
+        }
+        if let Some(def) = table.schema.table(name) {
+            return Some(RowSource::Table(def.clone()));
+        }
+        let virtual_table = Rc::clone(table.virtual_table(name)?);
+        Some(RowSource::Virtual(VirtualSource::new(
+            name,
+            virtual_table,
+            &[],
+        )))
+    }
+
+    pub fn def(&self) -> &TableDef {
+        match self {
+            RowSource::Table(def) => def,
+            RowSource::Temp(temp) => &temp.def,
+            RowSource::Function(function) => &function.def,
+            RowSource::Virtual(source) => &source.def,
+        }
+    }
+
+    /// returns every row, in key order for a table of the database. the
+    /// arguments of a table-valued function are evaluated in `scope`.
+    pub fn rows(&self, table: &mut Table, scope: &dyn Scope) -> Result<Vec<Row>, ExecuteResult> {
+        let def = match self {
+            RowSource::Table(def) => def,
+            RowSource::Temp(temp) => return Ok(temp.rows.clone()),
+            RowSource::Function(function) => return function.rows(scope),
+            RowSource::Virtual(source) => return source.rows(scope),
+        };
+
+        table
+            .table_start(def.root_page_num)
+            .rows()
+            .collect::<Result<_, _>>()
+            .map_err(ExecuteResult::InvalidRow)
+    }
+}
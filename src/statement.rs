@@ -1,145 +1,5778 @@
-use super::{btree::MAX_NUM_CELLS, row::*, table::*};
+use super::{
+    aggregate::{self, Aggregate},
+    btree::IndexNode,
+    expression::*,
+    function,
+    join::{self, Join, JoinKind, JoinStrategy},
+    json,
+    key::{decode_key, display_key, is_key_value, Key, MAX_KEY_SIZE},
+    lexer::Token,
+    parser::Parser,
+    result_set::{Column, ResultSet},
+    row::*,
+    schema::{ColumnDef, Index, Schema, TableDef},
+    sorter::Sorter,
+    source::{RowSource, TableFunction, TempTable},
+    table::*,
+    value::{hash_key, Value},
+    vtab::{ColumnConstraint, ConstraintOp},
+    window,
+};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fmt;
+use std::rc::Rc;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum StatementKind {
     Insert,
     Select,
+    Update,
+    Delete,
+    CreateTable,
+    CreateIndex,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// One entry of the column list of a select statement
+#[derive(Debug, PartialEq, Clone)]
+pub enum SelectItem {
+    /// `*`, expanding to every column of the table
+    Wildcard,
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+    },
+}
+
+/// Value a select is sorted by
+#[derive(Debug, PartialEq, Clone)]
+pub enum SortKey {
+    /// position in the select list, from `order by <alias>` or `order by <n>`
+    Output(usize),
+    Expr(Expr),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OrderTerm {
+    key: SortKey,
+    descending: bool,
+}
+
+/// What an insert does with a row whose primary key or unique value is taken
+#[derive(Debug, PartialEq, Clone)]
+pub enum OnConflict {
+    /// fail the statement, the default
+    Abort,
+    /// `insert or replace`: delete the stored rows in the way
+    Replace,
+    /// `insert or ignore` or `on conflict [(<column>, ...)] do nothing`: skip the row.
+    /// an empty target matches every constraint.
+    Nothing { target: Vec<String> },
+    /// `on conflict [(<column>, ...)] do update set ... [where <expr>]`: update the
+    /// stored row instead, where `excluded.<column>` reads the row that was not inserted
+    Update {
+        target: Vec<String>,
+        assignments: Vec<(String, Expr)>,
+        condition: Option<Expr>,
+    },
+}
+
+impl OnConflict {
+    /// returns the columns of the constraint the clause handles, empty for any
+    fn target(&self) -> &[String] {
+        match self {
+            OnConflict::Nothing { target } | OnConflict::Update { target, .. } => target,
+            OnConflict::Abort | OnConflict::Replace => &[],
+        }
+    }
+
+    /// returns if the clause skips or updates a row conflicting on the given columns
+    fn handles(&self, columns: &[&str]) -> bool {
+        let target = self.target();
+        match self {
+            OnConflict::Abort | OnConflict::Replace => false,
+            _ => {
+                target.is_empty()
+                    || (target.len() == columns.len()
+                        && columns.iter().all(|c| target.iter().any(|t| t == c)))
+            }
+        }
+    }
+}
+
+/// A query used inside an expression, along with its sql for printing
+#[derive(Debug, PartialEq, Clone)]
+pub struct Subquery {
+    pub statement: Statement,
+    pub sql: String,
+}
+
+/// `<name> [(<column> [, ...])] as (<select>)` of a `with` clause
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cte {
+    name: String,
+    /// names of the columns, or empty to name them after the result columns
+    columns: Vec<String>,
+    query: Statement,
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Statement {
     kind: StatementKind,
-    row: Option<Row>,
+    /// temporary tables of the `with` clause, computed before the statement runs
+    with: Vec<Cte>,
+    /// `with recursive`, letting a table of the `with` clause read its own rows
+    recursive: bool,
+    /// temporary tables the statement can read, set when it runs
+    temps: Vec<Rc<TempTable>>,
+    /// subqueries reading the columns of the rows of the select, set when it runs.
+    /// each is computed for every row into the hidden column of the given name.
+    correlated: Vec<(String, Expr)>,
+    /// table the statement reads or writes
+    table: String,
+    /// arguments when the table is a table-valued function, `from <function>(...)`
+    table_args: Option<Vec<Expr>>,
+    /// name the table is read under, `from <table> [as] <alias>`
+    alias: Option<String>,
+    /// tables joined with the table, in order
+    joins: Vec<Join>,
+    /// columns named by `insert into <table> (...)`. empty means every column.
+    columns: Vec<String>,
+    /// values of each row inserted by `values (...), ...`
+    rows: Vec<Vec<Expr>>,
+    /// select whose result rows are inserted
+    source: Option<Box<Statement>>,
+    on_conflict: OnConflict,
+    /// select list, or the `returning` list of an insert, update or delete
+    projection: Vec<SelectItem>,
+    /// selects whose rows are added by `union`, or `union all` when true.
+    /// `order by`, `limit` and `offset` then apply to the rows of all of them.
+    compound: Vec<(bool, Statement)>,
+    condition: Option<Expr>,
+    assignments: Vec<(String, Expr)>,
+    group_by: Vec<Expr>,
+    having: Option<Expr>,
+    order_by: Vec<OrderTerm>,
+    /// count and skip of `limit`, integers or parameters read once they are bound
+    limit: Option<(Expr, Expr)>,
+    /// table to create, and whether an existing table of that name is fine
+    create_table: Option<(TableDef, bool)>,
+    /// index to create
+    index: Option<Index>,
+    /// names of the parameters by their index, counted from 1
+    parameters: Vec<Option<String>>,
+    /// values bound to the parameters, NULL until bound
+    bindings: Vec<Value>,
+}
+
+pub type StatementError = String;
+
+/// Kind of a column constraint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    NotNull,
+    Unique,
+    Check,
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constraint::NotNull => write!(f, "NOT NULL"),
+            Constraint::Unique => write!(f, "UNIQUE"),
+            Constraint::Check => write!(f, "CHECK"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExecuteResult {
+    InsertSuccess,
+    SelectSuccess(ResultSet),
+    UpdateSuccess(usize),
+    DeleteSuccess(usize),
+    CreateTableSuccess,
+    CreateIndexSuccess,
+    TableFull,
+    EmptyRow,
+    DuplicatedKey(String),
+    /// a write would break the constraint on the column of the table
+    ConstraintViolation {
+        constraint: Constraint,
+        table: String,
+        column: String,
+    },
+    InvalidRow(RowError),
+    EvalError(EvalError),
+    SchemaError(String),
+    IoError(String),
+}
+
+impl ExecuteResult {
+    /// returns why the statement failed, or `None` when it succeeded
+    pub fn error(&self) -> Option<String> {
+        match self {
+            ExecuteResult::InsertSuccess
+            | ExecuteResult::SelectSuccess(_)
+            | ExecuteResult::UpdateSuccess(_)
+            | ExecuteResult::DeleteSuccess(_)
+            | ExecuteResult::CreateTableSuccess
+            | ExecuteResult::CreateIndexSuccess => None,
+            ExecuteResult::TableFull => Some(String::from("Table full")),
+            ExecuteResult::EmptyRow => Some(String::from("Empty row")),
+            ExecuteResult::DuplicatedKey(key) => Some(format!("Duplicate key {}", key)),
+            ExecuteResult::ConstraintViolation {
+                constraint: Constraint::Check,
+                column,
+                ..
+            } => Some(format!("CHECK constraint failed: {}", column)),
+            ExecuteResult::ConstraintViolation {
+                constraint,
+                table,
+                column,
+            } => Some(format!(
+                "{} constraint failed: {}.{}",
+                constraint, table, column
+            )),
+            ExecuteResult::InvalidRow(reason) => Some(reason.to_string()),
+            ExecuteResult::EvalError(reason) => Some(reason.to_string()),
+            ExecuteResult::SchemaError(reason) => Some(reason.clone()),
+            ExecuteResult::IoError(reason) => Some(reason.clone()),
+        }
+    }
+
+    fn violation(constraint: Constraint, table: &TableDef, column: &str) -> Self {
+        ExecuteResult::ConstraintViolation {
+            constraint,
+            table: table.name.clone(),
+            column: String::from(column),
+        }
+    }
+}
+
+// Table used by statements that do not name one
+const TABLE_NAME: &str = "users";
+/// table name an upsert reads the row it did not insert from
+const EXCLUDED: &str = "excluded";
+
+fn parse_table_name(parser: &mut Parser) -> Result<String, StatementError> {
+    Ok(parser.expect_ident()?.to_lowercase())
+}
+
+/// parses a table name, or a call of a table-valued function `<name>(<expr> [, ...])`
+fn parse_table(parser: &mut Parser) -> Result<(String, Option<Vec<Expr>>), StatementError> {
+    let name = parse_table_name(parser)?;
+    if !parser.consume_symbol("(") {
+        return Ok((name, None));
+    }
+    let args = parser.parse_expr_list()?;
+    parser.expect_symbol(")")?;
+    TableFunction::check(&name, args.len())?;
+    for arg in args.iter() {
+        check_expr(arg)?;
+        check_no_aggregate(arg, "FROM")?;
+        check_no_window(arg)?;
+    }
+    Ok((name, Some(args)))
+}
+
+/// parses an optional `[as] <alias>` following a table name
+fn parse_alias(parser: &mut Parser) -> Result<Option<String>, StatementError> {
+    if parser.consume_keyword("as") {
+        return Ok(Some(parser.expect_ident()?.to_lowercase()));
+    }
+    Ok(parser.expect_ident().ok().map(|alias| alias.to_lowercase()))
+}
+
+/// parses the joins following the first table of `from`:
+/// `, <table>`, `cross join <table>`, or `[inner | left [outer]] join <table> [on <expr>]`,
+/// each table optionally followed by an alias. a table can be a table-valued function.
+fn parse_joins(parser: &mut Parser) -> Result<Vec<Join>, StatementError> {
+    let mut joins = Vec::new();
+    loop {
+        let (kind, on_allowed) = if parser.consume_symbol(",") {
+            (JoinKind::Inner, false)
+        } else if parser.consume_keyword("cross") {
+            parser.expect_keyword("join")?;
+            (JoinKind::Inner, false)
+        } else if parser.consume_keyword("left") {
+            parser.consume_keyword("outer");
+            parser.expect_keyword("join")?;
+            (JoinKind::Left, true)
+        } else if parser.consume_keyword("inner") || parser.peek_keyword("join") {
+            parser.expect_keyword("join")?;
+            (JoinKind::Inner, true)
+        } else {
+            return Ok(joins);
+        };
+
+        let (table, args) = parse_table(parser)?;
+        let alias = parse_alias(parser)?;
+        let on = if on_allowed && parser.consume_keyword("on") {
+            let on = parser.parse_expr()?;
+            check_expr(&on)?;
+            check_no_aggregate(&on, "ON")?;
+            check_no_window(&on)?;
+            Some(on)
+        } else {
+            None
+        };
+        joins.push(Join {
+            kind,
+            table,
+            args,
+            alias,
+            on,
+        });
+    }
+}
+
+/// parses `<column> = <expr> [, ...]` following `set`
+fn parse_assignments(parser: &mut Parser) -> Result<Vec<(String, Expr)>, StatementError> {
+    let mut assignments = Vec::new();
+    loop {
+        let column = parser.expect_ident()?.to_lowercase();
+        parser.expect_symbol("=")?;
+        let value = parser.parse_expr()?;
+        check_expr(&value)?;
+        check_no_aggregate(&value, "SET")?;
+        assignments.push((column, value));
+
+        if !parser.consume_symbol(",") {
+            break;
+        }
+    }
+    Ok(assignments)
+}
+
+/// parses optional `where <expr>` at the end of a statement
+fn parse_where(parser: &mut Parser) -> Result<Option<Expr>, StatementError> {
+    if parser.consume_keyword("where") {
+        return Ok(Some(parser.parse_expr()?));
+    }
+    Ok(None)
+}
+
+/// checks that every function used in the expression exists and is called properly.
+/// columns are checked against the table when the statement is executed.
+fn check_expr(expr: &Expr) -> Result<(), StatementError> {
+    let mut error = None;
+    expr.walk(&mut |expr| {
+        if error.is_some() {
+            return false;
+        }
+        error = match expr {
+            Expr::Function { name, args, .. } if aggregate::is_aggregate(name) => {
+                aggregate::check_arguments(name, args.len())
+                    .err()
+                    .or_else(|| {
+                        // an aggregate can not run inside another one
+                        args.iter()
+                            .find(|arg| arg.contains_aggregate())
+                            .map(|_| format!("misuse of aggregate function {}()", name))
+                    })
+            }
+            Expr::Function { name, args, .. } if function::is_function(name) => {
+                function::check_arguments(name, args.len()).err()
+            }
+            Expr::Window { name, args, .. } => {
+                window::check_arguments(name, args.len()).err().or_else(|| {
+                    // a window function can not run inside another one
+                    args.iter().find_map(|arg| check_no_window(arg).err())
+                })
+            }
+            Expr::Function { name, .. } if window::is_window_function(name) => {
+                Some(format!("misuse of window function {}()", name))
+            }
+            // other functions may be defined by the application by the time the statement runs
+            _ => None,
+        };
+        error.is_none()
+    });
+
+    match error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// fails when the expression calls a function that is not built in, as the
+/// expressions of a table have to work without the functions of an application
+fn check_builtin(expr: &Expr) -> Result<(), StatementError> {
+    let mut error = None;
+    expr.walk(&mut |expr| {
+        if let Expr::Function { name, .. } = expr {
+            let builtin = function::is_function(name)
+                || aggregate::is_aggregate(name)
+                || window::is_window_function(name);
+            if !builtin && error.is_none() {
+                error = Some(format!("no such function: {}", name));
+            }
+        }
+        error.is_none()
+    });
+
+    match error {
+        Some(error) => Err(error),
+        None => check_expr(expr),
+    }
+}
+
+/// fails when the clause calls an aggregate function, which needs a whole group of rows
+fn check_no_aggregate(expr: &Expr, clause: &str) -> Result<(), StatementError> {
+    if expr.contains_aggregate() {
+        return Err(format!(
+            "aggregate functions are not allowed in the {} clause",
+            clause
+        ));
+    }
+    Ok(())
+}
+
+/// fails when the expression calls a window function, which only runs over result rows
+fn check_no_window(expr: &Expr) -> Result<(), StatementError> {
+    match expr.find_window() {
+        Some(Expr::Window { name, .. }) => Err(format!("misuse of window function {}()", name)),
+        _ => Ok(()),
+    }
+}
+
+/// fails when the expression reads a column the table does not have,
+/// or one that several joined tables have
+fn check_columns(table: &TableDef, expr: &Expr) -> Result<(), ExecuteResult> {
+    let mut missing = None;
+    expr.visit_columns(&mut |name| {
+        if missing.is_none() && table.column_index(name).is_none() {
+            missing = Some(if table.is_ambiguous(name) {
+                format!("ambiguous column name: {}", name)
+            } else {
+                format!("no such column: {}", name)
+            });
+        }
+    });
+
+    match missing {
+        Some(error) => Err(ExecuteResult::SchemaError(error)),
+        None => Ok(()),
+    }
+}
+
+/// converts the values of a row about to be written to the types of their
+/// columns, and checks the primary key, `not null` and `check` constraints
+/// and the text of `json` columns
+fn check_row(table: &TableDef, row: Row) -> Result<Row, ExecuteResult> {
+    let values: Vec<Value> = row
+        .values
+        .into_iter()
+        .zip(table.columns.iter())
+        .map(|(value, column)| value.apply_affinity(column.data_type()))
+        .collect();
+
+    for (i, (value, column)) in values.iter().zip(table.columns.iter()).enumerate() {
+        if value.is_null() && (column.not_null || table.is_primary_key(i)) {
+            return Err(ExecuteResult::violation(
+                Constraint::NotNull,
+                table,
+                &column.name,
+            ));
+        }
+        if column.is_json() && value.as_text().is_some_and(|text| !json::is_valid(&text)) {
+            return Err(ExecuteResult::InvalidRow(format!(
+                "`{}` is not valid JSON for {}",
+                value, column.name
+            )));
+        }
+    }
+
+    // keys are made of integers and text, and the integer primary key only holds integers
+    for &i in table.primary_key.iter() {
+        let valid = match values[i] {
+            Value::Text(_) => table.rowid() != Some(i),
+            ref value => is_key_value(value),
+        };
+        if !valid {
+            return Err(ExecuteResult::InvalidRow(format!(
+                "`{}` is not a valid {}",
+                values[i], table.columns[i].name
+            )));
+        }
+    }
+
+    let row = Row::new(values);
+    if table.key(&row).len() > MAX_KEY_SIZE {
+        return Err(ExecuteResult::InvalidRow(format!(
+            "primary key of table {} is too long",
+            table.name
+        )));
+    }
+    // a check only fails when it is false, not when it is NULL
+    for check in table.checks.iter() {
+        let result = check
+            .eval(&RowScope { table, row: &row })
+            .map_err(ExecuteResult::EvalError)?;
+        if result.truthy() == Some(false) {
+            return Err(ExecuteResult::violation(
+                Constraint::Check,
+                table,
+                &check.to_string(),
+            ));
+        }
+    }
+
+    Ok(row)
+}
+
+/// fails when the `added` rows would share a value of a unique column with each
+/// other or with a stored row. rows with the `replaced` keys, which must be
+/// sorted, are about to be rewritten and do not count.
+fn check_unique(
+    table: &Table,
+    def: &TableDef,
+    replaced: &[Key],
+    added: &[&Row],
+) -> Result<(), ExecuteResult> {
+    for index in table.schema.indexes_of(&def.name).filter(|i| i.unique) {
+        let column = def.column_index(&index.column).unwrap();
+        let node = table.pager.get_index(index.root_page_num).unwrap();
+
+        let mut seen = HashSet::new();
+        for row in added.iter() {
+            let value = &row.values[column];
+            // NULLs are distinct from each other
+            if value.is_null() {
+                continue;
+            }
+
+            let stored = node
+                .lookup(value)
+                .iter()
+                .any(|key| replaced.binary_search(key).is_err());
+            if stored || !seen.insert(hash_key(std::slice::from_ref(value))) {
+                return Err(ExecuteResult::violation(
+                    Constraint::Unique,
+                    def,
+                    &index.column,
+                ));
+            }
+        }
+    }
+    Ok(())
 }
 
-pub type StatementError = String;
+impl Statement {
+    fn new(kind: StatementKind) -> Self {
+        Statement {
+            kind,
+            with: Vec::new(),
+            recursive: false,
+            temps: Vec::new(),
+            correlated: Vec::new(),
+            table: String::from(TABLE_NAME),
+            table_args: None,
+            alias: None,
+            joins: Vec::new(),
+            columns: Vec::new(),
+            rows: Vec::new(),
+            source: None,
+            on_conflict: OnConflict::Abort,
+            projection: Vec::new(),
+            compound: Vec::new(),
+            condition: None,
+            assignments: Vec::new(),
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            limit: None,
+            create_table: None,
+            index: None,
+            parameters: Vec::new(),
+            bindings: Vec::new(),
+        }
+    }
+
+    pub fn prepare(input: &str) -> Result<Self, StatementError> {
+        let raw_args: Vec<&str> = input.split_whitespace().collect();
+        let insert_into = raw_args
+            .get(1)
+            .map(|arg| arg.eq_ignore_ascii_case("into") || arg.eq_ignore_ascii_case("or"))
+            .unwrap_or(false);
+        if input.starts_with("insert") && !insert_into {
+            return Statement::prepare_users_insert(&raw_args);
+        }
+
+        let mut parser = Parser::new(input)?;
+        let statement = if parser.peek_keyword("with") {
+            Statement::prepare_with(&mut parser)?
+        } else {
+            Statement::prepare_body(&mut parser)?
+        };
+        parser.expect_end()?;
+
+        let parameters = parser.parameters().to_vec();
+        if !parameters.is_empty()
+            && matches!(
+                statement.kind,
+                StatementKind::CreateTable | StatementKind::CreateIndex
+            )
+        {
+            return Err(String::from("parameters are not allowed in CREATE"));
+        }
+        Ok(Statement {
+            bindings: vec![Value::Null; parameters.len()],
+            parameters,
+            ..statement
+        })
+    }
+
+    /// returns the number of parameters, which is the largest index of one
+    pub fn parameter_count(&self) -> usize {
+        self.parameters.len()
+    }
+
+    /// returns the index of the parameter `:<name>`, counted from 1
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        self.parameters
+            .iter()
+            .position(|parameter| parameter.as_deref() == Some(name))
+            .map(|i| i + 1)
+    }
+
+    /// binds the value to the parameter at `index`, counted from 1. it is used
+    /// by every following execution until it is bound again or reset.
+    pub fn bind(&mut self, index: usize, value: Value) -> Result<(), StatementError> {
+        match index.checked_sub(1).and_then(|i| self.bindings.get_mut(i)) {
+            Some(binding) => {
+                *binding = value;
+                Ok(())
+            }
+            None => Err(format!("parameter index {} out of range", index)),
+        }
+    }
+
+    /// binds the value to the parameter `:<name>`
+    pub fn bind_named(&mut self, name: &str, value: Value) -> Result<(), StatementError> {
+        let index = self
+            .parameter_index(name)
+            .ok_or_else(|| format!("no such parameter: {}", name))?;
+        self.bind(index, value)
+    }
+
+    /// unbinds every parameter, which reads as NULL until it is bound again
+    pub fn reset(&mut self) {
+        self.bindings.fill(Value::Null);
+    }
+
+    /// parses a statement following its `with` clause
+    fn prepare_body(parser: &mut Parser) -> Result<Self, StatementError> {
+        if parser.consume_keyword("select") {
+            Statement::prepare_select(parser)?.parse_clauses(parser)
+        } else if parser.consume_keyword("update") {
+            Statement::prepare_update(parser)?.parse_clauses(parser)
+        } else if parser.consume_keyword("delete") {
+            Statement::prepare_delete(parser)?.parse_clauses(parser)
+        } else if parser.consume_keyword("insert") {
+            Statement::prepare_insert(parser)
+        } else if parser.consume_keyword("create") {
+            Statement::prepare_create(parser)
+        } else {
+            Err(String::from("invalid input"))
+        }
+    }
+
+    /// with [recursive] <name> [(<column> [, ...])] as (<select>) [, ...] <statement>
+    fn prepare_with(parser: &mut Parser) -> Result<Self, StatementError> {
+        parser.expect_keyword("with")?;
+        let recursive = parser.consume_keyword("recursive");
+        let mut with = Vec::new();
+        loop {
+            let name = parse_table_name(parser)?;
+            let mut columns = Vec::new();
+            if parser.consume_symbol("(") {
+                loop {
+                    columns.push(parser.expect_ident()?.to_lowercase());
+                    if !parser.consume_symbol(",") {
+                        break;
+                    }
+                }
+                parser.expect_symbol(")")?;
+            }
+            parser.expect_keyword("as")?;
+            parser.expect_symbol("(")?;
+            let query = Statement::parse_query(parser)?;
+            parser.expect_symbol(")")?;
+            with.push(Cte {
+                name,
+                columns,
+                query,
+            });
+
+            if !parser.consume_symbol(",") {
+                break;
+            }
+        }
+
+        let statement = Statement::prepare_body(parser)?;
+        if matches!(
+            statement.kind,
+            StatementKind::CreateTable | StatementKind::CreateIndex
+        ) {
+            return Err(String::from("WITH can not be used with CREATE"));
+        }
+        Ok(Statement {
+            with,
+            recursive,
+            ..statement
+        })
+    }
+
+    /// parses `[with ...] select ...`, the query of a subquery or of a `with` clause
+    pub fn parse_query(parser: &mut Parser) -> Result<Self, StatementError> {
+        let statement = if parser.peek_keyword("with") {
+            Statement::prepare_with(parser)?
+        } else {
+            parser.expect_keyword("select")?;
+            Statement::prepare_select(parser)?.parse_clauses(parser)?
+        };
+        if statement.kind != StatementKind::Select {
+            return Err(String::from("expected a select"));
+        }
+        Ok(statement)
+    }
+
+    /// parses the `where` clause and the clauses following it in a select,
+    /// then checks the expressions of the statement
+    fn parse_clauses(mut self, parser: &mut Parser) -> Result<Self, StatementError> {
+        self.condition = parse_where(parser)?;
+        if self.kind == StatementKind::Select {
+            self.parse_group_by(parser)?;
+            self.parse_compound(parser)?;
+            self.parse_order_by(parser)?;
+            self.parse_limit(parser)?;
+        } else {
+            self.parse_returning(parser)?;
+        }
+        self.check_clauses()
+    }
+
+    /// checks the expressions of the statement
+    fn check_clauses(self) -> Result<Self, StatementError> {
+        if let Some(condition) = &self.condition {
+            check_expr(condition)?;
+            check_no_aggregate(condition, "WHERE")?;
+            check_no_window(condition)?;
+        }
+        for expr in self.group_by.iter() {
+            check_expr(expr)?;
+            check_no_aggregate(expr, "GROUP BY")?;
+            check_no_window(expr)?;
+        }
+        if let Some(having) = &self.having {
+            check_no_window(having)?;
+        }
+        for expr in self.selected_exprs() {
+            check_expr(expr)?;
+        }
+
+        Ok(self)
+    }
+
+    /// insert [<id>] <username> <email>, the shorthand for inserting into users.
+    /// the id is allocated when it is left out.
+    fn prepare_users_insert(raw_args: &[&str]) -> Result<Self, StatementError> {
+        // check if length of arguments match length of the table
+        let (id, username, email) = match raw_args {
+            [_, id, username, email] => match id.parse::<i64>() {
+                Ok(id) => (Value::Integer(id), username, email),
+                Err(_) => return Err(format!("`{}` is not a valid id", id)),
+            },
+            [_, username, email] => (Value::Null, username, email),
+            _ => return Err(String::from("arguments length does not match")),
+        };
+
+        let values = vec![
+            Expr::Literal(id),
+            Expr::Literal(Value::Text(String::from(*username))),
+            Expr::Literal(Value::Text(String::from(*email))),
+        ];
+        Ok(Statement {
+            rows: vec![values],
+            ..Statement::new(StatementKind::Insert)
+        })
+    }
+
+    /// insert [or replace | or ignore] into <table> [(<column> [, ...])] <rows> [<upsert>]
+    /// where the rows are `values (<expr> [, ...]) [, ...]` or `select ...`, and the
+    /// upsert is `on conflict [(<column> [, ...])] do nothing | do update set ... [where ...]`
+    fn prepare_insert(parser: &mut Parser) -> Result<Self, StatementError> {
+        let mut on_conflict = OnConflict::Abort;
+        if parser.consume_keyword("or") {
+            on_conflict = if parser.consume_keyword("replace") {
+                OnConflict::Replace
+            } else if parser.consume_keyword("ignore") {
+                OnConflict::Nothing { target: Vec::new() }
+            } else {
+                parser.expect_keyword("abort")?;
+                OnConflict::Abort
+            };
+        }
+        parser.expect_keyword("into")?;
+        let table = parse_table_name(parser)?;
+
+        let mut columns = Vec::new();
+        if parser.consume_symbol("(") {
+            loop {
+                columns.push(parser.expect_ident()?.to_lowercase());
+                if !parser.consume_symbol(",") {
+                    break;
+                }
+            }
+            parser.expect_symbol(")")?;
+        }
+
+        let mut statement = Statement {
+            table,
+            columns,
+            ..Statement::new(StatementKind::Insert)
+        };
+        if parser.consume_keyword("select") {
+            let source = Statement::prepare_select(parser)?.parse_clauses(parser)?;
+            statement.source = Some(Box::new(source));
+        } else {
+            statement.rows = Statement::parse_values(parser)?;
+        }
+
+        if parser.consume_keyword("on") {
+            if on_conflict != OnConflict::Abort {
+                return Err(String::from(
+                    "ON CONFLICT can not be combined with OR REPLACE or OR IGNORE",
+                ));
+            }
+            on_conflict = Statement::parse_upsert(parser)?;
+        }
+        statement.on_conflict = on_conflict;
+        statement.parse_returning(parser)?;
+        Ok(statement)
+    }
+
+    /// parses `values (<expr> [, ...]) [, ...]`
+    fn parse_values(parser: &mut Parser) -> Result<Vec<Vec<Expr>>, StatementError> {
+        parser.expect_keyword("values")?;
+        let mut rows = Vec::new();
+        loop {
+            parser.expect_symbol("(")?;
+            let values = parser.parse_expr_list()?;
+            parser.expect_symbol(")")?;
+            for value in values.iter() {
+                check_expr(value)?;
+                check_no_aggregate(value, "VALUES")?;
+            }
+            if rows
+                .first()
+                .is_some_and(|first: &Vec<Expr>| first.len() != values.len())
+            {
+                return Err(String::from(
+                    "all VALUES must have the same number of terms",
+                ));
+            }
+            rows.push(values);
+            if !parser.consume_symbol(",") {
+                break;
+            }
+        }
+        Ok(rows)
+    }
+
+    /// parses the part of an upsert following `on`
+    fn parse_upsert(parser: &mut Parser) -> Result<OnConflict, StatementError> {
+        parser.expect_keyword("conflict")?;
+        let mut target = Vec::new();
+        if parser.consume_symbol("(") {
+            loop {
+                target.push(parser.expect_ident()?.to_lowercase());
+                if !parser.consume_symbol(",") {
+                    break;
+                }
+            }
+            parser.expect_symbol(")")?;
+        }
+
+        parser.expect_keyword("do")?;
+        if parser.consume_keyword("nothing") {
+            return Ok(OnConflict::Nothing { target });
+        }
+        parser.expect_keyword("update")?;
+        parser.expect_keyword("set")?;
+        let assignments = parse_assignments(parser)?;
+        let condition = parse_where(parser)?;
+        if let Some(condition) = &condition {
+            check_expr(condition)?;
+            check_no_aggregate(condition, "WHERE")?;
+        }
+        Ok(OnConflict::Update {
+            target,
+            assignments,
+            condition,
+        })
+    }
+
+    /// select [<item> [, ...]] [from <table>] [where <expr>]
+    /// where each item is `*` or `<expr> [[as] <alias>]`. no items means `*`
+    /// from the users table, while items without `from` are computed once.
+    fn prepare_select(parser: &mut Parser) -> Result<Self, StatementError> {
+        let mut projection = Vec::new();
+        let implicit_wildcard = parser.is_end()
+            || ["from", "where", "group", "having", "order", "limit"]
+                .iter()
+                .any(|k| parser.peek_keyword(k));
+        if !implicit_wildcard {
+            loop {
+                projection.push(Statement::parse_select_item(parser)?);
+                if !parser.consume_symbol(",") {
+                    break;
+                }
+            }
+        } else {
+            projection.push(SelectItem::Wildcard);
+        }
+
+        let mut statement = Statement::new(StatementKind::Select);
+        if parser.consume_keyword("from") {
+            let (table, args) = parse_table(parser)?;
+            // the first table has no columns before it to read
+            for arg in args.iter().flatten() {
+                let mut column = None;
+                arg.visit_columns(&mut |name| {
+                    column.get_or_insert_with(|| name.to_string());
+                });
+                if let Some(column) = column {
+                    return Err(format!("no such column: {}", column));
+                }
+            }
+            statement.table = table;
+            statement.table_args = args;
+            statement.alias = parse_alias(parser)?;
+            statement.joins = parse_joins(parser)?;
+        } else if !implicit_wildcard {
+            if projection.contains(&SelectItem::Wildcard) {
+                return Err(String::from("no tables specified"));
+            }
+            statement.table = String::new();
+        }
+
+        Ok(Statement {
+            projection,
+            ..statement
+        })
+    }
+
+    fn parse_select_item(parser: &mut Parser) -> Result<SelectItem, StatementError> {
+        if parser.consume_symbol("*") {
+            return Ok(SelectItem::Wildcard);
+        }
+
+        let expr = parser.parse_expr()?;
+        let alias = if parser.consume_keyword("as") {
+            Some(parser.expect_ident()?)
+        } else {
+            // the alias may also follow the expression directly
+            parser.expect_ident().ok()
+        };
+
+        Ok(SelectItem::Expr { expr, alias })
+    }
+
+    /// parses `union [all] select ...` following the `group by` clause of a select
+    fn parse_compound(&mut self, parser: &mut Parser) -> Result<(), StatementError> {
+        while parser.consume_keyword("union") {
+            let all = parser.consume_keyword("all");
+            parser.expect_keyword("select")?;
+            let mut core = Statement::prepare_select(parser)?;
+            core.condition = parse_where(parser)?;
+            core.parse_group_by(parser)?;
+            self.compound.push((all, core.check_clauses()?));
+        }
+        Ok(())
+    }
+
+    /// returning <expr> [[as] <alias>] [, ...], listing what a write statement
+    /// returns of every row it writes
+    fn parse_returning(&mut self, parser: &mut Parser) -> Result<(), StatementError> {
+        if !parser.consume_keyword("returning") {
+            return Ok(());
+        }
+        loop {
+            let item = Statement::parse_select_item(parser)?;
+            if let SelectItem::Expr { expr, .. } = &item {
+                check_expr(expr)?;
+                check_no_aggregate(expr, "RETURNING")?;
+            }
+            self.projection.push(item);
+            if !parser.consume_symbol(",") {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// group by <expr> [, ...] [having <expr>]. `having` may also be used alone.
+    fn parse_group_by(&mut self, parser: &mut Parser) -> Result<(), StatementError> {
+        if parser.consume_keyword("group") {
+            parser.expect_keyword("by")?;
+            self.group_by = parser.parse_expr_list()?;
+        }
+        if parser.consume_keyword("having") {
+            self.having = Some(parser.parse_expr()?);
+        }
+        Ok(())
+    }
+
+    /// order by <expr> [asc|desc] [, ...]
+    fn parse_order_by(&mut self, parser: &mut Parser) -> Result<(), StatementError> {
+        if !parser.consume_keyword("order") {
+            return Ok(());
+        }
+        parser.expect_keyword("by")?;
+
+        loop {
+            let key = SortKey::Expr(parser.parse_expr()?);
+            let descending = if parser.consume_keyword("desc") {
+                true
+            } else {
+                parser.consume_keyword("asc");
+                false
+            };
+            self.order_by.push(OrderTerm { key, descending });
+
+            if !parser.consume_symbol(",") {
+                return Ok(());
+            }
+        }
+    }
+
+    /// limit <count> [offset <skip>] or limit <skip>, <count>.
+    /// a negative count means no limit.
+    fn parse_limit(&mut self, parser: &mut Parser) -> Result<(), StatementError> {
+        if !parser.consume_keyword("limit") {
+            return Ok(());
+        }
+
+        let mut count = Statement::parse_count(parser)?;
+        let mut skip = Expr::Literal(Value::Integer(0));
+        if parser.consume_keyword("offset") {
+            skip = Statement::parse_count(parser)?;
+        } else if parser.consume_symbol(",") {
+            skip = count;
+            count = Statement::parse_count(parser)?;
+        }
+
+        self.limit = Some((count, skip));
+        Ok(())
+    }
+
+    /// an integer, or a parameter bound to one
+    fn parse_count(parser: &mut Parser) -> Result<Expr, StatementError> {
+        if let Some(Token::Parameter(_)) = parser.peek() {
+            return parser.parse_expr();
+        }
+        Ok(Expr::Literal(Value::Integer(parser.expect_integer()?)))
+    }
+
+    /// returns the `limit` and `offset` of the statement with the values bound
+    /// to their parameters, which must be integers. a negative count means no limit.
+    fn bound_limit(&self) -> Result<(Option<usize>, usize), ExecuteResult> {
+        let (count, skip) = match &self.limit {
+            Some(exprs) => exprs,
+            None => return Ok((None, 0)),
+        };
+        let integer = |clause: &str, expr: &Expr| match expr.eval(&EmptyScope) {
+            Ok(Value::Integer(n)) => Ok(n),
+            Ok(value) => Err(ExecuteResult::EvalError(format!(
+                "{} must be an integer, not {}",
+                clause, value
+            ))),
+            Err(e) => Err(ExecuteResult::EvalError(e)),
+        };
+        let (count, skip) = (integer("LIMIT", count)?, integer("OFFSET", skip)?);
+        let limit = if count < 0 {
+            None
+        } else {
+            Some(count as usize)
+        };
+        Ok((limit, skip.max(0) as usize))
+    }
+
+    /// returns a copy of the statement whose names are checked against the table,
+    /// with sort keys naming a result column by alias or position resolved
+    fn resolve(&self, schema: &Schema, table: &TableDef) -> Result<Statement, ExecuteResult> {
+        let mut statement = self.clone();
+        for term in statement.order_by.iter_mut() {
+            if let SortKey::Expr(expr) = &term.key {
+                term.key = self.resolve_sort_key(table, expr)?;
+            }
+        }
+
+        for column in statement.columns.iter() {
+            if table.column_index(column).is_none() {
+                return Err(ExecuteResult::SchemaError(format!(
+                    "table {} has no column named {}",
+                    table.name, column
+                )));
+            }
+        }
+        for (column, _) in statement.assignments.iter() {
+            if table.column_index(column).is_none() {
+                return Err(ExecuteResult::SchemaError(format!(
+                    "no such column: {}",
+                    column
+                )));
+            }
+        }
+        if let Some(index) = &statement.index {
+            if table.column_index(&index.column).is_none() {
+                return Err(ExecuteResult::SchemaError(format!(
+                    "no such column: {}",
+                    index.column
+                )));
+            }
+        }
+
+        statement.resolve_on_conflict(schema, table)?;
+
+        let mut exprs = statement.selected_exprs();
+        exprs.extend(statement.condition.iter());
+        exprs.extend(statement.group_by.iter());
+        exprs.extend(statement.assignments.iter().map(|(_, value)| value));
+        for expr in exprs {
+            check_columns(table, expr)?;
+        }
+
+        Ok(statement)
+    }
+
+    /// checks the conflict target names the primary key, a unique column or a unique index,
+    /// and the upsert reads and writes columns the table has
+    fn resolve_on_conflict(&self, schema: &Schema, table: &TableDef) -> Result<(), ExecuteResult> {
+        let target = self.on_conflict.target();
+        if target.is_empty() {
+            return Ok(());
+        }
+        for column in target.iter() {
+            if table.column_index(column).is_none() {
+                return Err(ExecuteResult::SchemaError(format!(
+                    "no such column: {}",
+                    column
+                )));
+            }
+        }
+
+        let primary_key: Vec<&str> = table
+            .primary_key
+            .iter()
+            .map(|&i| table.columns[i].name.as_str())
+            .collect();
+        let unique = target.len() == 1
+            && (table.column(&target[0]).is_some_and(|column| column.unique)
+                || schema
+                    .indexes_of(&table.name)
+                    .any(|index| index.unique && index.column.eq_ignore_ascii_case(&target[0])));
+        if !unique && !self.on_conflict.handles(&primary_key) {
+            return Err(ExecuteResult::SchemaError(String::from(
+                "ON CONFLICT clause does not match any PRIMARY KEY or UNIQUE constraint",
+            )));
+        }
+
+        if let OnConflict::Update {
+            assignments,
+            condition,
+            ..
+        } = &self.on_conflict
+        {
+            // `excluded.<column>` reads the row that was about to be inserted
+            let excluded = TableDef {
+                name: String::from(EXCLUDED),
+                ..table.clone()
+            };
+            let mut missing = None;
+            let mut exprs: Vec<&Expr> = assignments.iter().map(|(_, value)| value).collect();
+            exprs.extend(condition.iter());
+            for expr in exprs {
+                expr.visit_columns(&mut |name| {
+                    let known =
+                        table.column_index(name).is_some() || excluded.column_index(name).is_some();
+                    if missing.is_none() && !known {
+                        missing = Some(format!("no such column: {}", name));
+                    }
+                });
+            }
+            for (column, _) in assignments.iter() {
+                if missing.is_none() && table.column_index(column).is_none() {
+                    missing = Some(format!("no such column: {}", column));
+                }
+            }
+            if let Some(error) = missing {
+                return Err(ExecuteResult::SchemaError(error));
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_sort_key(&self, table: &TableDef, expr: &Expr) -> Result<SortKey, ExecuteResult> {
+        match expr {
+            Expr::Literal(Value::Integer(n)) => {
+                let count = self.result_columns(table).len();
+                if *n < 1 || *n as usize > count {
+                    return Err(ExecuteResult::SchemaError(format!(
+                        "ORDER BY term out of range - should be between 1 and {}",
+                        count
+                    )));
+                }
+                Ok(SortKey::Output(*n as usize - 1))
+            }
+            Expr::Column(name) => {
+                let mut position = 0;
+                for item in self.projection.iter() {
+                    match item {
+                        SelectItem::Wildcard => position += table.visible_columns().count(),
+                        SelectItem::Expr { alias, .. } => {
+                            if alias.as_deref().map(|a| a.eq_ignore_ascii_case(name)) == Some(true)
+                            {
+                                return Ok(SortKey::Output(position));
+                            }
+                            position += 1;
+                        }
+                    }
+                }
+                Ok(SortKey::Expr(expr.clone()))
+            }
+            _ => Ok(SortKey::Expr(expr.clone())),
+        }
+    }
+
+    /// returns `Some(descending)` when the rows can be read in B-tree order
+    /// instead of being sorted, which is when the statement orders by leading
+    /// columns of the primary key in the same direction
+    fn primary_key_order(&self, table: &TableDef) -> Option<bool> {
+        if self.order_by.len() > table.primary_key.len() {
+            return None;
+        }
+        let descending = self.order_by.first().is_some_and(|term| term.descending);
+        for (term, &position) in self.order_by.iter().zip(table.primary_key.iter()) {
+            match &term.key {
+                SortKey::Expr(Expr::Column(name))
+                    if *name == table.columns[position].name && term.descending == descending => {}
+                _ => return None,
+            }
+        }
+        Some(descending)
+    }
+
+    fn sort_keys(&self, scope: &dyn Scope, values: &[Value]) -> Result<Vec<Value>, EvalError> {
+        self.order_by
+            .iter()
+            .map(|term| match &term.key {
+                SortKey::Output(i) => Ok(values[*i].clone()),
+                SortKey::Expr(expr) => expr.eval(scope),
+            })
+            .collect()
+    }
+
+    /// returns the expressions of the select list, `having` and `order by`,
+    /// which are evaluated once per result row
+    fn selected_exprs(&self) -> Vec<&Expr> {
+        let mut exprs = Vec::new();
+        for item in self.projection.iter() {
+            if let SelectItem::Expr { expr, .. } = item {
+                exprs.push(expr);
+            }
+        }
+        exprs.extend(self.having.iter());
+        for term in self.order_by.iter() {
+            if let SortKey::Expr(expr) = &term.key {
+                exprs.push(expr);
+            }
+        }
+        exprs
+    }
+
+    /// returns if the select returns one row per group of rows instead of one
+    /// row per table row
+    fn is_grouped(&self) -> bool {
+        !self.group_by.is_empty()
+            || self.having.is_some()
+            || self.selected_exprs().iter().any(|e| e.contains_aggregate())
+    }
+
+    /// returns every distinct aggregate function call of the select
+    fn aggregate_calls(&self) -> Vec<&Expr> {
+        let mut calls: Vec<&Expr> = Vec::new();
+        for expr in self.selected_exprs() {
+            expr.walk(&mut |expr| {
+                if !expr.is_aggregate() {
+                    return true;
+                }
+                if !calls.contains(&expr) {
+                    calls.push(expr);
+                }
+                false
+            });
+        }
+        calls
+    }
+
+    /// returns every distinct window function call of the select
+    fn window_calls(&self) -> Vec<&Expr> {
+        let mut calls: Vec<&Expr> = Vec::new();
+        for expr in self.selected_exprs() {
+            expr.walk(&mut |expr| {
+                if let Expr::Window { .. } = expr {
+                    if !calls.contains(&expr) {
+                        calls.push(expr);
+                    }
+                    return false;
+                }
+                true
+            });
+        }
+        calls
+    }
+
+    /// adds the result rows of the scopes to the output once the window function
+    /// calls of the select have been computed over all of them
+    fn push_windows(
+        &self,
+        scopes: &[&dyn Scope],
+        output: &mut SelectOutput,
+    ) -> Result<(), ExecuteResult> {
+        let calls = self.window_calls();
+        let results = window::evaluate(&calls, scopes).map_err(ExecuteResult::EvalError)?;
+        for (scope, results) in scopes.iter().zip(results) {
+            let scope = WindowScope {
+                scope: *scope,
+                calls: &calls,
+                results,
+            };
+            if !output.push(&scope)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// returns description of the columns produced by the select list
+    fn result_columns(&self, table: &TableDef) -> Vec<Column> {
+        let column_type = |name: &str| table.column(name).and_then(ColumnDef::data_type);
+        let mut columns = Vec::new();
+        for item in self.projection.iter() {
+            match item {
+                SelectItem::Wildcard => {
+                    for column in table.visible_columns() {
+                        // columns of joined tables are headed by their own name
+                        let name = match column.name.split_once('.') {
+                            Some((_, name)) => name,
+                            None => &column.name,
+                        };
+                        columns.push(Column::new(name, column.data_type()));
+                    }
+                }
+                SelectItem::Expr { expr, alias } => {
+                    let name = match alias {
+                        Some(alias) => alias.clone(),
+                        None => expr.to_string(),
+                    };
+                    columns.push(Column::new(&name, expr.data_type(&column_type)));
+                }
+            }
+        }
+        columns
+    }
+
+    /// evaluates the select list against a row or a group
+    fn project(&self, table: &TableDef, scope: &dyn Scope) -> Result<Vec<Value>, EvalError> {
+        let mut values = Vec::new();
+        for item in self.projection.iter() {
+            match item {
+                SelectItem::Wildcard => {
+                    for column in table.visible_columns() {
+                        values.push(scope.column(&column.name).unwrap_or(Value::Null));
+                    }
+                }
+                SelectItem::Expr { expr, .. } => values.push(expr.eval(scope)?),
+            }
+        }
+        Ok(values)
+    }
+
+    /// update [<table>] set <column> = <expr> [, ...] [where <expr>]
+    fn prepare_update(parser: &mut Parser) -> Result<Self, StatementError> {
+        let mut statement = Statement::new(StatementKind::Update);
+        if !parser.peek_keyword("set") {
+            statement.table = parse_table_name(parser)?;
+        }
+        parser.expect_keyword("set")?;
+        statement.assignments = parse_assignments(parser)?;
+
+        Ok(statement)
+    }
+
+    /// delete [from <table>] [where <expr>]
+    fn prepare_delete(parser: &mut Parser) -> Result<Self, StatementError> {
+        let mut statement = Statement::new(StatementKind::Delete);
+        if parser.consume_keyword("from") {
+            statement.table = parse_table_name(parser)?;
+        }
+
+        Ok(statement)
+    }
+
+    /// create table [if not exists] <name> (<column> [, ...])
+    /// or create [unique] index <name> on <table> (<column>)
+    fn prepare_create(parser: &mut Parser) -> Result<Self, StatementError> {
+        if parser.consume_keyword("table") {
+            let if_not_exists = parser.consume_keyword("if");
+            if if_not_exists {
+                parser.expect_keyword("not")?;
+                parser.expect_keyword("exists")?;
+            }
+            let table = TableDef::parse(parser)?;
+            for check in table.checks.iter() {
+                check_builtin(check)?;
+            }
+            for column in table.columns.iter() {
+                if let Some(default) = &column.default {
+                    check_builtin(default)?;
+                }
+            }
+
+            return Ok(Statement {
+                table: table.name.clone(),
+                create_table: Some((table, if_not_exists)),
+                ..Statement::new(StatementKind::CreateTable)
+            });
+        }
+
+        let unique = parser.consume_keyword("unique");
+        parser.expect_keyword("index")?;
+        let index = Index::parse(parser, unique)?;
+
+        Ok(Statement {
+            table: index.table.clone(),
+            index: Some(index),
+            ..Statement::new(StatementKind::CreateIndex)
+        })
+    }
+
+    /// returns if the row is selected by the `where` clause of the statement
+    fn matches(&self, table: &TableDef, row: &Row) -> Result<bool, EvalError> {
+        match &self.condition {
+            Some(condition) => condition.matches(&RowScope { table, row }),
+            None => Ok(true),
+        }
+    }
+
+    /// returns the values supplied for each inserted row, either by the
+    /// `values` clause or by the select
+    fn supplied_rows(&self, table: &mut Table) -> Result<(usize, Vec<Vec<Value>>), ExecuteResult> {
+        if let Some(source) = &self.source {
+            return match source.execute_with(table, &self.temps) {
+                ExecuteResult::SelectSuccess(result) => Ok((result.columns.len(), result.rows)),
+                result => Err(result),
+            };
+        }
+
+        let rows = self
+            .rows
+            .iter()
+            .map(|exprs| {
+                exprs
+                    .iter()
+                    .map(|expr| expr.eval(&EmptyScope))
+                    .collect::<Result<Vec<Value>, EvalError>>()
+            })
+            .collect::<Result<Vec<Vec<Value>>, EvalError>>()
+            .map_err(ExecuteResult::EvalError)?;
+        Ok((self.rows[0].len(), rows))
+    }
+
+    /// returns the positions of the columns an insert supplies `count` values for
+    fn inserted_columns(
+        &self,
+        table: &TableDef,
+        count: usize,
+    ) -> Result<Vec<usize>, ExecuteResult> {
+        let positions: Vec<usize> = if self.columns.is_empty() {
+            (0..table.columns.len()).collect()
+        } else {
+            self.columns
+                .iter()
+                .map(|column| table.column_index(column).unwrap())
+                .collect()
+        };
+        if positions.len() != count {
+            return Err(ExecuteResult::SchemaError(if self.columns.is_empty() {
+                format!(
+                    "table {} has {} columns but {} values were supplied",
+                    table.name,
+                    positions.len(),
+                    count
+                )
+            } else {
+                format!("{} values for {} columns", count, positions.len())
+            }));
+        }
+        Ok(positions)
+    }
+
+    /// returns the row built from the values supplied for the columns at
+    /// `positions`. `rowid` is the key used when the primary key is left out
+    /// or NULL, `None` when none is left.
+    fn inserted_row(
+        table: &TableDef,
+        positions: &[usize],
+        supplied: Vec<Value>,
+        rowid: Option<i64>,
+    ) -> Result<Row, ExecuteResult> {
+        // columns left out of the insert get their default, or NULL
+        let mut values = table
+            .columns
+            .iter()
+            .map(|column| match &column.default {
+                Some(default) => default.eval(&EmptyScope),
+                None => Ok(Value::Null),
+            })
+            .collect::<Result<Vec<Value>, EvalError>>()
+            .map_err(ExecuteResult::EvalError)?;
+        for (position, value) in positions.iter().zip(supplied) {
+            values[*position] = value;
+        }
+        if let Some(key) = table.rowid() {
+            if values[key].is_null() {
+                values[key] = Value::Integer(rowid.ok_or(ExecuteResult::TableFull)?);
+            }
+        }
+        check_row(table, Row::new(values))
+    }
+
+    fn execute_insert(&self, table: &mut Table, def: &TableDef) -> ExecuteResult {
+        match self.inserted_rows(table, def) {
+            Ok(rows) => self.insert_rows(table, def, rows),
+            Err(result) => result,
+        }
+    }
+
+    /// returns every row the insert adds, checked against the table definition
+    fn inserted_rows(&self, table: &mut Table, def: &TableDef) -> Result<Vec<Row>, ExecuteResult> {
+        let (count, supplied) = self.supplied_rows(table)?;
+        let positions = self.inserted_columns(def, count)?;
+
+        // rows without a key get one past the largest key so far,
+        // including the keys of the rows before them
+        let mut next_rowid = table.next_rowid(def);
+        let mut rows = Vec::with_capacity(supplied.len());
+        for values in supplied {
+            let row = Statement::inserted_row(def, &positions, values, next_rowid)?;
+            if let Some(Value::Integer(rowid)) = def.rowid().map(|i| &row.values[i]) {
+                next_rowid = match (next_rowid, rowid.checked_add(1)) {
+                    (Some(next), Some(after)) => Some(next.max(after)),
+                    _ => None,
+                };
+            }
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    /// writes the rows into the table one after another. a row failing undoes
+    /// the rows written before it, so either all of them are inserted or none is.
+    fn insert_rows(&self, table: &mut Table, def: &TableDef, rows: Vec<Row>) -> ExecuteResult {
+        table.begin();
+        let mut written_rows = 0;
+        let mut returned = Vec::new();
+        for row in rows {
+            let written = match self.insert_row(table, def, row, &self.on_conflict) {
+                Ok(Inserted::Added(row)) => {
+                    if let Some(Value::Integer(rowid)) = def.rowid().map(|i| &row.values[i]) {
+                        table.inserted(def, *rowid);
+                    }
+                    row
+                }
+                Ok(Inserted::Updated(row)) => row,
+                Ok(Inserted::Skipped) => continue,
+                Err(result) => {
+                    table.rollback();
+                    return result;
+                }
+            };
+            written_rows += 1;
+            match self.returning(def, &written) {
+                Ok(Some(values)) => returned.push(values),
+                Ok(None) => {}
+                Err(result) => {
+                    table.rollback();
+                    return result;
+                }
+            }
+        }
+        table.commit();
+        table.changed(written_rows);
+
+        self.returned(def, returned, ExecuteResult::InsertSuccess)
+    }
+
+    /// writes a single row, resolving conflicts with stored rows as `on_conflict` says
+    fn insert_row(
+        &self,
+        table: &mut Table,
+        def: &TableDef,
+        row: Row,
+        on_conflict: &OnConflict,
+    ) -> Result<Inserted, ExecuteResult> {
+        let key = def.key(&row);
+
+        // conflicts on unique columns, not counting the row with the same key,
+        // which is a conflict on the primary key
+        let unique: Vec<(String, usize)> = table
+            .schema
+            .indexes_of(&def.name)
+            .filter(|index| index.unique)
+            .map(|index| (index.column.clone(), index.root_page_num))
+            .collect();
+        for (column, root_page_num) in unique {
+            let value = &row.values[def.column_index(&column).unwrap()];
+            // NULLs are distinct from each other
+            if value.is_null() {
+                continue;
+            }
+            let conflicting: Vec<Key> = table
+                .pager
+                .get_index(root_page_num)
+                .unwrap()
+                .lookup(value)
+                .into_iter()
+                .filter(|stored| *stored != key)
+                .collect();
+            if conflicting.is_empty() {
+                continue;
+            }
+
+            match on_conflict {
+                OnConflict::Replace => {
+                    for stored in conflicting {
+                        table
+                            .table_find(def.root_page_num, &stored)
+                            .delete_value()
+                            .map_err(ExecuteResult::InvalidRow)?;
+                    }
+                }
+                OnConflict::Update { .. } if on_conflict.handles(&[&column]) => {
+                    return self.upsert(table, def, &conflicting[0], row, on_conflict);
+                }
+                _ if on_conflict.handles(&[&column]) => return Ok(Inserted::Skipped),
+                _ => return Err(ExecuteResult::violation(Constraint::Unique, def, &column)),
+            }
+        }
+
+        // a single descent finds both the stored row with the same key and the
+        // place the row goes
+        let primary_key: Vec<&str> = def
+            .primary_key
+            .iter()
+            .map(|&i| def.columns[i].name.as_str())
+            .collect();
+        let mut cursor = table.table_find(def.root_page_num, &key);
+        if cursor.key() == Some(&key[..]) {
+            match on_conflict {
+                // the cursor then points where the row goes
+                OnConflict::Replace => cursor.delete_value().map_err(ExecuteResult::InvalidRow)?,
+                OnConflict::Update { .. } if on_conflict.handles(&primary_key) => {
+                    return self.upsert(table, def, &key, row, on_conflict);
+                }
+                _ if on_conflict.handles(&primary_key) => return Ok(Inserted::Skipped),
+                _ => return Err(ExecuteResult::DuplicatedKey(display_key(&key))),
+            }
+        }
+
+        if !cursor.table().has_room_for(def, &[], &[&row]) {
+            return Err(ExecuteResult::TableFull);
+        }
+        cursor
+            .insert_value(&row)
+            .map_err(|_| ExecuteResult::TableFull)?;
+        Ok(Inserted::Added(row))
+    }
+
+    /// applies `do update` to the stored row with the given key, which the
+    /// `excluded` row conflicted with
+    fn upsert(
+        &self,
+        table: &mut Table,
+        def: &TableDef,
+        key: &[u8],
+        excluded: Row,
+        on_conflict: &OnConflict,
+    ) -> Result<Inserted, ExecuteResult> {
+        let (assignments, condition) = match on_conflict {
+            OnConflict::Update {
+                assignments,
+                condition,
+                ..
+            } => (assignments, condition),
+            _ => unreachable!("only do update changes the stored row"),
+        };
+
+        let mut cursor = table.table_find(def.root_page_num, key);
+        let old_row = cursor.get_value().map_err(ExecuteResult::InvalidRow)?;
+        let scope = UpsertScope {
+            table: def,
+            row: &old_row,
+            excluded: &excluded,
+        };
+        if let Some(condition) = condition {
+            if !condition
+                .matches(&scope)
+                .map_err(ExecuteResult::EvalError)?
+            {
+                return Ok(Inserted::Skipped);
+            }
+        }
+
+        // every assignment sees the values from before the update
+        let mut new_row = old_row.clone();
+        for (column, expr) in assignments.iter() {
+            let value = expr.eval(&scope).map_err(ExecuteResult::EvalError)?;
+            new_row.values[def.column_index(column).unwrap()] = value;
+        }
+        let new_row = check_row(def, new_row)?;
+
+        cursor.delete_value().map_err(ExecuteResult::InvalidRow)?;
+        match self.insert_row(table, def, new_row, &OnConflict::Abort)? {
+            Inserted::Added(row) => Ok(Inserted::Updated(row)),
+            inserted => Ok(inserted),
+        }
+    }
+
+    /// evaluates the `returning` list against a written row, `None` without one
+    fn returning(&self, def: &TableDef, row: &Row) -> Result<Option<Vec<Value>>, ExecuteResult> {
+        if self.projection.is_empty() {
+            return Ok(None);
+        }
+        self.project(def, &RowScope { table: def, row })
+            .map(Some)
+            .map_err(ExecuteResult::EvalError)
+    }
+
+    /// returns the `returned` rows in the shape of a select result,
+    /// or `done` when the statement has no `returning` list
+    fn returned(
+        &self,
+        def: &TableDef,
+        rows: Vec<Vec<Value>>,
+        done: ExecuteResult,
+    ) -> ExecuteResult {
+        if self.projection.is_empty() {
+            return done;
+        }
+        ExecuteResult::SelectSuccess(ResultSet::new(self.result_columns(def), rows))
+    }
+
+    fn execute_select(&self, table: &mut Table, def: &TableDef) -> ExecuteResult {
+        let columns = self.result_columns(def);
+        match self.bound_limit() {
+            Ok((Some(0), _)) => {
+                return ExecuteResult::SelectSuccess(ResultSet::new(columns, Vec::new()))
+            }
+            Err(result) => return result,
+            Ok(_) => {}
+        }
+
+        let rows = if self.is_grouped() {
+            self.select_groups(table, def)
+        } else {
+            self.select_rows(table, def)
+        };
+        match rows {
+            Ok(rows) => ExecuteResult::SelectSuccess(ResultSet::new(columns, rows)),
+            Err(result) => result,
+        }
+    }
+
+    /// returns one result row per table row matching the `where` clause
+    fn select_rows(
+        &self,
+        table: &mut Table,
+        def: &TableDef,
+    ) -> Result<Vec<Vec<Value>>, ExecuteResult> {
+        if !self.window_calls().is_empty() {
+            // window functions need every row before the first result row
+            let rows = if self.is_joined() {
+                self.joined_rows(table)?
+            } else {
+                RowSource::Table(def.clone()).rows(table, &EmptyScope)?
+            };
+            let mut matching = Vec::new();
+            for row in rows {
+                if self.matches(def, &row).map_err(ExecuteResult::EvalError)? {
+                    matching.push(row);
+                }
+            }
+            let scopes: Vec<RowScope> = matching
+                .iter()
+                .map(|row| RowScope { table: def, row })
+                .collect();
+            let scopes: Vec<&dyn Scope> = scopes.iter().map(|s| s as &dyn Scope).collect();
+            let mut output = SelectOutput::new(self, def, !self.order_by.is_empty())?;
+            self.push_windows(&scopes, &mut output)?;
+            return output.finish();
+        }
+
+        if self.is_joined() {
+            let mut output = SelectOutput::new(self, def, !self.order_by.is_empty())?;
+            for row in self.joined_rows(table)? {
+                if !self.select_row(&row, &mut output)? {
+                    break;
+                }
+            }
+            return output.finish();
+        }
+
+        // sorting by the primary key walks the B-tree, backwards for `desc`
+        let btree_order = self.primary_key_order(def);
+        let reverse = btree_order == Some(true);
+        let mut output = SelectOutput::new(self, def, btree_order.is_none())?;
+
+        if let Some(mut keys) = self.index_lookup(table, def) {
+            if reverse {
+                keys.reverse();
+            }
+            for key in keys {
+                let row = table
+                    .table_find(def.root_page_num, &key)
+                    .get_value()
+                    .map_err(ExecuteResult::InvalidRow)?;
+                if !self.select_row(&row, &mut output)? {
+                    break;
+                }
+            }
+            return output.finish();
+        }
+
+        let rows = if reverse {
+            table.table_last(def.root_page_num).rows_rev()
+        } else {
+            table.table_start(def.root_page_num).rows()
+        };
+        for row in rows {
+            let row = row.map_err(ExecuteResult::InvalidRow)?;
+            if !self.select_row(&row, &mut output)? {
+                break;
+            }
+        }
+
+        output.finish()
+    }
+
+    /// adds the row to the output when it matches the `where` clause.
+    /// returns false once no more rows are needed.
+    fn select_row(&self, row: &Row, output: &mut SelectOutput) -> Result<bool, ExecuteResult> {
+        let table = output.table;
+        if !self.matches(table, row).map_err(ExecuteResult::EvalError)? {
+            return Ok(true);
+        }
+        output.push(&RowScope { table, row })
+    }
+
+    /// returns keys of the rows that can match the `where` clause, in key order,
+    /// when an index answers one of its `<column> = <value>` terms.
+    /// returns `None` when every row has to be scanned.
+    fn index_lookup(&self, table: &Table, def: &TableDef) -> Option<Vec<Key>> {
+        let mut terms = Vec::new();
+        let mut pending: Vec<&Expr> = self.condition.iter().collect();
+        while let Some(expr) = pending.pop() {
+            match expr {
+                Expr::Binary(BinaryOp::And, left, right) => {
+                    pending.push(right);
+                    pending.push(left);
+                }
+                Expr::Binary(BinaryOp::Eq, left, right) => match (&**left, &**right) {
+                    (Expr::Column(column), Expr::Literal(value))
+                    | (Expr::Literal(value), Expr::Column(column)) => terms.push((column, value)),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        terms.into_iter().find_map(|(column, value)| {
+            let index = table.schema.index_on(&def.name, column)?;
+            let node = table.pager.get_index(index.root_page_num)?;
+            Some(node.lookup(value))
+        })
+    }
+
+    /// returns one result row per group of the rows matching the `where` clause.
+    /// rows are grouped by hashing their `group by` values.
+    fn select_groups(
+        &self,
+        table: &mut Table,
+        def: &TableDef,
+    ) -> Result<Vec<Vec<Value>>, ExecuteResult> {
+        let calls = self.aggregate_calls();
+        let btree_aggregates = if self.is_joined() || !self.window_calls().is_empty() {
+            None
+        } else {
+            self.btree_aggregates(table, def, &calls)
+        };
+        if let Some(results) = btree_aggregates {
+            let mut output = SelectOutput::new(self, def, false)?;
+            output.push(&GroupScope {
+                table: def,
+                row: None,
+                calls: &calls,
+                results,
+            })?;
+            return output.finish();
+        }
+
+        // groups keep the order in which their first row was seen
+        let mut groups: Vec<Group> = Vec::new();
+        let mut positions: HashMap<Vec<u8>, usize> = HashMap::new();
+
+        let mut add = |row: Row| -> Result<(), ExecuteResult> {
+            if !self.matches(def, &row).map_err(ExecuteResult::EvalError)? {
+                return Ok(());
+            }
+
+            let scope = RowScope {
+                table: def,
+                row: &row,
+            };
+            let keys = self
+                .group_by
+                .iter()
+                .map(|expr| expr.eval(&scope))
+                .collect::<Result<Vec<Value>, EvalError>>()
+                .map_err(ExecuteResult::EvalError)?;
+            let position = *positions.entry(hash_key(&keys)).or_insert_with(|| {
+                groups.push(Group::new(Some(row.clone()), &calls));
+                groups.len() - 1
+            });
+            groups[position]
+                .step(&scope, &calls)
+                .map_err(ExecuteResult::EvalError)
+        };
+        if self.is_joined() {
+            for row in self.joined_rows(table)? {
+                add(row)?;
+            }
+        } else {
+            let mut cursor = table.table_start(def.root_page_num);
+            while !cursor.is_end() {
+                let row = cursor.get_value().map_err(ExecuteResult::InvalidRow)?;
+                cursor.advance();
+                add(row)?;
+            }
+        }
+
+        // without `group by` the whole table is one group, even when it is empty
+        if self.group_by.is_empty() && groups.is_empty() {
+            groups.push(Group::new(None, &calls));
+        }
+
+        let mut output = SelectOutput::new(self, def, !self.order_by.is_empty())?;
+        let windowed = !self.window_calls().is_empty();
+        let mut scopes = Vec::new();
+        for group in groups.iter_mut() {
+            let results = group
+                .aggregates
+                .iter_mut()
+                .map(|aggregate| aggregate.finalize())
+                .collect::<Result<Vec<Value>, EvalError>>()
+                .map_err(ExecuteResult::EvalError)?;
+            let scope = GroupScope {
+                table: def,
+                row: group.row.as_ref(),
+                calls: &calls,
+                results,
+            };
+
+            if let Some(having) = &self.having {
+                if !having.matches(&scope).map_err(ExecuteResult::EvalError)? {
+                    continue;
+                }
+            }
+            if windowed {
+                scopes.push(scope);
+            } else if !output.push(&scope)? {
+                break;
+            }
+        }
+        if windowed {
+            let scopes: Vec<&dyn Scope> = scopes.iter().map(|s| s as &dyn Scope).collect();
+            self.push_windows(&scopes, &mut output)?;
+        }
+
+        output.finish()
+    }
+
+    /// returns if the select reads its table under an alias, joins other tables
+    /// or reads a temporary table or table-valued function, which makes its rows
+    /// those of `joined_def`
+    fn is_joined(&self) -> bool {
+        self.alias.is_some()
+            || self.table_args.is_some()
+            || !self.joins.is_empty()
+            || !self.correlated.is_empty()
+            || (self.kind == StatementKind::Select
+                && self.temps.iter().any(|temp| temp.def.name == self.table))
+    }
+
+    /// returns the source of the rows of the table `name`, or of the
+    /// table-valued function `name` when it is called with `args`
+    fn find_source(
+        &self,
+        table: &Table,
+        name: &str,
+        args: Option<&[Expr]>,
+    ) -> Result<RowSource, ExecuteResult> {
+        RowSource::find(table, &self.temps, name, args).ok_or_else(|| {
+            ExecuteResult::SchemaError(match args {
+                Some(_) => format!("no such table-valued function: {}", name),
+                None => format!("no such table: {}", name),
+            })
+        })
+    }
+
+    /// returns the `<column> <op> <value>` terms of the `where` clause on the
+    /// columns of the first table, whose rows are those of `def`
+    fn constraints(&self, def: &TableDef) -> Vec<ColumnConstraint> {
+        let mut constraints = Vec::new();
+        let mut pending: Vec<&Expr> = self.condition.iter().collect();
+        while let Some(expr) = pending.pop() {
+            let (op, left, right) = match expr {
+                Expr::Binary(BinaryOp::And, left, right) => {
+                    pending.push(right);
+                    pending.push(left);
+                    continue;
+                }
+                Expr::Binary(op, left, right) => (op, &**left, &**right),
+                _ => continue,
+            };
+            let (column, value, flipped) = match (left, right) {
+                (Expr::Column(column), Expr::Literal(value)) => (column, value, false),
+                (Expr::Literal(value), Expr::Column(column)) => (column, value, true),
+                _ => continue,
+            };
+            let op = ConstraintOp::from_binary(op, flipped);
+            if let (Some(column), Some(op)) = (def.column_index(column), op) {
+                constraints.push(ColumnConstraint {
+                    column,
+                    op,
+                    value: value.clone(),
+                });
+            }
+        }
+        constraints
+    }
+
+    /// returns the definition of the joined rows of the select, whose columns
+    /// are qualified by the name of their table. `def` is the first table.
+    fn joined_def(&self, table: &Table, def: &TableDef) -> Result<TableDef, ExecuteResult> {
+        let mut names = vec![self.alias.as_deref().unwrap_or(&self.table)];
+        let mut joined = join::qualified_def(def, names[0]);
+        for join in self.joins.iter() {
+            let inner = self.find_source(table, &join.table, join.args.as_deref())?;
+            if names.contains(&join.name()) {
+                return Err(ExecuteResult::SchemaError(format!(
+                    "table {} is joined twice without an alias",
+                    join.name()
+                )));
+            }
+            names.push(join.name());
+
+            // the arguments of a table-valued function see the tables before it,
+            // and `on` sees the tables joined so far
+            for arg in join.args.iter().flatten() {
+                check_columns(&joined, arg)?;
+            }
+            joined = join::joined_def(&joined, inner.def(), join.name());
+            if let Some(on) = &join.on {
+                check_columns(&joined, on)?;
+            }
+        }
+        Ok(joined)
+    }
+
+    /// returns the rows of the table joined with the tables of the `join` clauses
+    fn joined_rows(&self, table: &mut Table) -> Result<Vec<Row>, ExecuteResult> {
+        let mut first = self.find_source(table, &self.table, self.table_args.as_deref())?;
+        let mut def =
+            join::qualified_def(first.def(), self.alias.as_deref().unwrap_or(&self.table));
+        if let RowSource::Virtual(source) = &mut first {
+            source.constraints = self.constraints(&def);
+        }
+        let mut rows = first.rows(table, &EmptyScope)?;
+        for join in self.joins.iter() {
+            let inner = self.find_source(table, &join.table, join.args.as_deref())?;
+            let strategy = JoinStrategy::plan(table, &def, &inner, join);
+            let (joined, joined_rows) = join::join_rows(table, &def, rows, &inner, join, strategy)?;
+            def = joined;
+            rows = joined_rows;
+        }
+
+        if !self.correlated.is_empty() {
+            for row in rows.iter_mut() {
+                let mut values = Vec::new();
+                for (_, expr) in self.correlated.iter() {
+                    values.push(self.run_correlated(table, &def, row, expr)?);
+                }
+                row.values.extend(values);
+            }
+        }
+        Ok(rows)
+    }
+
+    /// returns the value of a subquery reading the columns of the row, which
+    /// are replaced by their values before it runs
+    fn run_correlated(
+        &self,
+        table: &mut Table,
+        def: &TableDef,
+        row: &Row,
+        expr: &Expr,
+    ) -> Result<Value, ExecuteResult> {
+        let mut expr = expr.clone();
+        if let Expr::Subquery(query) | Expr::Exists(query) | Expr::InSubquery { query, .. } =
+            &mut expr
+        {
+            let outer = RowScope { table: def, row };
+            query.statement.temps = self.temps.clone();
+            let (_, inner) = query.statement.planned_source(table)?;
+            for expr in query.statement.exprs_mut() {
+                let _ = expr.walk_mut(&mut |expr| -> Result<bool, Infallible> {
+                    if let Expr::Column(name) = expr {
+                        if inner.column_index(name).is_none() {
+                            if let Some(value) = outer.column(name) {
+                                *expr = Expr::Literal(value);
+                            }
+                        }
+                    }
+                    Ok(true)
+                });
+            }
+        }
+        run_subquery(table, &self.temps, &mut expr)?;
+        expr.eval(&RowScope { table: def, row })
+            .map_err(ExecuteResult::EvalError)
+    }
+
+    /// answers `count(*)` and `min`/`max` of the leading primary key column over the whole table
+    /// from the B-tree without reading any row. returns `None` when the select needs more.
+    fn btree_aggregates(
+        &self,
+        table: &Table,
+        def: &TableDef,
+        calls: &[&Expr],
+    ) -> Option<Vec<Value>> {
+        if self.condition.is_some() || !self.group_by.is_empty() || self.having.is_some() {
+            return None;
+        }
+
+        // columns outside of aggregates would need a row to be read
+        let mut reads_rows = self.projection.contains(&SelectItem::Wildcard);
+        for expr in self.selected_exprs() {
+            expr.walk(&mut |expr| {
+                reads_rows |= matches!(expr, Expr::Column(_));
+                !expr.is_aggregate()
+            });
+        }
+        if reads_rows {
+            return None;
+        }
+
+        let node = table.get_node(def.root_page_num)?;
+        let num_cells = node.num_cells();
+        let key = |num: Option<usize>| -> Option<Value> {
+            match num.and_then(|num| node.get_key(num)) {
+                Some(key) => decode_key(key).ok()?.into_iter().next(),
+                None => Some(Value::Null),
+            }
+        };
+        let primary_key = &def.columns[def.primary_key[0]].name;
+
+        calls
+            .iter()
+            .map(|call| match call {
+                Expr::Function { name, args, .. } => match (name.as_str(), args.as_slice()) {
+                    ("count", []) => Some(Value::Integer(num_cells as i64)),
+                    ("min", [Expr::Column(column)]) if column == primary_key => key(Some(0)),
+                    ("max", [Expr::Column(column)]) if column == primary_key => {
+                        key(num_cells.checked_sub(1))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn execute_update(&self, table: &mut Table, def: &TableDef) -> ExecuteResult {
+        // compute every new row first so a failure leaves the table untouched
+        let mut updates: Vec<(Row, Row)> = Vec::new();
+        let mut untouched_keys = Vec::new();
+        let mut returned = Vec::new();
+
+        let mut cursor = table.table_start(def.root_page_num);
+        while !cursor.is_end() {
+            let old_row = match cursor.get_value() {
+                Ok(row) => row,
+                Err(e) => return ExecuteResult::InvalidRow(e),
+            };
+            cursor.advance();
+
+            match self.matches(def, &old_row) {
+                Ok(true) => {}
+                Ok(false) => {
+                    untouched_keys.push(def.key(&old_row));
+                    continue;
+                }
+                Err(e) => return ExecuteResult::EvalError(e),
+            }
+
+            // every assignment sees the values from before the update
+            let scope = RowScope {
+                table: def,
+                row: &old_row,
+            };
+            let mut new_row = old_row.clone();
+            for (column, expr) in self.assignments.iter() {
+                let value = match expr.eval(&scope) {
+                    Ok(value) => value,
+                    Err(e) => return ExecuteResult::EvalError(e),
+                };
+                new_row.values[def.column_index(column).unwrap()] = value;
+            }
+            let new_row = match check_row(def, new_row) {
+                Ok(row) => row,
+                Err(result) => return result,
+            };
+            match self.returning(def, &new_row) {
+                Ok(Some(values)) => returned.push(values),
+                Ok(None) => {}
+                Err(result) => return result,
+            }
+
+            updates.push((old_row, new_row));
+        }
+
+        let mut new_keys: Vec<Key> = updates.iter().map(|(_, new)| def.key(new)).collect();
+        new_keys.sort_unstable();
+        for pair in new_keys.windows(2) {
+            if pair[0] == pair[1] {
+                return ExecuteResult::DuplicatedKey(display_key(&pair[0]));
+            }
+        }
+        if let Some(key) = new_keys
+            .iter()
+            .find(|key| untouched_keys.binary_search(key).is_ok())
+        {
+            return ExecuteResult::DuplicatedKey(display_key(key));
+        }
+
+        let mut old_keys: Vec<Key> = updates.iter().map(|(old, _)| def.key(old)).collect();
+        old_keys.sort_unstable();
+        let removed: Vec<&Row> = updates.iter().map(|(old, _)| old).collect();
+        let added: Vec<&Row> = updates.iter().map(|(_, new)| new).collect();
+        if let Err(result) = check_unique(table, def, &old_keys, &added) {
+            return result;
+        }
+        if !table.has_room_for(def, &removed, &added) {
+            return ExecuteResult::TableFull;
+        }
+
+        for key in old_keys {
+            if let Err(e) = table.table_find(def.root_page_num, &key).delete_value() {
+                return ExecuteResult::InvalidRow(e);
+            }
+        }
+        for (_, new_row) in updates.iter() {
+            let mut cursor = table.table_find(def.root_page_num, &def.key(new_row));
+            if cursor.insert_value(new_row).is_err() {
+                return ExecuteResult::TableFull;
+            }
+        }
+
+        table.changed(updates.len());
+        self.returned(def, returned, ExecuteResult::UpdateSuccess(updates.len()))
+    }
+
+    fn execute_delete(&self, table: &mut Table, def: &TableDef) -> ExecuteResult {
+        // find every matching row first so a failure leaves the table untouched
+        let mut keys = Vec::new();
+        let mut returned = Vec::new();
+        for row in table.table_start(def.root_page_num).rows() {
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => return ExecuteResult::InvalidRow(e),
+            };
+            match self.matches(def, &row) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => return ExecuteResult::EvalError(e),
+            }
+            match self.returning(def, &row) {
+                Ok(Some(values)) => returned.push(values),
+                Ok(None) => {}
+                Err(result) => return result,
+            }
+            keys.push(def.key(&row));
+        }
+
+        for key in keys.iter() {
+            if let Err(e) = table.table_find(def.root_page_num, key).delete_value() {
+                return ExecuteResult::InvalidRow(e);
+            }
+        }
+
+        table.changed(keys.len());
+        self.returned(def, returned, ExecuteResult::DeleteSuccess(keys.len()))
+    }
+
+    fn execute_create_table(&self, table: &mut Table) -> ExecuteResult {
+        let (def, if_not_exists) = self.create_table.as_ref().unwrap();
+        if table.schema.table(&def.name).is_some() {
+            if *if_not_exists {
+                return ExecuteResult::CreateTableSuccess;
+            }
+            return ExecuteResult::SchemaError(format!("table {} already exists", def.name));
+        }
+
+        // unique columns are enforced through an index of their own,
+        // unless they make up the primary key by themselves
+        let indexes = def
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(i, column)| column.unique && def.primary_key != [*i])
+            .map(|(_, column)| column)
+            .enumerate()
+            .map(|(i, column)| Index {
+                name: format!("autoindex_{}_{}", def.name, i + 1),
+                table: def.name.clone(),
+                column: column.name.clone(),
+                unique: true,
+                root_page_num: 0,
+            })
+            .collect();
+
+        match table.create_table(def.clone(), indexes) {
+            Ok(()) => ExecuteResult::CreateTableSuccess,
+            Err(e) => ExecuteResult::SchemaError(e),
+        }
+    }
+
+    fn execute_create_index(&self, table: &mut Table, def: &TableDef) -> ExecuteResult {
+        let index = self.index.as_ref().unwrap();
+        if table.schema.index(&index.name).is_some() {
+            return ExecuteResult::SchemaError(format!("index {} already exists", index.name));
+        }
+
+        let column = def.column_index(&index.column).unwrap();
+        let mut node = IndexNode::new();
+        let mut cursor = table.table_start(def.root_page_num);
+        while !cursor.is_end() {
+            let row = match cursor.get_value() {
+                Ok(row) => row,
+                Err(e) => return ExecuteResult::InvalidRow(e),
+            };
+            cursor.advance();
+
+            let value = row.values[column].clone();
+            if index.unique && !value.is_null() && !node.lookup(&value).is_empty() {
+                return ExecuteResult::violation(Constraint::Unique, def, &index.column);
+            }
+            let key = def.key(&row);
+            if node.insert(value, key).is_err() {
+                return ExecuteResult::TableFull;
+            }
+        }
+
+        match table.add_index(index.clone(), node) {
+            Ok(()) => ExecuteResult::CreateIndexSuccess,
+            Err(e) => ExecuteResult::SchemaError(e),
+        }
+    }
+
+    pub fn execute(&self, table: &mut Table) -> ExecuteResult {
+        self.execute_with(table, &[])
+    }
+
+    pub fn kind(&self) -> StatementKind {
+        self.kind
+    }
+
+    /// returns if running the statement returns rows: a select, or a write with `returning`
+    pub fn returns_rows(&self) -> bool {
+        self.kind == StatementKind::Select || !self.projection.is_empty()
+    }
+
+    /// runs a select and returns its result rows one at a time. a select reading a
+    /// single table of the database, in key order and without grouping or window
+    /// functions, reads each row from the B-tree only when the next result row is
+    /// asked for, so it stops reading once the caller stops or the limit is reached.
+    /// any other select has its rows computed first.
+    pub fn query<'t>(&self, table: &'t mut Table) -> Result<QueryRows<'t>, ExecuteResult> {
+        if self.kind != StatementKind::Select {
+            return Err(ExecuteResult::SchemaError(String::from(
+                "only a select can be queried",
+            )));
+        }
+        let mut result = None;
+        if self.compound.is_empty() && self.joins.is_empty() {
+            let (statement, source, def) = self.instantiate(table, &[])?.planned(table)?;
+            let reverse = statement.primary_key_order(&def);
+            let streamed = matches!(source, RowSource::Table(_) | RowSource::Virtual(_))
+                && statement.correlated.is_empty()
+                && !statement.is_grouped()
+                && statement.window_calls().is_empty()
+                && statement.index_lookup(table, &def).is_none();
+            if let (true, Some(reverse)) = (streamed, reverse) {
+                let rows: ScanRows = match source {
+                    RowSource::Virtual(mut source) => {
+                        source.constraints = statement.constraints(&def);
+                        let rows = source.stream(table, &EmptyScope)?;
+                        Box::new(rows.map(|row| row.map_err(ExecuteResult::EvalError)))
+                    }
+                    _ if reverse => Box::new(
+                        table
+                            .table_last(def.root_page_num)
+                            .rows_rev()
+                            .map(|row| row.map_err(ExecuteResult::InvalidRow)),
+                    ),
+                    _ => Box::new(
+                        table
+                            .table_start(def.root_page_num)
+                            .rows()
+                            .map(|row| row.map_err(ExecuteResult::InvalidRow)),
+                    ),
+                };
+                let (limit, offset) = statement.bound_limit()?;
+                return Ok(QueryRows {
+                    columns: statement.result_columns(&def),
+                    stream: Stream::Scan(Box::new(Scan {
+                        statement,
+                        def,
+                        rows,
+                        limit,
+                        offset,
+                        skipped: 0,
+                        returned: 0,
+                    })),
+                });
+            }
+            result = Some(statement.execute_select(table, &def));
+        }
+
+        match result.unwrap_or_else(|| self.execute(table)) {
+            ExecuteResult::SelectSuccess(result) => Ok(QueryRows {
+                columns: result.columns,
+                stream: Stream::Computed(result.rows.into_iter()),
+            }),
+            result => Err(result),
+        }
+    }
+
+    /// executes the statement, which can read the temporary tables `temps`
+    /// of the `with` clauses of the statements it is part of
+    fn execute_with(&self, table: &mut Table, temps: &[Rc<TempTable>]) -> ExecuteResult {
+        if self.kind == StatementKind::CreateTable {
+            return self.execute_create_table(table);
+        }
+
+        let statement = match self.instantiate(table, temps) {
+            Ok(statement) => statement,
+            Err(result) => return result,
+        };
+        if !statement.compound.is_empty() {
+            return match statement.compound_rows(table) {
+                Ok(result) => ExecuteResult::SelectSuccess(result),
+                Err(result) => result,
+            };
+        }
+
+        let (statement, _, def) = match statement.planned(table) {
+            Ok(planned) => planned,
+            Err(result) => return result,
+        };
+
+        match statement.kind {
+            StatementKind::Insert => statement.execute_insert(table, &def),
+            StatementKind::Select => statement.execute_select(table, &def),
+            StatementKind::Update => statement.execute_update(table, &def),
+            StatementKind::Delete => statement.execute_delete(table, &def),
+            StatementKind::CreateIndex => statement.execute_create_index(table, &def),
+            StatementKind::CreateTable => unreachable!("tables are created above"),
+        }
+    }
+
+    /// returns the statement with its column names resolved against the table it
+    /// reads or writes, the source of that table's rows and the definition of the
+    /// rows, which joins every joined table
+    fn planned(&self, table: &Table) -> Result<(Statement, RowSource, TableDef), ExecuteResult> {
+        let (source, mut def) = self.planned_source(table)?;
+        // computed for each row after the columns of the tables
+        def.columns
+            .extend(self.correlated.iter().map(|(name, _)| ColumnDef {
+                hidden: true,
+                ..ColumnDef::new(name, "")
+            }));
+        Ok((self.resolve(&table.schema, &def)?, source, def))
+    }
+
+    /// returns the source of the rows of the statement, and the definition
+    /// of its rows once joined
+    fn planned_source(&self, table: &Table) -> Result<(RowSource, TableDef), ExecuteResult> {
+        let source = if self.kind == StatementKind::Select {
+            self.find_source(table, &self.table, self.table_args.as_deref())?
+        } else {
+            match table.schema.table(&self.table) {
+                Some(def) => RowSource::Table(def.clone()),
+                None => {
+                    return Err(ExecuteResult::SchemaError(format!(
+                        "no such table: {}",
+                        self.table
+                    )))
+                }
+            }
+        };
+        let mut def = source.def().clone();
+        if self.is_joined() {
+            def = self.joined_def(table, &def)?;
+        }
+        Ok((source, def))
+    }
+
+    /// returns a copy of the statement reading `temps` and the tables of its own
+    /// `with` clause, with every parameter replaced by the value bound to it and
+    /// every subquery replaced by its result
+    fn instantiate(
+        &self,
+        table: &mut Table,
+        temps: &[Rc<TempTable>],
+    ) -> Result<Statement, ExecuteResult> {
+        let mut statement = self.clone();
+        statement.substitute(&self.bindings);
+        statement.temps = temps.to_vec();
+        // a select without `from` reads a single row without columns
+        if self.kind == StatementKind::Select && self.table.is_empty() {
+            let row = TempTable::new("", &[], &[], vec![Vec::new()]);
+            statement.temps.push(Rc::new(row.unwrap()));
+        }
+        // a virtual table read by its name alone is called without arguments
+        if self.kind == StatementKind::Select
+            && self.table_args.is_none()
+            && !temps.iter().any(|temp| temp.def.name == self.table)
+            && !self.with.iter().any(|cte| cte.name == self.table)
+            && table.schema.table(&self.table).is_none()
+            && table.virtual_table(&self.table).is_some()
+        {
+            statement.table_args = Some(Vec::new());
+        }
+        statement.with = Vec::new();
+        for cte in self.with.iter() {
+            let temp = statement.materialize(table, cte)?;
+            statement.temps.push(Rc::new(temp));
+        }
+
+        // subqueries reading the columns of the rows of a select run for each row
+        let outer = match self.kind {
+            StatementKind::Select => statement.planned_source(table).ok(),
+            _ => None,
+        };
+        let outer = outer.as_ref().map(|(_, def)| def);
+        let temps = statement.temps.clone();
+        let mut correlated = Vec::new();
+        // result columns keep the name the subquery is written with
+        for item in statement.projection.iter_mut() {
+            if let SelectItem::Expr { expr, alias } = item {
+                let mut found = false;
+                expr.walk(&mut |expr| {
+                    found |= is_correlated(table, &temps, outer, expr);
+                    !found
+                });
+                if alias.is_none() && found {
+                    *alias = Some(expr.to_string());
+                }
+            }
+        }
+        for expr in statement.exprs_mut() {
+            expr.walk_mut(&mut |expr| resolve_function(table, expr))?;
+            expr.walk_mut(&mut |expr| {
+                if !is_correlated(table, &temps, outer, expr) {
+                    return run_subquery(table, &temps, expr);
+                }
+                let name = format!("subquery {}", correlated.len() + 1);
+                let subquery = std::mem::replace(expr, Expr::Column(name.clone()));
+                correlated.push((name, subquery));
+                Ok(false)
+            })?;
+        }
+        statement.correlated = correlated;
+        Ok(statement)
+    }
+
+    /// replaces the parameters of the statement, and of the statements inside
+    /// it, by their values. result columns keep the name they are written with.
+    fn substitute(&mut self, values: &[Value]) {
+        for item in self.projection.iter_mut() {
+            if let SelectItem::Expr { expr, alias } = item {
+                let mut has_parameter = false;
+                expr.walk(&mut |expr| {
+                    has_parameter |= matches!(expr, Expr::Parameter { .. });
+                    !has_parameter
+                });
+                if alias.is_none() && has_parameter {
+                    *alias = Some(expr.to_string());
+                }
+            }
+        }
+
+        let mut replace = |expr: &mut Expr| -> Result<bool, Infallible> {
+            match expr {
+                Expr::Parameter { index, .. } => {
+                    *expr = Expr::Literal(values[*index - 1].clone());
+                }
+                Expr::Subquery(query) | Expr::Exists(query) | Expr::InSubquery { query, .. } => {
+                    query.statement.substitute(values)
+                }
+                _ => {}
+            }
+            Ok(true)
+        };
+        for expr in self.exprs_mut() {
+            let _ = expr.walk_mut(&mut replace);
+        }
+        if let Some((count, skip)) = self.limit.as_mut() {
+            let _ = count.walk_mut(&mut replace);
+            let _ = skip.walk_mut(&mut replace);
+        }
+        for cte in self.with.iter_mut() {
+            cte.query.substitute(values);
+        }
+        for (_, core) in self.compound.iter_mut() {
+            core.substitute(values);
+        }
+        if let Some(source) = self.source.as_mut() {
+            source.substitute(values);
+        }
+    }
+
+    /// returns every expression of the statement
+    fn exprs_mut(&mut self) -> Vec<&mut Expr> {
+        let mut exprs: Vec<&mut Expr> = Vec::new();
+        for item in self.projection.iter_mut() {
+            if let SelectItem::Expr { expr, .. } = item {
+                exprs.push(expr);
+            }
+        }
+        for term in self.order_by.iter_mut() {
+            if let SortKey::Expr(expr) = &mut term.key {
+                exprs.push(expr);
+            }
+        }
+        exprs.extend(self.condition.iter_mut());
+        exprs.extend(self.group_by.iter_mut());
+        exprs.extend(self.having.iter_mut());
+        exprs.extend(self.assignments.iter_mut().map(|(_, value)| value));
+        exprs.extend(self.table_args.iter_mut().flatten());
+        for join in self.joins.iter_mut() {
+            exprs.extend(join.args.iter_mut().flatten());
+            exprs.extend(join.on.iter_mut());
+        }
+        exprs.extend(self.rows.iter_mut().flatten());
+        if let OnConflict::Update {
+            assignments,
+            condition,
+            ..
+        } = &mut self.on_conflict
+        {
+            exprs.extend(assignments.iter_mut().map(|(_, value)| value));
+            exprs.extend(condition.iter_mut());
+        }
+        exprs
+    }
+
+    /// returns if the select, or one of the selects of its `union`, reads the table `name`
+    fn reads_table(&self, name: &str) -> bool {
+        self.table == name
+            || self.joins.iter().any(|join| join.table == name)
+            || self.compound.iter().any(|(_, core)| core.reads_table(name))
+    }
+
+    /// returns the first select of a `union`, without the clauses applying to all of them
+    fn core(&self) -> Statement {
+        Statement {
+            compound: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+            ..self.clone()
+        }
+    }
+
+    /// runs the query of a `with` clause into a temporary table. under `with recursive`
+    /// the selects of its `union` reading the table itself run again on the rows the
+    /// previous round added, until a round adds none or the `limit` is reached.
+    fn materialize(&self, table: &mut Table, cte: &Cte) -> Result<TempTable, ExecuteResult> {
+        let query = &cte.query;
+        if !self.recursive || !query.reads_table(&cte.name) {
+            let result = select(query.execute_with(table, &self.temps))?;
+            return TempTable::new(&cte.name, &cte.columns, &result.columns, result.rows)
+                .map_err(ExecuteResult::SchemaError);
+        }
+        if !query.order_by.is_empty() {
+            return Err(ExecuteResult::SchemaError(String::from(
+                "ORDER BY is not supported in a recursive query",
+            )));
+        }
+
+        let mut cores = vec![(true, query.core())];
+        cores.extend(query.compound.iter().cloned());
+        let distinct = cores.iter().any(|(all, _)| !all);
+        let (recursive, initial): (Vec<_>, Vec<_>) = cores
+            .into_iter()
+            .map(|(_, core)| core)
+            .partition(|core| core.reads_table(&cte.name));
+        if initial.is_empty() {
+            return Err(ExecuteResult::SchemaError(format!(
+                "circular reference: {}",
+                cte.name
+            )));
+        }
+
+        let mut columns = Vec::new();
+        let mut round = Vec::new();
+        for core in initial.iter() {
+            let result = select(core.execute_with(table, &self.temps))?;
+            if columns.is_empty() {
+                columns = result.columns;
+            } else {
+                check_union(&columns, &result.columns)?;
+            }
+            round.extend(result.rows);
+        }
+
+        let (limit, offset) = query.bound_limit()?;
+        let limit = limit.map(|limit| limit + offset);
+        let mut seen = HashSet::new();
+        let mut rows = Vec::new();
+        loop {
+            let mut added = Vec::new();
+            for row in round {
+                if limit.is_some_and(|limit| rows.len() >= limit) {
+                    break;
+                }
+                if distinct && !seen.insert(hash_key(&row)) {
+                    continue;
+                }
+                rows.push(row.clone());
+                added.push(row);
+            }
+            if added.is_empty() || limit.is_some_and(|limit| rows.len() >= limit) {
+                break;
+            }
+
+            let added = TempTable::new(&cte.name, &cte.columns, &columns, added)
+                .map_err(ExecuteResult::SchemaError)?;
+            let mut temps = self.temps.clone();
+            temps.push(Rc::new(added));
+            round = Vec::new();
+            for core in recursive.iter() {
+                let result = select(core.execute_with(table, &temps))?;
+                check_union(&columns, &result.columns)?;
+                round.extend(result.rows);
+            }
+        }
+
+        rows.drain(..offset.min(rows.len()));
+        TempTable::new(&cte.name, &cte.columns, &columns, rows).map_err(ExecuteResult::SchemaError)
+    }
+
+    /// runs the selects of a `union` and sorts and limits their rows together.
+    /// `order by` names result columns, by name or position.
+    fn compound_rows(&self, table: &mut Table) -> Result<ResultSet, ExecuteResult> {
+        let ResultSet { columns, mut rows } = select(self.core().execute_with(table, &self.temps))?;
+        for (all, core) in self.compound.iter() {
+            let result = select(core.execute_with(table, &self.temps))?;
+            check_union(&columns, &result.columns)?;
+            rows.extend(result.rows);
+            if !all {
+                let mut seen = HashSet::new();
+                rows.retain(|row| seen.insert(hash_key(row)));
+            }
+        }
+
+        let mut keys = Vec::new();
+        for (i, term) in self.order_by.iter().enumerate() {
+            let position = match &term.key {
+                SortKey::Output(position) => Some(*position),
+                SortKey::Expr(Expr::Literal(Value::Integer(n))) => Some(*n as usize)
+                    .filter(|&n| n >= 1 && n <= columns.len())
+                    .map(|n| n - 1),
+                SortKey::Expr(expr) => {
+                    let name = expr.to_string();
+                    columns
+                        .iter()
+                        .position(|column| column.name.eq_ignore_ascii_case(&name))
+                }
+            };
+            match position {
+                Some(position) => keys.push((position, term.descending)),
+                None => {
+                    return Err(ExecuteResult::SchemaError(format!(
+                        "{} ORDER BY term does not match any column in the result set",
+                        ordinal(i + 1)
+                    )))
+                }
+            }
+        }
+        rows.sort_by(|a, b| {
+            for &(position, descending) in keys.iter() {
+                let ordering = a[position].compare(&b[position]);
+                if ordering != Ordering::Equal {
+                    return if descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    };
+                }
+            }
+            Ordering::Equal
+        });
+
+        let (limit, offset) = self.bound_limit()?;
+        rows.drain(..offset.min(rows.len()));
+        if let Some(limit) = limit {
+            rows.truncate(limit);
+        }
+        Ok(ResultSet::new(columns, rows))
+    }
+}
+
+/// returns the rows of a select, or the result of a select that failed
+fn select(result: ExecuteResult) -> Result<ResultSet, ExecuteResult> {
+    match result {
+        ExecuteResult::SelectSuccess(result) => Ok(result),
+        result => Err(result),
+    }
+}
+
+/// checks both sides of a `union` return as many columns
+fn check_union(left: &[Column], right: &[Column]) -> Result<(), ExecuteResult> {
+    if left.len() != right.len() {
+        return Err(ExecuteResult::SchemaError(String::from(
+            "SELECTs to the left and right of UNION do not have the same number of result columns",
+        )));
+    }
+    Ok(())
+}
+
+/// returns `1st`, `2nd`, `3rd`, `4th`, ...
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// replaces a call of a function the application defined by a call of its
+/// implementation. fails for functions nobody defined.
+fn resolve_function(table: &Table, expr: &mut Expr) -> Result<bool, ExecuteResult> {
+    if let Expr::Function {
+        name,
+        args,
+        distinct,
+    } = expr
+    {
+        if function::is_function(name) || aggregate::is_aggregate(name) {
+            return Ok(true);
+        }
+        let function = table
+            .function(name)
+            .ok_or_else(|| ExecuteResult::SchemaError(format!("no such function: {}", name)))?;
+        function
+            .check_arguments(args.len())
+            .map_err(ExecuteResult::SchemaError)?;
+        *expr = Expr::UserCall {
+            function: function.clone(),
+            args: std::mem::take(args),
+            distinct: *distinct,
+        };
+    }
+    Ok(true)
+}
+
+/// returns if the expression is a subquery reading columns of the `outer`
+/// rows, which its own tables do not have
+fn is_correlated(
+    table: &Table,
+    temps: &[Rc<TempTable>],
+    outer: Option<&TableDef>,
+    expr: &Expr,
+) -> bool {
+    let (outer, query) = match (outer, expr) {
+        (
+            Some(outer),
+            Expr::Subquery(query) | Expr::Exists(query) | Expr::InSubquery { query, .. },
+        ) => (outer, query),
+        _ => return false,
+    };
+    let mut statement = query.statement.clone();
+    statement.temps = temps.to_vec();
+    let inner = match statement.planned_source(table) {
+        Ok((_, inner)) => inner,
+        Err(_) => return false,
+    };
+    let mut found = false;
+    for expr in statement.exprs_mut() {
+        expr.visit_columns(&mut |name| {
+            found |= inner.column_index(name).is_none() && outer.column_index(name).is_some();
+        });
+    }
+    found
+}
+
+/// replaces a subquery by its result: the first value of a scalar subquery,
+/// the values of `in (select ...)` or whether `exists` found a row.
+/// returns whether the expression is searched for more subqueries.
+fn run_subquery(
+    table: &mut Table,
+    temps: &[Rc<TempTable>],
+    expr: &mut Expr,
+) -> Result<bool, ExecuteResult> {
+    let statement = match expr {
+        Expr::Subquery(query) | Expr::Exists(query) | Expr::InSubquery { query, .. } => {
+            query.statement.clone()
+        }
+        _ => return Ok(true),
+    };
+    let result = select(statement.execute_with(table, temps))?;
+    if !matches!(expr, Expr::Exists(_)) && result.columns.len() != 1 {
+        return Err(ExecuteResult::SchemaError(format!(
+            "sub-select returns {} columns - expected 1",
+            result.columns.len()
+        )));
+    }
+
+    let mut values = result.rows.into_iter().map(|mut row| row.swap_remove(0));
+    *expr = match std::mem::replace(expr, Expr::Literal(Value::Null)) {
+        Expr::Subquery(_) => Expr::Literal(values.next().unwrap_or(Value::Null)),
+        Expr::Exists(_) => Expr::Literal(Value::from(values.next().is_some())),
+        Expr::InSubquery { expr, negated, .. } => Expr::InList {
+            expr,
+            list: values.map(Expr::Literal).collect(),
+            negated,
+        },
+        _ => unreachable!("only subqueries are run"),
+    };
+    // the subquery ran with its own columns, and the result holds no other
+    Ok(false)
+}
+
+/// What became of a row an insert wrote
+enum Inserted {
+    /// the row was added to the table
+    Added(Row),
+    /// the stored row it conflicted with was updated to this row instead
+    Updated(Row),
+    /// the row was left out
+    Skipped,
+}
+
+/// Scope of a statement evaluated without any row, like the values of an insert
+pub struct EmptyScope;
+
+impl Scope for EmptyScope {
+    fn column(&self, _name: &str) -> Option<Value> {
+        None
+    }
+}
+
+/// Scope reading the columns of a single row of a table
+pub struct RowScope<'a> {
+    pub table: &'a TableDef,
+    pub row: &'a Row,
+}
+
+impl<'a> Scope for RowScope<'a> {
+    fn column(&self, name: &str) -> Option<Value> {
+        let position = self.table.column_index(name)?;
+        Some(self.row.values[position].clone())
+    }
+}
+
+/// Scope of a `do update` clause, reading the stored row, or the row that
+/// conflicted with it through `excluded.<column>`
+struct UpsertScope<'a> {
+    table: &'a TableDef,
+    row: &'a Row,
+    excluded: &'a Row,
+}
+
+impl<'a> Scope for UpsertScope<'a> {
+    fn column(&self, name: &str) -> Option<Value> {
+        let (row, name) = match name
+            .strip_prefix(EXCLUDED)
+            .and_then(|n| n.strip_prefix('.'))
+        {
+            Some(column) => (self.excluded, column),
+            None => (self.row, name),
+        };
+        let position = self.table.column_index(name)?;
+        Some(row.values[position].clone())
+    }
+}
+
+/// Collects the result rows of a select, applying `order by`, `offset` and `limit`
+struct SelectOutput<'a> {
+    statement: &'a Statement,
+    table: &'a TableDef,
+    sorter: Option<Sorter>,
+    rows: Vec<Vec<Value>>,
+    limit: Option<usize>,
+    offset: usize,
+    skipped: usize,
+}
+
+impl<'a> SelectOutput<'a> {
+    /// without `sort` the rows are expected to be pushed in their final order
+    fn new(
+        statement: &'a Statement,
+        table: &'a TableDef,
+        sort: bool,
+    ) -> Result<Self, ExecuteResult> {
+        let sorter = if sort {
+            Some(Sorter::new(
+                statement.order_by.iter().map(|t| t.descending).collect(),
+            ))
+        } else {
+            None
+        };
+
+        let (limit, offset) = statement.bound_limit()?;
+        Ok(SelectOutput {
+            statement,
+            table,
+            sorter,
+            rows: Vec::new(),
+            limit,
+            offset,
+            skipped: 0,
+        })
+    }
+
+    /// adds the result row projected from the scope.
+    /// returns false once the limit is reached and no more rows are needed.
+    fn push(&mut self, scope: &dyn Scope) -> Result<bool, ExecuteResult> {
+        let statement = self.statement;
+        let values = statement
+            .project(self.table, scope)
+            .map_err(ExecuteResult::EvalError)?;
+
+        if let Some(sorter) = self.sorter.as_mut() {
+            let keys = statement
+                .sort_keys(scope, &values)
+                .map_err(ExecuteResult::EvalError)?;
+            sorter
+                .push(keys, values)
+                .map_err(|e| ExecuteResult::IoError(e.to_string()))?;
+            return Ok(true);
+        }
+
+        // rows already come in order, so offset and limit apply right away
+        if self.skipped < self.offset {
+            self.skipped += 1;
+            return Ok(true);
+        }
+        self.rows.push(values);
+        Ok(Some(self.rows.len()) != self.limit)
+    }
+
+    fn finish(self) -> Result<Vec<Vec<Value>>, ExecuteResult> {
+        let mut rows = self.rows;
+        if let Some(sorter) = self.sorter {
+            let sorted = sorter
+                .finish()
+                .map_err(|e| ExecuteResult::IoError(e.to_string()))?;
+            let limit = self.limit.unwrap_or(usize::MAX);
+            for values in sorted.skip(self.offset).take(limit) {
+                rows.push(values.map_err(|e| ExecuteResult::IoError(e.to_string()))?);
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// Result rows of a select returned by `Statement::query`, read as they are asked for
+pub struct QueryRows<'t> {
+    columns: Vec<Column>,
+    stream: Stream<'t>,
+}
+
+enum Stream<'t> {
+    Scan(Box<Scan<'t>>),
+    /// rows computed before the first one was returned
+    Computed(std::vec::IntoIter<Vec<Value>>),
+    /// a row failed, so no more are returned
+    Done,
+}
+
+impl<'t> QueryRows<'t> {
+    /// returns the columns of the result rows
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+}
+
+impl<'t> Iterator for QueryRows<'t> {
+    type Item = Result<Vec<Value>, ExecuteResult>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = match &mut self.stream {
+            Stream::Scan(scan) => scan.next(),
+            Stream::Computed(rows) => rows.next().map(Ok),
+            Stream::Done => None,
+        };
+        if let Some(Err(_)) = next {
+            self.stream = Stream::Done;
+        }
+        next
+    }
+}
+
+/// Rows of a table or a virtual table, read as they are asked for
+type ScanRows<'t> = Box<dyn Iterator<Item = Result<Row, ExecuteResult>> + 't>;
+
+/// Result rows of a select projected from the rows matching its `where`
+/// clause as the cursor reads them
+struct Scan<'t> {
+    statement: Statement,
+    def: TableDef,
+    rows: ScanRows<'t>,
+    limit: Option<usize>,
+    offset: usize,
+    skipped: usize,
+    returned: usize,
+}
+
+impl<'t> Iterator for Scan<'t> {
+    type Item = Result<Vec<Value>, ExecuteResult>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let statement = &self.statement;
+        let def = &self.def;
+        if Some(self.returned) == self.limit {
+            return None;
+        }
+        for row in self.rows.by_ref() {
+            let row = match row {
+                Ok(row) => row,
+                Err(result) => return Some(Err(result)),
+            };
+            match statement.matches(def, &row) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(reason) => return Some(Err(ExecuteResult::EvalError(reason))),
+            }
+            if self.skipped < self.offset {
+                self.skipped += 1;
+                continue;
+            }
+            self.returned += 1;
+            let values = statement.project(
+                def,
+                &RowScope {
+                    table: def,
+                    row: &row,
+                },
+            );
+            return Some(values.map_err(ExecuteResult::EvalError));
+        }
+        None
+    }
+}
+
+/// Rows sharing the same `group by` values, with the running state of every aggregate call
+struct Group {
+    /// first row of the group, read by columns used outside of aggregates
+    row: Option<Row>,
+    aggregates: Vec<Box<dyn Aggregate>>,
+}
+
+impl Group {
+    fn new(row: Option<Row>, calls: &[&Expr]) -> Self {
+        let aggregates = calls
+            .iter()
+            .map(|call| match call {
+                Expr::Function { name, distinct, .. } => {
+                    aggregate::new_aggregate(name, *distinct).expect("not an aggregate")
+                }
+                Expr::UserCall {
+                    function, distinct, ..
+                } => {
+                    let aggregate = function.new_aggregate().expect("not an aggregate");
+                    if *distinct {
+                        aggregate::with_distinct(aggregate)
+                    } else {
+                        aggregate
+                    }
+                }
+                _ => unreachable!("not a function call"),
+            })
+            .collect();
+
+        Group { row, aggregates }
+    }
+
+    fn step(&mut self, scope: &dyn Scope, calls: &[&Expr]) -> Result<(), EvalError> {
+        for (call, aggregate) in calls.iter().zip(self.aggregates.iter_mut()) {
+            if let Expr::Function { args, .. } | Expr::UserCall { args, .. } = call {
+                let values = args
+                    .iter()
+                    .map(|arg| arg.eval(scope))
+                    .collect::<Result<Vec<Value>, EvalError>>()?;
+                aggregate.step(&values)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Scope of a finished group, where aggregate calls read their results
+struct GroupScope<'a> {
+    table: &'a TableDef,
+    row: Option<&'a Row>,
+    calls: &'a [&'a Expr],
+    results: Vec<Value>,
+}
+
+impl<'a> Scope for GroupScope<'a> {
+    fn column(&self, name: &str) -> Option<Value> {
+        match self.row {
+            Some(row) => RowScope {
+                table: self.table,
+                row,
+            }
+            .column(name),
+            // the single group of an empty table has no row to read from
+            None => self.table.column_index(name).map(|_| Value::Null),
+        }
+    }
+
+    fn aggregate(&self, expr: &Expr) -> Result<Value, EvalError> {
+        let position = self
+            .calls
+            .iter()
+            .position(|call| *call == expr)
+            .expect("aggregate call was not collected");
+        Ok(self.results[position].clone())
+    }
+}
+
+/// Scope of a result row, where window function calls read their computed values
+struct WindowScope<'a> {
+    scope: &'a dyn Scope,
+    calls: &'a [&'a Expr],
+    results: Vec<Value>,
+}
+
+impl<'a> Scope for WindowScope<'a> {
+    fn column(&self, name: &str) -> Option<Value> {
+        self.scope.column(name)
+    }
+
+    fn aggregate(&self, expr: &Expr) -> Result<Value, EvalError> {
+        self.scope.aggregate(expr)
+    }
+
+    fn window(&self, expr: &Expr) -> Result<Value, EvalError> {
+        let position = self
+            .calls
+            .iter()
+            .position(|call| *call == expr)
+            .expect("window function call was not collected");
+        Ok(self.results[position].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::pager::Page;
+    use super::super::table::Table;
+    use super::super::value::DataType;
+    use super::super::vtab::{GenerateSeries, VirtualCursor, VirtualTable};
+    use super::*;
+    use std::error::Error;
+    use std::fs;
+
+    /// result of selecting whole rows from the users table
+    fn selected(rows: Vec<Row>) -> ExecuteResult {
+        let columns = vec![
+            Column::new("id", Some(DataType::Integer)),
+            Column::new("username", Some(DataType::Text)),
+            Column::new("email", Some(DataType::Text)),
+        ];
+        ExecuteResult::SelectSuccess(ResultSet::new(
+            columns,
+            rows.into_iter().map(|row| row.values).collect(),
+        ))
+    }
+
+    fn user_row(id: u32, username: &str, email: &str) -> Row {
+        Row::new(vec![
+            Value::Integer(id as i64),
+            Value::Text(String::from(username)),
+            Value::Text(String::from(email)),
+        ])
+    }
+
+    #[test]
+    fn test_insert_then_select() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_then_select";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let stmt = Statement::prepare("insert 1 user user@example.com")?;
+
+            let result = stmt.execute(&mut table);
+            assert_eq!(result, ExecuteResult::InsertSuccess);
+
+            let stmt = Statement::prepare("select")?;
+            let result = stmt.execute(&mut table);
+            assert_eq!(
+                result,
+                selected(vec![user_row(1, "user", "user@example.com")])
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_should_sort_keys() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_should_sort_keys";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let stmt = Statement::prepare("insert 1 user user@example.com")?;
+            let _ = stmt.execute(&mut table);
+            let stmt = Statement::prepare("insert 3 user3 user3@example.com")?;
+            let _ = stmt.execute(&mut table);
+            let stmt = Statement::prepare("insert 2 user2 user2@example.com")?;
+            let _ = stmt.execute(&mut table);
+            let stmt = Statement::prepare("select")?;
+            let result = stmt.execute(&mut table);
+            assert_eq!(
+                result,
+                selected(vec![
+                    user_row(1, "user", "user@example.com"),
+                    user_row(2, "user2", "user2@example.com"),
+                    user_row(3, "user3", "user3@example.com"),
+                ])
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_is_full() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_table_is_full";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let mut i = 1;
+            let result = loop {
+                let stmt =
+                    Statement::prepare(&format!("insert {i} user{i} user{i}@example.com", i = i))?;
+                let result = stmt.execute(&mut table);
+
+                if i == 1401 {
+                    break result;
+                }
+
+                i += 1;
+            };
+
+            assert_eq!(result, ExecuteResult::TableFull);
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_with_max_input_length() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_with_max_input_length";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+
+            let long_username: String = ['a'; 32].iter().collect();
+            let long_email: String = ['a'; 255].iter().collect();
+
+            let stmt = Statement::prepare(&format!("insert 1 {} {}", long_username, long_email))?;
+
+            let result = stmt.execute(&mut table);
+            assert_eq!(result, ExecuteResult::InsertSuccess);
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_fails_with_too_long_string() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_fails_with_too_long_string";
+        let mut table = Table::open(TEST_FILE)?;
+        let long_username: String = ['a'; 33].iter().collect();
+        let long_email: String = ['a'; 256].iter().collect();
+
+        let stmt = Statement::prepare(&format!("insert 1 {} {}", long_username, long_email))?;
+        assert_eq!(
+            stmt.execute(&mut table),
+            ExecuteResult::ConstraintViolation {
+                constraint: Constraint::Check,
+                table: String::from("users"),
+                column: String::from("length(username) <= 32"),
+            }
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_username_length_counts_characters() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_username_length_counts_characters";
+        let mut table = Table::open(TEST_FILE)?;
+
+        let name: String = ['田'; 32].iter().collect();
+        let stmt = Statement::prepare(&format!("insert 1 {} a@example.com", name))?;
+        assert_eq!(stmt.execute(&mut table), ExecuteResult::InsertSuccess);
+
+        let name: String = ['田'; 33].iter().collect();
+        let stmt = Statement::prepare(&format!("insert 2 {} a@example.com", name))?;
+        assert!(matches!(
+            stmt.execute(&mut table),
+            ExecuteResult::ConstraintViolation {
+                constraint: Constraint::Check,
+                ..
+            }
+        ));
+
+        let stmt = Statement::prepare(&format!("update users set username = '{}'", name))?;
+        assert!(matches!(
+            stmt.execute(&mut table),
+            ExecuteResult::ConstraintViolation {
+                constraint: Constraint::Check,
+                ..
+            }
+        ));
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_persistence() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_persistence";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let stmt = Statement::prepare("insert 1 user user@example.com")?;
+
+            let result = stmt.execute(&mut table);
+            assert_eq!(result, ExecuteResult::InsertSuccess);
+            table.close();
+        }
+
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let stmt = Statement::prepare("select")?;
+            let result = stmt.execute(&mut table);
+            assert_eq!(
+                result,
+                selected(vec![user_row(1, "user", "user@example.com")])
+            );
+        }
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reject_duplicate_key() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_reject_duplicate_key";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let stmt = Statement::prepare("insert 1 user user@example.com")?;
+
+            let _ = stmt.execute(&mut table);
+            let result = stmt.execute(&mut table);
+            assert_eq!(result, ExecuteResult::DuplicatedKey(String::from("1")));
+            table.close();
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_value_larger_than_page() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_value_larger_than_page";
+        let long_email: String = ['a'; 10_000].iter().collect();
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let stmt = Statement::prepare(&format!("insert 1 user {}", long_email))?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::InsertSuccess);
+            let stmt = Statement::prepare("insert 2 user2 user2@example.com")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::InsertSuccess);
+            table.close();
+        }
+
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let stmt = Statement::prepare("select")?;
+            let result = stmt.execute(&mut table);
+            assert_eq!(
+                result,
+                selected(vec![
+                    user_row(1, "user", &long_email),
+                    user_row(2, "user2", "user2@example.com"),
+                ])
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    fn insert_users(table: &mut Table, users: &[(u32, &str)]) -> Result<(), Box<dyn Error>> {
+        for (id, name) in users.iter() {
+            let stmt = Statement::prepare(&format!("insert {} {} {}@example.com", id, name, name))?;
+            assert_eq!(stmt.execute(table), ExecuteResult::InsertSuccess);
+        }
+        Ok(())
+    }
+
+    fn user(id: u32, name: &str) -> Row {
+        user_row(id, name, &format!("{}@example.com", name))
+    }
+
+    /// prepares and executes a statement
+    fn run(sql: &str, table: &mut Table) -> Result<ExecuteResult, Box<dyn Error>> {
+        Ok(Statement::prepare(sql)?.execute(table))
+    }
+
+    /// returns the rows of a select result
+    fn rows(result: ExecuteResult) -> Vec<Vec<Value>> {
+        match result {
+            ExecuteResult::SelectSuccess(result) => result.rows,
+            result => panic!("{:?}", result),
+        }
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.into())
+    }
+
+    #[test]
+    fn test_select_with_where_clause() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_select_with_where_clause";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            insert_users(
+                &mut table,
+                &[(5, "alice"), (11, "adam"), (12, "bob"), (20, "anna")],
+            )?;
+
+            let stmt = Statement::prepare("select where username like 'a%' and id > 10")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                selected(vec![user(11, "adam"), user(20, "anna")])
+            );
+
+            let stmt = Statement::prepare(
+                "select * from users where id between 10 and 15 or id in (5, 99)",
+            )?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                selected(vec![user(5, "alice"), user(11, "adam"), user(12, "bob")])
+            );
+
+            let stmt = Statement::prepare("select where not (id * 2 >= 24) and email is not null")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                selected(vec![user(5, "alice"), user(11, "adam")])
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_rejects_unknown_names() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_execute_rejects_unknown_names";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let schema_error = |sql: &str, table: &mut Table| -> Result<String, Box<dyn Error>> {
+                match Statement::prepare(sql)?.execute(table) {
+                    ExecuteResult::SchemaError(e) => Ok(e),
+                    result => Err(format!("unexpected result {:?}", result).into()),
+                }
+            };
+
+            assert_eq!(
+                schema_error("select where age > 3", &mut table)?,
+                "no such column: age"
+            );
+            assert_eq!(
+                schema_error("select from accounts", &mut table)?,
+                "no such table: accounts"
+            );
+            assert_eq!(
+                schema_error("update users set age = 3", &mut table)?,
+                "no such column: age"
+            );
+            assert_eq!(
+                schema_error("insert into users (id, age) values (1, 3)", &mut table)?,
+                "table users has no column named age"
+            );
+            assert_eq!(
+                schema_error("insert into users values (1, 'a')", &mut table)?,
+                "table users has 3 columns but 2 values were supplied"
+            );
+            assert_eq!(
+                schema_error("select median(id) from users", &mut table)?,
+                "no such function: median"
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_with_where_clause() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_update_with_where_clause";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            insert_users(&mut table, &[(1, "alice"), (2, "bob"), (3, "carol")])?;
+
+            let stmt = Statement::prepare(
+                "update users set email = username || '@corp.com' where id >= 2",
+            )?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::UpdateSuccess(2));
+
+            // moving keys past each other only works because the update is applied as a whole
+            let stmt = Statement::prepare("update set id = id + 1")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::UpdateSuccess(3));
+
+            let stmt = Statement::prepare("update set id = 2 where id = 4")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::DuplicatedKey(String::from("2"))
+            );
+
+            let stmt = Statement::prepare("select")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                selected(vec![
+                    user(2, "alice"),
+                    user_row(3, "bob", "bob@corp.com"),
+                    user_row(4, "carol", "carol@corp.com"),
+                ])
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_with_where_clause() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_delete_with_where_clause";
+        let long_email: String = ['a'; 10_000].iter().collect();
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            insert_users(&mut table, &[(1, "alice"), (2, "bob"), (3, "carol")])?;
+            let stmt = Statement::prepare(&format!("insert 4 dave {}", long_email))?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::InsertSuccess);
+
+            let stmt = Statement::prepare("delete from users where id in (2, 3, 4)")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::DeleteSuccess(3));
+
+            let stmt = Statement::prepare("select")?;
+            assert_eq!(stmt.execute(&mut table), selected(vec![user(1, "alice")]));
+
+            // overflow pages of deleted rows are released
+            assert!(table.pager.pages.iter().skip(1).all(|p| *p == Page::Free));
+
+            let stmt = Statement::prepare("delete")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::DeleteSuccess(1));
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_delete_leaves_table_unchanged() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_failed_delete_leaves_table_unchanged";
+        let mut table = Table::open(TEST_FILE)?;
+        run(
+            r#"insert into users values (1, 'a', '{"a": 1}'), (2, 'b', '{"a": 2}'), (3, 'c', 'oops')"#,
+            &mut table,
+        )?;
+
+        // the third row fails after the first one matched
+        assert!(matches!(
+            run(
+                "delete from users where json_extract(email, '$.a') = 1",
+                &mut table
+            )?,
+            ExecuteResult::EvalError(_)
+        ));
+        assert!(matches!(
+            run(
+                "delete from users where id > 1 returning json_extract(email, '$.a')",
+                &mut table
+            )?,
+            ExecuteResult::EvalError(_)
+        ));
+        assert_eq!(
+            rows(run("select id from users", &mut table)?),
+            vec![
+                vec![Value::Integer(1)],
+                vec![Value::Integer(2)],
+                vec![Value::Integer(3)]
+            ]
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_with_projection() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_select_with_projection";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            insert_users(&mut table, &[(1, "alice"), (2, "bob")])?;
+
+            let stmt =
+                Statement::prepare("select email, id * 2 as double, id + 0.5 half from users")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("email", Some(DataType::Text)),
+                        Column::new("double", Some(DataType::Integer)),
+                        Column::new("half", Some(DataType::Real)),
+                    ],
+                    vec![
+                        vec![
+                            Value::Text("alice@example.com".into()),
+                            Value::Integer(2),
+                            Value::Real(1.5)
+                        ],
+                        vec![
+                            Value::Text("bob@example.com".into()),
+                            Value::Integer(4),
+                            Value::Real(2.5)
+                        ],
+                    ]
+                ))
+            );
+
+            let stmt = Statement::prepare("select upper_name, * from users where id = 2")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SchemaError(String::from("no such column: upper_name"))
+            );
+
+            let stmt =
+                Statement::prepare("select username || '!', *, null from users where id = 2")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("username || '!'", Some(DataType::Text)),
+                        Column::new("id", Some(DataType::Integer)),
+                        Column::new("username", Some(DataType::Text)),
+                        Column::new("email", Some(DataType::Text)),
+                        Column::new("NULL", None),
+                    ],
+                    vec![vec![
+                        Value::Text("bob!".into()),
+                        Value::Integer(2),
+                        Value::Text("bob".into()),
+                        Value::Text("bob@example.com".into()),
+                        Value::Null,
+                    ]]
+                ))
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_with_order_by_limit_and_offset() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_select_with_order_by_limit_and_offset";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            insert_users(
+                &mut table,
+                &[(1, "carol"), (2, "alice"), (3, "dave"), (4, "bob")],
+            )?;
+
+            let stmt = Statement::prepare("select order by username")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                selected(vec![
+                    user(2, "alice"),
+                    user(4, "bob"),
+                    user(1, "carol"),
+                    user(3, "dave")
+                ])
+            );
+
+            // walks the B-tree backwards
+            let stmt = Statement::prepare("select order by id desc limit 2")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                selected(vec![user(4, "bob"), user(3, "dave")])
+            );
+
+            let stmt = Statement::prepare("select where id > 1 order by id limit 1 offset 1")?;
+            assert_eq!(stmt.execute(&mut table), selected(vec![user(3, "dave")]));
+
+            let stmt =
+                Statement::prepare("select * from users order by id % 2 desc, 2 asc limit 1, 2")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                selected(vec![user(3, "dave"), user(2, "alice")])
+            );
+
+            let stmt = Statement::prepare("select id, -id as neg from users order by neg limit 1")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("id", Some(DataType::Integer)),
+                        Column::new("neg", Some(DataType::Integer)),
+                    ],
+                    vec![vec![Value::Integer(4), Value::Integer(-4)]]
+                ))
+            );
+
+            let stmt = Statement::prepare("select id from users order by 2")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SchemaError(String::from(
+                    "ORDER BY term out of range - should be between 1 and 1"
+                ))
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_with_aggregates_and_group_by() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_select_with_aggregates_and_group_by";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+
+            let stmt = Statement::prepare("select count(*), sum(id), max(username) from users")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("count(*)", Some(DataType::Integer)),
+                        Column::new("sum(id)", Some(DataType::Integer)),
+                        Column::new("max(username)", Some(DataType::Text)),
+                    ],
+                    vec![vec![Value::Integer(0), Value::Null, Value::Null]]
+                ))
+            );
+
+            insert_users(
+                &mut table,
+                &[
+                    (1, "bob"),
+                    (2, "alice"),
+                    (3, "bob"),
+                    (4, "carol"),
+                    (5, "alice"),
+                ],
+            )?;
+
+            let stmt = Statement::prepare(
+                "select username, count(*) as n, group_concat(id, '-') from users \
+                 where id < 5 group by username having count(*) > 1 or username = 'carol' \
+                 order by max(id) desc",
+            )?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("username", Some(DataType::Text)),
+                        Column::new("n", Some(DataType::Integer)),
+                        Column::new("group_concat(id, '-')", Some(DataType::Text)),
+                    ],
+                    vec![
+                        vec![
+                            Value::Text("carol".into()),
+                            Value::Integer(1),
+                            Value::Text("4".into())
+                        ],
+                        vec![
+                            Value::Text("bob".into()),
+                            Value::Integer(2),
+                            Value::Text("1-3".into())
+                        ],
+                    ]
+                ))
+            );
+
+            let stmt = Statement::prepare(
+                "select count(distinct username), avg(id), min(id % 2 = 0) from users",
+            )?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("count(DISTINCT username)", Some(DataType::Integer)),
+                        Column::new("avg(id)", Some(DataType::Real)),
+                        Column::new("min(id % 2 = 0)", Some(DataType::Integer)),
+                    ],
+                    vec![vec![Value::Integer(3), Value::Real(3.0), Value::Integer(0)]]
+                ))
+            );
+
+            // groups come out in the order their first row was scanned
+            let stmt =
+                Statement::prepare("select id % 2 as odd, count(*) from users group by id % 2")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("odd", Some(DataType::Integer)),
+                        Column::new("count(*)", Some(DataType::Integer)),
+                    ],
+                    vec![
+                        vec![Value::Integer(1), Value::Integer(3)],
+                        vec![Value::Integer(0), Value::Integer(2)],
+                    ]
+                ))
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_rejects_misused_aggregates() {
+        assert_eq!(
+            Statement::prepare("select where count(*) > 1"),
+            Err(String::from(
+                "aggregate functions are not allowed in the WHERE clause"
+            ))
+        );
+        assert_eq!(
+            Statement::prepare("select sum(count(*)) from users"),
+            Err(String::from("misuse of aggregate function sum()"))
+        );
+        assert_eq!(
+            Statement::prepare("select max(id, username) from users"),
+            Err(String::from("wrong number of arguments to function max()"))
+        );
+        // functions the application may still define are only known when run,
+        // but the expressions of a table can only use built-in ones
+        assert!(Statement::prepare("select median(id) from users").is_ok());
+        assert_eq!(
+            Statement::prepare("create table t (a integer primary key check (median(a) > 0))"),
+            Err(String::from("no such function: median"))
+        );
+        assert_eq!(
+            Statement::prepare("update set id = max(id)"),
+            Err(String::from(
+                "aggregate functions are not allowed in the SET clause"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_count_min_max_read_only_the_btree() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_count_min_max_read_only_the_btree";
+        let long_email: String = ['a'; 10_000].iter().collect();
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            insert_users(&mut table, &[(7, "alice"), (3, "bob")])?;
+            let stmt = Statement::prepare(&format!("insert 5 carol {}", long_email))?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::InsertSuccess);
+
+            // break the overflow chain of one row so reading it fails
+            if let Page::Overflow(page) = &mut table.pager.pages[1] {
+                page.data.push(0);
+            }
+            let stmt = Statement::prepare("select count(username) from users")?;
+            assert!(matches!(
+                stmt.execute(&mut table),
+                ExecuteResult::InvalidRow(_)
+            ));
+
+            let stmt = Statement::prepare(
+                "select count(*), min(id), max(id) - min(id) as span from users",
+            )?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("count(*)", Some(DataType::Integer)),
+                        Column::new("min(id)", Some(DataType::Integer)),
+                        Column::new("span", Some(DataType::Integer)),
+                    ],
+                    vec![vec![
+                        Value::Integer(3),
+                        Value::Integer(3),
+                        Value::Integer(4)
+                    ]]
+                ))
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_index_and_look_up_rows() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_create_index_and_look_up_rows";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            insert_users(&mut table, &[(1, "alice"), (2, "bob")])?;
+
+            let stmt = Statement::prepare("create index idx_email on users (email)")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::CreateIndexSuccess);
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SchemaError(String::from("index idx_email already exists"))
+            );
+
+            // the index is kept up to date by every write
+            insert_users(&mut table, &[(3, "carol"), (4, "bob")])?;
+            let stmt = Statement::prepare("update set email = 'bob@example.com' where id = 1")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::UpdateSuccess(1));
+            let stmt = Statement::prepare("delete where id = 2")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::DeleteSuccess(1));
+            table.close();
+        }
+
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let stmt = Statement::prepare(
+                "select id, username from users where email = 'bob@example.com' order by id desc",
+            )?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("id", Some(DataType::Integer)),
+                        Column::new("username", Some(DataType::Text)),
+                    ],
+                    vec![
+                        vec![Value::Integer(4), Value::Text("bob".into())],
+                        vec![Value::Integer(1), Value::Text("alice".into())],
+                    ]
+                ))
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_lookup_reads_only_matching_rows() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_index_lookup_reads_only_matching_rows";
+        let long_email: String = ['a'; 10_000].iter().collect();
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let stmt = Statement::prepare("create index idx_username on users (username)")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::CreateIndexSuccess);
+
+            insert_users(&mut table, &[(1, "alice"), (3, "carol")])?;
+            let stmt = Statement::prepare(&format!("insert 2 bob {}", long_email))?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::InsertSuccess);
+
+            // break the overflow chain of bob so reading that row fails
+            for page in table.pager.pages.iter_mut() {
+                if let Page::Overflow(page) = page {
+                    page.data.push(0);
+                }
+            }
+            let stmt = Statement::prepare("select where 'carol' = username and id > 0")?;
+            assert_eq!(stmt.execute(&mut table), selected(vec![user(3, "carol")]));
+            let stmt = Statement::prepare("select where email = 'carol@example.com'")?;
+            assert!(matches!(
+                stmt.execute(&mut table),
+                ExecuteResult::InvalidRow(_)
+            ));
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_table_with_not_null_and_unique_columns() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_create_table_with_not_null_and_unique_columns";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            let stmt = Statement::prepare(
+                "create table accounts (id integer primary key, email text not null unique, \
+                 nickname varchar(20) unique, age int)",
+            )?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::CreateTableSuccess);
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SchemaError(String::from("table accounts already exists"))
+            );
+            let stmt =
+                Statement::prepare("create table if not exists accounts (id int primary key)")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::CreateTableSuccess);
+
+            assert_eq!(
+                run(
+                    "insert into accounts values (1, 'a@example.com', 'a', 7)",
+                    &mut table
+                )?,
+                ExecuteResult::InsertSuccess
+            );
+            // NULLs never collide with each other in a unique column
+            assert_eq!(
+                run(
+                    "insert into accounts (email, id) values ('b@example.com', 2)",
+                    &mut table
+                )?,
+                ExecuteResult::InsertSuccess
+            );
+            assert_eq!(
+                run(
+                    "insert into accounts (id, email) values (3, 'c@example.com')",
+                    &mut table
+                )?,
+                ExecuteResult::InsertSuccess
+            );
+
+            let violation = |constraint, column: &str| ExecuteResult::ConstraintViolation {
+                constraint,
+                table: String::from("accounts"),
+                column: String::from(column),
+            };
+            assert_eq!(
+                run(
+                    "insert into accounts (id, nickname) values (4, 'd')",
+                    &mut table
+                )?,
+                violation(Constraint::NotNull, "email")
+            );
+            assert_eq!(
+                run(
+                    "insert into accounts (id, email) values (4, 'a@example.com')",
+                    &mut table
+                )?,
+                violation(Constraint::Unique, "email")
+            );
+            assert_eq!(
+                run(
+                    "update accounts set nickname = 'a' where id = 2",
+                    &mut table
+                )?,
+                violation(Constraint::Unique, "nickname")
+            );
+            assert_eq!(
+                run("update accounts set nickname = 'x'", &mut table)?,
+                violation(Constraint::Unique, "nickname")
+            );
+            assert_eq!(
+                run("update accounts set email = null where id = 3", &mut table)?,
+                violation(Constraint::NotNull, "email")
+            );
+            // a row may keep its own value, and values may move between rows
+            assert_eq!(
+                run(
+                    "update accounts set email = 'z' || email, nickname = nickname",
+                    &mut table
+                )?,
+                ExecuteResult::UpdateSuccess(3)
+            );
+            assert_eq!(
+                run("delete from accounts where id = 1", &mut table)?,
+                ExecuteResult::DeleteSuccess(1)
+            );
+            assert_eq!(
+                run(
+                    "insert into accounts values (1, 'za@example.com', 'a', '42')",
+                    &mut table
+                )?,
+                ExecuteResult::InsertSuccess
+            );
+            table.close();
+        }
+
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            assert_eq!(
+                Statement::prepare("insert into accounts values (5, 'zb@example.com', 'e', 1)")?
+                    .execute(&mut table),
+                ExecuteResult::ConstraintViolation {
+                    constraint: Constraint::Unique,
+                    table: String::from("accounts"),
+                    column: String::from("email"),
+                }
+            );
+
+            // values are stored with the affinity of their column
+            let stmt = Statement::prepare("select id, age from accounts where age is not null")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("id", Some(DataType::Integer)),
+                        Column::new("age", Some(DataType::Integer)),
+                    ],
+                    vec![vec![Value::Integer(1), Value::Integer(42)]]
+                ))
+            );
+            // the users table is still there
+            assert_eq!(
+                Statement::prepare("select")?.execute(&mut table),
+                selected(vec![])
+            );
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_allocates_rowid() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_allocates_rowid";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            assert_eq!(table.last_insert_rowid(), 0);
+            assert_eq!(
+                Statement::prepare("insert x1 bob bob@example.com"),
+                Err(String::from("`x1` is not a valid id"))
+            );
+
+            // without autoincrement the largest key is reused once deleted
+            insert_users(&mut table, &[(4, "alice")])?;
+            assert_eq!(table.last_insert_rowid(), 4);
+            assert_eq!(
+                run("insert bob bob@example.com", &mut table)?,
+                ExecuteResult::InsertSuccess
+            );
+            assert_eq!(table.last_insert_rowid(), 5);
+            run("delete where id = 5", &mut table)?;
+            run("insert carol carol@example.com", &mut table)?;
+            assert_eq!(table.last_insert_rowid(), 5);
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_autoincrement_does_not_reuse_rowids() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_autoincrement_does_not_reuse_rowids";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            assert_eq!(
+                run(
+                    "create table notes (id integer primary key autoincrement, body text)",
+                    &mut table
+                )?,
+                ExecuteResult::CreateTableSuccess
+            );
+            run("insert into notes (body) values ('a')", &mut table)?;
+            run("insert into notes values (null, 'b')", &mut table)?;
+            assert_eq!(table.last_insert_rowid(), 2);
+            run("insert into notes values (10, 'c')", &mut table)?;
+            run("delete from notes where id >= 2", &mut table)?;
+            table.close();
+        }
+
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            // ids of deleted rows are not handed out again, even after reopening
+            run("insert into notes (body) values ('d')", &mut table)?;
+            assert_eq!(table.last_insert_rowid(), 11);
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_rowid_after_the_largest() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_no_rowid_after_the_largest";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            assert_eq!(
+                run(
+                    "insert 9223372036854775807 dave dave@example.com",
+                    &mut table
+                )?,
+                ExecuteResult::InsertSuccess
+            );
+            assert_eq!(
+                run("insert erin erin@example.com", &mut table)?,
+                ExecuteResult::TableFull
+            );
+            assert_eq!(table.last_insert_rowid(), i64::MAX);
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_many_rows() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_many_rows";
+        let mut table = Table::open(TEST_FILE)?;
+        assert_eq!(
+            Statement::prepare("insert into users values (1, 'a', 'b'), (2, 'c')"),
+            Err(String::from(
+                "all VALUES must have the same number of terms"
+            ))
+        );
+
+        assert_eq!(
+            run(
+                "insert into users (username, email) values \
+                 ('alice', 'alice@example.com'), ('bob', 'bob@example.com')",
+                &mut table
+            )?,
+            ExecuteResult::InsertSuccess
+        );
+        assert_eq!(table.last_insert_rowid(), 2);
+        assert_eq!(
+            run("select", &mut table)?,
+            selected(vec![user(1, "alice"), user(2, "bob")])
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_many_rows_atomically() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_many_rows_atomically";
+        let mut table = Table::open(TEST_FILE)?;
+        insert_users(&mut table, &[(1, "alice"), (2, "bob")])?;
+
+        // a failing row leaves the rows before it out as well
+        assert_eq!(
+            run(
+                "insert into users values (5, 'carol', 'carol@example.com'), \
+                 (2, 'dave', 'dave@example.com')",
+                &mut table
+            )?,
+            ExecuteResult::DuplicatedKey(String::from("2"))
+        );
+        assert_eq!(
+            run(
+                "insert into users values (5, 'carol', 'carol@example.com'), \
+                 (5, 'dave', 'dave@example.com')",
+                &mut table
+            )?,
+            ExecuteResult::DuplicatedKey(String::from("5"))
+        );
+        assert_eq!(
+            run(
+                "insert into users (username, email) values ('carol', 'c'), (null, 'd')",
+                &mut table
+            )?,
+            ExecuteResult::violation(
+                Constraint::NotNull,
+                table.schema.table("users").unwrap(),
+                "username"
+            )
+        );
+        assert_eq!(
+            run("select", &mut table)?,
+            selected(vec![user(1, "alice"), user(2, "bob")])
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_select() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_select";
+        let mut table = Table::open(TEST_FILE)?;
+        insert_users(&mut table, &[(1, "alice"), (2, "bob")])?;
+        run(
+            "create table names (id integer primary key, name text unique)",
+            &mut table,
+        )?;
+
+        // allocated keys follow the keys of the rows inserted before them
+        assert_eq!(
+            run(
+                "insert into names (name) select username from users order by id desc",
+                &mut table
+            )?,
+            ExecuteResult::InsertSuccess
+        );
+        assert_eq!(
+            run(
+                "insert into names select id + 10, username || '2' from users where id = 1",
+                &mut table
+            )?,
+            ExecuteResult::InsertSuccess
+        );
+        assert_eq!(
+            run(
+                "insert into names (name) values ('x'), ('alice')",
+                &mut table
+            )?,
+            ExecuteResult::violation(
+                Constraint::Unique,
+                table.schema.table("names").unwrap(),
+                "name"
+            )
+        );
+        run("insert into names (name) values ('x'), ('y')", &mut table)?;
+        assert_eq!(table.last_insert_rowid(), 13);
+        assert_eq!(
+            run("select id, name from names", &mut table)?,
+            ExecuteResult::SelectSuccess(ResultSet::new(
+                vec![
+                    Column::new("id", Some(DataType::Integer)),
+                    Column::new("name", Some(DataType::Text)),
+                ],
+                vec![
+                    vec![Value::Integer(1), Value::Text("bob".into())],
+                    vec![Value::Integer(2), Value::Text("alice".into())],
+                    vec![Value::Integer(11), Value::Text("alice2".into())],
+                    vec![Value::Integer(12), Value::Text("x".into())],
+                    vec![Value::Integer(13), Value::Text("y".into())],
+                ]
+            ))
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_select_with_other_columns() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_select_with_other_columns";
+        let mut table = Table::open(TEST_FILE)?;
+        run(
+            "create table names (id integer primary key, name text unique)",
+            &mut table,
+        )?;
+        assert_eq!(
+            run("insert into names select * from users", &mut table)?,
+            ExecuteResult::SchemaError(String::from(
+                "table names has 2 columns but 3 values were supplied"
+            ))
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    /// creates the `stock` table holding apples and pears
+    fn create_stock(table: &mut Table) -> Result<(), Box<dyn Error>> {
+        run(
+            "create table stock (id integer primary key, name text unique, count integer)",
+            table,
+        )?;
+        run(
+            "insert into stock values (1, 'apple', 3), (2, 'pear', 5)",
+            table,
+        )?;
+        Ok(())
+    }
+
+    fn item(id: i64, name: &str, count: i64) -> Vec<Value> {
+        vec![
+            Value::Integer(id),
+            Value::Text(name.into()),
+            Value::Integer(count),
+        ]
+    }
+
+    #[test]
+    fn test_insert_or_replace() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_or_replace";
+        let mut table = Table::open(TEST_FILE)?;
+        create_stock(&mut table)?;
+
+        // replace deletes every row in the way, on the key and on unique columns
+        assert_eq!(
+            run(
+                "insert or replace into stock values (2, 'apple', 7)",
+                &mut table
+            )?,
+            ExecuteResult::InsertSuccess
+        );
+        assert_eq!(
+            rows(run("select * from stock", &mut table)?),
+            vec![item(2, "apple", 7)]
+        );
+        assert_eq!(
+            rows(run("select id from stock where name = 'pear'", &mut table)?),
+            Vec::<Vec<Value>>::new()
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_or_ignore() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_or_ignore";
+        let mut table = Table::open(TEST_FILE)?;
+        create_stock(&mut table)?;
+
+        // ignore skips conflicting rows and inserts the others
+        assert_eq!(
+            run(
+                "insert or ignore into stock values (2, 'plum', 1), (3, 'apple', 1), (4, 'fig', 2)",
+                &mut table
+            )?,
+            ExecuteResult::InsertSuccess
+        );
+        assert_eq!(
+            rows(run("select * from stock", &mut table)?),
+            vec![item(1, "apple", 3), item(2, "pear", 5), item(4, "fig", 2)]
+        );
+        assert_eq!(table.last_insert_rowid(), 4);
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert_do_update() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_upsert_do_update";
+        let mut table = Table::open(TEST_FILE)?;
+        create_stock(&mut table)?;
+
+        // do update reads the stored row and the excluded one
+        assert_eq!(
+            run(
+                "insert into stock values (5, 'pear', 10) \
+                 on conflict (name) do update set count = count + excluded.count",
+                &mut table
+            )?,
+            ExecuteResult::InsertSuccess
+        );
+        assert_eq!(
+            run(
+                "insert into stock values (2, 'kiwi', 1) \
+                 on conflict (id) do update set name = excluded.name where stock.count > 5",
+                &mut table
+            )?,
+            ExecuteResult::InsertSuccess
+        );
+        assert_eq!(
+            run(
+                "insert into stock values (1, 'lime', 1) \
+                 on conflict (id) do update set name = excluded.name where stock.count > 50",
+                &mut table
+            )?,
+            ExecuteResult::InsertSuccess
+        );
+        assert_eq!(
+            rows(run("select * from stock", &mut table)?),
+            vec![item(1, "apple", 3), item(2, "kiwi", 15)]
+        );
+        assert_eq!(table.last_insert_rowid(), 2);
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert_fails_on_other_conflicts() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_upsert_fails_on_other_conflicts";
+        let mut table = Table::open(TEST_FILE)?;
+        create_stock(&mut table)?;
+        let unique_name = ExecuteResult::violation(
+            Constraint::Unique,
+            table.schema.table("stock").unwrap(),
+            "name",
+        );
+
+        // a conflict the clause does not name fails, undoing the whole statement
+        assert_eq!(
+            run(
+                "insert into stock values (6, 'plum', 1), (7, 'pear', 1) \
+                 on conflict (id) do nothing",
+                &mut table
+            )?,
+            unique_name
+        );
+        assert_eq!(
+            run(
+                "insert into stock values (2, 'plum', 1) \
+                 on conflict (name) do update set count = 0",
+                &mut table
+            )?,
+            ExecuteResult::DuplicatedKey(String::from("2"))
+        );
+        // the update itself may conflict as well
+        assert_eq!(
+            run(
+                "insert into stock values (8, 'plum', 1) \
+                 on conflict (name) do update set name = 'pear'",
+                &mut table
+            )?,
+            ExecuteResult::InsertSuccess
+        );
+        assert_eq!(
+            run(
+                "insert into stock values (9, 'plum', 1) \
+                 on conflict (name) do update set name = 'pear'",
+                &mut table
+            )?,
+            unique_name
+        );
+        assert_eq!(
+            rows(run("select * from stock", &mut table)?),
+            vec![item(1, "apple", 3), item(2, "pear", 5), item(8, "plum", 1)]
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert_errors() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_upsert_errors";
+        let mut table = Table::open(TEST_FILE)?;
+        create_stock(&mut table)?;
+
+        assert_eq!(
+            run(
+                "insert into stock values (1, 'a', 1) on conflict (count) do nothing",
+                &mut table
+            )?,
+            ExecuteResult::SchemaError(String::from(
+                "ON CONFLICT clause does not match any PRIMARY KEY or UNIQUE constraint"
+            ))
+        );
+        assert_eq!(
+            run(
+                "insert into stock values (1, 'a', 1) \
+                 on conflict (id) do update set count = excluded.amount",
+                &mut table
+            )?,
+            ExecuteResult::SchemaError(String::from("no such column: excluded.amount"))
+        );
+        assert_eq!(
+            Statement::prepare(
+                "insert or ignore into stock values (1, 'a', 1) on conflict do nothing"
+            ),
+            Err(String::from(
+                "ON CONFLICT can not be combined with OR REPLACE or OR IGNORE"
+            ))
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert_on_unique_index() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_upsert_on_unique_index";
+        let mut table = Table::open(TEST_FILE)?;
+        create_stock(&mut table)?;
+
+        // a unique index is a conflict target as well as a unique column
+        run("create unique index idx_count on stock (count)", &mut table)?;
+        assert_eq!(
+            run(
+                "insert into stock values (9, 'fig', 5) \
+                 on conflict (count) do update set name = excluded.name",
+                &mut table
+            )?,
+            ExecuteResult::InsertSuccess
+        );
+        assert_eq!(
+            rows(run("select * from stock", &mut table)?),
+            vec![item(1, "apple", 3), item(2, "fig", 5)]
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    /// creates the `notes` table with the given rows
+    fn create_notes(table: &mut Table, values: &str) -> Result<(), Box<dyn Error>> {
+        run(
+            "create table notes (id integer primary key, body text, stars integer default 1)",
+            table,
+        )?;
+        if !values.is_empty() {
+            run(&format!("insert into notes values {}", values), table)?;
+        }
+        Ok(())
+    }
+
+    fn note(id: i64, body: &str, stars: i64) -> Vec<Value> {
+        vec![
+            Value::Integer(id),
+            Value::Text(body.into()),
+            Value::Integer(stars),
+        ]
+    }
+
+    #[test]
+    fn test_insert_returning() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_insert_returning";
+        let mut table = Table::open(TEST_FILE)?;
+        create_notes(&mut table, "")?;
+
+        // generated keys and defaults come back with the row
+        assert_eq!(
+            run(
+                "insert into notes (body) values ('a'), ('b') returning *",
+                &mut table
+            )?,
+            ExecuteResult::SelectSuccess(ResultSet::new(
+                vec![
+                    Column::new("id", Some(DataType::Integer)),
+                    Column::new("body", Some(DataType::Text)),
+                    Column::new("stars", Some(DataType::Integer)),
+                ],
+                vec![note(1, "a", 1), note(2, "b", 1)]
+            ))
+        );
+        assert_eq!(
+            run(
+                "insert into notes values (2, 'c', 5), (3, 'd', 1) \
+                 on conflict do update set stars = excluded.stars returning id, stars * 10 as score",
+                &mut table
+            )?,
+            ExecuteResult::SelectSuccess(ResultSet::new(
+                vec![
+                    Column::new("id", Some(DataType::Integer)),
+                    Column::new("score", Some(DataType::Integer)),
+                ],
+                vec![
+                    vec![Value::Integer(2), Value::Integer(50)],
+                    vec![Value::Integer(3), Value::Integer(10)],
+                ]
+            ))
+        );
+        assert_eq!(
+            run(
+                "insert or ignore into notes values (1, 'x', 1) returning id",
+                &mut table
+            )?,
+            ExecuteResult::SelectSuccess(ResultSet::new(
+                vec![Column::new("id", Some(DataType::Integer))],
+                Vec::new()
+            ))
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_returning() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_update_returning";
+        let mut table = Table::open(TEST_FILE)?;
+        create_notes(&mut table, "(1, 'a', 1), (2, 'b', 5), (3, 'd', 1)")?;
+
+        assert_eq!(
+            run(
+                "update notes set stars = stars + 1 where id < 3 returning body, stars",
+                &mut table
+            )?,
+            ExecuteResult::SelectSuccess(ResultSet::new(
+                vec![
+                    Column::new("body", Some(DataType::Text)),
+                    Column::new("stars", Some(DataType::Integer)),
+                ],
+                vec![
+                    vec![Value::Text("a".into()), Value::Integer(2)],
+                    vec![Value::Text("b".into()), Value::Integer(6)],
+                ]
+            ))
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_returning() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_delete_returning";
+        let mut table = Table::open(TEST_FILE)?;
+        create_notes(&mut table, "(1, 'a', 2), (2, 'b', 6), (3, 'd', 1)")?;
+
+        assert_eq!(
+            run("delete from notes where stars > 5 returning *", &mut table)?,
+            ExecuteResult::SelectSuccess(ResultSet::new(
+                vec![
+                    Column::new("id", Some(DataType::Integer)),
+                    Column::new("body", Some(DataType::Text)),
+                    Column::new("stars", Some(DataType::Integer)),
+                ],
+                vec![note(2, "b", 6)]
+            ))
+        );
+        assert_eq!(
+            run("delete from notes where id = 3", &mut table)?,
+            ExecuteResult::DeleteSuccess(1)
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_returning_errors() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_returning_errors";
+        let mut table = Table::open(TEST_FILE)?;
+        create_notes(&mut table, "(1, 'a', 2)")?;
+
+        // the list is checked before any row is written
+        assert_eq!(
+            run("delete from notes returning missing", &mut table)?,
+            ExecuteResult::SchemaError(String::from("no such column: missing"))
+        );
+        assert_eq!(
+            rows(run("select * from notes", &mut table)?),
+            vec![note(1, "a", 2)]
+        );
+        assert!(Statement::prepare("delete from notes returning count(*)").is_err());
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_with_joins() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_select_with_joins";
+        let mut table = Table::open(TEST_FILE)?;
+        insert_users(&mut table, &[(1, "ann"), (2, "bo"), (3, "cy")])?;
+        for sql in [
+            "create table posts (id integer primary key, author integer, title text)",
+            "create table tags (post integer, tag text, primary key (post, tag))",
+            "insert into posts values (10, 2, 'hi'), (11, 1, 'yo'), (12, 2, 'ok'), (13, 9, 'lost')",
+            "insert into tags values (10, 'a'), (10, 'b'), (12, 'b')",
+        ] {
+            run(sql, &mut table)?;
+        }
+
+        assert_eq!(
+            rows(run(
+                "select username, title from users join posts on posts.author = users.id \
+                 order by title",
+                &mut table
+            )?),
+            vec![
+                vec![text("bo"), text("hi")],
+                vec![text("bo"), text("ok")],
+                vec![text("ann"), text("yo")],
+            ]
+        );
+        assert_eq!(
+            rows(run(
+                "select u.username, p.title, t.tag from users as u \
+                 left join posts p on p.author = u.id \
+                 left outer join tags t on t.post = p.id where u.id > 1",
+                &mut table
+            )?),
+            vec![
+                vec![text("bo"), text("hi"), text("a")],
+                vec![text("bo"), text("hi"), text("b")],
+                vec![text("bo"), text("ok"), text("b")],
+                vec![text("cy"), Value::Null, Value::Null],
+            ]
+        );
+        assert_eq!(
+            rows(run(
+                "select username, count(tag) from users left join posts on author = users.id \
+                 left join tags on post = posts.id and tag <> 'a' group by username",
+                &mut table
+            )?),
+            vec![
+                vec![text("ann"), Value::Integer(0)],
+                vec![text("bo"), Value::Integer(2)],
+                vec![text("cy"), Value::Integer(0)],
+            ]
+        );
+        assert_eq!(
+            rows(run(
+                "select count(*) from posts, tags where title = 'hi'",
+                &mut table
+            )?),
+            vec![vec![Value::Integer(3)]]
+        );
+
+        match run("select * from tags join posts on post = id", &mut table)? {
+            ExecuteResult::SelectSuccess(result) => {
+                assert_eq!(result.header(), "(post, tag, id, author, title)");
+                assert_eq!(result.rows.len(), 3);
+            }
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(
+            run(
+                "select id from users join posts on author = users.id",
+                &mut table
+            )?,
+            ExecuteResult::SchemaError(String::from("ambiguous column name: id"))
+        );
+        assert_eq!(
+            run(
+                "select * from users u join posts on posts.author = users.id",
+                &mut table
+            )?,
+            ExecuteResult::SchemaError(String::from("no such column: users.id"))
+        );
+        assert_eq!(
+            run("select * from users join nothing", &mut table)?,
+            ExecuteResult::SchemaError(String::from("no such table: nothing"))
+        );
+        assert_eq!(
+            run("select * from users join users", &mut table)?,
+            ExecuteResult::SchemaError(String::from(
+                "table users is joined twice without an alias"
+            ))
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_subqueries_unions_and_common_table_expressions() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_subqueries_unions_and_common_table_expressions";
+        let mut table = Table::open(TEST_FILE)?;
+        insert_users(&mut table, &[(1, "ann"), (2, "bo"), (3, "cy")])?;
+        for sql in [
+            "create table staff (id integer primary key, name text, boss integer)",
+            "insert into staff values (1, 'root', null), (2, 'a', 1), (3, 'b', 1), \
+             (4, 'c', 2), (5, 'd', 4), (6, 'other', null)",
+        ] {
+            run(sql, &mut table)?;
+        }
+
+        assert_eq!(
+            rows(run(
+                "select username from users where id = (select min(boss) from staff where boss > 1)",
+                &mut table
+            )?),
+            vec![vec![text("bo")]]
+        );
+        assert_eq!(
+            rows(run(
+                "select username from users where id not in (select boss from staff \
+                 where boss is not null) and exists (select * from staff)",
+                &mut table
+            )?),
+            vec![vec![text("cy")]]
+        );
+        assert_eq!(
+            rows(run(
+                "select id from users where not exists (select * from staff where id > 6)",
+                &mut table
+            )?)
+            .len(),
+            3
+        );
+        assert_eq!(
+            rows(run(
+                "select id from users union select boss from staff where boss > 1 \
+                 order by 1 desc limit 2",
+                &mut table
+            )?),
+            vec![vec![Value::Integer(4)], vec![Value::Integer(3)]]
+        );
+        assert_eq!(
+            rows(run(
+                "select id from users where id < 3 union all select id from users where id < 3",
+                &mut table
+            )?)
+            .len(),
+            4
+        );
+
+        // a table of the `with` clause hides a table of the same name
+        assert_eq!(
+            rows(run(
+                "with users (id, username) as (select id, name from staff where boss = 1), \
+                 picked as (select username from users) \
+                 select u.username from picked u order by username desc",
+                &mut table
+            )?),
+            vec![vec![text("b")], vec![text("a")]]
+        );
+        assert_eq!(
+            rows(run(
+                "with recursive below (id, depth) as (select id, 0 from staff where id = 2 union all \
+                 select staff.id, depth + 1 from staff join below on staff.boss = below.id) \
+                 select name, depth from staff join below on staff.id = below.id order by name",
+                &mut table
+            )?),
+            vec![
+                vec![text("a"), Value::Integer(0)],
+                vec![text("c"), Value::Integer(1)],
+                vec![text("d"), Value::Integer(2)],
+            ]
+        );
+        assert_eq!(
+            rows(run(
+                "with recursive counter (n) as (select id from staff where id = 1 \
+                 union select n + 1 from counter limit 5) \
+                 select sum(n) from counter",
+                &mut table
+            )?),
+            vec![vec![Value::Integer(15)]]
+        );
+        run(
+            "with old as (select * from staff where boss is null) \
+             insert into users select id + 10, name, 'x' from old",
+            &mut table,
+        )?;
+        assert_eq!(
+            rows(run("select count(*) from users where id > 10", &mut table)?),
+            vec![vec![Value::Integer(2)]]
+        );
+
+        assert_eq!(
+            run(
+                "select id from users where id = (select id, name from staff)",
+                &mut table
+            )?,
+            ExecuteResult::SchemaError(String::from("sub-select returns 2 columns - expected 1"))
+        );
+        // a subquery reading columns of the select runs for each of its rows
+        assert_eq!(
+            rows(run(
+                "select username from users where exists (select * from staff where boss = users.id)",
+                &mut table
+            )?),
+            vec![vec![text("ann")], vec![text("bo")]]
+        );
+        match run(
+            "select username, (select count(*) from staff where boss = users.id) from users \
+             order by id",
+            &mut table,
+        )? {
+            ExecuteResult::SelectSuccess(result) => {
+                assert_eq!(
+                    result.header(),
+                    "(username, (select count(*) from staff where boss = users.id))"
+                );
+                assert_eq!(
+                    result.rows,
+                    vec![
+                        vec![text("ann"), Value::Integer(2)],
+                        vec![text("bo"), Value::Integer(1)],
+                        vec![text("cy"), Value::Integer(0)],
+                        vec![text("root"), Value::Integer(0)],
+                        vec![text("other"), Value::Integer(0)],
+                    ]
+                );
+            }
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(
+            rows(run(
+                "select u.username from users u \
+                 where not exists (select * from staff s where s.boss = u.id) \
+                 and 'x' not in (select name from staff where boss = u.id)",
+                &mut table
+            )?),
+            vec![vec![text("cy")], vec![text("root")], vec![text("other")]]
+        );
+        assert_eq!(
+            rows(run(
+                "select username from users where 'a' in (select name from staff where boss = users.id)",
+                &mut table
+            )?),
+            vec![vec![text("ann")]]
+        );
+
+        assert_eq!(
+            run("select id from users union select id, name from staff", &mut table)?,
+            ExecuteResult::SchemaError(String::from(
+                "SELECTs to the left and right of UNION do not have the same number of result columns"
+            ))
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
 
-#[derive(Debug, Eq, PartialEq)]
-pub enum ExecuteResult {
-    InsertSuccess,
-    SelectSuccess(Vec<Row>),
-    TableFull,
-    EmptyRow,
-    DuplicatedKey(u32),
-}
+    #[test]
+    fn test_select_without_from() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_select_without_from";
+        let mut table = Table::open(TEST_FILE)?;
 
-// Hard coded table
+        // a single row however many users there are, while a bare `select` reads them
+        assert_eq!(
+            rows(run("select 1 + 1, 'a' as b", &mut table)?),
+            vec![vec![Value::Integer(2), text("a")]]
+        );
+        assert_eq!(rows(run("select", &mut table)?).len(), 0);
+        run(
+            "insert into users values (1, 'ann', 'a@x.com'), (2, 'bo', 'b@x.com')",
+            &mut table,
+        )?;
+        assert_eq!(
+            rows(run("select 1 + 1, 'a' as b", &mut table)?),
+            vec![vec![Value::Integer(2), text("a")]]
+        );
+        assert_eq!(rows(run("select", &mut table)?).len(), 2);
+        assert_eq!(
+            rows(run(
+                "select count(*), (select max(id) from users) + 1",
+                &mut table
+            )?),
+            vec![vec![Value::Integer(1), Value::Integer(3)]]
+        );
+        assert_eq!(rows(run("select 1 where 0", &mut table)?).len(), 0);
 
-impl Statement {
-    pub fn prepare(input: &str) -> Result<Self, StatementError> {
-        // TODO: refactor to use function to match first word in input
+        assert_eq!(
+            rows(run(
+                "with recursive c(n) as (select 1 union all select n + 1 from c where n < 5) \
+                 select n from c",
+                &mut table
+            )?),
+            (1..=5).map(|n| vec![Value::Integer(n)]).collect::<Vec<_>>()
+        );
 
-        if input.starts_with("insert") {
-            // scan arguments
+        assert_eq!(
+            run("select id", &mut table)?,
+            ExecuteResult::SchemaError(String::from("no such column: id"))
+        );
+        assert_eq!(
+            Statement::prepare("select *"),
+            Err(String::from("no tables specified"))
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
 
-            let raw_args: Vec<&str> = input.split_whitespace().collect();
+    #[test]
+    fn test_select_with_scalar_functions() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_select_with_scalar_functions";
+        let mut table = Table::open(TEST_FILE)?;
+        for sql in [
+            "insert into users values (1, 'ann', 'Ann@Example.com')",
+            "insert into users values (2, 'bo', 'bo@example.COM')",
+        ] {
+            Statement::prepare(sql)?.execute(&mut table);
+        }
 
-            // check if length of arguments match length of the table
-            // FIXME: hard coded length
-            if raw_args.len() != 4 {
-                return Err(String::from("arguments length does not match"));
+        let stmt = Statement::prepare(
+            "select printf('%03d:%s', id, upper(username)), substr(email, instr(email, '@') + 1) \
+             from users where lower(email) = 'ann@example.com'",
+        )?;
+        match stmt.execute(&mut table) {
+            ExecuteResult::SelectSuccess(result) => {
+                assert_eq!(
+                    result.rows,
+                    vec![vec![
+                        Value::Text("001:ANN".into()),
+                        Value::Text("Example.com".into())
+                    ]]
+                );
+                assert_eq!(result.columns[0].data_type, Some(DataType::Text));
             }
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(
+            Statement::prepare("select substr(email) from users"),
+            Err(String::from(
+                "wrong number of arguments to function substr()"
+            ))
+        );
 
-            let id = raw_args[1].parse::<u32>().unwrap();
+        // without `from` the functions are computed once, however many users there are
+        assert_eq!(
+            rows(run(
+                "select abs(-3), upper('a'), coalesce(null, round(2.5))",
+                &mut table
+            )?),
+            vec![vec![Value::Integer(3), text("A"), Value::Real(3.0)]]
+        );
 
-            if raw_args[2].len() > COLUMN_USERNAME_SIZE {
-                return Err(String::from("Too long string."));
-            }
-            let username = String::from(raw_args[2]);
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
 
-            if raw_args[3].len() > COLUMN_EMAIL_SIZE {
-                return Err(String::from("Too long string."));
-            }
-            let email = String::from(raw_args[3]);
+    #[test]
+    fn test_filter_audit_rows_by_time() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_filter_audit_rows_by_time";
+        let mut table = Table::open(TEST_FILE)?;
+        // times are stored as ISO-8601 text or as seconds since 1970
+        for sql in [
+            "create table audit (id integer primary key, action text, \
+             at timestamp default (datetime('now')), at_epoch integer)",
+            "insert into audit values (1, 'login', '2024-02-28 23:50:00', 1709164200), \
+             (2, 'update', '2024-03-01 08:00:00', 1709280000), \
+             (3, 'logout', '2024-03-10 17:30:00', 1710091800)",
+            "insert into audit (id, action) values (4, 'now')",
+        ] {
+            run(sql, &mut table)?;
+        }
 
-            let row = Row::new(id, username, email);
+        let ids = |result: ExecuteResult| -> Vec<Value> {
+            rows(result).into_iter().map(|row| row[0].clone()).collect()
+        };
+        assert_eq!(
+            ids(run(
+                "select id from audit where at between '2024-02-29' \
+                 and datetime('2024-03-01', '+7 days')",
+                &mut table
+            )?),
+            vec![Value::Integer(2)]
+        );
+        assert_eq!(
+            ids(run(
+                "select id from audit \
+                 where at_epoch >= unixepoch('2024-03-10 17:30:00', '-9 days', 'start of day')",
+                &mut table
+            )?),
+            vec![Value::Integer(2), Value::Integer(3)]
+        );
+        assert_eq!(
+            ids(run(
+                "select id from audit where at > datetime('now', '-1 minute')",
+                &mut table
+            )?),
+            vec![Value::Integer(4)]
+        );
+        assert_eq!(
+            rows(run(
+                "select date(at_epoch, 'unixepoch'), strftime('%H:%M', at, '+30 minutes'), \
+                 round((julianday(at) - julianday('2024-02-28')) * 1440) \
+                 from audit where id = 1",
+                &mut table
+            )?),
+            vec![vec![
+                Value::Text("2024-02-28".into()),
+                Value::Text("00:20".into()),
+                Value::Real(1430.0)
+            ]]
+        );
 
-            Ok(Statement {
-                kind: StatementKind::Insert,
-                row: Some(row),
-            })
-        } else if input.starts_with("select") {
-            Ok(Statement {
-                kind: StatementKind::Select,
-                row: None,
-            })
-        } else {
-            Err(String::from("invalid input"))
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_json_columns() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_query_json_columns";
+        let mut table = Table::open(TEST_FILE)?;
+        for sql in [
+            "create table docs (id integer primary key, meta json)",
+            r#"insert into docs values (1, '{"owner": "ann", "tags": ["db", "rust"]}')"#,
+            r#"insert into docs values (2, '{"owner": "bo", "tags": []}'), (3, null)"#,
+        ] {
+            run(sql, &mut table)?;
         }
+        assert_eq!(
+            run("insert into docs values (4, '{\"owner\": }')", &mut table)?,
+            ExecuteResult::InvalidRow(String::from("`{\"owner\": }` is not valid JSON for meta"))
+        );
+
+        assert_eq!(
+            rows(run(
+                "select id, json_extract(meta, '$.owner'), json_array_length(meta, '$.tags') \
+                 from docs where json_extract(meta, '$.tags[0]') = 'db'",
+                &mut table
+            )?),
+            vec![vec![Value::Integer(1), text("ann"), Value::Integer(2)]]
+        );
+        assert_eq!(
+            run(
+                "update docs set meta = json_set(meta, '$.tags[#]', 'new', '$.owner', 'cy') \
+                 where id = 2",
+                &mut table
+            )?,
+            ExecuteResult::UpdateSuccess(1)
+        );
+        assert_eq!(
+            rows(run("select meta from docs where id = 2", &mut table)?),
+            vec![vec![text(r#"{"owner":"cy","tags":["new"]}"#)]]
+        );
+
+        // json_each reads the tags of each row it is joined with
+        assert_eq!(
+            rows(run(
+                "select d.id, t.key, t.value from docs d, json_each(d.meta, '$.tags') as t \
+                 order by t.value",
+                &mut table
+            )?),
+            vec![
+                vec![Value::Integer(1), Value::Integer(0), text("db")],
+                vec![Value::Integer(2), Value::Integer(0), text("new")],
+                vec![Value::Integer(1), Value::Integer(1), text("rust")],
+            ]
+        );
+        assert_eq!(
+            rows(run(
+                "select key, type, atom from json_each('{\"a\": 1, \"b\": [true]}')",
+                &mut table
+            )?),
+            vec![
+                vec![text("a"), text("integer"), Value::Integer(1)],
+                vec![text("b"), text("array"), Value::Null],
+            ]
+        );
+
+        assert_eq!(
+            run("select json_extract(meta, 'owner') from docs", &mut table)?,
+            ExecuteResult::EvalError(String::from("bad JSON path: 'owner'"))
+        );
+        assert_eq!(
+            Statement::prepare("select * from json_each(meta)"),
+            Err(String::from("no such column: meta"))
+        );
+        assert_eq!(
+            run("select * from docs, json_tree(meta)", &mut table)?,
+            ExecuteResult::SchemaError(String::from("no such table-valued function: json_tree"))
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
     }
 
-    fn execute_insert(&self, table: &mut Table) -> ExecuteResult {
-        if let Some(row_to_insert) = &self.row {
-            let key_to_insert = row_to_insert.id;
-            let node = table.get_node(table.root_page_num).unwrap();
-            if node.key_duplicated(key_to_insert) {
-                return ExecuteResult::DuplicatedKey(key_to_insert);
+    #[test]
+    fn test_bind_parameters() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_bind_parameters";
+        let mut table = Table::open(TEST_FILE)?;
+
+        // prepared once, then run for each row
+        let mut insert = Statement::prepare("insert into users values (?, ?, :email)")?;
+        assert_eq!(insert.parameter_count(), 3);
+        assert_eq!(insert.parameter_index(":email"), Some(3));
+        for (id, username) in [(1, "ann"), (2, "bo"), (3, "x'); delete from users; --")] {
+            insert.bind(1, Value::Integer(id))?;
+            insert.bind(2, text(username))?;
+            insert.bind_named(":email", text(&format!("{}@example.com", id)))?;
+            assert_eq!(insert.execute(&mut table), ExecuteResult::InsertSuccess);
+            insert.reset();
+        }
+        // unbound parameters are NULL
+        assert_eq!(
+            insert.execute(&mut table),
+            ExecuteResult::ConstraintViolation {
+                constraint: Constraint::NotNull,
+                table: String::from("users"),
+                column: String::from("username"),
             }
+        );
 
-            let num_cells = node.num_cells();
-            if num_cells >= MAX_NUM_CELLS {
-                return ExecuteResult::TableFull;
+        // a name used twice is one parameter, and subqueries share them
+        let mut select = Statement::prepare(
+            "select id, ?2 || username from users where id >= :min \
+             and id <> (select max(id) from users where id > :min) order by id",
+        )?;
+        assert_eq!(select.parameter_count(), 3);
+        select.bind_named(":min", Value::Integer(2))?;
+        select.bind(2, text("@"))?;
+        match select.execute(&mut table) {
+            ExecuteResult::SelectSuccess(result) => {
+                assert_eq!(result.header(), "(id, ?2 || username)");
+                assert_eq!(result.rows, vec![vec![Value::Integer(2), text("@bo")]]);
             }
+            result => panic!("{:?}", result),
+        }
+        select.bind_named(":min", Value::Integer(1))?;
+        assert_eq!(rows(select.execute(&mut table)).len(), 2);
 
-            let mut cursor = table.table_find(key_to_insert);
-            let _ = cursor.insert_value(&row_to_insert);
+        // limit and offset take parameters bound to integers
+        let mut page = Statement::prepare("select id from users order by id limit ? offset :skip")?;
+        page.bind(1, Value::Integer(2))?;
+        page.bind_named(":skip", Value::Integer(1))?;
+        assert_eq!(
+            rows(page.execute(&mut table)),
+            vec![vec![Value::Integer(2)], vec![Value::Integer(3)]]
+        );
+        let mut page = Statement::prepare("select id from users order by id limit ?, ?")?;
+        page.bind(1, Value::Integer(2))?;
+        page.bind(2, Value::Integer(-1))?;
+        assert_eq!(
+            rows(page.execute(&mut table)),
+            vec![vec![Value::Integer(3)]]
+        );
+        page.bind(2, text("two"))?;
+        assert_eq!(
+            page.execute(&mut table),
+            ExecuteResult::EvalError(String::from("LIMIT must be an integer, not two"))
+        );
+        page.reset();
+        page.bind(2, Value::Integer(1))?;
+        assert_eq!(
+            page.execute(&mut table),
+            ExecuteResult::EvalError(String::from("OFFSET must be an integer, not NULL"))
+        );
+        assert_eq!(
+            rows(
+                Statement::prepare("select username from users where id = 3")?.execute(&mut table)
+            ),
+            vec![vec![text("x'); delete from users; --")]]
+        );
 
-            return ExecuteResult::InsertSuccess;
-        }
+        assert_eq!(
+            select.bind(4, Value::Null),
+            Err(String::from("parameter index 4 out of range"))
+        );
+        assert_eq!(
+            select.bind_named(":max", Value::Null),
+            Err(String::from("no such parameter: :max"))
+        );
+        assert_eq!(
+            Statement::prepare("select ?0"),
+            Err(String::from(
+                "variable number must be between ?1 and ?32766"
+            ))
+        );
+        assert_eq!(
+            Statement::prepare("create table t (id int primary key default ?)"),
+            Err(String::from("parameters are not allowed in CREATE"))
+        );
 
-        // This should not happen.
-        ExecuteResult::EmptyRow
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
     }
 
-    fn execute_select(&self, table: &mut Table) -> ExecuteResult {
-        let mut res = Vec::new();
+    /// keeps the longest text it is fed
+    #[derive(Default)]
+    struct Longest(Option<String>);
 
-        let mut cursor = table.table_start();
-        while !cursor.is_end() {
-            if let Some(row) = cursor.get_value() {
-                res.push(row);
+    impl Aggregate for Longest {
+        fn step(&mut self, args: &[Value]) -> Result<(), EvalError> {
+            if let Some(text) = args[0].as_text() {
+                if self
+                    .0
+                    .as_ref()
+                    .is_none_or(|longest| text.len() > longest.len())
+                {
+                    self.0 = Some(text);
+                }
             }
-            cursor.advance();
+            Ok(())
         }
 
-        ExecuteResult::SelectSuccess(res)
+        fn finalize(&mut self) -> Result<Value, EvalError> {
+            Ok(self.0.clone().map_or(Value::Null, Value::Text))
+        }
     }
 
-    pub fn execute(&self, table: &mut Table) -> ExecuteResult {
-        match self.kind {
-            StatementKind::Insert => self.execute_insert(table),
-            StatementKind::Select => self.execute_select(table),
+    /// registers `domain(email)`, `pick(...)` returning its last argument and `longest(text)`
+    fn create_functions(table: &mut Table) -> Result<(), Box<dyn Error>> {
+        table.create_scalar_function("domain", Some(1), |args| {
+            Ok(match args[0].as_text() {
+                Some(email) => Value::Text(email.rsplit('@').next().unwrap_or_default().into()),
+                None => Value::Null,
+            })
+        })?;
+        table.create_scalar_function("pick", None, |args| {
+            Ok(args.last().cloned().unwrap_or(Value::Null))
+        })?;
+        table.create_aggregate_function::<Longest>("longest", Some(1))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_defined_functions() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_user_defined_functions";
+        let mut table = Table::open(TEST_FILE)?;
+        insert_users(&mut table, &[(1, "ann"), (2, "bob"), (3, "carol")])?;
+        create_functions(&mut table)?;
+
+        match run(
+            "select domain(email), longest(username), longest(distinct pick()) from users \
+             where pick(1, id) > 1 group by domain(email)",
+            &mut table,
+        )? {
+            ExecuteResult::SelectSuccess(result) => {
+                assert_eq!(
+                    result.header(),
+                    "(domain(email), longest(username), longest(DISTINCT pick()))"
+                );
+                assert_eq!(
+                    result.rows,
+                    vec![vec![
+                        Value::Text("example.com".into()),
+                        Value::Text("carol".into()),
+                        Value::Null
+                    ]]
+                );
+            }
+            result => panic!("{:?}", result),
         }
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::super::table::Table;
-    use super::*;
-    use std::error::Error;
-    use std::fs;
+    #[test]
+    fn test_user_defined_functions_in_writes() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_user_defined_functions_in_writes";
+        let mut table = Table::open(TEST_FILE)?;
+        insert_users(&mut table, &[(1, "ann"), (2, "bob")])?;
+        create_functions(&mut table)?;
+
+        assert_eq!(
+            run(
+                "update users set email = domain(email) where id = 1",
+                &mut table
+            )?,
+            ExecuteResult::UpdateSuccess(1)
+        );
+        assert_eq!(
+            rows(run("select email from users", &mut table)?),
+            vec![vec![text("example.com")], vec![text("bob@example.com")]]
+        );
 
-    const TEST_FILE: &str = "db_test";
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
 
     #[test]
-    fn test_insert_then_select() -> Result<(), Box<dyn Error>> {
+    fn test_user_defined_function_errors() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_user_defined_function_errors";
+        let mut table = Table::open(TEST_FILE)?;
+        insert_users(&mut table, &[(1, "ann")])?;
+        create_functions(&mut table)?;
+
+        assert_eq!(
+            run("select domain(id, email) from users", &mut table)?,
+            ExecuteResult::SchemaError(String::from(
+                "wrong number of arguments to function domain()"
+            ))
+        );
+        assert_eq!(
+            run(
+                "select id from users where longest(email) = 'x'",
+                &mut table
+            )?,
+            ExecuteResult::EvalError(String::from("misuse of aggregate: longest()"))
+        );
+        assert_eq!(
+            table.create_scalar_function("lower", Some(1), |args| Ok(args[0].clone())),
+            Err(String::from(
+                "built-in function lower() can not be redefined"
+            ))
+        );
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_with_window_functions() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_select_with_window_functions";
+        let mut table = Table::open(TEST_FILE)?;
+        for sql in [
+            "create table sales (id integer primary key, region text, amount integer)",
+            "insert into sales values (1, 'n', 10), (2, 's', 5), (3, 'n', 30), (4, 'n', 10), \
+             (5, 's', 7)",
+        ] {
+            run(sql, &mut table)?;
+        }
+        let int =
+            |values: &[i64]| -> Vec<Value> { values.iter().map(|&v| Value::Integer(v)).collect() };
+
+        assert_eq!(
+            rows(run(
+                "select id, row_number() over (partition by region order by amount, id), \
+                 rank() over (partition by region order by amount), \
+                 sum(amount) over (partition by region order by amount) from sales",
+                &mut table
+            )?),
+            vec![
+                int(&[1, 1, 1, 20]),
+                int(&[2, 1, 1, 5]),
+                int(&[3, 3, 3, 50]),
+                int(&[4, 2, 1, 20]),
+                int(&[5, 2, 2, 12]),
+            ]
+        );
+        assert_eq!(
+            rows(run(
+                "select id, lag(amount) over (order by id), lead(amount, 2, 0) over (order by id) \
+                 from sales where id > 1 order by id desc limit 3",
+                &mut table
+            )?),
+            vec![
+                vec![Value::Integer(5), Value::Integer(10), Value::Integer(0)],
+                vec![Value::Integer(4), Value::Integer(30), Value::Integer(0)],
+                vec![Value::Integer(3), Value::Integer(5), Value::Integer(7)],
+            ]
+        );
+        // windows run over the groups, ordered by an aggregate
+        assert_eq!(
+            rows(run(
+                "select region, sum(amount), rank() over (order by sum(amount) desc) r, \
+                 count(*) over () from sales group by region order by r",
+                &mut table
+            )?),
+            vec![
+                vec![
+                    Value::Text("n".into()),
+                    Value::Integer(50),
+                    Value::Integer(1),
+                    Value::Integer(2)
+                ],
+                vec![
+                    Value::Text("s".into()),
+                    Value::Integer(12),
+                    Value::Integer(2),
+                    Value::Integer(2)
+                ],
+            ]
+        );
+        match run(
+            "select sum(amount) over (partition by region) from sales",
+            &mut table,
+        )? {
+            ExecuteResult::SelectSuccess(result) => {
+                assert_eq!(result.header(), "(sum(amount) OVER (PARTITION BY region))");
+                assert_eq!(
+                    result.rows,
+                    vec![int(&[50]), int(&[12]), int(&[50]), int(&[50]), int(&[12])]
+                );
+            }
+            result => panic!("{:?}", result),
+        }
+
+        for (sql, error) in [
+            (
+                "select id from sales where row_number() over () > 1",
+                "misuse of window function row_number()",
+            ),
+            (
+                "select rank() from sales",
+                "misuse of window function rank()",
+            ),
+            (
+                "select length(id) over () from sales",
+                "length() may not be used as a window function",
+            ),
+            (
+                "select lag() over () from sales",
+                "wrong number of arguments to function lag()",
+            ),
+        ] {
+            assert_eq!(Statement::prepare(sql), Err(String::from(error)));
+        }
+
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_and_composite_primary_keys() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_text_and_composite_primary_keys";
+        let tags = |rows: Vec<Vec<Value>>| {
+            ExecuteResult::SelectSuccess(ResultSet::new(
+                vec![
+                    Column::new("user_id", Some(DataType::Integer)),
+                    Column::new("tag", Some(DataType::Text)),
+                ],
+                rows,
+            ))
+        };
         {
             let mut table = Table::open(TEST_FILE)?;
-            let stmt = Statement::prepare("insert 1 user user@example.com")?;
+            run(
+                "create table tags (user_id int, tag text, primary key (user_id, tag))",
+                &mut table,
+            )?;
+            for (user_id, tag) in [(2, "b"), (-1, "z"), (2, "a"), (10, "a")].iter() {
+                let sql = format!("insert into tags values ({}, '{}')", user_id, tag);
+                assert_eq!(run(&sql, &mut table)?, ExecuteResult::InsertSuccess);
+            }
+            assert_eq!(
+                run("insert into tags values (2, 'a')", &mut table)?,
+                ExecuteResult::DuplicatedKey(String::from("2, a"))
+            );
+            assert_eq!(
+                run("insert into tags (tag) values ('c')", &mut table)?,
+                ExecuteResult::violation(
+                    Constraint::NotNull,
+                    table.schema.table("tags").unwrap(),
+                    "user_id"
+                )
+            );
+            assert_eq!(
+                run("update tags set tag = 'a' where user_id = 2", &mut table)?,
+                ExecuteResult::DuplicatedKey(String::from("2, a"))
+            );
+            table.close();
+        }
 
-            let result = stmt.execute(&mut table);
-            assert_eq!(result, ExecuteResult::InsertSuccess);
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            // rows come back in key order without being sorted
+            let stmt = Statement::prepare("select * from tags order by user_id desc")?;
+            assert_eq!(
+                stmt.primary_key_order(table.schema.table("tags").unwrap()),
+                Some(true)
+            );
+            assert_eq!(
+                stmt.execute(&mut table),
+                tags(vec![
+                    vec![Value::Integer(10), Value::Text("a".into())],
+                    vec![Value::Integer(2), Value::Text("b".into())],
+                    vec![Value::Integer(2), Value::Text("a".into())],
+                    vec![Value::Integer(-1), Value::Text("z".into())],
+                ])
+            );
 
-            let stmt = Statement::prepare("select")?;
-            let result = stmt.execute(&mut table);
+            run(
+                "create table words (word text primary key, n int)",
+                &mut table,
+            )?;
+            run("insert into words values ('pear', 1)", &mut table)?;
+            run("insert into words values (12, 2)", &mut table)?;
+            run("insert into words values ('apple', 3)", &mut table)?;
             assert_eq!(
-                result,
-                ExecuteResult::SelectSuccess(vec![Row::new(
-                    1,
-                    String::from("user"),
-                    String::from("user@example.com")
-                )])
+                run("select min(word), max(word) from words", &mut table)?,
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("min(word)", Some(DataType::Text)),
+                        Column::new("max(word)", Some(DataType::Text)),
+                    ],
+                    vec![vec![Value::Text("12".into()), Value::Text("pear".into())]]
+                ))
+            );
+            let long_word: String = ['a'; 300].iter().collect();
+            assert_eq!(
+                run(
+                    &format!("insert into words values ('{}', 4)", long_word),
+                    &mut table
+                )?,
+                ExecuteResult::InvalidRow(String::from("primary key of table words is too long"))
+            );
+            assert_eq!(
+                run("insert into words (n) values (5)", &mut table)?,
+                ExecuteResult::violation(
+                    Constraint::NotNull,
+                    table.schema.table("words").unwrap(),
+                    "word"
+                )
             );
         }
         let _ = fs::remove_file(TEST_FILE);
@@ -147,24 +5780,86 @@ mod tests {
     }
 
     #[test]
-    fn test_insert_should_sort_keys() -> Result<(), Box<dyn Error>> {
+    fn test_create_table_with_check_and_default() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_create_table_with_check_and_default";
+        let violation = |check: &str| ExecuteResult::ConstraintViolation {
+            constraint: Constraint::Check,
+            table: String::from("items"),
+            column: String::from(check),
+        };
         {
             let mut table = Table::open(TEST_FILE)?;
-            let stmt = Statement::prepare("insert 1 user user@example.com")?;
-            let _ = stmt.execute(&mut table);
-            let stmt = Statement::prepare("insert 3 user3 user3@example.com")?;
-            let _ = stmt.execute(&mut table);
-            let stmt = Statement::prepare("insert 2 user2 user2@example.com")?;
-            let _ = stmt.execute(&mut table);
-            let stmt = Statement::prepare("select")?;
-            let result = stmt.execute(&mut table);
             assert_eq!(
-                result,
-                ExecuteResult::SelectSuccess(vec![
-                    Row::new(1, String::from("user"), String::from("user@example.com")),
-                    Row::new(2, String::from("user2"), String::from("user2@example.com")),
-                    Row::new(3, String::from("user3"), String::from("user3@example.com")),
-                ])
+                run(
+                    "create table items (id integer primary key, \
+                     price real default 1.5 check (price >= 0), \
+                     qty int default -1, label text default ('item'), \
+                     check (qty <> 0))",
+                    &mut table
+                )?,
+                ExecuteResult::CreateTableSuccess
+            );
+            assert_eq!(
+                run("insert into items (id) values (1)", &mut table)?,
+                ExecuteResult::InsertSuccess
+            );
+            assert_eq!(
+                run("insert into items (id, price) values (2, -3)", &mut table)?,
+                violation("price >= 0")
+            );
+            // a NULL does not fail a check
+            assert_eq!(
+                run("insert into items values (2, null, 5, null)", &mut table)?,
+                ExecuteResult::InsertSuccess
+            );
+            assert_eq!(
+                run("update items set qty = qty + 1", &mut table)?,
+                violation("qty <> 0")
+            );
+            table.close();
+        }
+
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            assert_eq!(
+                run(
+                    "update items set price = price - 2 where id = 1",
+                    &mut table
+                )?,
+                violation("price >= 0")
+            );
+            assert_eq!(
+                run("select * from items", &mut table)?,
+                ExecuteResult::SelectSuccess(ResultSet::new(
+                    vec![
+                        Column::new("id", Some(DataType::Integer)),
+                        Column::new("price", Some(DataType::Real)),
+                        Column::new("qty", Some(DataType::Integer)),
+                        Column::new("label", Some(DataType::Text)),
+                    ],
+                    vec![
+                        vec![
+                            Value::Integer(1),
+                            Value::Real(1.5),
+                            Value::Integer(-1),
+                            Value::Text("item".into())
+                        ],
+                        vec![
+                            Value::Integer(2),
+                            Value::Null,
+                            Value::Integer(5),
+                            Value::Null
+                        ],
+                    ]
+                ))
+            );
+            assert_eq!(
+                Statement::prepare("create table bad (id int primary key, n int default id)"),
+                Err(String::from("default value of column [n] is not constant"))
+            );
+            assert_eq!(
+                Statement::prepare("create table bad (id int primary key, check (n > 0))"),
+                Err(String::from("no such column: n"))
             );
         }
         let _ = fs::remove_file(TEST_FILE);
@@ -172,97 +5867,392 @@ mod tests {
     }
 
     #[test]
-    fn test_table_is_full() -> Result<(), Box<dyn Error>> {
+    fn test_create_unique_index() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_create_unique_index";
         {
             let mut table = Table::open(TEST_FILE)?;
-            let mut i = 1;
-            let result = loop {
-                let stmt =
-                    Statement::prepare(&format!("insert {i} user{i} user{i}@example.com", i = i))?;
-                let result = stmt.execute(&mut table);
+            insert_users(&mut table, &[(1, "alice"), (2, "bob"), (3, "alice")])?;
 
-                if i == 1401 {
-                    break result;
+            let stmt = Statement::prepare("create unique index idx_username on users (username)")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::ConstraintViolation {
+                    constraint: Constraint::Unique,
+                    table: String::from("users"),
+                    column: String::from("username"),
                 }
+            );
+            assert!(table.schema.indexes.is_empty());
 
-                i += 1;
-            };
+            let stmt = Statement::prepare("delete where id = 3")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::DeleteSuccess(1));
+            let stmt = Statement::prepare("create unique index idx_username on users (username)")?;
+            assert_eq!(stmt.execute(&mut table), ExecuteResult::CreateIndexSuccess);
 
-            assert_eq!(result, ExecuteResult::TableFull);
+            let stmt = Statement::prepare("insert 3 bob bob@example.com")?;
+            assert_eq!(
+                stmt.execute(&mut table),
+                ExecuteResult::ConstraintViolation {
+                    constraint: Constraint::Unique,
+                    table: String::from("users"),
+                    column: String::from("username"),
+                }
+            );
         }
         let _ = fs::remove_file(TEST_FILE);
         Ok(())
     }
 
+    fn query<'t>(table: &'t mut Table, sql: &str) -> QueryRows<'t> {
+        let stmt = Statement::prepare(sql).unwrap();
+        stmt.query(table).unwrap()
+    }
+
+    fn ids(rows: QueryRows) -> Vec<i64> {
+        rows.map(|values| match values.unwrap()[0] {
+            Value::Integer(id) => id,
+            ref value => panic!("{:?}", value),
+        })
+        .collect()
+    }
+
+    /// six users whose usernames are json, except for the third one
+    fn insert_json_users(table: &mut Table) -> Result<(), Box<dyn Error>> {
+        let insert = Statement::prepare(
+            "insert into users values (1, '\"a\"', 'a'), (2, '\"b\"', 'b'), \
+             (3, 'not json', 'c'), (4, '\"d\"', 'd'), (5, '\"e\"', 'e'), (6, '\"f\"', 'f')",
+        )?;
+        assert_eq!(insert.execute(table), ExecuteResult::InsertSuccess);
+        Ok(())
+    }
+
     #[test]
-    fn test_insert_with_max_input_length() -> Result<(), Box<dyn Error>> {
+    fn test_query_rows_lazily() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_query_rows_lazily";
         {
             let mut table = Table::open(TEST_FILE)?;
+            insert_json_users(&mut table)?;
 
-            let long_username: String = ['a'; 32].iter().collect();
-            let long_email: String = ['a'; 255].iter().collect();
+            let rows = query(&mut table, "select id, username from users where id <> 4");
+            assert_eq!(
+                rows.columns(),
+                &[
+                    Column::new("id", Some(DataType::Integer)),
+                    Column::new("username", Some(DataType::Text)),
+                ]
+            );
+            assert_eq!(ids(rows), vec![1, 2, 3, 5, 6]);
 
-            let stmt = Statement::prepare(&format!("insert 1 {} {}", long_username, long_email))?;
+            // rows past the ones taken are never read, so the bad one does not fail
+            let rows = query(&mut table, "select id, json(username) from users");
+            assert!(rows.take(2).all(|row| row.is_ok()));
+            let rows = query(&mut table, "select id, json(username) from users limit 2");
+            assert_eq!(ids(rows), vec![1, 2]);
+        }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
 
-            let result = stmt.execute(&mut table);
-            assert_eq!(result, ExecuteResult::InsertSuccess);
+    #[test]
+    fn test_query_ends_at_the_failing_row() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_query_ends_at_the_failing_row";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            insert_json_users(&mut table)?;
+
+            let mut rows = query(&mut table, "select id, json(username) from users");
+            assert!(rows.next().unwrap().is_ok());
+            assert!(rows.next().unwrap().is_ok());
+            assert!(matches!(
+                rows.next(),
+                Some(Err(ExecuteResult::EvalError(_)))
+            ));
+            assert!(rows.next().is_none());
         }
         let _ = fs::remove_file(TEST_FILE);
         Ok(())
     }
 
     #[test]
-    fn test_insert_fails_with_too_long_string() -> Result<(), Box<dyn Error>> {
-        let long_username: String = ['a'; 33].iter().collect();
-        let long_email: String = ['a'; 256].iter().collect();
+    fn test_query_rows_in_order() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_query_rows_in_order";
+        {
+            let mut table = Table::open(TEST_FILE)?;
+            insert_json_users(&mut table)?;
 
-        let result = Statement::prepare(&format!("insert 1 {} {}", long_username, long_email));
-        assert_eq!(result, Err(String::from("Too long string.")));
+            let rows = query(
+                &mut table,
+                "select id from users order by id desc limit 2 offset 1",
+            );
+            assert_eq!(ids(rows), vec![5, 4]);
 
+            // selects that need every row are computed first
+            let rows = query(
+                &mut table,
+                "select id from users order by email desc limit 2",
+            );
+            assert_eq!(ids(rows), vec![6, 5]);
+            let rows = query(&mut table, "select count(*) from users");
+            assert_eq!(ids(rows), vec![6]);
+        }
+        let _ = fs::remove_file(TEST_FILE);
         Ok(())
     }
 
     #[test]
-    #[ignore]
-    fn test_persistence() -> Result<(), Box<dyn Error>> {
+    fn test_query_virtual_table_rows_lazily() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_query_virtual_table_rows_lazily";
         {
             let mut table = Table::open(TEST_FILE)?;
-            let stmt = Statement::prepare("insert 1 user user@example.com")?;
 
-            let result = stmt.execute(&mut table);
-            assert_eq!(result, ExecuteResult::InsertSuccess);
-            table.close();
+            // a virtual table is read from its cursor as well, so the rows of
+            // a series without a stop end at the limit
+            let rows = query(
+                &mut table,
+                "select value from generate_series(1) where value % 2 = 0 limit 3",
+            );
+            assert_eq!(ids(rows), vec![2, 4, 6]);
         }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
 
+    #[test]
+    fn test_query_errors() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_query_errors";
         {
             let mut table = Table::open(TEST_FILE)?;
-            let stmt = Statement::prepare("select")?;
-            let result = stmt.execute(&mut table);
+
+            let stmt = Statement::prepare("delete from users")?;
+            assert!(stmt.query(&mut table).is_err());
+            let stmt = Statement::prepare("select id from missing")?;
             assert_eq!(
-                result,
-                ExecuteResult::SelectSuccess(vec![Row::new(
-                    1,
-                    String::from("user"),
-                    String::from("user@example.com")
-                )])
+                stmt.query(&mut table).err(),
+                Some(ExecuteResult::SchemaError(String::from(
+                    "no such table: missing"
+                )))
             );
         }
+        let _ = fs::remove_file(TEST_FILE);
+        Ok(())
+    }
+
+    /// log lines, read as a virtual table of their level and message
+    struct Logs {
+        lines: Vec<(&'static str, &'static str)>,
+        /// constraints handed to the cursors
+        seen: Rc<std::cell::RefCell<Vec<ColumnConstraint>>>,
+    }
+
+    struct LogsCursor<'a> {
+        logs: &'a Logs,
+        position: usize,
+    }
+
+    impl VirtualTable for Logs {
+        fn columns(&self) -> Vec<ColumnDef> {
+            vec![
+                ColumnDef::new("level", "text"),
+                ColumnDef::new("message", "text"),
+            ]
+        }
+
+        fn open(&self) -> Result<Box<dyn VirtualCursor + '_>, EvalError> {
+            Ok(Box::new(LogsCursor {
+                logs: self,
+                position: 0,
+            }))
+        }
+    }
+
+    impl<'a> VirtualCursor for LogsCursor<'a> {
+        fn filter(
+            &mut self,
+            args: &[Value],
+            constraints: &[ColumnConstraint],
+        ) -> Result<(), EvalError> {
+            if !args.is_empty() {
+                return Err(String::from("logs takes no arguments"));
+            }
+            self.logs.seen.borrow_mut().extend_from_slice(constraints);
+            self.position = 0;
+            Ok(())
+        }
+
+        fn next(&mut self) -> Result<(), EvalError> {
+            self.position += 1;
+            Ok(())
+        }
+
+        fn eof(&self) -> bool {
+            self.position >= self.logs.lines.len()
+        }
+
+        fn column(&self, i: usize) -> Result<Value, EvalError> {
+            let (level, message) = self.logs.lines[self.position];
+            Ok(Value::Text(String::from([level, message][i])))
+        }
+
+        fn rowid(&self) -> Result<i64, EvalError> {
+            Ok(self.position as i64 + 100)
+        }
+    }
+
+    #[test]
+    fn test_select_from_virtual_tables() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_select_from_virtual_tables";
+        let int = Value::Integer;
+        let mut table = Table::open(TEST_FILE)?;
+        let seen = Rc::new(std::cell::RefCell::new(Vec::new()));
+        table.register_virtual_table(
+            "Logs",
+            Logs {
+                lines: vec![
+                    ("info", "started"),
+                    ("error", "disk full"),
+                    ("error", "retrying"),
+                ],
+                seen: Rc::clone(&seen),
+            },
+        )?;
+        insert_users(&mut table, &[(1, "ann"), (2, "bo")])?;
+
+        // the rowid and the arguments of generate_series are hidden from `*`
+        assert_eq!(
+            run("select * from generate_series(1, 3)", &mut table)?,
+            ExecuteResult::SelectSuccess(ResultSet::new(
+                vec![Column::new("value", Some(DataType::Integer))],
+                vec![vec![int(1)], vec![int(2)], vec![int(3)]]
+            ))
+        );
+        assert_eq!(
+            rows(run(
+                "select rowid, value, step from generate_series(10, 30, 10)",
+                &mut table
+            )?),
+            vec![
+                vec![int(1), int(10), int(10)],
+                vec![int(2), int(20), int(10)],
+                vec![int(3), int(30), int(10)],
+            ]
+        );
+        // the bound of the `where` clause keeps the series from running to its default stop
+        assert_eq!(
+            rows(run(
+                "select value from generate_series(1) where 3 >= value",
+                &mut table
+            )?),
+            vec![vec![int(1)], vec![int(2)], vec![int(3)]]
+        );
+        assert_eq!(
+            rows(run(
+                "select username, s.value from users, generate_series(1, id) as s \
+                 order by username, s.value",
+                &mut table
+            )?),
+            vec![
+                vec![text("ann"), int(1)],
+                vec![text("bo"), int(1)],
+                vec![text("bo"), int(2)],
+            ]
+        );
+
+        // a virtual table read by name sees the terms of the `where` clause on its columns
+        assert_eq!(
+            rows(run(
+                "select rowid, message from logs where level = 'error' and rowid > 101",
+                &mut table
+            )?),
+            vec![vec![int(102), text("retrying")]]
+        );
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                ColumnConstraint {
+                    column: 0,
+                    op: ConstraintOp::Eq,
+                    value: text("error"),
+                },
+                ColumnConstraint {
+                    column: 2,
+                    op: ConstraintOp::Gt,
+                    value: int(101),
+                },
+            ]
+        );
+        assert_eq!(
+            rows(run(
+                "select l.message, u.username from logs l join users u on u.id = l.rowid - 99",
+                &mut table
+            )?),
+            vec![
+                vec![text("started"), text("ann")],
+                vec![text("disk full"), text("bo")]
+            ]
+        );
+        assert_eq!(
+            rows(run(
+                "select level, count(*) from logs group by level",
+                &mut table
+            )?),
+            vec![vec![text("info"), int(1)], vec![text("error"), int(2)]]
+        );
+
+        assert_eq!(
+            run("select * from logs('x')", &mut table)?,
+            ExecuteResult::EvalError(String::from("logs takes no arguments"))
+        );
+        assert_eq!(
+            run("select * from generate_series(1, 2, 0)", &mut table)?,
+            ExecuteResult::EvalError(String::from("generate_series() step must be positive"))
+        );
+        assert_eq!(
+            run("delete from logs", &mut table)?,
+            ExecuteResult::SchemaError(String::from("no such table: logs"))
+        );
+        assert_eq!(
+            table.register_virtual_table("json_each", GenerateSeries),
+            Err(String::from(
+                "built-in table-valued function json_each() can not be redefined"
+            ))
+        );
 
         let _ = fs::remove_file(TEST_FILE);
         Ok(())
     }
 
     #[test]
-    fn test_reject_duplicate_key() -> Result<(), Box<dyn Error>> {
-        {
-            let mut table = Table::open(TEST_FILE)?;
-            let stmt = Statement::prepare("insert 1 user user@example.com")?;
+    fn test_hidden_columns_take_arguments_from_where() -> Result<(), Box<dyn Error>> {
+        const TEST_FILE: &str = "db_test_hidden_columns_take_arguments_from_where";
+        let int = Value::Integer;
+        let mut table = Table::open(TEST_FILE)?;
 
-            let _ = stmt.execute(&mut table);
-            let result = stmt.execute(&mut table);
-            assert_eq!(result, ExecuteResult::DuplicatedKey(1));
-            table.close();
-        }
+        assert_eq!(
+            rows(run(
+                "select value from generate_series where start = 1 and stop = 3",
+                &mut table
+            )?),
+            vec![vec![int(1)], vec![int(2)], vec![int(3)]]
+        );
+        // the terms follow the arguments given
+        assert_eq!(
+            rows(run(
+                "select value from generate_series(2) where 6 = stop and step = 2",
+                &mut table
+            )?),
+            vec![vec![int(2)], vec![int(4)], vec![int(6)]]
+        );
+        assert_eq!(
+            run(
+                "select value from generate_series where stop = 3",
+                &mut table
+            )?,
+            ExecuteResult::EvalError(String::from(
+                "wrong number of arguments to table-valued function generate_series()"
+            ))
+        );
+
+        drop(table);
         let _ = fs::remove_file(TEST_FILE);
         Ok(())
     }
@@ -1,21 +1,66 @@
-use super::{btree::LeafNode, cursor::Cursor, pager::Pager};
+use super::{
+    aggregate::{self, Aggregate},
+    btree::{cell_size, IndexNode, LeafNode},
+    cursor::Cursor,
+    expression::EvalError,
+    function::{self, UserFunction},
+    key::decode_key,
+    pager::{Page, Pager},
+    row::Row,
+    schema::{Index, Schema, SchemaPage, TableDef},
+    source::TableFunction,
+    value::Value,
+    vtab::{GenerateSeries, VirtualTable},
+    window,
+};
+use std::collections::HashMap;
 use std::error::Error;
+use std::rc::Rc;
 
 pub struct Table {
-    pub root_page_num: usize, // index of root node
     pub pager: Pager,
+    pub schema: Schema,
+    last_insert_rowid: i64,
+    /// number of rows written by the most recent insert, update or delete
+    changes: usize,
+    /// schema and last inserted rowid from before `begin`
+    saved: Option<(Schema, i64)>,
+    /// functions the application defined, by name
+    functions: HashMap<String, UserFunction>,
+    /// virtual tables the application registered, by name
+    virtual_tables: HashMap<String, Rc<dyn VirtualTable>>,
 }
 
 impl Table {
     pub fn open(filename: &str) -> Result<Self, Box<dyn Error>> {
         let mut pager = Pager::open(filename)?;
         if pager.pages.is_empty() {
-            pager.pages.push(LeafNode::new(true, 0, 0, Vec::new()));
+            // page 0 is the root of the built-in users table
+            pager
+                .pages
+                .push(Page::Leaf(LeafNode::new(true, 0, 0, Vec::new())));
         }
 
+        let schema = pager
+            .pages
+            .iter()
+            .find_map(|page| match page {
+                Page::Schema(page) => Some(page.schema.clone()),
+                _ => None,
+            })
+            .unwrap_or_default();
+
         Ok(Table {
-            root_page_num: 0,
             pager,
+            schema,
+            last_insert_rowid: 0,
+            changes: 0,
+            saved: None,
+            functions: HashMap::new(),
+            virtual_tables: HashMap::from([(
+                String::from("generate_series"),
+                Rc::new(GenerateSeries) as Rc<dyn VirtualTable>,
+            )]),
         })
     }
 
@@ -28,23 +73,34 @@ impl Table {
         }
     }
 
-    /// returns cursor pointing to the start of the table
-    pub fn table_start(&mut self) -> Cursor {
-        let num_cells = self.pager.pages[self.root_page_num].num_cells();
+    /// returns cursor pointing to the start of the B-tree at `root_page_num`
+    pub fn table_start(&mut self, root_page_num: usize) -> Cursor<'_> {
+        let num_cells = self.get_node(root_page_num).unwrap().num_cells();
+
+        Cursor::new(self, root_page_num, 0, num_cells == 0)
+    }
+
+    /// returns cursor pointing to the end of the B-tree at `root_page_num`
+    pub fn table_end(&mut self, root_page_num: usize) -> Cursor<'_> {
+        let num_cells = self.get_node(root_page_num).unwrap().num_cells();
 
-        Cursor::new(self, self.root_page_num, 0, num_cells == 0)
+        Cursor::new(self, root_page_num, num_cells, true)
     }
 
-    /// returns cursor pointing to the end of the table
-    pub fn table_end(&mut self) -> Cursor {
-        let num_cells = self.pager.pages[self.root_page_num].num_cells();
+    /// returns cursor pointing to the last row of the B-tree, for walking it backwards
+    pub fn table_last(&mut self, root_page_num: usize) -> Cursor<'_> {
+        let num_cells = self.get_node(root_page_num).unwrap().num_cells();
 
-        Cursor::new(self, self.root_page_num, num_cells, true)
+        Cursor::new(
+            self,
+            root_page_num,
+            num_cells.saturating_sub(1),
+            num_cells == 0,
+        )
     }
 
-    /// returns cursor pointing to the given key
-    pub fn table_find(&mut self, key: u32) -> Cursor {
-        let root_page_num = self.root_page_num;
+    /// returns cursor pointing to the given key in the B-tree at `root_page_num`
+    pub fn table_find(&mut self, root_page_num: usize, key: &[u8]) -> Cursor<'_> {
         if let Some(root_node) = self.get_node(root_page_num) {
             if root_node.is_root() {
                 return self.leaf_node_find(root_page_num, key);
@@ -54,7 +110,7 @@ impl Table {
         panic!("root node does not exist")
     }
 
-    pub fn leaf_node_find(&mut self, page_num: usize, key: u32) -> Cursor {
+    pub fn leaf_node_find(&mut self, page_num: usize, key: &[u8]) -> Cursor<'_> {
         let root_node = self.get_node(page_num).unwrap();
         let num_cells = root_node.num_cells();
 
@@ -76,14 +132,225 @@ impl Table {
             }
         }
 
-        return Cursor::new(self, page_num, min_index, false);
+        Cursor::new(self, page_num, min_index, false)
+    }
+
+    /// starts a statement whose changes are undone by `rollback`
+    pub fn begin(&mut self) {
+        self.pager.begin();
+        self.saved = Some((self.schema.clone(), self.last_insert_rowid));
+    }
+
+    /// keeps the changes made since `begin`
+    pub fn commit(&mut self) {
+        self.pager.commit();
+        self.saved = None;
+    }
+
+    /// undoes the changes made since `begin`
+    pub fn rollback(&mut self) {
+        self.pager.rollback();
+        if let Some((schema, last_insert_rowid)) = self.saved.take() {
+            self.schema = schema;
+            self.last_insert_rowid = last_insert_rowid;
+        }
+    }
+
+    /// defines the scalar function `name` for the statements run on this table, taking
+    /// `num_args` arguments or any number for `None`. a function of the same name is replaced.
+    pub fn create_scalar_function<F>(
+        &mut self,
+        name: &str,
+        num_args: Option<usize>,
+        f: F,
+    ) -> Result<(), String>
+    where
+        F: Fn(&[Value]) -> Result<Value, EvalError> + 'static,
+    {
+        self.add_function(UserFunction::scalar(name, num_args, f))
+    }
+
+    /// defines the aggregate function `name`, which feeds the rows of each group to
+    /// a fresh `A`, taking `num_args` arguments or any number for `None`
+    pub fn create_aggregate_function<A: Aggregate + Default + 'static>(
+        &mut self,
+        name: &str,
+        num_args: Option<usize>,
+    ) -> Result<(), String> {
+        self.add_function(UserFunction::aggregate::<A>(name, num_args))
+    }
+
+    fn add_function(&mut self, function: UserFunction) -> Result<(), String> {
+        let name = &function.name;
+        if function::is_function(name)
+            || aggregate::is_aggregate(name)
+            || window::is_window_function(name)
+        {
+            return Err(format!("built-in function {}() can not be redefined", name));
+        }
+        self.functions.insert(name.clone(), function);
+        Ok(())
+    }
+
+    /// returns the function the application defined with the given name
+    pub fn function(&self, name: &str) -> Option<&UserFunction> {
+        self.functions.get(name)
+    }
+
+    /// registers the virtual table `name` for the selects run on this table.
+    /// a virtual table of the same name is replaced.
+    pub fn register_virtual_table<T: VirtualTable + 'static>(
+        &mut self,
+        name: &str,
+        table: T,
+    ) -> Result<(), String> {
+        let name = name.to_lowercase();
+        if TableFunction::exists(&name) {
+            return Err(format!(
+                "built-in table-valued function {}() can not be redefined",
+                name
+            ));
+        }
+        self.virtual_tables.insert(name, Rc::new(table));
+        Ok(())
+    }
+
+    /// returns the virtual table registered with the given name
+    pub fn virtual_table(&self, name: &str) -> Option<&Rc<dyn VirtualTable>> {
+        self.virtual_tables.get(name)
+    }
+
+    /// returns the key of the most recent successful insert, or 0 before the first one
+    pub fn last_insert_rowid(&self) -> i64 {
+        self.last_insert_rowid
+    }
+
+    /// returns the number of rows the most recent successful insert, update or delete wrote
+    pub fn changes(&self) -> usize {
+        self.changes
+    }
+
+    /// records the number of rows an insert, update or delete wrote
+    pub fn changed(&mut self, count: usize) {
+        self.changes = count;
+    }
+
+    /// returns the key allocated to a row inserted without one: one past the
+    /// largest key, or past the largest key ever used by an `autoincrement`
+    /// table. `None` when the keys are exhausted.
+    pub fn next_rowid(&self, table: &TableDef) -> Option<i64> {
+        let node = self.get_node(table.root_page_num).unwrap();
+        let mut largest = match node.num_cells() {
+            0 => 0,
+            num_cells => match decode_key(node.get_key(num_cells - 1).unwrap()).as_deref() {
+                Ok([Value::Integer(key)]) => *key,
+                _ => return None,
+            },
+        };
+        if table.autoincrement() {
+            largest = largest.max(table.sequence);
+        }
+        largest.checked_add(1)
+    }
+
+    /// records a row inserted with the given key, advancing the sequence of
+    /// an `autoincrement` table
+    pub fn inserted(&mut self, table: &TableDef, rowid: i64) {
+        self.last_insert_rowid = rowid;
+        if table.autoincrement() && rowid > table.sequence {
+            let mut schema = self.schema.clone();
+            if let Some(def) = schema.tables.iter_mut().find(|t| t.name == table.name) {
+                def.sequence = rowid;
+            }
+            self.save_schema(schema);
+        }
     }
 
     pub fn get_node(&self, page_num: usize) -> Option<&LeafNode> {
-        if page_num < self.pager.pages.len() {
-            return Some(&self.pager.pages[page_num]);
+        self.pager.get_leaf(page_num)
+    }
+
+    /// writes the schema into the schema page, allocating it on first use
+    fn save_schema(&mut self, schema: Schema) {
+        let page = Page::Schema(SchemaPage::new(schema.clone()));
+        match self
+            .pager
+            .pages
+            .iter()
+            .position(|page| matches!(page, Page::Schema(_)))
+        {
+            Some(page_num) => self.pager.set_page(page_num, page),
+            None => {
+                self.pager.allocate_page(page);
+            }
         }
+        self.schema = schema;
+    }
 
-        None
+    /// allocates a root page for the table and records it in the schema,
+    /// together with the indexes backing its unique columns
+    pub fn create_table(&mut self, table: TableDef, indexes: Vec<Index>) -> Result<(), String> {
+        let first_index = self.schema.indexes.len();
+        let mut schema = self.schema.clone();
+        schema.tables.push(TableDef {
+            // page numbers are stored with a fixed size, so any page but the
+            // built-in table's one measures the same
+            root_page_num: usize::MAX,
+            ..table
+        });
+        schema.indexes.extend(indexes);
+        if !SchemaPage::new(schema.clone()).fits() {
+            return Err(String::from("schema is full"));
+        }
+
+        let root_page_num =
+            self.pager
+                .allocate_page(Page::Leaf(LeafNode::new(true, 0, 0, Vec::new())));
+        schema.tables.last_mut().unwrap().root_page_num = root_page_num;
+        for index in schema.indexes[first_index..].iter_mut() {
+            index.root_page_num = self.pager.allocate_page(Page::Index(IndexNode::new()));
+        }
+
+        self.save_schema(schema);
+        Ok(())
+    }
+
+    /// stores the index node in a new page and records the index in the schema page
+    pub fn add_index(&mut self, index: Index, node: IndexNode) -> Result<(), String> {
+        let mut schema = self.schema.clone();
+        schema.indexes.push(index);
+        if !SchemaPage::new(schema.clone()).fits() {
+            return Err(String::from("schema is full"));
+        }
+
+        let root_page_num = self.pager.allocate_page(Page::Index(node));
+        schema.indexes.last_mut().unwrap().root_page_num = root_page_num;
+        self.save_schema(schema);
+        Ok(())
+    }
+
+    /// returns if the `added` rows fit into the table and its indexes
+    /// once the `removed` rows are gone
+    pub fn has_room_for(&self, table: &TableDef, removed: &[&Row], added: &[&Row]) -> bool {
+        let cells = |rows: &[&Row]| -> usize {
+            rows.iter()
+                .map(|row| cell_size(table.key(row).len(), row.serialize().len()))
+                .sum()
+        };
+        let node = self.get_node(table.root_page_num).unwrap();
+        if node.free_space() + cells(removed) < cells(added) {
+            return false;
+        }
+
+        self.schema.indexes_of(&table.name).all(|index| {
+            let column = table.column_index(&index.column).unwrap();
+            let entries = |rows: &[&Row]| -> usize {
+                rows.iter()
+                    .map(|row| IndexNode::entry_size(&row.values[column], &table.key(row)))
+                    .sum()
+            };
+            let node = self.pager.get_index(index.root_page_num).unwrap();
+            node.free_space() + entries(removed) >= entries(added)
+        })
     }
 }
@@ -0,0 +1,346 @@
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fmt;
+
+// RECORD ENCODING TAGS
+const TAG_NULL: u8 = 0;
+const TAG_INTEGER: u8 = 1;
+const TAG_REAL: u8 = 2;
+const TAG_TEXT: u8 = 3;
+
+/// Storage class of a value or a result column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Integer,
+    Real,
+    Text,
+}
+
+impl DataType {
+    /// returns the type a column declared with the given type name stores,
+    /// using the same substring rules as sqlite. `None` keeps values as they are.
+    pub fn from_declared(type_name: &str) -> Option<DataType> {
+        let type_name = type_name.to_ascii_lowercase();
+        if type_name.contains("int") {
+            Some(DataType::Integer)
+        } else if ["char", "clob", "text"]
+            .iter()
+            .any(|t| type_name.contains(t))
+        {
+            Some(DataType::Text)
+        } else if ["real", "floa", "doub"]
+            .iter()
+            .any(|t| type_name.contains(t))
+        {
+            Some(DataType::Real)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataType::Integer => write!(f, "integer"),
+            DataType::Real => write!(f, "real"),
+            DataType::Text => write!(f, "text"),
+        }
+    }
+}
+
+/// returns the number the text spells out, or `None` when it is not a number
+fn parse_number(s: &str) -> Option<Value> {
+    let s = s.trim();
+    if let Ok(i) = s.parse::<i64>() {
+        Some(Value::Integer(i))
+    } else {
+        s.parse::<f64>().ok().map(Value::Real)
+    }
+}
+
+/// A single dynamically typed value produced while evaluating expressions
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    /// returns type of the value, or `None` for NULL
+    pub fn data_type(&self) -> Option<DataType> {
+        match self {
+            Value::Null => None,
+            Value::Integer(_) => Some(DataType::Integer),
+            Value::Real(_) => Some(DataType::Real),
+            Value::Text(_) => Some(DataType::Text),
+        }
+    }
+
+    /// returns truthiness of the value as used by `where` clauses.
+    /// `None` means the value is NULL, which is neither true nor false.
+    pub fn truthy(&self) -> Option<bool> {
+        match self.to_numeric() {
+            Value::Null => None,
+            Value::Integer(i) => Some(i != 0),
+            Value::Real(f) => Some(f != 0.0),
+            _ => Some(false),
+        }
+    }
+
+    /// converts text into a number the same way arithmetic does.
+    /// text that does not look like a number becomes 0.
+    pub fn to_numeric(&self) -> Value {
+        match self {
+            Value::Text(s) => parse_number(s).unwrap_or(Value::Integer(0)),
+            v => v.clone(),
+        }
+    }
+
+    pub fn as_real(&self) -> Option<f64> {
+        match self.to_numeric() {
+            Value::Integer(i) => Some(i as f64),
+            Value::Real(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    /// returns the value rendered as text, or `None` for NULL
+    pub fn as_text(&self) -> Option<String> {
+        match self {
+            Value::Null => None,
+            Value::Text(s) => Some(s.clone()),
+            v => Some(v.to_string()),
+        }
+    }
+
+    /// converts the value for storage in a column of the given type.
+    /// values that can not be converted without losing information are kept as they are.
+    pub fn apply_affinity(self, data_type: Option<DataType>) -> Value {
+        match (data_type, self) {
+            (_, Value::Null) => Value::Null,
+            (Some(DataType::Integer), Value::Real(f)) if f.fract() == 0.0 && f.abs() < 9e18 => {
+                Value::Integer(f as i64)
+            }
+            (Some(DataType::Integer), Value::Text(s)) => match parse_number(&s) {
+                Some(number) => number.apply_affinity(Some(DataType::Integer)),
+                None => Value::Text(s),
+            },
+            (Some(DataType::Real), Value::Integer(i)) => Value::Real(i as f64),
+            (Some(DataType::Real), Value::Text(s)) => match parse_number(&s) {
+                Some(number) => number.apply_affinity(Some(DataType::Real)),
+                None => Value::Text(s),
+            },
+            (Some(DataType::Text), v @ Value::Integer(_))
+            | (Some(DataType::Text), v @ Value::Real(_)) => Value::Text(v.to_string()),
+            (_, v) => v,
+        }
+    }
+
+    fn type_order(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Integer(_) | Value::Real(_) => 1,
+            Value::Text(_) => 2,
+        }
+    }
+
+    /// total order used for comparisons: NULL < numbers < text
+    pub fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            (a, b) if a.type_order() == 1 && b.type_order() == 1 => {
+                let (a, b) = (a.as_real().unwrap(), b.as_real().unwrap());
+                a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+            }
+            (a, b) => a.type_order().cmp(&b.type_order()),
+        }
+    }
+}
+
+/// serializes a list of values as `count | (tag | data)*`.
+/// numbers take 8 bytes and text is prefixed by its byte length.
+pub fn encode_record(values: &[Value]) -> Vec<u8> {
+    let mut buff = Vec::new();
+    buff.extend_from_slice(&(values.len() as u32).to_le_bytes());
+
+    for value in values.iter() {
+        match value {
+            Value::Null => buff.push(TAG_NULL),
+            Value::Integer(i) => {
+                buff.push(TAG_INTEGER);
+                buff.extend_from_slice(&i.to_le_bytes());
+            }
+            Value::Real(f) => {
+                buff.push(TAG_REAL);
+                buff.extend_from_slice(&f.to_le_bytes());
+            }
+            Value::Text(s) => {
+                buff.push(TAG_TEXT);
+                buff.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                buff.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+
+    buff
+}
+
+/// returns bytes identifying the values for grouping and `distinct`.
+/// numbers that compare equal, like `1` and `1.0`, produce the same key.
+pub fn hash_key(values: &[Value]) -> Vec<u8> {
+    let normalized: Vec<Value> = values
+        .iter()
+        .map(|value| match value {
+            Value::Real(f) if f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64 => {
+                Value::Integer(*f as i64)
+            }
+            v => v.clone(),
+        })
+        .collect();
+    encode_record(&normalized)
+}
+
+/// deserializes values written by `encode_record`
+pub fn decode_record(input: &[u8]) -> Result<Vec<Value>, String> {
+    fn take<'a>(input: &'a [u8], offset: &mut usize, n: usize) -> Result<&'a [u8], String> {
+        let bytes = input
+            .get(*offset..*offset + n)
+            .ok_or_else(|| String::from("record is truncated"))?;
+        *offset += n;
+        Ok(bytes)
+    }
+
+    let mut offset = 0;
+    let count = u32::from_le_bytes(take(input, &mut offset, 4)?.try_into().unwrap());
+
+    // every value takes at least its tag byte, so a corrupt count can not reserve more
+    let mut values = Vec::with_capacity((count as usize).min(input.len() - offset));
+    for _ in 0..count {
+        let value = match take(input, &mut offset, 1)?[0] {
+            TAG_NULL => Value::Null,
+            TAG_INTEGER => Value::Integer(i64::from_le_bytes(
+                take(input, &mut offset, 8)?.try_into().unwrap(),
+            )),
+            TAG_REAL => Value::Real(f64::from_le_bytes(
+                take(input, &mut offset, 8)?.try_into().unwrap(),
+            )),
+            TAG_TEXT => {
+                let length = u32::from_le_bytes(take(input, &mut offset, 4)?.try_into().unwrap());
+                let raw = take(input, &mut offset, length as usize)?;
+                Value::Text(
+                    String::from_utf8(raw.to_vec())
+                        .map_err(|e| format!("invalid UTF-8 in record: {}", e))?,
+                )
+            }
+            tag => return Err(format!("unknown value tag {}", tag)),
+        };
+        values.push(value);
+    }
+
+    if offset != input.len() {
+        return Err(String::from("record has unexpected trailing bytes"));
+    }
+
+    Ok(values)
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Integer(b as i64)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Real(r) => {
+                if r.fract() == 0.0 && r.is_finite() {
+                    write!(f, "{:.1}", r)
+                } else {
+                    write!(f, "{}", r)
+                }
+            }
+            Value::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_decode_record() {
+        let values = vec![
+            Value::Null,
+            Value::Integer(-42),
+            Value::Real(2.5),
+            Value::Text(String::from("田中")),
+        ];
+        let encoded = encode_record(&values);
+        assert_eq!(decode_record(&encoded), Ok(values));
+
+        assert!(decode_record(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_apply_affinity() {
+        let integer = Some(DataType::Integer);
+        assert_eq!(
+            Value::Text(" 42 ".into()).apply_affinity(integer),
+            Value::Integer(42)
+        );
+        assert_eq!(Value::Real(3.0).apply_affinity(integer), Value::Integer(3));
+        assert_eq!(Value::Real(3.5).apply_affinity(integer), Value::Real(3.5));
+        assert_eq!(
+            Value::Text("abc".into()).apply_affinity(integer),
+            Value::Text("abc".into())
+        );
+        assert_eq!(
+            Value::Integer(2).apply_affinity(Some(DataType::Real)),
+            Value::Real(2.0)
+        );
+        assert_eq!(
+            Value::Integer(2).apply_affinity(Some(DataType::Text)),
+            Value::Text("2".into())
+        );
+        assert_eq!(DataType::from_declared("VARCHAR"), Some(DataType::Text));
+        assert_eq!(DataType::from_declared("bigint"), Some(DataType::Integer));
+        assert_eq!(DataType::from_declared("timestamp"), None);
+    }
+
+    #[test]
+    fn test_hash_key_matches_equal_numbers() {
+        assert_eq!(
+            hash_key(&[Value::Integer(1), Value::Null]),
+            hash_key(&[Value::Real(1.0), Value::Null])
+        );
+        assert_ne!(
+            hash_key(&[Value::Integer(1)]),
+            hash_key(&[Value::Text(String::from("1"))])
+        );
+    }
+
+    #[test]
+    fn test_compare_orders_types() {
+        assert_eq!(Value::Null.compare(&Value::Integer(1)), Ordering::Less);
+        assert_eq!(
+            Value::Integer(2).compare(&Value::Real(1.5)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            Value::Integer(100).compare(&Value::Text(String::from("1"))),
+            Ordering::Less
+        );
+    }
+}
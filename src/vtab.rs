@@ -0,0 +1,264 @@
+use super::expression::{BinaryOp, EvalError};
+use super::schema::ColumnDef;
+use super::value::Value;
+use std::cmp::Ordering;
+
+/// Comparison of a `<column> <op> <value>` term of the `where` clause
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl ConstraintOp {
+    /// returns the comparison `op`, with its operands swapped when `flipped`
+    pub fn from_binary(op: &BinaryOp, flipped: bool) -> Option<Self> {
+        let op = match op {
+            BinaryOp::Eq => ConstraintOp::Eq,
+            BinaryOp::NotEq => ConstraintOp::NotEq,
+            BinaryOp::Lt => ConstraintOp::Lt,
+            BinaryOp::LtEq => ConstraintOp::LtEq,
+            BinaryOp::Gt => ConstraintOp::Gt,
+            BinaryOp::GtEq => ConstraintOp::GtEq,
+            _ => return None,
+        };
+        if !flipped {
+            return Some(op);
+        }
+        Some(match op {
+            ConstraintOp::Lt => ConstraintOp::Gt,
+            ConstraintOp::LtEq => ConstraintOp::GtEq,
+            ConstraintOp::Gt => ConstraintOp::Lt,
+            ConstraintOp::GtEq => ConstraintOp::LtEq,
+            op => op,
+        })
+    }
+}
+
+/// A `<column> <op> <value>` term of the `where` clause handed to a virtual table,
+/// which can use it to skip rows. rows it returns anyway are still checked
+/// against the whole clause.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnConstraint {
+    /// position of the column in `VirtualTable::columns`
+    pub column: usize,
+    pub op: ConstraintOp,
+    pub value: Value,
+}
+
+impl ColumnConstraint {
+    /// returns if a column value satisfies the constraint. NULL satisfies none.
+    pub fn matches(&self, value: &Value) -> bool {
+        if value.is_null() || self.value.is_null() {
+            return false;
+        }
+        let ordering = value.compare(&self.value);
+        match self.op {
+            ConstraintOp::Eq => ordering == Ordering::Equal,
+            ConstraintOp::NotEq => ordering != Ordering::Equal,
+            ConstraintOp::Lt => ordering == Ordering::Less,
+            ConstraintOp::LtEq => ordering != Ordering::Greater,
+            ConstraintOp::Gt => ordering == Ordering::Greater,
+            ConstraintOp::GtEq => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// Rows the application computes instead of reading them from a B-tree, read by
+/// selects like a table once registered with `Table::register_virtual_table`.
+/// `from <name>(<expr> [, ...])` passes arguments to `VirtualCursor::filter`, and so
+/// does `where <hidden column> = <value>` for each hidden column past them, in order.
+pub trait VirtualTable {
+    /// returns the columns of the rows. a column named `rowid` is added
+    /// as a hidden column unless one is declared.
+    fn columns(&self) -> Vec<ColumnDef>;
+
+    /// returns a cursor reading the rows from the start
+    fn open(&self) -> Result<Box<dyn VirtualCursor + '_>, EvalError>;
+}
+
+/// Walks the rows of a virtual table
+pub trait VirtualCursor {
+    /// moves to the first of the rows for the arguments the table is called with.
+    /// the rows can be narrowed to those matching the constraints.
+    fn filter(&mut self, args: &[Value], constraints: &[ColumnConstraint])
+        -> Result<(), EvalError>;
+
+    /// moves to the next row
+    fn next(&mut self) -> Result<(), EvalError>;
+
+    /// returns if the cursor moved past the last row
+    fn eof(&self) -> bool;
+
+    /// returns the value of the column of the current row
+    fn column(&self, i: usize) -> Result<Value, EvalError>;
+
+    /// returns the rowid of the current row
+    fn rowid(&self) -> Result<i64, EvalError>;
+}
+
+/// `generate_series(<start> [, <stop> [, <step>]])`, the integers from start
+/// to stop, both included, counting by step. its arguments are hidden columns.
+pub struct GenerateSeries;
+
+impl VirtualTable for GenerateSeries {
+    fn columns(&self) -> Vec<ColumnDef> {
+        let hidden = |name| ColumnDef {
+            hidden: true,
+            ..ColumnDef::new(name, "integer")
+        };
+        vec![
+            ColumnDef::new("value", "integer"),
+            hidden("start"),
+            hidden("stop"),
+            hidden("step"),
+        ]
+    }
+
+    fn open(&self) -> Result<Box<dyn VirtualCursor + '_>, EvalError> {
+        Ok(Box::new(SeriesCursor {
+            args: [0, 0, 1],
+            value: None,
+            rowid: 1,
+            stop: 0,
+        }))
+    }
+}
+
+struct SeriesCursor {
+    /// start, stop and step
+    args: [i64; 3],
+    /// `None` once the value went past `i64::MAX`
+    value: Option<i64>,
+    rowid: i64,
+    /// last value returned, which the constraints on `value` can lower
+    stop: i64,
+}
+
+impl VirtualCursor for SeriesCursor {
+    fn filter(
+        &mut self,
+        args: &[Value],
+        constraints: &[ColumnConstraint],
+    ) -> Result<(), EvalError> {
+        if args.is_empty() || args.len() > 3 {
+            return Err(String::from(
+                "wrong number of arguments to table-valued function generate_series()",
+            ));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            self.args[i] = match arg {
+                Value::Integer(n) => *n,
+                _ => {
+                    return Err(format!(
+                        "generate_series() argument {} is not an integer",
+                        arg
+                    ))
+                }
+            };
+        }
+        if args.len() < 2 {
+            self.args[1] = u32::MAX as i64;
+        }
+        let [start, stop, step] = self.args;
+        if step <= 0 {
+            return Err(String::from("generate_series() step must be positive"));
+        }
+
+        self.value = Some(start);
+        self.rowid = 1;
+        self.stop = stop;
+        // stop at the bound of `value < n` or `value <= n` instead of going on to no avail
+        for constraint in constraints.iter().filter(|c| c.column == 0) {
+            let bound = match (constraint.op, &constraint.value) {
+                (ConstraintOp::Lt, Value::Integer(n)) => n.saturating_sub(1),
+                (ConstraintOp::LtEq, Value::Integer(n)) | (ConstraintOp::Eq, Value::Integer(n)) => {
+                    *n
+                }
+                _ => continue,
+            };
+            self.stop = self.stop.min(bound);
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<(), EvalError> {
+        self.value = self.value.and_then(|value| value.checked_add(self.args[2]));
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.value.is_none_or(|value| value > self.stop)
+    }
+
+    fn column(&self, i: usize) -> Result<Value, EvalError> {
+        match i {
+            0 => Ok(self.value.map_or(Value::Null, Value::Integer)),
+            1..=3 => Ok(Value::Integer(self.args[i - 1])),
+            _ => Err(format!("generate_series has no column {}", i)),
+        }
+    }
+
+    fn rowid(&self) -> Result<i64, EvalError> {
+        Ok(self.rowid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(args: &[i64], constraints: &[ColumnConstraint]) -> Result<Vec<i64>, EvalError> {
+        let args: Vec<Value> = args.iter().map(|n| Value::Integer(*n)).collect();
+        let mut cursor = GenerateSeries.open()?;
+        cursor.filter(&args, constraints)?;
+        let mut values = Vec::new();
+        while !cursor.eof() {
+            assert_eq!(cursor.rowid()?, values.len() as i64 + 1);
+            match cursor.column(0)? {
+                Value::Integer(n) => values.push(n),
+                value => panic!("{:?}", value),
+            }
+            cursor.next()?;
+        }
+        Ok(values)
+    }
+
+    #[test]
+    fn test_generate_series() -> Result<(), EvalError> {
+        assert_eq!(series(&[1, 5], &[])?, vec![1, 2, 3, 4, 5]);
+        assert_eq!(series(&[0, 10, 4], &[])?, vec![0, 4, 8]);
+        assert_eq!(series(&[3, 1], &[])?, Vec::<i64>::new());
+        assert_eq!(
+            series(&[i64::MAX - 1, i64::MAX], &[])?,
+            vec![i64::MAX - 1, i64::MAX]
+        );
+
+        let below = |op, n| ColumnConstraint {
+            column: 0,
+            op,
+            value: Value::Integer(n),
+        };
+        assert_eq!(series(&[1], &[below(ConstraintOp::Lt, 4)])?, vec![1, 2, 3]);
+        assert_eq!(
+            series(
+                &[1, 10, 2],
+                &[below(ConstraintOp::LtEq, 5), below(ConstraintOp::Gt, 2)]
+            )?,
+            vec![1, 3, 5]
+        );
+
+        assert_eq!(
+            series(&[1, 10, 0], &[]),
+            Err(String::from("generate_series() step must be positive"))
+        );
+        assert!(below(ConstraintOp::GtEq, 2).matches(&Value::Real(2.5)));
+        assert!(!below(ConstraintOp::NotEq, 2).matches(&Value::Null));
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+use super::aggregate;
+use super::expression::{EvalError, Expr, Scope};
+use super::value::{DataType, Value};
+use std::cmp::Ordering;
+
+/// functions that can only be called with `over`. aggregates can be called with it too.
+const WINDOW_FUNCTIONS: [&str; 5] = ["dense_rank", "lag", "lead", "rank", "row_number"];
+
+pub fn is_window_function(name: &str) -> bool {
+    WINDOW_FUNCTIONS.contains(&name)
+}
+
+/// checks the function can be called with `over` and the number of arguments passed to it
+pub fn check_arguments(name: &str, num_args: usize) -> Result<(), String> {
+    let valid = match name {
+        "dense_rank" | "rank" | "row_number" => num_args == 0,
+        // lag(<expr> [, <offset> [, <default>]])
+        "lag" | "lead" => (1..=3).contains(&num_args),
+        _ if aggregate::is_aggregate(name) => return aggregate::check_arguments(name, num_args),
+        _ => return Err(format!("{}() may not be used as a window function", name)),
+    };
+
+    if !valid {
+        return Err(format!("wrong number of arguments to function {}()", name));
+    }
+    Ok(())
+}
+
+/// returns the type the window function produces from arguments of the given type
+pub fn return_type(name: &str, arg_type: Option<DataType>) -> Option<DataType> {
+    match name {
+        "dense_rank" | "rank" | "row_number" => Some(DataType::Integer),
+        "lag" | "lead" => arg_type,
+        _ => aggregate::return_type(name, arg_type),
+    }
+}
+
+fn compare_keys(a: &[Value], b: &[Value], descending: &[bool]) -> Ordering {
+    for (i, (a, b)) in a.iter().zip(b.iter()).enumerate() {
+        let ordering = a.compare(b);
+        if ordering != Ordering::Equal {
+            return if descending.get(i) == Some(&true) {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+        }
+    }
+    Ordering::Equal
+}
+
+/// computes every window function call for the rows read through `scopes`.
+/// the rows are split into partitions by their `partition by` values and ordered
+/// within them by `order by`. returns the values of the calls for each row.
+pub fn evaluate(calls: &[&Expr], scopes: &[&dyn Scope]) -> Result<Vec<Vec<Value>>, EvalError> {
+    let mut results = vec![Vec::with_capacity(calls.len()); scopes.len()];
+    for call in calls.iter() {
+        let (partition_by, order_by) = match call {
+            Expr::Window {
+                partition_by,
+                order_by,
+                ..
+            } => (partition_by, order_by),
+            _ => unreachable!("not a window function call"),
+        };
+        let keys = |exprs: Vec<&Expr>| -> Result<Vec<Vec<Value>>, EvalError> {
+            scopes
+                .iter()
+                .map(|scope| exprs.iter().map(|expr| expr.eval(*scope)).collect())
+                .collect()
+        };
+        let partitions = keys(partition_by.iter().collect())?;
+        let orders = keys(order_by.iter().map(|(expr, _)| expr).collect())?;
+        let descending: Vec<bool> = order_by.iter().map(|(_, desc)| *desc).collect();
+
+        // rows of the same partition end up next to each other, in window order
+        let mut positions: Vec<usize> = (0..scopes.len()).collect();
+        positions.sort_by(|&a, &b| {
+            compare_keys(&partitions[a], &partitions[b], &[])
+                .then_with(|| compare_keys(&orders[a], &orders[b], &descending))
+        });
+
+        let mut values = vec![Value::Null; scopes.len()];
+        let mut start = 0;
+        while start < positions.len() {
+            let first = &partitions[positions[start]];
+            let end = positions[start..]
+                .iter()
+                .position(|&p| compare_keys(&partitions[p], first, &[]) != Ordering::Equal)
+                .map_or(positions.len(), |n| start + n);
+            let partition = Partition {
+                rows: &positions[start..end],
+                scopes,
+                orders: &orders,
+                ordered: !order_by.is_empty(),
+            };
+            for (&position, value) in partition.rows.iter().zip(partition.evaluate(call)?) {
+                values[position] = value;
+            }
+            start = end;
+        }
+
+        for (result, value) in results.iter_mut().zip(values) {
+            result.push(value);
+        }
+    }
+    Ok(results)
+}
+
+/// Rows sharing the same `partition by` values, in window order
+struct Partition<'a> {
+    /// positions of the rows in `scopes`
+    rows: &'a [usize],
+    scopes: &'a [&'a dyn Scope],
+    orders: &'a [Vec<Value>],
+    ordered: bool,
+}
+
+impl<'a> Partition<'a> {
+    fn scope(&self, i: usize) -> &'a dyn Scope {
+        self.scopes[self.rows[i]]
+    }
+
+    /// returns if the i-th and j-th rows have the same `order by` values
+    fn peers(&self, i: usize, j: usize) -> bool {
+        compare_keys(&self.orders[self.rows[i]], &self.orders[self.rows[j]], &[]) == Ordering::Equal
+    }
+
+    /// returns the value of the call for each row of the partition
+    fn evaluate(&self, call: &Expr) -> Result<Vec<Value>, EvalError> {
+        let (name, args) = match call {
+            Expr::Window { name, args, .. } => (name.as_str(), args),
+            _ => unreachable!("not a window function call"),
+        };
+        let count = self.rows.len();
+        let mut values = Vec::with_capacity(count);
+        match name {
+            "row_number" => values.extend((1..=count as i64).map(Value::Integer)),
+            "rank" | "dense_rank" => {
+                // peers share a rank. `rank` then skips as many ranks as there were peers.
+                let mut rank = 1;
+                for i in 0..count {
+                    if i > 0 && !self.peers(i - 1, i) {
+                        rank = if name == "rank" { i + 1 } else { rank + 1 };
+                    }
+                    values.push(Value::Integer(rank as i64));
+                }
+            }
+            "lag" | "lead" => {
+                for i in 0..count {
+                    let scope = self.scope(i);
+                    let offset = match args.get(1) {
+                        Some(offset) => match offset.eval(scope)?.to_numeric() {
+                            Value::Integer(n) if n >= 0 => n,
+                            _ => {
+                                return Err(format!(
+                                    "second argument to {}() must be a non-negative integer",
+                                    name
+                                ))
+                            }
+                        },
+                        None => 1,
+                    };
+                    // an offset overflowing the row number is out of range too
+                    let target = if name == "lag" {
+                        (i as i64).checked_sub(offset)
+                    } else {
+                        (i as i64).checked_add(offset)
+                    };
+                    values.push(match target.filter(|&t| t >= 0 && (t as usize) < count) {
+                        Some(target) => args[0].eval(self.scope(target as usize))?,
+                        None => match args.get(2) {
+                            Some(default) => default.eval(scope)?,
+                            None => Value::Null,
+                        },
+                    });
+                }
+            }
+            _ => {
+                // an aggregate runs over the rows up to the last peer of the current row,
+                // or over the whole partition without `order by`
+                let mut aggregate =
+                    aggregate::new_aggregate(name, false).expect("not an aggregate");
+                let mut i = 0;
+                while i < count {
+                    let mut end = i + 1;
+                    while end < count && (!self.ordered || self.peers(i, end)) {
+                        end += 1;
+                    }
+                    for j in i..end {
+                        let scope = self.scope(j);
+                        let args =
+                            args.iter()
+                                .map(|arg| arg.eval(scope))
+                                .collect::<Result<Vec<Value>, EvalError>>()?;
+                        aggregate.step(&args)?;
+                    }
+                    let value = aggregate.finalize()?;
+                    values.extend((i..end).map(|_| value.clone()));
+                    i = end;
+                }
+            }
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// a row with a `team` and a `score`
+    struct TestRow(HashMap<&'static str, Value>);
+
+    impl Scope for TestRow {
+        fn column(&self, name: &str) -> Option<Value> {
+            self.0.get(name).cloned()
+        }
+    }
+
+    fn window(name: &str, args: Vec<Expr>, order_by: &str) -> Expr {
+        Expr::Window {
+            name: name.into(),
+            args,
+            partition_by: vec![Expr::Column("team".into())],
+            order_by: vec![(Expr::Column(order_by.into()), false)],
+        }
+    }
+
+    #[test]
+    fn test_functions_run_over_each_partition() {
+        let rows: Vec<TestRow> = [("a", 3), ("b", 5), ("a", 1), ("a", 3), ("b", 2)]
+            .iter()
+            .map(|&(team, score)| {
+                let mut values = HashMap::new();
+                values.insert("team", Value::Text(team.into()));
+                values.insert("score", Value::Integer(score));
+                TestRow(values)
+            })
+            .collect();
+        let scopes: Vec<&dyn Scope> = rows.iter().map(|row| row as &dyn Scope).collect();
+        let score = || Expr::Column("score".into());
+        let calls = [
+            window("row_number", vec![], "score"),
+            window("rank", vec![], "score"),
+            window("dense_rank", vec![], "score"),
+            window("lag", vec![score()], "score"),
+            window(
+                "lead",
+                vec![score(), Expr::Literal(Value::Integer(2))],
+                "score",
+            ),
+            window("sum", vec![score()], "score"),
+        ];
+        let calls: Vec<&Expr> = calls.iter().collect();
+
+        let row = |values: [Option<i64>; 6]| -> Vec<Value> {
+            values
+                .iter()
+                .map(|value| value.map_or(Value::Null, Value::Integer))
+                .collect()
+        };
+        assert_eq!(
+            evaluate(&calls, &scopes).unwrap(),
+            vec![
+                row([Some(2), Some(2), Some(2), Some(1), None, Some(7)]),
+                row([Some(2), Some(2), Some(2), Some(2), None, Some(7)]),
+                row([Some(1), Some(1), Some(1), None, Some(3), Some(1)]),
+                row([Some(3), Some(2), Some(2), Some(3), None, Some(7)]),
+                row([Some(1), Some(1), Some(1), None, None, Some(2)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_offsets_overflowing_the_row_number_return_the_default() {
+        let mut values = HashMap::new();
+        values.insert("team", Value::Text("a".into()));
+        values.insert("score", Value::Integer(1));
+        let row = TestRow(values);
+        let scopes: Vec<&dyn Scope> = vec![&row];
+        let call = |name: &str| {
+            let args = vec![
+                Expr::Column("score".into()),
+                Expr::Literal(Value::Integer(i64::MAX)),
+                Expr::Literal(Value::Integer(-1)),
+            ];
+            window(name, args, "score")
+        };
+        let (lag, lead) = (call("lag"), call("lead"));
+        assert_eq!(
+            evaluate(&[&lag, &lead], &scopes).unwrap(),
+            vec![vec![Value::Integer(-1), Value::Integer(-1)]]
+        );
+    }
+}